@@ -0,0 +1,219 @@
+use crate::errors::CarguixError;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Package entry of a `Cargo.lock` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    /// Missing for path dependencies and workspace members.
+    pub source: Option<String>,
+    /// SHA-256 of the crate tarball, in hexadecimal, missing in version 1 lock files which list
+    /// checksums in their `[metadata]` table instead.
+    pub checksum: Option<String>,
+}
+
+/// `Cargo.lock` file of any version.
+///
+/// Version 1 lock files describe dependencies as `"name version (source)"` strings and list
+/// checksums in `[metadata]`, later versions shorten dependencies to the name when unambiguous and
+/// give each package a `checksum` key. Dependencies are left out since only the packages matter.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CargoLock {
+    #[serde(default)]
+    pub package: Vec<LockedPackage>,
+    /// Only used by version 1 lock files, for checksums.
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+impl CargoLock {
+    pub fn open(path: &Path) -> Result<Self, CarguixError> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| CarguixError::CargoLockIoError(err, path.to_path_buf()))?;
+        let mut lock = toml::from_str::<CargoLock>(&content)
+            .map_err(|err| CarguixError::CargoLockParsingError(err, path.to_path_buf()))?;
+        let metadata = std::mem::take(&mut lock.metadata);
+        for (key, checksum) in metadata {
+            // Keys read `checksum <name> <version> (<source>)`
+            let mut fields = key.split_whitespace();
+            if fields.next() != Some("checksum") || checksum == "<none>" {
+                continue;
+            }
+            let (name, version, source) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(version), Some(source)) => (name, version, source),
+                _ => continue,
+            };
+            let source = source.trim_start_matches('(').trim_end_matches(')');
+            if let Some(package) = lock.package.iter_mut().find(|package| {
+                package.name == name
+                    && package.version == version
+                    && package.source.as_deref() == Some(source)
+            }) {
+                package.checksum.get_or_insert(checksum);
+            }
+        }
+        Ok(lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+    const MIRROR: &str = "registry+https://example.org/index";
+    const GIT: &str = "git+https://example.org/rand#0123456789abcdef";
+
+    fn open(content: &str) -> CargoLock {
+        let dir = TempDir::new("carguix-cargo-lock").unwrap();
+        let path = dir.path().join("Cargo.lock");
+        fs::write(&path, content).unwrap();
+        CargoLock::open(&path).unwrap()
+    }
+
+    /// `(name, version, source, checksum)` of every package, in order.
+    fn packages(lock: &CargoLock) -> Vec<(&str, &str, Option<&str>, Option<&str>)> {
+        lock.package
+            .iter()
+            .map(|package| {
+                (
+                    package.name.as_str(),
+                    package.version.as_str(),
+                    package.source.as_deref(),
+                    package.checksum.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_version_1_checksums_from_metadata() {
+        let lock = open(&format!(
+            r#"[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "rand 0.7.3 (registry+https://github.com/rust-lang/crates.io-index)",
+ "rand 0.7.3 (git+https://example.org/rand#0123456789abcdef)",
+ "libc 0.2.66 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.66"
+source = "{crates_io}"
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{crates_io}"
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{git}"
+
+[metadata]
+"checksum libc 0.2.66 ({crates_io})" = "<none>"
+"checksum rand 0.7.3 ({crates_io})" = "aaaa"
+"checksum rand 0.7.3 ({git})" = "<none>"
+"checksum serde 1.0.0 ({crates_io})" = "bbbb"
+"#,
+            crates_io = CRATES_IO,
+            git = GIT,
+        ));
+        assert_eq!(
+            packages(&lock),
+            vec![
+                ("app", "0.1.0", None, None),
+                ("libc", "0.2.66", Some(CRATES_IO), None),
+                ("rand", "0.7.3", Some(CRATES_IO), Some("aaaa")),
+                ("rand", "0.7.3", Some(GIT), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_version_2_inline_checksums() {
+        let lock = open(&format!(
+            r#"[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "libc",
+ "rand 0.7.3 (registry+https://github.com/rust-lang/crates.io-index)",
+ "rand 0.7.3 (registry+https://example.org/index)",
+]
+
+[[package]]
+name = "libc"
+version = "0.2.66"
+source = "{crates_io}"
+checksum = "cccc"
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{crates_io}"
+checksum = "aaaa"
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{mirror}"
+checksum = "dddd"
+"#,
+            crates_io = CRATES_IO,
+            mirror = MIRROR,
+        ));
+        assert_eq!(
+            packages(&lock),
+            vec![
+                ("app", "0.1.0", None, None),
+                ("libc", "0.2.66", Some(CRATES_IO), Some("cccc")),
+                ("rand", "0.7.3", Some(CRATES_IO), Some("aaaa")),
+                ("rand", "0.7.3", Some(MIRROR), Some("dddd")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_version_3_inline_checksums() {
+        let lock = open(&format!(
+            r#"version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "rand 0.7.3 (registry+https://github.com/rust-lang/crates.io-index)",
+ "rand 0.7.3 (git+https://example.org/rand#0123456789abcdef)",
+]
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{crates_io}"
+checksum = "aaaa"
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{git}"
+"#,
+            crates_io = CRATES_IO,
+            git = GIT,
+        ));
+        assert_eq!(
+            packages(&lock),
+            vec![
+                ("app", "0.1.0", None, None),
+                ("rand", "0.7.3", Some(CRATES_IO), Some("aaaa")),
+                ("rand", "0.7.3", Some(GIT), None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    errors::CarguixError,
+    path_source::{read_manifest, Workspace},
+};
+use cargo_toml::Manifest;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+/// Output of `cargo metadata --format-version 1 --no-deps`, only the fields carguix reads.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+}
+
+/// Package of the workspace, as cargo normalizes its manifest: inherited fields and
+/// dependencies are filled from the workspace root.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub id: String,
+    pub license: Option<String>,
+    pub license_file: Option<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub documentation: Option<String>,
+    pub readme: Option<String>,
+    pub links: Option<String>,
+    pub publish: Option<Vec<String>>,
+    pub edition: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    pub features: BTreeMap<String, Vec<String>>,
+    pub manifest_path: PathBuf,
+    pub targets: Vec<Target>,
+    pub dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub kind: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    /// `registry+<index>`, `sparse+<index>` or `git+<url>?<reference>`, `None` for paths.
+    pub source: Option<String>,
+    pub req: String,
+    /// `None` for normal dependencies, `dev` or `build` otherwise.
+    pub kind: Option<String>,
+    pub rename: Option<String>,
+    pub optional: bool,
+    pub uses_default_features: bool,
+    pub features: Vec<String>,
+    pub target: Option<String>,
+    /// Index of the alternative registry, `None` for crates.io.
+    pub registry: Option<String>,
+    pub path: Option<PathBuf>,
+}
+
+impl Metadata {
+    /// Metadata of the workspace `path` belongs to, or of the crate at `path` if it doesn't
+    /// belong to any, `None` when cargo can't be found.
+    pub fn read(path: &Path) -> Result<Option<Self>, CarguixError> {
+        let manifest_path = path.join("Cargo.toml");
+        let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let output = Command::new(cargo)
+            .args([
+                "metadata",
+                "--format-version",
+                "1",
+                "--no-deps",
+                "--offline",
+            ])
+            .arg("--manifest-path")
+            .arg(&manifest_path)
+            .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(CarguixError::CargoMetadataError(err, manifest_path)),
+        };
+        if !output.status.success() {
+            return Err(CarguixError::CargoMetadataFailed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                manifest_path,
+            ));
+        }
+        serde_json::from_slice(&output.stdout)
+            .map(Some)
+            .map_err(|err| CarguixError::CargoMetadataParsingError(err, manifest_path))
+    }
+
+    /// Packages of the workspace members, by directory.
+    pub fn members(&self) -> impl Iterator<Item = (PathBuf, &Package)> {
+        self.packages
+            .iter()
+            .filter(move |package| self.workspace_members.contains(&package.id))
+            .map(|package| (package.dir(), package))
+    }
+}
+
+impl Package {
+    pub fn dir(&self) -> PathBuf {
+        self.manifest_path
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf)
+    }
+
+    /// Manifest with the fields and dependencies cargo resolved, the file selection being taken
+    /// from `parsed`, the manifest as carguix reads it, since cargo doesn't report it.
+    pub fn manifest(&self, parsed: Option<&Manifest>) -> Result<Manifest, CarguixError> {
+        let mut package = toml::value::Table::new();
+        let mut set = |key: &str, value: toml::Value| {
+            package.insert(key.to_string(), value);
+        };
+        set("name", self.name.clone().into());
+        set("version", self.version.clone().into());
+        set("edition", self.edition.clone().into());
+        let optional_fields = [
+            ("license", &self.license),
+            ("license-file", &self.license_file),
+            ("description", &self.description),
+            ("homepage", &self.homepage),
+            ("repository", &self.repository),
+            ("documentation", &self.documentation),
+            ("readme", &self.readme),
+            ("links", &self.links),
+        ];
+        for (key, value) in optional_fields.iter() {
+            if let Some(value) = value {
+                set(key, value.clone().into());
+            }
+        }
+        for (key, values) in [
+            ("authors", &self.authors),
+            ("keywords", &self.keywords),
+            ("categories", &self.categories),
+        ]
+        .iter()
+        {
+            set(key, (*values).clone().into());
+        }
+        if let Some(registries) = &self.publish {
+            set("publish", registries.clone().into());
+        }
+        if let Some(parsed) = parsed.and_then(|parsed| parsed.package.as_ref()) {
+            set("include", parsed.include().to_vec().into());
+            set("exclude", parsed.exclude().to_vec().into());
+        }
+        let mut manifest = toml::value::Table::new();
+        manifest.insert("package".to_string(), package.into());
+        let features = self
+            .features
+            .iter()
+            .map(|(feature, implied)| (feature.clone(), implied.clone().into()))
+            .collect::<toml::value::Table>();
+        manifest.insert("features".to_string(), features.into());
+        if self
+            .targets
+            .iter()
+            .any(|target| target.kind.iter().any(|kind| kind == "proc-macro"))
+        {
+            let mut lib = toml::value::Table::new();
+            lib.insert("proc-macro".to_string(), true.into());
+            manifest.insert("lib".to_string(), lib.into());
+        }
+        for dependency in &self.dependencies {
+            let table_name = match dependency.kind.as_deref() {
+                Some("dev") => "dev-dependencies",
+                Some("build") => "build-dependencies",
+                _ => "dependencies",
+            };
+            let mut tables = &mut manifest;
+            if let Some(target) = &dependency.target {
+                tables = table(table(tables, "target"), target);
+            }
+            let key = dependency.rename.as_ref().unwrap_or(&dependency.name);
+            table(tables, table_name).insert(key.clone(), dependency.detail().into());
+        }
+        toml::Value::Table(manifest)
+            .try_into::<Manifest>()
+            .map_err(|err| {
+                CarguixError::ManifestParsingError(err.into(), self.manifest_path.clone())
+            })
+    }
+}
+
+impl Dependency {
+    fn detail(&self) -> toml::value::Table {
+        let mut detail = toml::value::Table::new();
+        let mut set = |key: &str, value: toml::Value| {
+            detail.insert(key.to_string(), value);
+        };
+        if self.path.is_none() || self.req != "*" {
+            set("version", self.req.clone().into());
+        }
+        if self.rename.is_some() {
+            set("package", self.name.clone().into());
+        }
+        if let Some(path) = &self.path {
+            set("path", path.to_string_lossy().into_owned().into());
+        }
+        if let Some(index) = &self.registry {
+            set("registry-index", index.clone().into());
+        }
+        if let Some(git) = self
+            .source
+            .as_deref()
+            .and_then(|source| source.strip_prefix("git+"))
+        {
+            let (url, reference) = match git.split_once('?') {
+                Some((url, reference)) => (url, Some(reference)),
+                None => (git, None),
+            };
+            set(
+                "git",
+                url.split('#').next().unwrap_or(url).to_string().into(),
+            );
+            if let Some((key, value)) = reference.and_then(|reference| reference.split_once('=')) {
+                set(key, value.to_string().into());
+            }
+        }
+        set("optional", self.optional.into());
+        set("default-features", self.uses_default_features.into());
+        set("features", self.features.clone().into());
+        detail
+    }
+}
+
+/// Table `key` of `tables`, inserted if missing.
+fn table<'a>(tables: &'a mut toml::value::Table, key: &str) -> &'a mut toml::value::Table {
+    tables
+        .entry(key.to_string())
+        .or_insert_with(|| toml::value::Table::new().into())
+        .as_table_mut()
+        .expect("dependency tables are tables")
+}
+
+/// Manifests and workspaces of the local crates as `cargo metadata` reports them, read a
+/// workspace at a time.
+#[derive(Debug, Default)]
+pub struct CargoManifests {
+    /// Manifests of the workspace members, by directory.
+    manifests: HashMap<PathBuf, Arc<Manifest>>,
+    /// Workspaces with more than the crate at their root, by root.
+    workspaces: HashMap<PathBuf, Arc<Workspace>>,
+    /// Whether cargo couldn't be found.
+    pub missing: bool,
+}
+
+impl CargoManifests {
+    /// Manifest of the crate at `path`, `parsed` being the one carguix reads itself if it could,
+    /// `None` when cargo can't be found.
+    pub fn manifest(
+        &mut self,
+        path: &Path,
+        parsed: Option<&Manifest>,
+    ) -> Result<Option<Arc<Manifest>>, CarguixError> {
+        if !self.manifests.contains_key(path) {
+            self.read(path, parsed)?;
+        }
+        match self.manifests.get(path) {
+            Some(manifest) => Ok(Some(manifest.clone())),
+            None if self.missing => Ok(None),
+            None => Err(CarguixError::NoPackageInManifest(path.join("Cargo.toml"))),
+        }
+    }
+
+    /// Workspace whose root is `root`, `None` when it is a single crate or cargo can't be found.
+    pub fn workspace(&mut self, root: &Path) -> Result<Option<Arc<Workspace>>, CarguixError> {
+        if !self.manifests.contains_key(root) && !self.workspaces.contains_key(root) {
+            self.read(root, None)?;
+        }
+        Ok(self.workspaces.get(root).cloned())
+    }
+
+    /// Read the workspace `path` belongs to, unless cargo can't be found.
+    fn read(&mut self, path: &Path, parsed: Option<&Manifest>) -> Result<(), CarguixError> {
+        let metadata = match Metadata::read(path)? {
+            Some(metadata) => metadata,
+            None => {
+                self.missing = true;
+                return Ok(());
+            }
+        };
+        let root = metadata.workspace_root.clone();
+        let root_manifest = read_manifest(&root, None);
+        let mut members = BTreeMap::new();
+        for (dir, package) in metadata.members() {
+            let parsed = match parsed {
+                Some(parsed) if dir == path => Some(parsed.clone()),
+                _ => {
+                    let workspace = match &root_manifest {
+                        Ok(root_manifest) if dir != root => Some((root_manifest, root.as_path())),
+                        _ => None,
+                    };
+                    read_manifest(&dir, workspace).ok()
+                }
+            };
+            let manifest = package.manifest(parsed.as_ref())?;
+            members.insert(package.name.clone(), dir.clone());
+            self.manifests.insert(dir, Arc::new(manifest));
+        }
+        let single_crate = members.len() == 1 && members.values().all(|dir| *dir == root);
+        if !single_crate {
+            let workspace = Workspace {
+                root: root.clone(),
+                manifest: root_manifest?,
+                members,
+            };
+            self.workspaces.insert(root, Arc::new(workspace));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::errors::CarguixError;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, path::Path};
+
+pub const REGISTRY_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// Version chosen for a crate, either for a given requirement or as a root of the generation.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Resolution {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirement: Option<String>,
+    pub version: String,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Commit of the index a wildcard requirement was resolved against, informational only: the
+    /// version is what later runs reuse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_commit: Option<String>,
+}
+
+/// Content of `carguix.lock`, recording every resolution decision of a run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CarguixLock {
+    #[serde(default, rename = "resolution")]
+    pub resolutions: Vec<Resolution>,
+}
+
+impl CarguixLock {
+    /// Read a lock file, a missing file being an empty lock.
+    pub fn open(path: &Path) -> Result<Self, CarguixError> {
+        match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|err| CarguixError::LockParsingError(err, path.to_path_buf())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CarguixError::LockIoError(err, path.to_path_buf())),
+        }
+    }
+
+    /// Write the lock with its entries sorted so that successive runs produce readable diffs.
+    pub fn save(&self, path: &Path) -> Result<(), CarguixError> {
+        let mut lock = self.clone();
+        lock.resolutions.sort();
+        lock.resolutions.dedup();
+        let content = toml::to_string(&lock)
+            .map_err(|err| CarguixError::LockSerializationError(err, path.to_path_buf()))?;
+        fs::write(path, content).map_err(|err| CarguixError::LockIoError(err, path.to_path_buf()))
+    }
+
+    /// Version pinned for a crate and requirement, `None` standing for a root crate.
+    pub fn version(&self, crate_name: &str, requirement: Option<&str>) -> Option<&str> {
+        self.resolution(crate_name, requirement)
+            .map(|resolution| resolution.version.as_str())
+    }
+
+    /// Resolution recorded for a crate and requirement, `None` standing for a root crate.
+    pub fn resolution(&self, crate_name: &str, requirement: Option<&str>) -> Option<&Resolution> {
+        self.resolutions.iter().find(|resolution| {
+            resolution.crate_name == crate_name && resolution.requirement.as_deref() == requirement
+        })
+    }
+
+    /// Whether some requirement on a crate is pinned to the version.
+    pub fn pins(&self, crate_name: &str, version: &str) -> bool {
+        self.resolutions
+            .iter()
+            .any(|resolution| resolution.crate_name == crate_name && resolution.version == version)
+    }
+
+    /// Hash recorded for a crate version.
+    pub fn hash(&self, crate_name: &str, version: &str) -> Option<&str> {
+        self.resolutions
+            .iter()
+            .filter(|resolution| {
+                resolution.crate_name == crate_name && resolution.version == version
+            })
+            .find_map(|resolution| resolution.hash.as_deref())
+    }
+
+    pub fn record(&mut self, resolution: Resolution) {
+        let hash = self.hash(&resolution.crate_name, &resolution.version);
+        let resolution = Resolution {
+            hash: resolution.hash.or_else(|| hash.map(str::to_string)),
+            ..resolution
+        };
+        self.resolutions.retain(|recorded| {
+            recorded.crate_name != resolution.crate_name
+                || recorded.requirement != resolution.requirement
+        });
+        self.resolutions.push(resolution);
+    }
+
+    /// Pin the requirements resolved to version `from` of a crate to version `to` instead.
+    pub fn repin(&mut self, crate_name: &str, from: &str, to: &str) {
+        let hash = self.hash(crate_name, to).map(str::to_string);
+        for resolution in &mut self.resolutions {
+            if resolution.crate_name == crate_name
+                && resolution.version == from
+                && resolution.requirement.is_some()
+            {
+                resolution.version = to.to_string();
+                resolution.hash = hash.clone();
+            }
+        }
+    }
+
+    pub fn record_hash(&mut self, crate_name: &str, version: &str, hash: &str) {
+        for resolution in &mut self.resolutions {
+            if resolution.crate_name == crate_name && resolution.version == version {
+                resolution.hash = Some(hash.to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Guix channel serving the generated packages, ready to be published.
+
+use crate::{
+    errors::CarguixError,
+    guix::{self, Module, Package},
+    template::Templates,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Files of a channel named `name`: its `.guix-channel` file, the module
+/// `(<name> packages rust-crates)` defining every package and the module `(<name> packages)`
+/// re-exporting the root ones.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    name: String,
+    crates_module: Module,
+    /// Variables of the root packages.
+    roots: Vec<String>,
+}
+
+impl Channel {
+    pub fn new(
+        name: &str,
+        packages: Vec<Package>,
+        index_commit: Option<String>,
+        parameterized: bool,
+    ) -> Self {
+        let roots = guix::root_packages(&packages)
+            .into_iter()
+            .map(Package::variable)
+            .collect();
+        let crates_module_name = vec![
+            name.to_string(),
+            "packages".to_string(),
+            "rust-crates".to_string(),
+        ];
+        Self {
+            name: name.to_string(),
+            crates_module: Module::new(crates_module_name, packages, index_commit, parameterized),
+            roots,
+        }
+    }
+
+    pub fn with_templates(self, templates: Templates) -> Self {
+        Self {
+            crates_module: self.crates_module.with_templates(templates),
+            ..self
+        }
+    }
+
+    /// Paths of the channel files relative to the channel directory, with their content.
+    pub fn files(&self) -> Vec<(PathBuf, String)> {
+        let module_path = |module: &[String]| {
+            let mut path = module.iter().collect::<PathBuf>();
+            path.set_extension("scm");
+            path
+        };
+        let packages_module = vec![self.name.clone(), "packages".to_string()];
+        vec![
+            (
+                PathBuf::from(".guix-channel"),
+                format!(
+                    "{}\n",
+                    lexpr::Value::list(vec![
+                        lexpr::Value::symbol("channel"),
+                        lexpr::Value::list(vec![
+                            lexpr::Value::symbol("version"),
+                            lexpr::Value::from(0)
+                        ]),
+                    ])
+                ),
+            ),
+            (
+                module_path(&self.crates_module.name),
+                self.crates_module.to_scheme(),
+            ),
+            (
+                module_path(&packages_module),
+                self.packages_module_scheme(&packages_module),
+            ),
+        ]
+    }
+
+    /// Write the channel files below `dir`, which fails on existing files unless `force` is set,
+    /// and return their paths.
+    pub fn write(&self, dir: &Path, force: bool) -> Result<Vec<PathBuf>, CarguixError> {
+        let files = self
+            .files()
+            .into_iter()
+            .map(|(path, content)| (dir.join(path), content))
+            .collect::<Vec<_>>();
+        if !force {
+            if let Some((existing, _)) = files.iter().find(|(path, _)| path.exists()) {
+                return Err(CarguixError::ChannelFileExists(existing.clone()));
+            }
+        }
+        for (path, content) in &files {
+            let parent = path.parent().unwrap_or(dir);
+            fs::create_dir_all(parent)
+                .map_err(|err| CarguixError::OutputDirError(err, parent.to_path_buf()))?;
+            fs::write(path, content).map_err(|err| CarguixError::OutputError(err, path.clone()))?;
+        }
+        Ok(files.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// Module re-exporting the root packages, for users of the channel to import.
+    fn packages_module_scheme(&self, name: &[String]) -> String {
+        let symbols = |parts: &[String]| {
+            lexpr::Value::list(
+                parts
+                    .iter()
+                    .map(|part| guix::symbol(part))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let header = lexpr::Value::list(vec![
+            lexpr::Value::symbol("define-module"),
+            symbols(name),
+            lexpr::Value::keyword("use-module"),
+            symbols(&self.crates_module.name),
+            lexpr::Value::keyword("re-export"),
+            symbols(&self.roots),
+        ]);
+        format!("{}\n", header)
+    }
+}
@@ -0,0 +1,594 @@
+use crate::{errors::CarguixError, guix, version};
+use semver::VersionReq;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, fs, io::ErrorKind, marker::PhantomData, path::Path};
+
+/// How a Guix package is given to the root packages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKind {
+    /// Listed in `inputs`, available at build time and kept as a runtime reference.
+    #[default]
+    Regular,
+    /// Listed in `propagated-inputs`, also installed in profiles along with the package.
+    Propagated,
+}
+
+/// Non-Rust input of the root packages, such as a tool the application executes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Input {
+    /// Guix variable of the package, e.g. `git`.
+    pub package: String,
+    #[serde(default)]
+    pub kind: InputKind,
+}
+
+/// Environment variable listing the files found under given directories of a profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SearchPath {
+    pub variable: String,
+    /// Directories relative to the root of the profile, e.g. `lib/app/plugins`.
+    pub files: Vec<String>,
+    /// Separator between the entries, the variable holding a single entry if empty.
+    pub separator: Option<String>,
+    /// Type of the files listed: `directory` (default), `regular`, `symlink` or `any`.
+    pub file_type: Option<String>,
+    /// Regular expression the listed file names have to match.
+    pub file_pattern: Option<String>,
+}
+
+/// Files an application ships besides its binaries, installed by an extra phase of the root
+/// packages. Paths are relative to the root of the crate sources.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Install {
+    /// Commands generating the files, such as `["cargo", "xtask", "mangen"]`, run beforehand.
+    #[serde(default)]
+    pub commands: Vec<Vec<String>>,
+    #[serde(default)]
+    pub bash_completions: Vec<String>,
+    #[serde(default)]
+    pub zsh_completions: Vec<String>,
+    #[serde(default)]
+    pub fish_completions: Vec<String>,
+    /// Man pages, whose extension gives the section.
+    #[serde(default)]
+    pub man_pages: Vec<String>,
+    #[serde(default)]
+    pub desktop_files: Vec<String>,
+}
+
+impl Install {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Build system asset-only crates are packaged with, copying their files as is.
+pub const COPY_BUILD_SYSTEM: &str = "copy-build-system";
+
+/// Build system replacing `cargo-build-system` for a crate, such as `meson-build-system` for a
+/// crate bundling C sources.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuildSystem {
+    /// Guix variable of the build system, e.g. `meson-build-system`.
+    pub name: String,
+    /// Scheme expression of the package arguments, e.g. `(list #:tests? #f)`, replacing the
+    /// cargo arguments. Gexps are written with their long forms, `(gexp ...)` for `#~`.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_sexpr",
+        serialize_with = "serialize_sexpr"
+    )]
+    pub arguments: Option<lexpr::Value>,
+    /// Files `copy-build-system` installs when no arguments are given, as `[source, target]`
+    /// pairs, the crate sources being installed to `share/cargo/src/<name>-<version>/` by
+    /// default.
+    #[serde(default)]
+    pub install_plan: Vec<(String, String)>,
+    /// Modules to import, e.g. `(guix build-system meson)`, the module of the build system being
+    /// derived from its name if none is given.
+    #[serde(default)]
+    pub modules: Vec<String>,
+}
+
+impl BuildSystem {
+    /// Modules the package definition needs, e.g. `["guix", "build-system", "meson"]` for
+    /// `meson-build-system`.
+    pub fn modules(&self) -> Vec<Vec<String>> {
+        if !self.modules.is_empty() {
+            return self
+                .modules
+                .iter()
+                .map(|module| guix::Module::parse_name(module))
+                .collect();
+        }
+        match self.name.strip_suffix("-build-system") {
+            Some(name) => vec![vec![
+                "guix".to_string(),
+                "build-system".to_string(),
+                name.to_string(),
+            ]],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Descriptive fields of a package set by hand, taking precedence over the ones of the crate.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MetadataOverride {
+    pub home_page: Option<String>,
+    pub synopsis: Option<String>,
+    /// Description in Texinfo markup, e.g. `@code{serde}`.
+    pub description: Option<String>,
+    /// SPDX license expression.
+    pub license: Option<String>,
+}
+
+impl MetadataOverride {
+    /// Fields of `self`, overridden by the ones `specific` sets.
+    fn inherit(&mut self, specific: &Self) {
+        for (field, specific_field) in [
+            (&mut self.home_page, &specific.home_page),
+            (&mut self.synopsis, &specific.synopsis),
+            (&mut self.description, &specific.description),
+            (&mut self.license, &specific.license),
+        ] {
+            if specific_field.is_some() {
+                field.clone_from(specific_field);
+            }
+        }
+    }
+}
+
+/// Guix packages a crate binding a system library, such as `openssl-sys`, needs to build.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SystemDependency {
+    /// Guix variables of the libraries, `variable:output` selecting an output, e.g. `zstd:lib`.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Guix variables of the tools run at build time, e.g. `pkg-config`.
+    #[serde(default)]
+    pub native_inputs: Vec<String>,
+    /// Modules defining the variables, e.g. `(gnu packages tls)`.
+    #[serde(default)]
+    pub modules: Vec<String>,
+}
+
+impl SystemDependency {
+    /// Lists of `self`, replaced by the ones `specific` doesn't leave empty.
+    fn inherit(&mut self, specific: &Self) {
+        for (list, specific_list) in [
+            (&mut self.inputs, &specific.inputs),
+            (&mut self.native_inputs, &specific.native_inputs),
+            (&mut self.modules, &specific.modules),
+        ] {
+            if !specific_list.is_empty() {
+                list.clone_from(specific_list);
+            }
+        }
+    }
+
+    pub fn modules(&self) -> Vec<Vec<String>> {
+        self.modules
+            .iter()
+            .map(|module| guix::Module::parse_name(module))
+            .collect()
+    }
+}
+
+/// Crates a configuration entry applies to: a crate name or a glob pattern such as `*-sys`,
+/// optionally followed by `@` and a version requirement, e.g. `windows-*@<0.50`.
+#[derive(Debug, Clone)]
+pub struct CratePattern {
+    pattern: glob::Pattern,
+    is_glob: bool,
+    requirement: Option<VersionReq>,
+}
+
+impl CratePattern {
+    pub fn parse(key: &str) -> Result<Self, String> {
+        let (name, requirement) = match key.split_once('@') {
+            Some((name, requirement)) => (name, Some(requirement)),
+            None => (key, None),
+        };
+        let pattern = glob::Pattern::new(name)
+            .map_err(|err| format!("invalid crate pattern {:?}: {}", name, err))?;
+        let requirement = requirement
+            .map(|requirement| {
+                version::parse_requirement(name, requirement, false).map_err(|err| {
+                    format!("invalid version requirement {:?}: {}", requirement, err)
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            pattern,
+            is_glob: name.contains(['*', '?', '[']),
+            requirement,
+        })
+    }
+
+    /// Whether the pattern matches a crate, patterns with a version requirement only matching
+    /// crates whose version is known.
+    pub fn matches(&self, crate_name: &str, version: Option<&str>) -> bool {
+        self.pattern.matches(crate_name)
+            && match (&self.requirement, version) {
+                (None, _) => true,
+                (Some(requirement), Some(version)) => {
+                    version::parse_version(crate_name, version, false)
+                        .is_ok_and(|version| requirement.matches(&version))
+                }
+                (Some(_), None) => false,
+            }
+    }
+
+    /// Crate names being more specific than patterns, then entries with a version requirement,
+    /// then longer patterns.
+    fn specificity(&self) -> (bool, bool, usize) {
+        (
+            !self.is_glob,
+            self.requirement.is_some(),
+            self.pattern.as_str().len(),
+        )
+    }
+}
+
+/// Entries of a configuration table keyed by [`CratePattern`], in the order of the file.
+#[derive(Debug, Clone)]
+pub struct Overrides<T>(Vec<(CratePattern, T)>);
+
+impl<T> Default for Overrides<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: Clone> Overrides<T> {
+    /// Entries matching a crate, from the least to the most specific, later entries coming after
+    /// earlier ones as specific.
+    pub fn matching(&self, crate_name: &str, version: Option<&str>) -> Vec<&T> {
+        let mut matching = self
+            .0
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(crate_name, version))
+            .collect::<Vec<_>>();
+        matching.sort_by_key(|(pattern, _)| pattern.specificity());
+        matching.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    /// Matching entries of a crate folded into one, each entry inheriting what it doesn't set
+    /// from the less specific ones through `inherit`.
+    fn merged(
+        &self,
+        crate_name: &str,
+        version: Option<&str>,
+        inherit: impl Fn(&mut T, &T),
+    ) -> Option<T> {
+        let mut matching = self.matching(crate_name, version).into_iter();
+        let mut merged = matching.next()?.clone();
+        for entry in matching {
+            inherit(&mut merged, entry);
+        }
+        Some(merged)
+    }
+
+    /// Put the entries of `other` before the ones of `self`, which override them.
+    fn extend_under(&mut self, mut other: Self) {
+        other.0.append(&mut self.0);
+        self.0 = other.0;
+    }
+}
+
+impl Overrides<MetadataOverride> {
+    /// Descriptive fields of a crate, each matching entry overriding the fields the less
+    /// specific ones set.
+    pub fn crate_metadata(&self, crate_name: &str, version: &str) -> Option<MetadataOverride> {
+        self.merged(crate_name, Some(version), MetadataOverride::inherit)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Overrides<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OverridesVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> de::Visitor<'de> for OverridesVisitor<T> {
+            type Value = Overrides<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a table keyed by crate name or pattern")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = Vec::new();
+                while let Some((key, entry)) = map.next_entry::<String, T>()? {
+                    entries.push((CratePattern::parse(&key).map_err(de::Error::custom)?, entry));
+                }
+                Ok(Overrides(entries))
+            }
+        }
+
+        deserializer.deserialize_map(OverridesVisitor(PhantomData))
+    }
+}
+
+/// System dependencies of the usual `-sys` crates by the `links` key of their manifest: inputs,
+/// native inputs and modules defining them.
+#[allow(clippy::type_complexity)]
+const BUILTIN_SYSTEM_DEPENDENCIES: &[(&str, &[&str], &[&str], &[&str])] = &[
+    (
+        "asound",
+        &["alsa-lib"],
+        &["pkg-config"],
+        &["gnu packages linux"],
+    ),
+    ("bzip2", &["bzip2"], &[], &["gnu packages compression"]),
+    ("curl", &["curl"], &["pkg-config"], &["gnu packages curl"]),
+    ("dbus", &["dbus"], &["pkg-config"], &["gnu packages glib"]),
+    ("expat", &["expat"], &["pkg-config"], &["gnu packages xml"]),
+    (
+        "fontconfig",
+        &["fontconfig"],
+        &["pkg-config"],
+        &["gnu packages fontutils"],
+    ),
+    (
+        "freetype",
+        &["freetype"],
+        &["pkg-config"],
+        &["gnu packages fontutils"],
+    ),
+    (
+        "git2",
+        &["libgit2"],
+        &["pkg-config"],
+        &["gnu packages version-control"],
+    ),
+    (
+        "lzma",
+        &["xz"],
+        &["pkg-config"],
+        &["gnu packages compression"],
+    ),
+    (
+        "onig",
+        &["oniguruma"],
+        &["pkg-config"],
+        &["gnu packages textutils"],
+    ),
+    (
+        "openssl",
+        &["openssl"],
+        &["pkg-config"],
+        &["gnu packages tls"],
+    ),
+    ("pcre2", &["pcre2"], &["pkg-config"], &["gnu packages pcre"]),
+    ("pq", &["postgresql"], &[], &["gnu packages databases"]),
+    (
+        "sodium",
+        &["libsodium"],
+        &["pkg-config"],
+        &["gnu packages crypto"],
+    ),
+    (
+        "sqlite3",
+        &["sqlite"],
+        &["pkg-config"],
+        &["gnu packages sqlite"],
+    ),
+    (
+        "ssh2",
+        &["libssh2", "openssl", "zlib"],
+        &["pkg-config"],
+        &[
+            "gnu packages ssh",
+            "gnu packages tls",
+            "gnu packages compression",
+        ],
+    ),
+    ("udev", &["eudev"], &["pkg-config"], &["gnu packages linux"]),
+    (
+        "z",
+        &["zlib"],
+        &["pkg-config"],
+        &["gnu packages compression"],
+    ),
+    (
+        "zstd",
+        &["zstd:lib"],
+        &["pkg-config"],
+        &["gnu packages compression"],
+    ),
+];
+
+fn deserialize_sexpr<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<lexpr::Value>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|sexpr| {
+            lexpr::from_str(&sexpr).map_err(|err| {
+                de::Error::custom(format!("invalid Scheme expression {:?}: {}", sexpr, err))
+            })
+        })
+        .transpose()
+}
+
+fn serialize_sexpr<S: Serializer>(
+    sexpr: &Option<lexpr::Value>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    sexpr
+        .as_ref()
+        .map(ToString::to_string)
+        .serialize(serializer)
+}
+
+/// Content of `carguix.toml`, tweaking the generated definitions.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// URI templates of crate tarball mirrors, e.g.
+    /// `https://mirror.example.org/crates/{name}/{name}-{version}.crate`.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    #[serde(default, rename = "input")]
+    pub inputs: Vec<Input>,
+    #[serde(default)]
+    pub install: Install,
+    #[serde(default, rename = "native-search-path")]
+    pub native_search_paths: Vec<SearchPath>,
+    #[serde(default, rename = "search-path")]
+    pub search_paths: Vec<SearchPath>,
+    /// Build systems replacing `cargo-build-system`, by crate name or pattern.
+    #[serde(default, rename = "build-system")]
+    pub build_systems: Overrides<BuildSystem>,
+    /// Registry crates which are procedural macros, besides the ones named like one, e.g.
+    /// `serde_derive` or `tokio-macros`.
+    #[serde(default, rename = "proc-macros")]
+    pub proc_macros: Vec<String>,
+    /// System dependencies by crate name, `links` key or pattern, extending and overriding the
+    /// built-in ones.
+    #[serde(default, rename = "system-dependency")]
+    pub system_dependencies: Overrides<SystemDependency>,
+    /// Descriptive fields of packages, by crate name or pattern.
+    #[serde(default)]
+    pub metadata: Overrides<MetadataOverride>,
+    /// Features enabled wherever a crate is packaged, by crate name or pattern, so that a single
+    /// package serves every dependent.
+    #[serde(default)]
+    pub features: Overrides<Vec<String>>,
+}
+
+/// Names of the presets `--preset` accepts.
+pub const PRESET_NAMES: &[&str] = &["gtk", "tokio-stack"];
+
+/// Configuration shipped for a well-known ecosystem, in the `presets` directory of the sources.
+fn preset_content(name: &str) -> Option<&'static str> {
+    match name {
+        "gtk" => Some(include_str!("../presets/gtk.toml")),
+        "tokio-stack" => Some(include_str!("../presets/tokio-stack.toml")),
+        _ => None,
+    }
+}
+
+impl Config {
+    /// URIs of a crate tarball on the mirrors.
+    pub fn mirror_uris(&self, crate_name: &str, version: &str) -> Vec<String> {
+        self.mirrors
+            .iter()
+            .map(|template| mirror_uri(template, crate_name, version))
+            .collect()
+    }
+
+    /// Whether a registry crate is a procedural macro, which the index doesn't tell: crates listed
+    /// as such or named with a `derive`, `macro` or `macros` suffix.
+    pub fn is_proc_macro(&self, crate_name: &str) -> bool {
+        self.proc_macros.iter().any(|name| name == crate_name)
+            || ["derive", "macro", "macros"].iter().any(|suffix| {
+                crate_name.ends_with(&format!("-{}", suffix))
+                    || crate_name.ends_with(&format!("_{}", suffix))
+            })
+    }
+
+    /// Build system of a crate, the most specific entry matching it.
+    pub fn build_system(&self, crate_name: &str, version: &str) -> Option<&BuildSystem> {
+        self.build_systems.matching(crate_name, Some(version)).pop()
+    }
+
+    /// Features of every entry matching a crate, `version` being `None` when it isn't resolved
+    /// yet.
+    pub fn features(&self, crate_name: &str, version: Option<&str>) -> Vec<String> {
+        self.features
+            .matching(crate_name, version)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// System dependency of a crate, declared for its name or the system library it `links` to,
+    /// the entries of the crate overriding the lists the ones of the library set, or else built
+    /// in for the library.
+    pub fn system_dependency(
+        &self,
+        crate_name: &str,
+        version: &str,
+        links: Option<&str>,
+    ) -> Option<SystemDependency> {
+        let by_links = links.and_then(|links| {
+            self.system_dependencies
+                .merged(links, None, SystemDependency::inherit)
+        });
+        let by_name =
+            self.system_dependencies
+                .merged(crate_name, Some(version), SystemDependency::inherit);
+        match (by_links, by_name) {
+            (Some(mut declared), Some(by_name)) => {
+                declared.inherit(&by_name);
+                return Some(declared);
+            }
+            (Some(declared), None) | (None, Some(declared)) => return Some(declared),
+            (None, None) => (),
+        }
+        let links = links?;
+        let to_strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        BUILTIN_SYSTEM_DEPENDENCIES
+            .iter()
+            .find(|(library, ..)| *library == links)
+            .map(|(_, inputs, native_inputs, modules)| SystemDependency {
+                inputs: to_strings(inputs),
+                native_inputs: to_strings(native_inputs),
+                modules: to_strings(modules),
+            })
+    }
+
+    /// Configuration of a preset of [`PRESET_NAMES`].
+    pub fn preset(name: &str) -> Result<Self, CarguixError> {
+        let path = Path::new("presets").join(name).with_extension("toml");
+        let content = preset_content(name)
+            .ok_or_else(|| CarguixError::ConfigIoError(ErrorKind::NotFound.into(), path.clone()))?;
+        toml::from_str(content).map_err(|err| CarguixError::ConfigParsingError(err, path))
+    }
+
+    /// Add the settings of `presets` the configuration doesn't declare itself, lists being
+    /// extended and the entries of the tables overriding the ones of the presets as specific.
+    pub fn with_presets(mut self, presets: &[String]) -> Result<Self, CarguixError> {
+        for name in presets {
+            let preset = Self::preset(name)?;
+            self.mirrors.extend(preset.mirrors);
+            self.inputs.extend(preset.inputs);
+            if self.install.is_empty() {
+                self.install = preset.install;
+            }
+            self.native_search_paths.extend(preset.native_search_paths);
+            self.search_paths.extend(preset.search_paths);
+            self.proc_macros.extend(preset.proc_macros);
+            self.build_systems.extend_under(preset.build_systems);
+            self.system_dependencies
+                .extend_under(preset.system_dependencies);
+            self.metadata.extend_under(preset.metadata);
+            self.features.extend_under(preset.features);
+        }
+        Ok(self)
+    }
+
+    /// Read a configuration file, a missing file being an empty configuration.
+    pub fn open(path: &Path) -> Result<Self, CarguixError> {
+        match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|err| CarguixError::ConfigParsingError(err, path.to_path_buf())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CarguixError::ConfigIoError(err, path.to_path_buf())),
+        }
+    }
+}
+
+/// URI of a crate tarball on a mirror, `{name}` and `{version}` being replaced in the template.
+pub fn mirror_uri(template: &str, crate_name: &str, version: &str) -> String {
+    template
+        .replace("{name}", crate_name)
+        .replace("{version}", version)
+}
@@ -0,0 +1,62 @@
+use crate::guix;
+use heck::KebabCase;
+use lexpr::sexp;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CrateRef {
+    pub name: String,
+    pub version: String,
+}
+
+impl CrateRef {
+    /// Crate name and version of the `name@version` shorthand of cargo, e.g. `serde@1.0.197`,
+    /// the version being `None` for a bare name.
+    pub fn split_version(spec: &str) -> (&str, Option<&str>) {
+        match spec.split_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None => (spec, None),
+        }
+    }
+
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    // unquoted expressions in `sexp!` need their parentheses
+    #[allow(unused_parens)]
+    pub fn to_dependency_sexpr(&self) -> lexpr::Value {
+        let formatted_name = self.format_name_version();
+        sexp!((
+            list,
+            (formatted_name.clone()),
+            (guix::symbol(&formatted_name))
+        ))
+    }
+
+    /// Input referring to the package built with the `rust` variable in scope, as a procedure
+    /// parameterized over the compiler sees it.
+    #[allow(unused_parens)]
+    pub fn to_parameterized_dependency_sexpr(&self) -> lexpr::Value {
+        let formatted_name = self.format_name_version();
+        let procedure_name = guix::symbol(&self.format_procedure_name());
+        sexp!((list ,(formatted_name) (,(procedure_name) rust)))
+    }
+
+    pub fn format_name(&self) -> String {
+        format!("rust-{}", self.name.to_kebab_case())
+    }
+
+    pub fn format_name_version(&self) -> String {
+        let variable = format!("rust-{}-{}", self.name.to_kebab_case(), self.version);
+        guix::mangle_symbol(&variable).into_owned()
+    }
+
+    /// Name of the procedure returning the package built with a given rust compiler.
+    pub fn format_procedure_name(&self) -> String {
+        format!("make-{}", self.format_name_version())
+    }
+}
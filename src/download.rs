@@ -0,0 +1,723 @@
+use crate::{
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    generator::Options,
+    guix::RegistryDownload,
+    hash_db::{HashDatabase, HashMethod, StoredHash},
+    hash_mirror,
+    nix_hash::{self, HashingWriter},
+    registries::Registries,
+    run_report::HashSource,
+    stats::CacheStats,
+};
+use once_cell::sync::OnceCell;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, copy, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time allowed to connect to a server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed for each read or write of a request, rather than for the whole transfer, so that
+/// large crates downloaded slowly aren't interrupted.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Delay before retrying a failed download, doubled on each further attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Where the hash of each crate came from, `None` if it couldn't be hashed, and the time it took,
+/// by registry and crate.
+pub type HashRuns = HashMap<(Option<String>, CrateRef), (Option<HashSource>, Duration)>;
+
+/// HTTP client going through the proxies of the `HTTP_PROXY` and `HTTPS_PROXY` environment
+/// variables, if any.
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_sys_proxy()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(READ_TIMEOUT)
+        .build()
+        .expect("TLS backend cannot be initialized")
+}
+
+/// Downloads and hashes crates, several at a time.
+///
+/// Hashes already in the database are reused and new ones are inserted into it, the database
+/// being safe to share between threads.
+pub struct Downloader<'a> {
+    client: reqwest::Client,
+    hashdb: &'a HashDatabase,
+    tmpdir: &'a Path,
+    options: &'a Options,
+    registries: &'a Registries,
+    /// Hashes the downloaded tarballs must have, converted from the checksums of `Cargo.lock`
+    /// files or of the index, by registry and crate.
+    checksums: HashMap<(Option<String>, CrateRef), String>,
+    /// Keeps confirmation prompts of concurrent downloads from interleaving.
+    prompt: Mutex<()>,
+    cached: AtomicUsize,
+    mirrored: AtomicUsize,
+    stored: AtomicUsize,
+    downloaded: AtomicUsize,
+    hash_runs: Mutex<HashRuns>,
+    /// Tool and version the tarballs are hashed with, recorded along with their hashes.
+    hash_tool: OnceCell<String>,
+}
+
+impl<'a> Downloader<'a> {
+    pub fn new(
+        hashdb: &'a HashDatabase,
+        tmpdir: &'a Path,
+        options: &'a Options,
+        registries: &'a Registries,
+    ) -> Self {
+        Self {
+            client: http_client(),
+            hashdb,
+            tmpdir,
+            options,
+            registries,
+            checksums: HashMap::new(),
+            prompt: Mutex::new(()),
+            cached: AtomicUsize::new(0),
+            mirrored: AtomicUsize::new(0),
+            stored: AtomicUsize::new(0),
+            downloaded: AtomicUsize::new(0),
+            hash_runs: Mutex::new(HashMap::new()),
+            hash_tool: OnceCell::new(),
+        }
+    }
+
+    /// Verify the downloaded tarballs against these hashes, by registry and crate, a crate whose
+    /// tarball differs being downloaded again.
+    pub fn with_checksums(self, checksums: HashMap<(Option<String>, CrateRef), String>) -> Self {
+        Self { checksums, ..self }
+    }
+
+    /// How many hashes were found in the database, how many were fetched from the hash mirror,
+    /// how many were computed from stored tarballs and how many crates were downloaded so far.
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            cached: self.cached.load(Ordering::Relaxed),
+            mirrored: self.mirrored.load(Ordering::Relaxed),
+            stored: self.stored.load(Ordering::Relaxed),
+            downloaded: self.downloaded.load(Ordering::Relaxed),
+            ..CacheStats::default()
+        }
+    }
+
+    /// Hashes of the crates, in the same order, computed by at most `options.jobs` threads.
+    pub fn crate_hashes(
+        &self,
+        crates: &[(CrateRef, Option<RegistryDownload>)],
+    ) -> Vec<Result<String, CarguixError>> {
+        self.concurrently(crates, |crate_ref, registry| {
+            self.crate_hash(&crate_ref.name, &crate_ref.version, registry)
+        })
+    }
+
+    /// Hashes of fresh downloads of the crates, in the same order, the database being neither
+    /// read nor updated.
+    pub fn downloaded_hashes(
+        &self,
+        crates: &[(CrateRef, Option<RegistryDownload>)],
+    ) -> Vec<Result<String, CarguixError>> {
+        self.concurrently(crates, |crate_ref, registry| {
+            self.download_hash(&crate_ref.name, &crate_ref.version, registry)
+        })
+    }
+
+    /// Results of `hash` for every crate, in the same order, computed by at most `options.jobs`
+    /// threads.
+    fn concurrently(
+        &self,
+        crates: &[(CrateRef, Option<RegistryDownload>)],
+        hash: impl Fn(&CrateRef, &Option<RegistryDownload>) -> Result<String, CarguixError> + Sync,
+    ) -> Vec<Result<String, CarguixError>> {
+        let next = AtomicUsize::new(0);
+        let hashes = Mutex::new(crates.iter().map(|_| None).collect::<Vec<_>>());
+        thread::scope(|scope| {
+            for _ in 0..self.options.jobs.clamp(1, crates.len().max(1)) {
+                scope.spawn(|| loop {
+                    let position = next.fetch_add(1, Ordering::Relaxed);
+                    let (crate_ref, registry) = match crates.get(position) {
+                        Some(crate_) => crate_,
+                        None => break,
+                    };
+                    let hash = hash(crate_ref, registry);
+                    hashes.lock().unwrap_or_else(|err| err.into_inner())[position] = Some(hash);
+                });
+            }
+        });
+        hashes
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+            .into_iter()
+            .map(|hash| hash.expect("every crate has been hashed"))
+            .collect()
+    }
+
+    /// Where the hashes of the crates hashed so far came from and how long each took.
+    pub fn hash_runs(&self) -> HashRuns {
+        self.hash_runs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Hash of a crate from crates.io, or from an alternative registry, recording where it came
+    /// from and how long it took in [`Self::hash_runs`].
+    pub fn crate_hash(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+    ) -> Result<String, CarguixError> {
+        let started = Instant::now();
+        let hash = self.sourced_crate_hash(crate_name, version, registry);
+        let registry_name = registry.as_ref().map(|registry| registry.registry.clone());
+        self.hash_runs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(
+                (registry_name, CrateRef::new(crate_name, version)),
+                (
+                    hash.as_ref().ok().map(|(_, source)| *source),
+                    started.elapsed(),
+                ),
+            );
+        hash.map(|(hash, _)| hash)
+    }
+
+    /// Hash of a crate from crates.io, or from an alternative registry, whose crates are told apart
+    /// from the ones of crates.io in the database by a `<registry>/` prefix.
+    ///
+    /// Hashes missing from the database are asked to the hash mirror, if any, before downloading
+    /// the crate. Neither is looked up for the crates of `options.refresh_hashes`, nor with
+    /// `options.verify_checksums`.
+    ///
+    /// With `options.tarball_dir`, hashes are computed from the tarballs stored there instead,
+    /// the missing or corrupted ones being downloaded again.
+    fn sourced_crate_hash(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+    ) -> Result<(String, HashSource), CarguixError> {
+        let key = &match registry {
+            Some(registry) => (
+                format!("{}/{}", registry.registry, crate_name),
+                version.to_string(),
+            ),
+            None => (crate_name.to_string(), version.to_string()),
+        };
+        let refreshed = self.options.verify_checksums
+            || self
+                .options
+                .refresh_hashes
+                .iter()
+                .any(|name| name == crate_name);
+        let registry_name = registry.as_ref().map(|registry| registry.registry.as_str());
+        let tarball = self
+            .options
+            .tarball_path(crate_name, version, registry_name);
+        match tarball {
+            Some(tarball) if !refreshed && tarball.is_file() => {
+                match self.verified_hash(crate_name, version, registry, &tarball) {
+                    Ok(hash) => {
+                        self.stored.fetch_add(1, Ordering::Relaxed);
+                        self.hashdb.insert(key, self.tarball_hash(&hash))?;
+                        return Ok((hash, HashSource::Stored));
+                    }
+                    Err(err) => {
+                        log::warn!("downloading stored tarball {:?} again: {}", tarball, err)
+                    }
+                }
+            }
+            Some(_) => (),
+            None if !refreshed => {
+                if let Some(hash) = self.hashdb.get(key, HashMethod::Tarball)? {
+                    self.cached.fetch_add(1, Ordering::Relaxed);
+                    return Ok((hash, HashSource::Cached));
+                }
+                if let Some((hash, mirror)) = self.mirrored_hash(key, crate_name, version, registry)
+                {
+                    self.mirrored.fetch_add(1, Ordering::Relaxed);
+                    let tool = format!("hash mirror {}", mirror);
+                    self.hashdb
+                        .insert(key, StoredHash::new(&hash, HashMethod::Tarball, &tool))?;
+                    return Ok((hash, HashSource::Mirrored));
+                }
+            }
+            None => (),
+        }
+        let hash = self.download_hash(crate_name, version, registry)?;
+        self.hashdb.insert(key, self.tarball_hash(&hash))?;
+        Ok((hash, HashSource::Downloaded))
+    }
+
+    /// Hash of a tarball as recorded in the database, along with the tool which computed it,
+    /// e.g. `carguix 0.1.0`, or `guix 1.4.0` with `options.guix_hash`.
+    fn tarball_hash(&self, hash: &str) -> StoredHash {
+        let tool = self.hash_tool.get_or_init(|| match self.options.guix_hash {
+            true => self.options.tooling.guix_version().map_or_else(
+                |_| "guix".to_string(),
+                |version| format!("guix {}", version),
+            ),
+            false => format!("carguix {}", env!("CARGO_PKG_VERSION")),
+        });
+        StoredHash::new(hash, HashMethod::Tarball, tool)
+    }
+
+    /// Hash of a crate served by the hash mirror, if any, along with the mirror. An unreachable
+    /// mirror, or an answer which isn't a SHA256 or differs from the expected checksum, such as
+    /// the error page of a proxy, is only warned about since the crate can still be downloaded.
+    fn mirrored_hash<'m>(
+        &'m self,
+        key: &(String, String),
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+    ) -> Option<(String, &'m str)> {
+        let mirror = self.options.hash_mirror.as_ref()?;
+        if self.options.offline {
+            return None;
+        }
+        let hash = match hash_mirror::fetch(&self.client, mirror, key) {
+            Ok(hash) => hash?,
+            Err(err) => {
+                log::warn!("could not ask hash mirror {}: {}", mirror, err);
+                return None;
+            }
+        };
+        if !nix_hash::is_sha256(&hash) {
+            log::warn!(
+                "ignoring the answer of hash mirror {} for crate {} in version {}, which isn't a hash",
+                mirror,
+                crate_name,
+                version
+            );
+            return None;
+        }
+        if let Some(expected) = self.expected_checksum(crate_name, version, registry) {
+            if *expected != hash {
+                log::warn!(
+                    "ignoring hash {} of hash mirror {} for crate {} in version {}, {} being expected",
+                    hash,
+                    mirror,
+                    crate_name,
+                    version,
+                    expected
+                );
+                return None;
+            }
+        }
+        Some((hash, mirror.as_str()))
+    }
+
+    /// Download a crate and hash it, making `options.download_retries` more attempts when the
+    /// download fails in a way the next one may not, waiting exponentially longer between them.
+    fn download_hash(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+    ) -> Result<String, CarguixError> {
+        let mut confirmed = false;
+        let mut attempt = 0;
+        loop {
+            match self.try_download_hash(crate_name, version, registry, &mut confirmed) {
+                Err(err) if attempt < self.options.download_retries && is_transient(&err) => {
+                    let delay = RETRY_DELAY * 2u32.saturating_pow(attempt as u32);
+                    log::warn!(
+                        "attempt {} to download crate {} in version {} failed, retrying in {}s: {}",
+                        attempt + 1,
+                        crate_name,
+                        version,
+                        delay.as_secs(),
+                        err
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Single attempt of [`Self::download_hash`], the size of the crate being confirmed unless
+    /// `confirmed` tells a former attempt did already.
+    fn try_download_hash(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+        confirmed: &mut bool,
+    ) -> Result<String, CarguixError> {
+        let (urls, token) = match registry {
+            Some(registry) => (
+                vec![registry.url.clone()],
+                self.registries.get(&registry.registry)?.token(),
+            ),
+            None => {
+                let crates_io_url = format!(
+                    "https://crates.io/api/v1/crates/{}/{}/download",
+                    crate_name, version
+                );
+                let crates_io_url = self.options.mirror.is_none().then_some(crates_io_url);
+                let urls = crates_io_url
+                    .into_iter()
+                    .chain(self.options.mirror_uris(crate_name, version))
+                    .collect();
+                (urls, None)
+            }
+        };
+        // the size is confirmed before the download starts, a response left waiting for the
+        // answer timing out
+        if !*confirmed && self.options.confirm_large_crates {
+            if let Some(size) = self.crate_size(crate_name, version, registry, &urls, token) {
+                self.confirm_crate_size(crate_name, version, size)?;
+            }
+            *confirmed = true;
+        }
+        let mut download_request = self.download(crate_name, urls.into_iter(), token)?;
+        // only warned about, the size of the response will do
+        if !*confirmed {
+            if let Some(size) = download_request.content_length() {
+                self.warn_crate_size(crate_name, version, size);
+            }
+            *confirmed = true;
+        }
+        let registry_name = registry.as_ref().map(|registry| registry.registry.as_str());
+        let tarball = self
+            .options
+            .tarball_path(crate_name, version, registry_name);
+        let store_error = |err, tarball: &Path| {
+            CarguixError::TarballStoreError(err, crate_name.to_string(), tarball.to_path_buf())
+        };
+        // the response is hashed as it is received, only written to a file when `guix hash`
+        // needs one or when the tarball is stored, in which case it is written next to its final
+        // path and moved there once verified
+        let downloaded_crate_path = match (self.options.guix_hash, &tarball) {
+            (true, _) => Some(
+                self.tmpdir
+                    .join(format!("{}-{}.tar.gz", crate_name, version)),
+            ),
+            (false, Some(tarball)) => {
+                if let Some(parent) = tarball.parent() {
+                    fs::create_dir_all(parent).map_err(|err| store_error(err, tarball))?;
+                }
+                Some(partial_path(tarball))
+            }
+            (false, None) => None,
+        };
+        let stored = self
+            .hash_download(
+                crate_name,
+                version,
+                registry,
+                &mut download_request,
+                downloaded_crate_path.as_deref(),
+            )
+            .and_then(|hash| {
+                self.downloaded.fetch_add(1, Ordering::Relaxed);
+                if let (Some(tarball), Some(path)) = (&tarball, &downloaded_crate_path) {
+                    if self.options.guix_hash {
+                        if let Some(parent) = tarball.parent() {
+                            fs::create_dir_all(parent).map_err(|err| store_error(err, tarball))?;
+                        }
+                        fs::copy(path, tarball).map_err(|err| store_error(err, tarball))?;
+                    } else {
+                        fs::rename(path, tarball).map_err(|err| store_error(err, tarball))?;
+                    }
+                }
+                Ok(hash)
+            });
+        // a download cut short or failing verification is never left behind, nor the copy
+        // `guix hash` was run on
+        if let Some(path) = &downloaded_crate_path {
+            if stored.is_err() || self.options.guix_hash {
+                fs::remove_file(path).ok();
+            }
+        }
+        stored
+    }
+
+    /// Hash of a downloaded crate, computed as the response is written to `path`, if any, which
+    /// must match the checksum expected for the crate.
+    fn hash_download(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+        download_request: &mut reqwest::Response,
+        path: Option<&Path>,
+    ) -> Result<String, CarguixError> {
+        let destination: Box<dyn Write> = match path {
+            Some(path) => Box::new(
+                File::create(path)
+                    .map_err(|err| CarguixError::FileCreationFailed(err, crate_name.to_string()))?,
+            ),
+            None => Box::new(io::sink()),
+        };
+        let mut hashing_writer = HashingWriter::new(destination);
+        copy(download_request, &mut hashing_writer)
+            .map_err(|err| CarguixError::CopyError(err, crate_name.to_string()))?;
+        let checksum = hashing_writer.finish();
+        self.check_checksum(crate_name, version, registry, &checksum)?;
+        match path {
+            Some(path) if self.options.guix_hash => self
+                .options
+                .tooling
+                .guix_hash(path)
+                .map_err(|err| CarguixError::GuixHashError(err, crate_name.to_string())),
+            _ => Ok(checksum),
+        }
+    }
+
+    /// Hash of a crate tarball, which must match the checksum expected for the crate, if any.
+    fn verified_hash(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+        tarball: &Path,
+    ) -> Result<String, CarguixError> {
+        let checksum = nix_hash::hash(tarball)
+            .map_err(|err| CarguixError::HashError(err, crate_name.to_string()))?;
+        self.check_checksum(crate_name, version, registry, &checksum)?;
+        let hash = if self.options.guix_hash {
+            self.options
+                .tooling
+                .guix_hash(tarball)
+                .map_err(|err| CarguixError::GuixHashError(err, crate_name.to_string()))?
+        } else {
+            checksum
+        };
+        Ok(hash)
+    }
+
+    /// Nix-base32 SHA256 the tarball of a crate must have, if known.
+    fn expected_checksum(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+    ) -> Option<&String> {
+        let registry_name = registry.as_ref().map(|registry| registry.registry.clone());
+        self.checksums
+            .get(&(registry_name, CrateRef::new(crate_name, version)))
+    }
+
+    /// Make sure the Nix-base32 SHA256 of a crate tarball is the one expected for the crate, if
+    /// any.
+    fn check_checksum(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+        checksum: &str,
+    ) -> Result<(), CarguixError> {
+        match self.expected_checksum(crate_name, version, registry) {
+            Some(expected) if expected != checksum => Err(CarguixError::ChecksumMismatch {
+                name: crate_name.to_string(),
+                version: version.to_string(),
+                expected: expected.clone(),
+                actual: checksum.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Response of the first URL the crate can be downloaded from, authenticated with `token`.
+    fn download(
+        &self,
+        crate_name: &str,
+        urls: impl Iterator<Item = String>,
+        token: Option<&str>,
+    ) -> Result<reqwest::Response, CarguixError> {
+        if self.options.offline {
+            return Err(CarguixError::OfflineNetworkAccess(format!(
+                "downloading crate {}",
+                crate_name
+            )));
+        }
+        let mut download_error = None;
+        for url in urls {
+            let mut request = self.client.get(&url);
+            if let Some(token) = token {
+                request = request.header(reqwest::header::AUTHORIZATION, token);
+            }
+            match request
+                .send()
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    log::warn!("{}", err);
+                    download_error = Some(err);
+                }
+            }
+        }
+        let err = download_error.expect("at least one URL is always tried");
+        Err(CarguixError::CrateDownloadError(
+            err,
+            crate_name.to_string(),
+        ))
+    }
+
+    /// Size of the tarball of a crate, only looked up when a threshold is set: the `crate_size`
+    /// crates.io records for its crates, or the `Content-Length` the download URLs answer a `HEAD`
+    /// request with otherwise.
+    fn crate_size(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: &Option<RegistryDownload>,
+        urls: &[String],
+        token: Option<&str>,
+    ) -> Option<u64> {
+        if self.options.max_crate_size.is_none() || self.options.offline {
+            return None;
+        }
+        if registry.is_none() && self.options.mirror.is_none() {
+            if let Some(size) = self.crates_io_crate_size(crate_name, version) {
+                return Some(size);
+            }
+        }
+        urls.iter().find_map(|url| {
+            let mut request = self.client.head(url);
+            if let Some(token) = token {
+                request = request.header(reqwest::header::AUTHORIZATION, token);
+            }
+            request
+                .send()
+                .and_then(|response| response.error_for_status())
+                .ok()?
+                .content_length()
+        })
+    }
+
+    /// Size of the tarball of a crate according to the crates.io API.
+    fn crates_io_crate_size(&self, crate_name: &str, version: &str) -> Option<u64> {
+        let url = format!("https://crates.io/api/v1/crates/{}/{}", crate_name, version);
+        let response = self
+            .client
+            .get(&url)
+            .header(
+                reqwest::header::USER_AGENT,
+                concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
+            )
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| response.json::<serde_json::Value>());
+        match response {
+            Ok(response) => response["version"]["crate_size"].as_u64(),
+            Err(err) => {
+                log::debug!(
+                    "could not look the size of crate {} in version {} up on crates.io: {}",
+                    crate_name,
+                    version,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Warn about a crate above the size threshold, telling whether it is.
+    fn warn_crate_size(&self, crate_name: &str, version: &str, size: u64) -> bool {
+        let max_crate_size = match self.options.max_crate_size {
+            Some(max_crate_size) if size > max_crate_size => max_crate_size,
+            _ => return false,
+        };
+        log::warn!(
+            "crate {} in version {} weighs {} bytes, above the {} bytes threshold",
+            crate_name,
+            version,
+            size,
+            max_crate_size
+        );
+        true
+    }
+
+    /// Ask whether to download a crate above the size threshold, unless `assume_yes` answers
+    /// for the user. Without a terminal, such as in CI, nobody can answer and the download fails.
+    fn confirm_crate_size(
+        &self,
+        crate_name: &str,
+        version: &str,
+        size: u64,
+    ) -> Result<(), CarguixError> {
+        if !self.warn_crate_size(crate_name, version, size) || self.options.assume_yes {
+            return Ok(());
+        }
+        if !io::stdin().is_terminal() {
+            return Err(CarguixError::CrateSizeUnconfirmed {
+                name: crate_name.to_string(),
+                version: version.to_string(),
+                size,
+            });
+        }
+        let _prompt = self.prompt.lock().unwrap_or_else(|err| err.into_inner());
+        eprint!(
+            "download {} bytes for crate {} in version {}? [y/N] ",
+            size, crate_name, version
+        );
+        io::stderr().flush().ok();
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).ok();
+        if answer.trim().eq_ignore_ascii_case("y") || answer.trim().eq_ignore_ascii_case("yes") {
+            Ok(())
+        } else {
+            Err(CarguixError::CrateTooLarge {
+                name: crate_name.to_string(),
+                version: version.to_string(),
+                size,
+            })
+        }
+    }
+}
+
+/// Path a tarball is written to while it is downloaded, in the same directory so that it is
+/// moved to `tarball` atomically, and hidden so that an interrupted download doesn't pass for a
+/// stored tarball.
+fn partial_path(tarball: &Path) -> PathBuf {
+    let file_name = tarball
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy())
+        .unwrap_or_default();
+    tarball.with_file_name(format!(".{}.part", file_name))
+}
+
+/// Whether a download failed in a way another attempt may not, such as a network error, a server
+/// error or a corrupted tarball, unlike a crate missing from every URL.
+fn is_transient(err: &CarguixError) -> bool {
+    match err {
+        CarguixError::CrateDownloadError(err, _) => err.status().is_none_or(|status| {
+            status.is_server_error()
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }),
+        CarguixError::CopyError(..) | CarguixError::ChecksumMismatch { .. } => true,
+        _ => false,
+    }
+}
+
+/// Whether a download failed because the server doesn't have the crate tarball, such as a version
+/// yanked server-side, which another version of the crate may not miss.
+pub fn is_missing_tarball(err: &CarguixError) -> bool {
+    match err {
+        CarguixError::CrateDownloadError(err, _) => err.status().is_some_and(|status| {
+            status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE
+        }),
+        _ => false,
+    }
+}
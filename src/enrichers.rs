@@ -0,0 +1,230 @@
+//! Sources of the descriptive metadata of the packages, applied in order, each one filling the
+//! fields the previous ones left empty.
+
+use crate::{
+    config::{Config, MetadataOverride, Overrides},
+    crate_ref::CrateRef,
+    download,
+    errors::CarguixError,
+    metadata::CrateMetadata,
+};
+use cargo_toml::Manifest;
+use std::{collections::HashMap, error::Error, fmt, fs, path::Path, str::FromStr};
+
+/// Enrichers applied when none are selected, in this order.
+pub const DEFAULT_ENRICHERS: &[EnricherKind] = &[
+    EnricherKind::Overrides,
+    EnricherKind::Manifest,
+    EnricherKind::CratesIo,
+    EnricherKind::LibRs,
+];
+
+/// Source of metadata, as selected on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnricherKind {
+    /// `[metadata.<crate>]` tables of `carguix.toml`.
+    Overrides,
+    /// Manifest of local crates.
+    Manifest,
+    /// crates.io API, for the crates published there.
+    CratesIo,
+    /// Crate-level documentation of the library of local crates.
+    LibRs,
+}
+
+impl EnricherKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EnricherKind::Overrides => "overrides",
+            EnricherKind::Manifest => "manifest",
+            EnricherKind::CratesIo => "crates-io",
+            EnricherKind::LibRs => "lib-rs",
+        }
+    }
+
+    pub fn enricher(self, config: &Config, offline: bool) -> Box<dyn Enricher> {
+        match self {
+            EnricherKind::Overrides => Box::new(OverridesEnricher {
+                overrides: config.metadata.clone(),
+            }),
+            EnricherKind::Manifest => Box::new(ManifestEnricher),
+            EnricherKind::CratesIo => Box::new(CratesIoEnricher {
+                responses: HashMap::new(),
+                offline,
+            }),
+            EnricherKind::LibRs => Box::new(LibRsEnricher),
+        }
+    }
+}
+
+impl FromStr for EnricherKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        [
+            EnricherKind::Overrides,
+            EnricherKind::Manifest,
+            EnricherKind::CratesIo,
+            EnricherKind::LibRs,
+        ]
+        .iter()
+        .copied()
+        .find(|enricher| enricher.as_str() == kind)
+        .ok_or_else(|| {
+            format!(
+                "unknown enricher {}, expected overrides, manifest, crates-io or lib-rs",
+                kind
+            )
+        })
+    }
+}
+
+/// Crate the metadata is looked for.
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataTarget<'a> {
+    pub crate_ref: &'a CrateRef,
+    /// Alternative registry the crate is published on.
+    pub registry: Option<&'a str>,
+    /// Directory and manifest of local crates.
+    pub local: Option<(&'a Path, &'a Manifest)>,
+}
+
+/// Source of metadata for some crates.
+pub trait Enricher: fmt::Debug {
+    /// Metadata found for the crate, the fields this source knows nothing about being empty.
+    fn metadata(&mut self, target: &MetadataTarget) -> CrateMetadata;
+}
+
+/// Metadata of a crate out of `enrichers`, the first ones taking precedence. Enrichers are
+/// skipped once every field is filled.
+pub fn enrich(enrichers: &mut [Box<dyn Enricher>], target: &MetadataTarget) -> CrateMetadata {
+    let mut metadata = CrateMetadata::default();
+    for enricher in enrichers {
+        if metadata.is_complete() {
+            break;
+        }
+        metadata.fill(enricher.metadata(target));
+    }
+    metadata
+}
+
+#[derive(Debug)]
+struct OverridesEnricher {
+    overrides: Overrides<MetadataOverride>,
+}
+
+impl Enricher for OverridesEnricher {
+    fn metadata(&mut self, target: &MetadataTarget) -> CrateMetadata {
+        match self
+            .overrides
+            .crate_metadata(&target.crate_ref.name, &target.crate_ref.version)
+        {
+            Some(metadata) => CrateMetadata {
+                home_page: metadata.home_page.clone(),
+                synopsis: metadata.synopsis.clone(),
+                description: metadata.description.clone(),
+                license: metadata.license.clone(),
+            },
+            None => CrateMetadata::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ManifestEnricher;
+
+impl Enricher for ManifestEnricher {
+    fn metadata(&mut self, target: &MetadataTarget) -> CrateMetadata {
+        match target.local {
+            Some((_, manifest)) => CrateMetadata::from_manifest(manifest),
+            None => CrateMetadata::default(),
+        }
+    }
+}
+
+/// Responses of the `https://crates.io/api/v1/crates/<name>` endpoint, fetched once per crate.
+#[derive(Debug)]
+struct CratesIoEnricher {
+    responses: HashMap<String, serde_json::Value>,
+    offline: bool,
+}
+
+impl Enricher for CratesIoEnricher {
+    /// Metadata is only informative: failing to fetch it is reported and yields the crates.io
+    /// page of the crate as home page.
+    fn metadata(&mut self, target: &MetadataTarget) -> CrateMetadata {
+        if target.registry.is_some() || target.local.is_some() || self.offline {
+            return CrateMetadata::default();
+        }
+        let crate_name = target.crate_ref.name.as_str();
+        if !self.responses.contains_key(crate_name) {
+            match fetch_crates_io_metadata(crate_name) {
+                Ok(response) => {
+                    self.responses.insert(crate_name.to_string(), response);
+                }
+                Err(err) => {
+                    log::warn!(
+                        "{}: {}",
+                        err,
+                        err.source().map(ToString::to_string).unwrap_or_default()
+                    );
+                    return CrateMetadata::from_crate_name(crate_name);
+                }
+            }
+        }
+        CrateMetadata::from_crates_io(
+            crate_name,
+            &target.crate_ref.version,
+            &self.responses[crate_name],
+        )
+    }
+}
+
+fn fetch_crates_io_metadata(crate_name: &str) -> Result<serde_json::Value, CarguixError> {
+    let url = format!("https://crates.io/api/v1/crates/{}", crate_name);
+    let fetch_error = |err| CarguixError::MetadataFetchError(err, crate_name.to_string());
+    download::http_client()
+        .get(&url)
+        .header(
+            reqwest::header::USER_AGENT,
+            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
+        )
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|mut response| response.json())
+        .map_err(fetch_error)
+}
+
+/// Description out of the first paragraph of the `//!` comments opening the library of local
+/// crates, headings aside.
+#[derive(Debug)]
+struct LibRsEnricher;
+
+impl Enricher for LibRsEnricher {
+    fn metadata(&mut self, target: &MetadataTarget) -> CrateMetadata {
+        let (path, manifest) = match target.local {
+            Some(local) => local,
+            None => return CrateMetadata::default(),
+        };
+        let library = manifest
+            .lib
+            .as_ref()
+            .and_then(|lib| lib.path.as_deref())
+            .unwrap_or("src/lib.rs");
+        let content = match fs::read_to_string(path.join(library)) {
+            Ok(content) => content,
+            Err(_) => return CrateMetadata::default(),
+        };
+        let paragraph = content
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.starts_with("//!"))
+            .map(|line| line.trim_start_matches("//!").trim())
+            .filter(|line| !line.starts_with('#'))
+            .skip_while(|line| line.is_empty())
+            .take_while(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        CrateMetadata::new(Some(&paragraph), None, None)
+    }
+}
@@ -0,0 +1,318 @@
+// err-derive expands its implementations inside anonymous constants
+#![allow(non_local_definitions)]
+
+use err_derive::Error;
+use std::path::PathBuf;
+
+#[derive(Debug, Error)]
+pub enum CarguixError {
+    #[error(display = "could not create temporary directory")]
+    TmpdirError(#[error(cause)] std::io::Error),
+    #[error(display = "could not open hash database (crates_hash.sqlite)")]
+    HashdbError(#[error(cause)] rusqlite::Error),
+    #[error(display = "could not migrate legacy hash database {:?}", _1)]
+    HashdbMigrationError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not update index")]
+    IndexUpdateError(#[error(cause, no_from)] std::io::Error),
+    #[error(display = "could not read index file of crate {}", _1)]
+    IndexFileError(#[error(cause)] std::io::Error, String),
+    #[error(display = "could not fetch sparse index file of crate {}", _1)]
+    SparseIndexError(#[error(cause)] reqwest::Error, String),
+    #[error(display = "could not access index repository")]
+    IndexGitError(#[error(cause, no_from)] std::io::Error),
+    #[error(display = "could not check out git repository {}", _1)]
+    PatchGitError(#[error(cause, no_from)] std::io::Error, String),
+    #[error(display = "could not write git checkout {:?}", _1)]
+    PatchCheckoutError(#[error(cause, no_from)] std::io::Error, PathBuf),
+    #[error(
+        display = "index update from commit {} to {} is not a fast-forward",
+        previous,
+        fetched
+    )]
+    IndexNotFastForward { previous: String, fetched: String },
+    #[error(display = "could not package version {:?} of crate {}", version, name)]
+    CratePackagingFailed {
+        name: String,
+        version: Option<String>,
+    },
+    #[error(display = "could not find crate {}", _0)]
+    CrateNotFound(String),
+    #[error(display = "index file of crate {} lists no valid version", _0)]
+    EmptyIndexEntry(String),
+    #[error(
+        display = "every version of crate {} is yanked, pass --allow-yanked to use them anyway",
+        _0
+    )]
+    AllVersionsYanked(String),
+    #[error(display = "registry {} is not declared in cargo configuration", _0)]
+    UnknownRegistry(String),
+    #[error(display = "unknown target triple {}", _0)]
+    UnknownTargetTriple(String),
+    #[error(
+        display = "index {} of registry {} is not a sparse index, the only kind of alternative registry supported",
+        _1,
+        _0
+    )]
+    UnsupportedRegistry(String, String),
+    #[error(display = "could not fetch the configuration of registry {}", _1)]
+    RegistryConfigError(#[error(cause)] reqwest::Error, String),
+    #[error(
+        display = "no version of crate {} matching requirement {} locked in Cargo.lock",
+        name,
+        requirement
+    )]
+    CrateNotLocked { name: String, requirement: String },
+    #[error(display = "crate {} in version {} not vendored in {:?}", _0, _1, _2)]
+    CrateNotVendored(String, String, PathBuf),
+    #[error(display = "{} needs network access, which offline mode forbids", _0)]
+    OfflineNetworkAccess(String),
+    #[error(display = "failure while retrieving key {:?} in hash database", _0)]
+    HashRetrieveFailed(#[error(cause)] rusqlite::Error, (String, String)),
+    #[error(display = "could not download crate {}", _0)]
+    CrateDownloadError(#[error(cause)] reqwest::Error, String),
+    #[error(display = "could not fetch metadata of crate {} from crates.io", _1)]
+    MetadataFetchError(#[error(cause)] reqwest::Error, String),
+    #[error(
+        display = "crate {} in version {} weighs {} bytes and its download was declined",
+        name,
+        version,
+        size
+    )]
+    CrateTooLarge {
+        name: String,
+        version: String,
+        size: u64,
+    },
+    #[error(
+        display = "crate {} in version {} weighs {} bytes, above --max-crate-size, and there is no terminal to confirm its download on, pass --assume-yes to download it anyway",
+        name,
+        version,
+        size
+    )]
+    CrateSizeUnconfirmed {
+        name: String,
+        version: String,
+        size: u64,
+    },
+    #[error(
+        display = "crate {} in version {} was downloaded with hash {} instead of {}",
+        name,
+        version,
+        actual,
+        expected
+    )]
+    ChecksumMismatch {
+        name: String,
+        version: String,
+        expected: String,
+        actual: String,
+    },
+    #[error(display = "could not create crate {} destination file", _0)]
+    FileCreationFailed(#[error(cause)] std::io::Error, String),
+    #[error(display = "failure while inserting key {:?} in hash database", _0)]
+    HashInsertionFailed(#[error(cause)] rusqlite::Error, (String, String)),
+    #[error(display = "could not list hash database entries")]
+    HashListFailed(#[error(cause, no_from)] rusqlite::Error),
+    #[error(display = "could not purge hashes of crate {} from hash database", _1)]
+    HashPurgeFailed(#[error(cause)] rusqlite::Error, String),
+    #[error(
+        display = "{} cached hashes differ from the ones of fresh downloads",
+        _0
+    )]
+    HashMismatches(usize),
+    #[error(display = "could not flush hash database")]
+    HashDatabaseFlushFailed(#[error(cause, no_from)] rusqlite::Error),
+    #[error(display = "could not compute hash of crate {}", _1)]
+    GuixHashError(#[error(cause, no_from)] std::io::Error, String),
+    #[error(display = "could not compute hash of crate {}", _1)]
+    HashError(#[error(cause)] std::io::Error, String),
+    #[error(display = "could not create data directory {:?}", _1)]
+    DataDirError(#[error(cause, no_from)] std::io::Error, PathBuf),
+    #[error(display = "could not create tarball directory {:?}", _1)]
+    TarballDirError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not store tarball of crate {} in {:?}", _1, _2)]
+    TarballStoreError(#[error(cause)] std::io::Error, String, PathBuf),
+    #[error(display = "could not copy crate {} source to destination", _0)]
+    CopyError(#[error(cause)] std::io::Error, String),
+    #[error(display = "no version of crate {} matching {} found", name, version)]
+    NoMatchingVersion { name: String, version: String },
+    #[error(
+        display = "no version of crate {} matching requirement {} found",
+        name,
+        requirement
+    )]
+    NoVersionMatchingRequirement { name: String, requirement: String },
+    #[error(display = "parsing of version {} for crate {} failed", _2, _1)]
+    VersionParsingError(#[error(cause)] semver::SemVerError, String, String),
+    #[error(display = "parsing of requirement {} for crate {} failed", _1, _0)]
+    RequirementParsingError(#[error(cause)] semver::ReqParseError, String, String),
+    #[error(
+        display = "could not process a dependency of crate {} in version {}",
+        _0,
+        _1
+    )]
+    DependencyProcessingFailed(#[error(cause)] Box<CarguixError>, String, String),
+    #[error(display = "could not access path {:?}", _1)]
+    InvalidPath(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not scan Guix checkout at {:?}", _1)]
+    GuixCheckoutError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not create output directory {:?}", _1)]
+    OutputDirError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not serialize the packages to JSON")]
+    JsonSerializationError(#[error(cause)] serde_json::Error),
+    #[error(display = "could not write dependency graph {:?}", _1)]
+    GraphError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not write license report {:?}", _1)]
+    LicenseReportError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "{} warnings of denied categories emitted", _0)]
+    DeniedWarnings(usize),
+    #[error(display = "{} hashes differ from the ones of the Guix checkout", _0)]
+    UpstreamHashMismatches(usize),
+    #[error(display = "could not write run report {:?}", _1)]
+    RunReportError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not write module file {:?}", _1)]
+    OutputError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not read or write module file {:?}", _1)]
+    ModuleIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse module file {:?}: {}", _1, _0)]
+    ModuleParsingError(String, PathBuf),
+    #[error(display = "module file {:?} packages no crate {}", _1, _0)]
+    SubtreeRootNotFound(String, PathBuf),
+    #[error(
+        display = "module file {:?} packages several versions of crate {}, pick one of {}",
+        _1,
+        _0,
+        _2
+    )]
+    AmbiguousSubtreeRoot(String, PathBuf, String),
+    #[error(display = "could not read or parse template {:?}", _1)]
+    TemplateError(#[error(cause)] mustache::Error, PathBuf),
+    #[error(
+        display = "{} package template renders the definitions of {} as invalid Scheme: {}",
+        _0,
+        _1,
+        _2
+    )]
+    PackageTemplateRenderingError(String, String, String),
+    #[error(
+        display = "a guix.scm file evaluates to a single local crate, pass --manifest to write a manifest for these ones instead: {}",
+        _0
+    )]
+    EnvironmentRootsError(String),
+    #[error(display = "no local crate was packaged to make a development environment for")]
+    NoEnvironmentRoot,
+    #[error(
+        display = "channel file {:?} already exists, pass --force to overwrite it",
+        _0
+    )]
+    ChannelFileExists(PathBuf),
+    #[error(
+        display = "example file {:?} already exists, pass --force to overwrite it",
+        _0
+    )]
+    ExampleFileExists(PathBuf),
+    #[error(display = "unknown example template {}", _0)]
+    UnknownExampleTemplate(String),
+    #[error(display = "could not serve hashes on {}", _1)]
+    HashMirrorBindError(#[error(cause)] std::io::Error, String),
+    #[error(display = "could not serve the registry of the self-test")]
+    SelfTestRegistryError(#[error(cause, no_from)] std::io::Error),
+    #[error(display = "{} self-test checks failed", _0)]
+    SelfTestFailed(usize),
+    #[error(display = "could not read crate list {:?}", _1)]
+    CrateListError(#[error(cause, no_from)] std::io::Error, PathBuf),
+    #[error(display = "could not read exclusion list {:?}", _1)]
+    ExclusionListError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not read configuration file {:?}", _1)]
+    ConfigIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse configuration file {:?}", _1)]
+    ConfigParsingError(#[error(cause)] toml::de::Error, PathBuf),
+    #[error(display = "could not read or write statistics file {:?}", _1)]
+    StatsIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse or serialize statistics file {:?}", _1)]
+    StatsParsingError(#[error(cause)] serde_json::Error, PathBuf),
+    #[error(display = "could not read or write run state {:?}", _1)]
+    RunStateIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse or serialize run state {:?}", _1)]
+    RunStateParsingError(#[error(cause)] serde_json::Error, PathBuf),
+    #[error(
+        display = "no frozen package set at {:?}, which a run packaging every crate writes",
+        _0
+    )]
+    NoFrozenPackageSet(PathBuf),
+    #[error(display = "could not read or write frozen package set {:?}", _1)]
+    FrozenIoError(#[error(cause, no_from)] std::io::Error, PathBuf),
+    #[error(display = "could not parse or serialize frozen package set {:?}", _1)]
+    FrozenParsingError(#[error(cause, no_from)] serde_json::Error, PathBuf),
+    #[error(display = "could not read cargo configuration file {:?}", _1)]
+    CargoConfigIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse cargo configuration file {:?}", _1)]
+    CargoConfigParsingError(#[error(cause)] toml::de::Error, PathBuf),
+    #[error(display = "could not read Cargo.lock file {:?}", _1)]
+    CargoLockIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse Cargo.lock file {:?}", _1)]
+    CargoLockParsingError(#[error(cause)] toml::de::Error, PathBuf),
+    #[error(display = "could not read or write lock file {:?}", _1)]
+    LockIoError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(display = "could not parse lock file {:?}", _1)]
+    LockParsingError(#[error(cause)] toml::de::Error, PathBuf),
+    #[error(display = "could not serialize lock file {:?}", _1)]
+    LockSerializationError(#[error(cause)] toml::ser::Error, PathBuf),
+    #[error(display = "could not parse manifest {:?}", _1)]
+    ManifestParsingError(#[error(cause)] cargo_toml::Error, PathBuf),
+    #[error(display = "could not run cargo metadata for {:?}", _1)]
+    CargoMetadataError(#[error(cause, no_from)] std::io::Error, PathBuf),
+    #[error(display = "cargo metadata failed for {:?}: {}", _1, _0)]
+    CargoMetadataFailed(String, PathBuf),
+    #[error(display = "could not parse cargo metadata of {:?}", _1)]
+    CargoMetadataParsingError(#[error(cause, no_from)] serde_json::Error, PathBuf),
+    #[error(display = "no package nor workspace in manifest {:?}", _0)]
+    NoPackageInManifest(PathBuf),
+    #[error(display = "no crate named {} at {:?}", _0, _1)]
+    NoPathMember(String, PathBuf),
+    #[error(display = "invalid workspace member pattern {} in {:?}", _1, _2)]
+    WorkspaceMemberPatternError(#[error(cause)] glob::PatternError, String, PathBuf),
+    #[error(display = "invalid include or exclude pattern {} in {:?}", _1, _2)]
+    PackageFilePatternError(#[error(cause)] ignore::Error, String, PathBuf),
+    #[error(display = "could not list package files in {:?}", _1)]
+    PackageFilesError(#[error(cause)] std::io::Error, PathBuf),
+    #[error(
+        display = "version {:?} of crate {} is required differently by the merged projects: {}; pick how to package it with --merge-conflicts",
+        version,
+        name,
+        provenances
+    )]
+    MergeConflict {
+        name: String,
+        version: Option<String>,
+        /// How every project requires the crate, separated by semicolons.
+        provenances: String,
+    },
+    #[error(
+        display = "crates depend on each other through regular dependencies: {}",
+        _0
+    )]
+    DependencyCycle(String),
+    #[error(
+        display = "dependency {} of crate {} is neither a path nor a registry dependency",
+        _0,
+        _1
+    )]
+    UnsupportedDependency(String, String),
+    #[error(
+        display = "package {} of crate {} in version {} would not load in Guix: {}",
+        variable,
+        name,
+        version,
+        problems
+    )]
+    InvalidPackage {
+        variable: String,
+        name: String,
+        version: String,
+        /// What is wrong with the package, separated by semicolons.
+        problems: String,
+    },
+    #[error(display = "{} packages would not load in Guix", _0)]
+    InvalidPackages(usize),
+}
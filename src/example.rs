@@ -0,0 +1,89 @@
+//! Example projects `carguix example` scaffolds along with their generated definitions, as living
+//! documentation and as a smoke test of the whole pipeline.
+//!
+//! The projects only have local dependencies, so that packaging them needs no network access.
+
+use crate::errors::CarguixError;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Names of the templates `carguix example` accepts.
+pub const EXAMPLE_TEMPLATES: &[&str] = &["hello", "workspace"];
+
+/// Files of a template, by path relative to the project directory. Manifests are stored with an
+/// `.in` suffix so that cargo doesn't take them for packages nested in carguix.
+fn template_files(template: &str) -> Option<Vec<(&'static str, &'static str)>> {
+    match template {
+        "hello" => Some(vec![
+            (
+                "Cargo.toml",
+                include_str!("../templates/examples/hello/Cargo.toml.in"),
+            ),
+            (
+                "src/main.rs",
+                include_str!("../templates/examples/hello/src/main.rs"),
+            ),
+        ]),
+        "workspace" => Some(vec![
+            (
+                "Cargo.toml",
+                include_str!("../templates/examples/workspace/Cargo.toml.in"),
+            ),
+            (
+                "app/Cargo.toml",
+                include_str!("../templates/examples/workspace/app/Cargo.toml.in"),
+            ),
+            (
+                "app/src/main.rs",
+                include_str!("../templates/examples/workspace/app/src/main.rs"),
+            ),
+            (
+                "greeting/Cargo.toml",
+                include_str!("../templates/examples/workspace/greeting/Cargo.toml.in"),
+            ),
+            (
+                "greeting/src/lib.rs",
+                include_str!("../templates/examples/workspace/greeting/src/lib.rs"),
+            ),
+        ]),
+        _ => None,
+    }
+}
+
+/// Rust project of a template of [`EXAMPLE_TEMPLATES`].
+#[derive(Debug, Clone)]
+pub struct Example {
+    files: Vec<(&'static str, &'static str)>,
+}
+
+impl Example {
+    pub fn new(template: &str) -> Result<Self, CarguixError> {
+        template_files(template)
+            .map(|files| Self { files })
+            .ok_or_else(|| CarguixError::UnknownExampleTemplate(template.to_string()))
+    }
+
+    /// Write the project files below `dir`, which fails on existing files unless `force` is set,
+    /// and return their paths.
+    pub fn write(&self, dir: &Path, force: bool) -> Result<Vec<PathBuf>, CarguixError> {
+        let files = self
+            .files
+            .iter()
+            .map(|(path, content)| (dir.join(path), content))
+            .collect::<Vec<_>>();
+        if !force {
+            if let Some((existing, _)) = files.iter().find(|(path, _)| path.exists()) {
+                return Err(CarguixError::ExampleFileExists(existing.clone()));
+            }
+        }
+        for (path, content) in &files {
+            let parent = path.parent().unwrap_or(dir);
+            fs::create_dir_all(parent)
+                .map_err(|err| CarguixError::OutputDirError(err, parent.to_path_buf()))?;
+            fs::write(path, content).map_err(|err| CarguixError::OutputError(err, path.clone()))?;
+        }
+        Ok(files.into_iter().map(|(path, _)| path).collect())
+    }
+}
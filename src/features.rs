@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Features requested for a crate, either from the command line or from the declaration of a
+/// dependency.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Features {
+    pub features: BTreeSet<String>,
+    pub default_features: bool,
+    pub all_features: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            features: BTreeSet::new(),
+            default_features: true,
+            all_features: false,
+        }
+    }
+}
+
+impl Features {
+    /// Features can be given separately or as comma or space separated lists, like cargo does.
+    pub fn new(features: &[String], default_features: bool, all_features: bool) -> Self {
+        Self {
+            features: features
+                .iter()
+                .flat_map(|features| features.split(|c: char| c == ',' || c.is_whitespace()))
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect(),
+            default_features,
+            all_features,
+        }
+    }
+
+    /// Whether every feature enabled by `other` is already enabled by `self`.
+    pub fn contains(&self, other: &Features) -> bool {
+        self.all_features
+            || (other.features.is_subset(&self.features)
+                && (self.default_features || !other.default_features)
+                && !other.all_features)
+    }
+
+    pub fn merge(&mut self, other: &Features) {
+        self.features.extend(other.features.iter().cloned());
+        self.default_features |= other.default_features;
+        self.all_features |= other.all_features;
+    }
+
+    /// Flags passed to cargo so that the package is built with the selected features.
+    pub fn cargo_build_flags(&self) -> Vec<String> {
+        if *self == Self::default() {
+            return Vec::new();
+        }
+        let mut flags = vec!["--release".to_string()];
+        if self.all_features {
+            flags.push("--all-features".to_string());
+            return flags;
+        }
+        if !self.default_features {
+            flags.push("--no-default-features".to_string());
+        }
+        if !self.features.is_empty() {
+            flags.push("--features".to_string());
+            flags.push(self.features.iter().cloned().collect::<Vec<_>>().join(","));
+        }
+        flags
+    }
+
+    /// Expand the selection through the `[features]` table of a crate to find out which optional
+    /// dependencies are enabled and which features of its dependencies are requested.
+    pub fn activate(
+        &self,
+        crate_name: &str,
+        feature_map: &BTreeMap<String, Vec<String>>,
+        optional_dependencies: &HashSet<String>,
+    ) -> Activation {
+        // optional dependencies referenced as `dep:name` don't get an implicit feature
+        let explicit_dependencies = feature_map
+            .values()
+            .flatten()
+            .filter_map(|feature| feature.strip_prefix("dep:"))
+            .collect::<HashSet<_>>();
+        let mut pending = if self.all_features {
+            feature_map
+                .keys()
+                .cloned()
+                .chain(
+                    optional_dependencies
+                        .iter()
+                        .map(|name| format!("dep:{}", name)),
+                )
+                .collect::<Vec<_>>()
+        } else {
+            self.features.iter().cloned().collect()
+        };
+        if self.default_features && feature_map.contains_key("default") {
+            pending.push("default".to_string());
+        }
+        let mut activation = Activation::default();
+        let mut enabled_features = HashSet::new();
+        while let Some(feature) = pending.pop() {
+            if !enabled_features.insert(feature.clone()) {
+                continue;
+            }
+            if let Some((dependency, dependency_feature)) = feature.split_once('/') {
+                let (dependency, weak) = match dependency.strip_suffix('?') {
+                    Some(dependency) => (dependency, true),
+                    None => (dependency, false),
+                };
+                activation
+                    .dependency_features
+                    .entry(dependency.to_string())
+                    .or_default()
+                    .insert(dependency_feature.to_string());
+                if !weak {
+                    activation.dependencies.insert(dependency.to_string());
+                }
+            } else if let Some(dependency) = feature.strip_prefix("dep:") {
+                activation.dependencies.insert(dependency.to_string());
+            } else if let Some(implied_features) = feature_map.get(&feature) {
+                activation.features.insert(feature.clone());
+                pending.extend(implied_features.iter().cloned());
+            } else if optional_dependencies.contains(&feature)
+                && !explicit_dependencies.contains(feature.as_str())
+            {
+                activation.dependencies.insert(feature);
+            } else {
+                log::warn!("crate {} has no feature named {}", crate_name, feature);
+            }
+        }
+        activation
+    }
+}
+
+/// Outcome of the feature resolution of a crate.
+#[derive(Debug, Default)]
+pub struct Activation {
+    /// Features of the `[features]` table enabled by the selection, directly or not.
+    pub features: BTreeSet<String>,
+    /// Optional dependencies enabled by the selected features.
+    pub dependencies: HashSet<String>,
+    /// Features requested on dependencies with the `dependency/feature` syntax.
+    pub dependency_features: HashMap<String, BTreeSet<String>>,
+}
+
+impl Activation {
+    /// Paragraph telling which features of the `[features]` table of a crate are enabled and
+    /// which aren't, `None` for crates declaring no feature but `default`.
+    pub fn summary(&self, feature_map: &BTreeMap<String, Vec<String>>) -> Option<String> {
+        let (enabled, disabled): (Vec<_>, Vec<_>) = feature_map
+            .keys()
+            .filter(|feature| *feature != "default")
+            .partition(|feature| self.features.contains(*feature));
+        let summary = match (enabled.is_empty(), disabled.is_empty()) {
+            (true, true) => return None,
+            (false, true) => format!("Built with the {}.", feature_list(&enabled)),
+            (true, false) => format!("Built without the {}.", feature_list(&disabled)),
+            (false, false) => format!(
+                "Built with the {}, and without the {}.",
+                feature_list(&enabled),
+                feature_list(&disabled)
+            ),
+        };
+        Some(summary)
+    }
+
+    pub fn is_enabled(&self, dependency: &str, optional: bool) -> bool {
+        !optional || self.dependencies.contains(dependency)
+    }
+
+    /// Features a dependency must be built with, given its declaration.
+    pub fn dependency_features(
+        &self,
+        dependency: &str,
+        declared_features: &[String],
+        default_features: bool,
+    ) -> Features {
+        let mut features = Features::new(declared_features, default_features, false);
+        if let Some(requested_features) = self.dependency_features.get(dependency) {
+            features.features.extend(requested_features.iter().cloned());
+        }
+        features
+    }
+}
+
+/// Most features named by [`Activation::summary`] in each list, the others being counted.
+const SUMMARY_FEATURES: usize = 8;
+
+/// Features in Texinfo, e.g. `@code{std} and @code{alloc} features`.
+fn feature_list(features: &[&String]) -> String {
+    let mut names = features
+        .iter()
+        .take(SUMMARY_FEATURES)
+        .map(|feature| format!("@code{{{}}}", feature))
+        .collect::<Vec<_>>();
+    let others = features.len().saturating_sub(SUMMARY_FEATURES);
+    if others > 0 {
+        names.push(format!(
+            "{} other{}",
+            others,
+            if others == 1 { "" } else { "s" }
+        ));
+    }
+    let last = names.pop().unwrap_or_default();
+    let list = if names.is_empty() {
+        last
+    } else {
+        format!("{} and {}", names.join(", "), last)
+    };
+    let plural = if features.len() == 1 { "" } else { "s" };
+    format!("{} feature{}", list, plural)
+}
@@ -0,0 +1,165 @@
+//! C interface, letting tools written in other languages drive the resolution.
+//!
+//! Requests and responses are JSON documents exchanged as NUL-terminated strings. Strings
+//! returned by carguix must be released with [`carguix_free_string`].
+
+use crate::{
+    config::Config,
+    generate,
+    schema::{ErrorReport, Versioned},
+    CrateRef, Features, Options, PackageSpec, Target,
+};
+use serde::Deserialize;
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic,
+    path::PathBuf,
+    ptr,
+};
+
+/// Request accepted by [`carguix_resolve_json`], either a crate name or a path being required.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Request {
+    #[serde(rename = "crate")]
+    crate_name: Option<String>,
+    version: Option<String>,
+    path: Option<PathBuf>,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    no_default_features: bool,
+    #[serde(default)]
+    all_features: bool,
+    #[serde(default)]
+    skip_dev_dependencies: bool,
+    #[serde(default)]
+    sparse_index: bool,
+    lock_file: Option<PathBuf>,
+    /// URL template of a mirror replacing crates.io.
+    mirror: Option<String>,
+    /// URL of a hash mirror asked for the hashes missing from the hash database.
+    hash_mirror: Option<String>,
+    /// Path of a `carguix.toml` file.
+    config: Option<PathBuf>,
+    /// Module name, e.g. `["my", "channel", "rust-crates"]`.
+    #[serde(default)]
+    module_name: Vec<String>,
+    #[serde(default)]
+    parameterize_rust: bool,
+    #[serde(default)]
+    requirement_comments: bool,
+    #[serde(default)]
+    skip_proc_macro_builds: bool,
+    #[serde(default)]
+    skip_dependency_builds: bool,
+}
+
+impl Request {
+    fn into_spec(self) -> Result<PackageSpec, String> {
+        let config = match &self.config {
+            Some(config) => Config::open(config).map_err(|err| err.to_string())?,
+            None => Config::default(),
+        };
+        let target = match (self.path, self.crate_name) {
+            (Some(path), None) => Target::Path(path),
+            (None, Some(name)) => match (CrateRef::split_version(&name), self.version) {
+                ((_, Some("")), _) | ((_, Some(_)), Some(_)) => {
+                    return Err(
+                        "`name@version` needs a version, and no `version` besides".to_string()
+                    )
+                }
+                ((name, Some(version)), None) => Target::Crate {
+                    name: name.to_string(),
+                    version: Some(version.to_string()),
+                },
+                (_, version) => Target::Crate { name, version },
+            },
+            _ => return Err("exactly one of `crate` and `path` is required".to_string()),
+        };
+        Ok(PackageSpec {
+            target,
+            features: Features::new(&self.features, !self.no_default_features, self.all_features),
+            options: Options {
+                skip_dev_dependencies: self.skip_dev_dependencies,
+                sparse_index: self.sparse_index,
+                lock_file: self.lock_file,
+                mirror: self.mirror,
+                hash_mirror: self.hash_mirror,
+                requirement_comments: self.requirement_comments,
+                skip_proc_macro_builds: self.skip_proc_macro_builds,
+                skip_dependency_builds: self.skip_dependency_builds,
+                jobs: 8,
+                download_retries: 3,
+                config,
+                ..Options::default()
+            },
+            module_name: self.module_name,
+            parameterize_rust: self.parameterize_rust,
+        })
+    }
+}
+
+/// Resolve the packages described by a JSON request and return the resulting module as JSON.
+///
+/// Failures are returned as `{"error": "...", "causes": [...]}`. The returned string is owned by
+/// the caller, who must release it with [`carguix_free_string`].
+///
+/// # Safety
+///
+/// `input` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn carguix_resolve_json(input: *const c_char) -> *mut c_char {
+    let input = if input.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(input).to_string_lossy().into_owned())
+    };
+    let response = panic::catch_unwind(|| resolve_json(input.as_deref()))
+        .unwrap_or_else(|_| error_response("resolution panicked", Vec::new()));
+    CString::new(response)
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Release a string returned by carguix.
+///
+/// # Safety
+///
+/// `string` must be null or have been returned by carguix, and not released already.
+#[no_mangle]
+pub unsafe extern "C" fn carguix_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+fn resolve_json(input: Option<&str>) -> String {
+    let input = match input {
+        Some(input) => input,
+        None => return error_response("no request given", Vec::new()),
+    };
+    let spec = match serde_json::from_str::<Request>(input) {
+        Ok(request) => request.into_spec(),
+        Err(err) => Err(format!("invalid request: {}", err)),
+    };
+    let spec = match spec {
+        Ok(spec) => spec,
+        Err(err) => return error_response(&err, Vec::new()),
+    };
+    match generate(spec) {
+        Ok(module) => serde_json::to_string(&Versioned::new(module))
+            .unwrap_or_else(|err| error_response(&err.to_string(), Vec::new())),
+        Err(err) => versioned_error(ErrorReport::from_error(&err)),
+    }
+}
+
+fn error_response(error: &str, causes: Vec<String>) -> String {
+    versioned_error(ErrorReport::new(error, causes))
+}
+
+fn versioned_error(report: ErrorReport) -> String {
+    serde_json::to_string(&Versioned::new(report))
+        .expect("error reports only hold strings, which always serialize")
+}
@@ -0,0 +1,93 @@
+use crate::{crate_ref::CrateRef, errors::CarguixError, guix, guix_packages::ExistingPackage};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fs, io::ErrorKind, path::Path};
+
+/// File the package set of the last successful run is frozen to, alongside `carguix_state.json`.
+pub const FROZEN_FILE: &str = "carguix_frozen.json";
+
+/// Package of the frozen set, along with the inputs its JSON form only serializes by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FrozenPackage {
+    pub package: guix::Package,
+    pub existing_inputs: Vec<(CrateRef, ExistingPackage)>,
+    pub renamed_inputs: Vec<(CrateRef, String)>,
+}
+
+/// Fully hashed packages of the last run whose crates could all be packaged, which
+/// `carguix render --frozen` renders again in any format without resolving anything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FrozenPackageSet {
+    /// Commit of the crates.io index the packages were resolved against.
+    pub index_commit: Option<String>,
+    pub packages: Vec<FrozenPackage>,
+    /// Crates the run left out, which the packages may still refer to.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub left_out_crates: BTreeSet<String>,
+}
+
+impl FrozenPackageSet {
+    pub fn new<'a>(
+        index_commit: Option<String>,
+        packages: impl IntoIterator<Item = &'a guix::Package>,
+        left_out_crates: BTreeSet<String>,
+    ) -> Self {
+        let packages = packages
+            .into_iter()
+            .map(|package| FrozenPackage {
+                package: package.clone(),
+                existing_inputs: package
+                    .existing_inputs
+                    .iter()
+                    .map(|(crate_ref, existing)| (crate_ref.clone(), existing.clone()))
+                    .collect(),
+                renamed_inputs: package
+                    .renamed_inputs
+                    .iter()
+                    .map(|(crate_ref, variable)| (crate_ref.clone(), variable.clone()))
+                    .collect(),
+            })
+            .collect();
+        Self {
+            index_commit,
+            packages,
+            left_out_crates,
+        }
+    }
+
+    /// Read the package set frozen by the last successful run.
+    pub fn open(path: &Path) -> Result<Self, CarguixError> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|err| CarguixError::FrozenParsingError(err, path.to_path_buf())),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Err(CarguixError::NoFrozenPackageSet(path.to_path_buf()))
+            }
+            Err(err) => Err(CarguixError::FrozenIoError(err, path.to_path_buf())),
+        }
+    }
+
+    /// Write the package set through a temporary file, so that being interrupted while saving
+    /// leaves the previous set intact.
+    pub fn save(&self, path: &Path) -> Result<(), CarguixError> {
+        let content = serde_json::to_string(self)
+            .map_err(|err| CarguixError::FrozenParsingError(err, path.to_path_buf()))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, content)
+            .and_then(|()| fs::rename(&tmp_path, path))
+            .map_err(|err| CarguixError::FrozenIoError(err, path.to_path_buf()))
+    }
+
+    /// Packages of the set, as the run which froze them generated them.
+    pub fn into_packages(self) -> Vec<guix::Package> {
+        self.packages
+            .into_iter()
+            .map(|frozen| guix::Package {
+                existing_inputs: frozen.existing_inputs.into_iter().collect(),
+                renamed_inputs: frozen.renamed_inputs.into_iter().collect(),
+                ..frozen.package
+            })
+            .collect()
+    }
+}
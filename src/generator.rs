@@ -0,0 +1,2444 @@
+use crate::{
+    cargo_lock::CargoLock,
+    carguix_lock::{CarguixLock, Resolution, REGISTRY_SOURCE},
+    config::{self, Config},
+    crate_ref::CrateRef,
+    download::{self, Downloader},
+    enrichers::{self, Enricher, EnricherKind, MetadataTarget, DEFAULT_ENRICHERS},
+    errors::CarguixError,
+    features::Features,
+    frozen::{FrozenPackageSet, FROZEN_FILE},
+    graph::{DependencyGraph, Edge},
+    guix::{self, InputStyle, Origin, RegistryDownload},
+    guix_packages::{ExistingPackage, GuixPackages},
+    hash_db::{HashDatabase, HashEntry, HashMethod, HASHES_DIR},
+    index::{self, Registry},
+    license,
+    merge::{self, MergeStrategy, ProjectRequests},
+    metadata::CrateMetadata,
+    nix_hash,
+    patches::Patches,
+    path_source::{ManifestCache, PathSource, Workspace},
+    platform::Platform,
+    print_error,
+    registries::Registries,
+    registry_source::RegistrySource,
+    run_report::{HashSource, PackageRun},
+    run_state::{ResolvedCrate, RunState, RUN_STATE_FILE},
+    source::{DependencyKind, Source, SourceState},
+    stats::CacheStats,
+    tooling::SharedTooling,
+    unify,
+    vendor::VendoredCrates,
+    version,
+    warnings::{WarningCode, Warnings},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    env, fs, mem,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tempdir::TempDir;
+
+/// Directory the crates.io index is cloned into, in the data directory.
+const INDEX_DIR: &str = "index";
+/// Directory former versions cloned the index into, in the working directory.
+const LEGACY_INDEX_DIR: &str = "_index";
+
+/// Directory the clone of the crates.io index, the hash database and the git checkouts are kept
+/// in when no other is given: `$CARGUIX_HOME`, else `carguix` in the XDG cache directory, the
+/// working directory when there is no home directory.
+pub fn default_data_dir() -> PathBuf {
+    env::var_os("CARGUIX_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let cache_home = env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".cache")))?;
+            Some(cache_home.join(env!("CARGO_PKG_NAME")))
+        })
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Open the hash database of `data_dir`, created if needed. The default data directory takes
+/// over the hashes former versions kept in the working directory.
+pub fn open_hash_database(data_dir: &Path) -> Result<HashDatabase, CarguixError> {
+    let hashes_dir = data_dir.join(HASHES_DIR);
+    fs::create_dir_all(&hashes_dir)
+        .map_err(|err| CarguixError::DataDirError(err, data_dir.to_path_buf()))?;
+    let hashdb = HashDatabase::open(&hashes_dir)?;
+    // run from the hashes directory, the database of the working directory is the one just opened
+    let in_hashes_dir = fs::canonicalize(".")
+        .and_then(|dir| Ok(dir == fs::canonicalize(&hashes_dir)?))
+        .unwrap_or(false);
+    if data_dir == default_data_dir() && !in_hashes_dir {
+        if let Err(err) = hashdb.import_legacy(Path::new(".")) {
+            log::warn!(
+                "could not import the hashes of the working directory: {}",
+                err
+            );
+        }
+    }
+    Ok(hashdb)
+}
+
+/// Move the clone of the index former versions made in the working directory to `index_dir`,
+/// which is left to be cloned again if it can't be moved.
+fn migrate_legacy_index(index_dir: &Path) {
+    let legacy_dir = Path::new(LEGACY_INDEX_DIR);
+    if !legacy_dir.is_dir() || index_dir.exists() {
+        return;
+    }
+    match fs::rename(legacy_dir, index_dir) {
+        Ok(()) => log::info!("moved the index clone {:?} to {:?}", legacy_dir, index_dir),
+        Err(err) => log::warn!(
+            "could not move the index clone {:?} to {:?}, cloning it again: {}",
+            legacy_dir,
+            index_dir,
+            err
+        ),
+    }
+}
+
+/// Crates most of the ecosystem depends on, whose incompatible versions weigh the most on the
+/// closure when packaged side by side.
+const FOUNDATIONAL_CRATES: &[&str] = &["syn", "quote", "proc-macro2", "windows-sys"];
+
+/// Least time between two saves of the progress of a run.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What becomes of the packages reached through more build-dependency edges than
+/// [`Options::max_build_dependency_depth`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeepBuildDependencies {
+    /// Comment their definitions.
+    #[default]
+    Annotate,
+    /// Leave them unbuilt and drop their development inputs, along with the packages only
+    /// those pulled in.
+    Collapse,
+}
+
+impl std::str::FromStr for DeepBuildDependencies {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "annotate" => Ok(DeepBuildDependencies::Annotate),
+            "collapse" => Ok(DeepBuildDependencies::Collapse),
+            _ => Err(format!(
+                "unknown policy {}, expected annotate or collapse",
+                policy
+            )),
+        }
+    }
+}
+
+/// Settings tweaking how crates are packaged.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Size in bytes above which downloading a crate is warned about.
+    pub max_crate_size: Option<u64>,
+    /// Ask for confirmation before downloading a crate above `max_crate_size` instead of only
+    /// warning, the download failing when there is no terminal to ask on.
+    pub confirm_large_crates: bool,
+    /// Answer yes to every confirmation.
+    pub assume_yes: bool,
+    /// Leave dev-dependencies out of the generated packages.
+    pub skip_dev_dependencies: bool,
+    /// Platforms whose target-specific dependencies are packaged, the host by default.
+    pub platform: Platform,
+    /// Fail on versions and requirements semver rejects instead of repairing them.
+    pub strict_semver: bool,
+    /// Let requirements resolve to yanked versions even when other versions match.
+    pub allow_yanked: bool,
+    /// Let requirements which don't mention a pre-release, and crates packaged without a
+    /// version, resolve to pre-releases.
+    pub allow_prerelease: bool,
+    /// Package every version requirements resolve to, rather than a single version per
+    /// semver-compatible range of each crate where possible.
+    pub keep_duplicate_versions: bool,
+    /// Comment each input of the packages with the requirements it was resolved from.
+    pub requirement_comments: bool,
+    /// Tell in the descriptions which features of the crates the packages are built with.
+    pub feature_summaries: bool,
+    /// Give the packages the properties update bots track their upstream releases with.
+    pub update_properties: bool,
+    /// Form the packages list their crate dependencies in, which depends on the targeted Guix.
+    pub input_style: InputStyle,
+    /// Categories of warnings silenced or turned into errors.
+    pub warnings: Warnings,
+    /// Sources of the descriptive metadata of the packages, applied in order, `None` for
+    /// [`DEFAULT_ENRICHERS`].
+    pub enrichers: Option<Vec<EnricherKind>>,
+    /// Leave the procedural macros and the packages only they depend on unbuilt.
+    pub skip_proc_macro_builds: bool,
+    /// Leave every package but the root ones unbuilt, their dependents building them from their
+    /// sources.
+    pub skip_dependency_builds: bool,
+    /// Levels of dependencies packaged below the root crates, the crates of the next level being
+    /// referred to as inputs without being packaged.
+    pub max_depth: Option<usize>,
+    /// Build-dependency edges a package may be reached through from the root packages before
+    /// `deep_build_dependencies` applies to it, e.g. 1 for the build-dependencies of
+    /// build-dependencies.
+    pub max_build_dependency_depth: Option<usize>,
+    pub deep_build_dependencies: DeepBuildDependencies,
+    /// Crates referred to as inputs without being packaged, nor their dependencies, to be
+    /// supplied by another module.
+    pub excluded_crates: Vec<String>,
+    /// Variables of the packages defined already, such as by the module being completed, which
+    /// are referred to as inputs without being packaged again.
+    pub defined_variables: HashSet<String>,
+    /// Resolve crates from `cargo_lock` and `vendor_dir` only, any network access being an error.
+    pub offline: bool,
+    /// Directories whose `.cargo/config.toml` and the ones of their ancestors declare alternative
+    /// registries, the current directory if empty. The first directory declaring a registry takes
+    /// precedence.
+    pub cargo_config_dirs: Vec<PathBuf>,
+    /// Directory the clone of the crates.io index, the hash database and the git checkouts are
+    /// kept in instead of [`default_data_dir`], generators given different ones resolving
+    /// independently of each other.
+    pub data_dir: Option<PathBuf>,
+    /// `Cargo.lock` file the versions are taken from in offline mode, by default the one of the
+    /// first project, or of the workspace it belongs to.
+    pub cargo_lock: Option<PathBuf>,
+    /// Directory `cargo vendor` unpacked the crates into, used in offline mode, by default the
+    /// `vendor` directory next to the default `cargo_lock`.
+    pub vendor_dir: Option<PathBuf>,
+    /// Read the manifests of the local crates with `cargo metadata`, which resolves workspace
+    /// inheritance like cargo does, carguix's own parser being used when cargo can't be found.
+    pub cargo_metadata: bool,
+    /// Look crates up in the sparse HTTP index rather than in a clone of the git index.
+    pub sparse_index: bool,
+    /// Index looked up instead of the one of crates.io, such as a local fixture: a sparse index
+    /// served over HTTP, with or without the `sparse+` prefix, or a `file://` directory laid out
+    /// like an index.
+    pub index_url: Option<String>,
+    /// Accept index updates rewriting the history of the current checkout.
+    pub allow_index_rewrite: bool,
+    /// File pinning resolution decisions across runs.
+    pub lock_file: Option<PathBuf>,
+    /// Ignore the pins of the lock file, which is rewritten with fresh decisions.
+    pub refresh_lock: bool,
+    /// Pick up the interrupted run persisted in `carguix_state.json`, if it packaged the same
+    /// crates.
+    pub resume: bool,
+    /// Number of crates downloaded and hashed concurrently.
+    pub jobs: usize,
+    /// Further attempts at downloading a crate after a transient failure.
+    pub download_retries: usize,
+    /// Shell out to `guix hash` rather than hashing natively.
+    pub guix_hash: bool,
+    /// How crates the merged projects require differently are packaged, such crates being
+    /// reported as conflicts if `None`.
+    pub merge_strategy: Option<MergeStrategy>,
+    /// Crates whose hashes are computed from fresh downloads, neither the lock file nor the
+    /// hash database being trusted.
+    pub refresh_hashes: Vec<String>,
+    /// Download every crate to hash it, even when its checksum is known from `Cargo.lock`,
+    /// failing on the crates whose tarball doesn't match that checksum.
+    pub verify_checksums: bool,
+    /// URI template of a mirror crates.io tarballs are fetched from instead of crates.io, with
+    /// the same markers as [`Config::mirrors`].
+    pub mirror: Option<String>,
+    /// URL of a hash mirror, see [`crate::hash_mirror`], asked for the hashes missing from the
+    /// hash database before downloading crates.
+    pub hash_mirror: Option<String>,
+    /// Directory the crate tarballs are kept in, each crate being downloaded once and hashed from
+    /// its stored tarball, which the packages fetch instead of the registry one.
+    pub tarball_dir: Option<PathBuf>,
+    /// URL the tarballs of `tarball_dir` are published at, such as an artifact server, `file://`
+    /// URIs of the directory being used otherwise.
+    pub tarball_url: Option<String>,
+    /// Guix checkout whose Rust packages are reused.
+    pub guix_checkout: Option<PathBuf>,
+    /// Runs `guix` and git, see [`crate::tooling`].
+    pub tooling: SharedTooling,
+    /// Content of `carguix.toml`.
+    pub config: Config,
+}
+
+impl Options {
+    /// URIs of a crate tarball on the mirror replacing crates.io, if any, then on the mirrors of
+    /// `carguix.toml`.
+    pub fn mirror_uris(&self, crate_name: &str, version: &str) -> Vec<String> {
+        self.mirror
+            .iter()
+            .map(|template| config::mirror_uri(template, crate_name, version))
+            .chain(self.config.mirror_uris(crate_name, version))
+            .collect()
+    }
+
+    /// Path of a crate tarball in `tarball_dir`, below a directory named after its registry for
+    /// the crates of alternative registries.
+    pub fn tarball_path(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: Option<&str>,
+    ) -> Option<PathBuf> {
+        let tarball_dir = self.tarball_dir.as_ref()?;
+        Some(tarball_dir.join(tarball_relative_path(crate_name, version, registry)))
+    }
+
+    /// URI the packages fetch a crate tarball of `tarball_dir` from.
+    pub fn tarball_uri(
+        &self,
+        crate_name: &str,
+        version: &str,
+        registry: Option<&str>,
+    ) -> Option<String> {
+        let path = self.tarball_path(crate_name, version, registry)?;
+        Some(match &self.tarball_url {
+            Some(tarball_url) => format!(
+                "{}/{}",
+                tarball_url.trim_end_matches('/'),
+                tarball_relative_path(crate_name, version, registry)
+            ),
+            None => format!("file://{}", path.display()),
+        })
+    }
+}
+
+/// Path of a crate tarball relative to `tarball_dir`, named like cargo names them.
+fn tarball_relative_path(crate_name: &str, version: &str, registry: Option<&str>) -> String {
+    let file_name = format!("{}-{}.crate", crate_name, version);
+    match registry {
+        Some(registry) => format!("{}/{}", registry, file_name),
+        None => file_name,
+    }
+}
+
+/// Version of a crate as published on its registry, see [`Generator::published_versions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedVersion {
+    pub version: String,
+    pub yanked: bool,
+    /// Hash cached in the hash database, if the version has been hashed already.
+    pub hash: Option<String>,
+}
+
+/// Queue of crates to package, each packaged crate enqueuing its dependencies in turn until the
+/// whole dependency graph is packaged.
+#[derive(Debug)]
+pub struct Generator {
+    crates: VecDeque<Box<dyn Source>>,
+    roots: HashSet<(String, Option<String>)>,
+    /// Sources of the packaged crates, with the features of every dependent merged, along with
+    /// the position of their packages.
+    resolved_crates: HashMap<(String, Option<String>), (SourceState, usize)>,
+    packages: Vec<Result<guix::Package, CarguixError>>,
+    /// What was done for each crate of `packages`, at the same position.
+    package_runs: Vec<PackageRun>,
+    pub(crate) registry: Registry,
+    tmpdir: TempDir,
+    hashdb: HashDatabase,
+    /// Sources of the metadata of the packages, see [`Options::enrichers`].
+    enrichers: Vec<Box<dyn Enricher>>,
+    pub(crate) options: Options,
+    pub(crate) pinned: CarguixLock,
+    lock: CarguixLock,
+    guix_packages: GuixPackages,
+    /// Crates resolved to packages already defined in Guix.
+    existing_packages: HashMap<CrateRef, ExistingPackage>,
+    cache_stats: CacheStats,
+    graph: DependencyGraph,
+    /// Crates of the `Cargo.lock` file and vendor directory, in offline mode.
+    vendored: Option<VendoredCrates>,
+    /// Alternative registries declared in cargo configuration.
+    pub(crate) registries: Registries,
+    /// When the progress of the run was last persisted.
+    last_checkpoint: Instant,
+    /// Paths of the projects merged into a single module, whose roots are enqueued one project
+    /// after the other.
+    projects: Vec<PathBuf>,
+    /// Root sources of the projects after the one being packaged.
+    pending_projects: VecDeque<Vec<Box<dyn Source>>>,
+    /// Position of the project being packaged.
+    project: usize,
+    /// How each project requires the crates, when several projects are merged.
+    requests: ProjectRequests,
+    /// Hashes of the crates locked by the `Cargo.lock` files of the projects, by registry,
+    /// `None` standing for crates.io, and crate.
+    locked_hashes: HashMap<(Option<String>, CrateRef), String>,
+    /// Levels below the root crates the crates were first reached at, the roots being at 0.
+    depths: HashMap<(String, Option<String>), usize>,
+    /// Overrides of crates.io crates declared by the projects.
+    patches: Patches,
+    /// Manifests of the local and vendored crates parsed so far.
+    manifests: ManifestCache,
+    /// Repository of the `git` subcommand, checked out once the options tell where.
+    git_target: Option<GitTarget>,
+    /// Crates [`Self::enqueue_dependencies`] left out, by name.
+    left_out_crates: BTreeSet<String>,
+}
+
+/// Revision of a git repository whose crates are packaged, see [`Generator::from_git`].
+#[derive(Debug)]
+struct GitTarget {
+    url: String,
+    revision: String,
+    subdir: Option<PathBuf>,
+    features: Features,
+}
+
+impl Generator {
+    pub fn new(
+        crate_name: &str,
+        crate_version: &Option<String>,
+        features: &Features,
+    ) -> Result<Self, CarguixError> {
+        Self::with_sources(vec![Box::new(RegistrySource::new(
+            crate_name,
+            crate_version,
+            features,
+        ))])
+    }
+
+    /// Package several crates of the registry together, the dependencies they share being
+    /// packaged once, each one in its default version unless one is given.
+    pub fn from_crates(
+        crates: &[(String, Option<String>)],
+        features: &Features,
+    ) -> Result<Self, CarguixError> {
+        Self::with_sources(
+            crates
+                .iter()
+                .map(|(crate_name, version)| {
+                    Box::new(RegistrySource::new(crate_name, version, features)) as Box<dyn Source>
+                })
+                .collect(),
+        )
+    }
+
+    pub fn from_path(path: &Path, features: &Features) -> Result<Self, CarguixError> {
+        Self::from_paths(&[path.to_path_buf()], features)
+    }
+
+    /// Package the local crates or workspaces of several projects together, the dependencies they
+    /// share being packaged once.
+    ///
+    /// Projects are packaged one after the other, so that crates they require differently are
+    /// detected and packaged according to [`Options::merge_strategy`].
+    pub fn from_paths(paths: &[PathBuf], features: &Features) -> Result<Self, CarguixError> {
+        let mut projects = paths
+            .iter()
+            .map(|path| {
+                Ok(PathSource::from_path(path, features)?
+                    .into_iter()
+                    .map(|source| Box::new(source) as Box<dyn Source>)
+                    .collect::<Vec<_>>())
+            })
+            .collect::<Result<VecDeque<_>, CarguixError>>()?;
+        let mut generator = Self::with_sources(projects.pop_front().unwrap_or_default())?;
+        generator
+            .roots
+            .extend(projects.iter().flatten().map(|source| source.crate_key()));
+        generator.projects = paths.to_vec();
+        generator.pending_projects = projects;
+        Ok(generator)
+    }
+
+    /// Package a single crate of the project at `path`, such as a member of a workspace.
+    pub fn from_path_member(
+        path: &Path,
+        member: &str,
+        features: &Features,
+    ) -> Result<Self, CarguixError> {
+        let sources = PathSource::from_path(path, features)?
+            .into_iter()
+            .filter(|source| source.crate_ref().name == member)
+            .map(|source| Box::new(source) as Box<dyn Source>)
+            .collect::<Vec<_>>();
+        if sources.is_empty() {
+            return Err(CarguixError::NoPathMember(
+                member.to_string(),
+                path.to_path_buf(),
+            ));
+        }
+        let mut generator = Self::with_sources(sources)?;
+        generator.projects = vec![path.to_path_buf()];
+        Ok(generator)
+    }
+
+    /// Package the crate, or the members of the workspace, at `subdir` of a revision of the git
+    /// repository at `url`, fetched from its commit. The repository is checked out below `git`
+    /// of the data directory like the ones of git patches, by [`Generator::with_options`].
+    pub fn from_git(
+        url: &str,
+        revision: &str,
+        subdir: Option<&Path>,
+        features: &Features,
+    ) -> Result<Self, CarguixError> {
+        let mut generator = Self::with_sources(Vec::new())?;
+        generator.git_target = Some(GitTarget {
+            url: url.to_string(),
+            revision: revision.to_string(),
+            subdir: subdir.map(Path::to_path_buf),
+            features: features.clone(),
+        });
+        Ok(generator)
+    }
+
+    /// Check the repository of [`Generator::from_git`] out and queue its crates.
+    fn check_out_git_target(&mut self, target: &GitTarget) -> Result<(), CarguixError> {
+        let (checkout_dir, git) =
+            self.patches
+                .checkout(&target.url, &target.revision, self.tmpdir.path())?;
+        let (project, sources) = PathSource::from_git_checkout(
+            &checkout_dir,
+            &git,
+            target.subdir.as_deref(),
+            &target.features,
+        )?;
+        self.roots
+            .extend(sources.iter().map(|source| source.crate_key()));
+        self.crates.extend(
+            sources
+                .into_iter()
+                .map(|source| Box::new(source) as Box<dyn Source>),
+        );
+        self.projects = vec![project];
+        Ok(())
+    }
+
+    pub fn from_reverse_dependencies(crate_name: &str, limit: usize) -> Result<Self, CarguixError> {
+        let mut generator = Self::with_sources(Vec::new())?;
+        generator.retrieve_git_index()?;
+        let index = generator
+            .registry
+            .git_index()
+            .expect("the git index is the default registry");
+        let dependents = RegistrySource::popular_dependents(index, crate_name, limit)?;
+        generator.crates.extend(
+            dependents
+                .into_iter()
+                .map(|source| Box::new(source) as Box<dyn Source>),
+        );
+        Ok(generator)
+    }
+
+    pub fn with_sources(sources: Vec<Box<dyn Source>>) -> Result<Self, CarguixError> {
+        let generator = Generator {
+            roots: sources.iter().map(|source| source.crate_key()).collect(),
+            crates: sources.into(),
+            resolved_crates: HashMap::new(),
+            packages: Vec::new(),
+            package_runs: Vec::new(),
+            registry: Registry::git(&default_data_dir().join(INDEX_DIR)),
+            tmpdir: TempDir::new(env!("CARGO_PKG_NAME")).map_err(CarguixError::TmpdirError)?,
+            hashdb: HashDatabase::in_memory()?,
+            enrichers: Vec::new(),
+            options: Options::default(),
+            pinned: CarguixLock::default(),
+            lock: CarguixLock::default(),
+            guix_packages: GuixPackages::default(),
+            existing_packages: HashMap::new(),
+            cache_stats: CacheStats::default(),
+            graph: DependencyGraph::default(),
+            vendored: None,
+            registries: Registries::default(),
+            last_checkpoint: Instant::now(),
+            projects: Vec::new(),
+            pending_projects: VecDeque::new(),
+            project: 0,
+            requests: ProjectRequests::default(),
+            locked_hashes: HashMap::new(),
+            depths: HashMap::new(),
+            patches: Patches::default(),
+            manifests: ManifestCache::default(),
+            git_target: None,
+            left_out_crates: BTreeSet::new(),
+        };
+        Ok(generator)
+    }
+
+    pub fn with_options(mut self, mut options: Options) -> Result<Self, CarguixError> {
+        self.pinned = match &options.lock_file {
+            Some(lock_file) if !options.refresh_lock => CarguixLock::open(lock_file)?,
+            _ => CarguixLock::default(),
+        };
+        let data_dir = options.data_dir.take().unwrap_or_else(default_data_dir);
+        self.hashdb = open_hash_database(&data_dir)?;
+        let index_dir = data_dir.join(INDEX_DIR);
+        if data_dir == default_data_dir() {
+            migrate_legacy_index(&index_dir);
+        }
+        self.registry = Registry::git(&index_dir);
+        self.patches.set_checkouts_dir(&data_dir);
+        self.patches.set_tooling(options.tooling.clone());
+        options.data_dir = Some(data_dir);
+        if let Some(target) = self.git_target.take() {
+            self.check_out_git_target(&target)?;
+        }
+        if options.cargo_metadata {
+            self.manifests = ManifestCache::cargo_metadata();
+            self.reread_root_manifests()?;
+        }
+        if options.sparse_index {
+            self.registry = Registry::sparse();
+        }
+        if let Some(index_url) = &options.index_url {
+            let url = index_url.strip_prefix("sparse+").unwrap_or(index_url);
+            self.registry = Registry::sparse_with_url(url, None);
+        }
+        if let Some(guix_checkout) = &options.guix_checkout {
+            self.guix_packages = GuixPackages::scan(guix_checkout)?;
+        }
+        if options.cargo_config_dirs.is_empty() {
+            self.registries = Registries::load(Path::new("."))?;
+        }
+        for cargo_config_dir in &options.cargo_config_dirs {
+            self.registries.merge(Registries::load(cargo_config_dir)?);
+        }
+        let mut project_roots = Vec::new();
+        for project in self.projects.clone() {
+            // a workspace member shares the lock file and overrides of the workspace root
+            let project = fs::canonicalize(&project)
+                .ok()
+                .and_then(|path| self.manifests.enclosing_workspace(&path))
+                .map_or(project, |workspace| workspace.root.clone());
+            let cargo_lock = project.join("Cargo.lock");
+            if cargo_lock.is_file() {
+                self.read_locked_hashes(&cargo_lock)?;
+            }
+            self.patches.read(&project)?;
+            project_roots.push(project);
+        }
+        if let Some(tarball_dir) = &options.tarball_dir {
+            let absolute_dir = fs::create_dir_all(tarball_dir)
+                .and_then(|_| fs::canonicalize(tarball_dir))
+                .map_err(|err| CarguixError::TarballDirError(err, tarball_dir.clone()))?;
+            options.tarball_dir = Some(absolute_dir);
+        }
+        if options.offline {
+            let project_root = project_roots
+                .first()
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from("."));
+            let cargo_lock = options
+                .cargo_lock
+                .clone()
+                .unwrap_or_else(|| project_root.join("Cargo.lock"));
+            let vendor_dir = options
+                .vendor_dir
+                .clone()
+                .unwrap_or_else(|| project_root.join("vendor"));
+            self.vendored = Some(VendoredCrates::open(&cargo_lock, &vendor_dir)?);
+        }
+        self.enrichers = options
+            .enrichers
+            .as_deref()
+            .unwrap_or(DEFAULT_ENRICHERS)
+            .iter()
+            .map(|kind| kind.enricher(&options.config, options.offline))
+            .collect();
+        self.options = options;
+        if self.options.resume {
+            self.resume()?;
+        }
+        Ok(self)
+    }
+
+    /// Read the manifests of the local root crates again, once [`Options::cargo_metadata`] has
+    /// them read with cargo.
+    fn reread_root_manifests(&mut self) -> Result<(), CarguixError> {
+        let roots = &mut self.roots;
+        let manifests = &mut self.manifests;
+        let mut reread = |source: Box<dyn Source>| {
+            let crate_key = source.crate_key();
+            let source = source.state().into_source(manifests)?;
+            if roots.remove(&crate_key) {
+                roots.insert(source.crate_key());
+            }
+            Ok(source)
+        };
+        self.crates = mem::take(&mut self.crates)
+            .into_iter()
+            .map(&mut reread)
+            .collect::<Result<_, CarguixError>>()?;
+        self.pending_projects = mem::take(&mut self.pending_projects)
+            .into_iter()
+            .map(|project| project.into_iter().map(&mut reread).collect())
+            .collect::<Result<_, CarguixError>>()?;
+        Ok(())
+    }
+
+    /// Append a source of metadata carguix doesn't know about to the enrichers, which
+    /// [`Generator::with_options`] sets from [`Options::enrichers`] beforehand.
+    pub fn with_enricher(mut self, enricher: Box<dyn Enricher>) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
+    /// Convert the checksums of a `Cargo.lock` file to hashes, sparing the download of the crates
+    /// it locks. Crates of registries which are not declared are skipped.
+    pub fn read_locked_hashes(&mut self, cargo_lock: &Path) -> Result<(), CarguixError> {
+        for package in CargoLock::open(cargo_lock)?.package {
+            let (source, checksum) = match (&package.source, &package.checksum) {
+                (Some(source), Some(checksum)) => (source, checksum),
+                _ => continue,
+            };
+            let index_url = source.strip_prefix("registry+").unwrap_or(source);
+            let registry = match self.registries.name_by_index_url(index_url) {
+                Ok(registry) => registry.map(str::to_string),
+                Err(_) => continue,
+            };
+            match nix_hash::from_hex(checksum) {
+                Some(hash) => {
+                    let crate_ref = CrateRef::new(&package.name, &package.version);
+                    self.locked_hashes.insert((registry, crate_ref), hash);
+                }
+                None => log::warn!(
+                    "invalid checksum {} of crate {} in version {} in {:?}",
+                    checksum,
+                    package.name,
+                    package.version,
+                    cargo_lock
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore the progress of the interrupted run persisted in the run state file, if it was
+    /// started for the same crates. Crates which could not be packaged are tried again.
+    fn resume(&mut self) -> Result<(), CarguixError> {
+        let state = match RunState::open(&self.data_dir().join(RUN_STATE_FILE))? {
+            Some(state) => state,
+            None => {
+                log::warn!("no interrupted run to resume, starting from scratch");
+                return Ok(());
+            }
+        };
+        if state.roots != self.roots.iter().cloned().collect::<BTreeSet<_>>() {
+            log::warn!("the interrupted run packaged other crates, starting from scratch");
+            return Ok(());
+        }
+        let mut packages = state.packages;
+        let mut retried = Vec::new();
+        for resolved in state.resolved_crates {
+            match packages.get_mut(resolved.position).and_then(Option::take) {
+                Some(package) => {
+                    self.resolved_crates
+                        .insert(resolved.crate_key, (resolved.source, self.packages.len()));
+                    self.package_runs.push(PackageRun::new(
+                        &package.crate_ref.name,
+                        Some(&package.crate_ref.version),
+                    ));
+                    self.packages.push(Ok(package));
+                }
+                None => retried.push(resolved.source),
+            }
+        }
+        self.crates = retried
+            .into_iter()
+            .chain(state.queue)
+            .map(|source| source.into_source(&mut self.manifests))
+            .collect::<Result<_, _>>()?;
+        self.pending_projects = state
+            .pending_projects
+            .into_iter()
+            .map(|project| {
+                project
+                    .into_iter()
+                    .map(|source| source.into_source(&mut self.manifests))
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        self.project = state.project;
+        self.requests = ProjectRequests::from_requests(state.requests);
+        self.existing_packages = state.existing_packages.into_iter().collect();
+        self.graph = state.graph;
+        self.lock = state.lock;
+        log::info!(
+            "resuming interrupted run with {} crates packaged and {} pending",
+            self.packages.len(),
+            self.crates.len()
+        );
+        Ok(())
+    }
+
+    /// Persist the progress of the run, unless it was persisted less than
+    /// [`CHECKPOINT_INTERVAL`] ago and `force` isn't set.
+    fn checkpoint(&mut self, force: bool) {
+        if !force && self.last_checkpoint.elapsed() < CHECKPOINT_INTERVAL {
+            return;
+        }
+        self.last_checkpoint = Instant::now();
+        let state = RunState {
+            roots: self.roots.iter().cloned().collect(),
+            queue: self.crates.iter().map(|source| source.state()).collect(),
+            resolved_crates: self
+                .resolved_crates
+                .iter()
+                .map(|(crate_key, (source, position))| ResolvedCrate {
+                    crate_key: crate_key.clone(),
+                    source: source.clone(),
+                    position: *position,
+                })
+                .collect(),
+            packages: self
+                .packages
+                .iter()
+                .map(|package| package.as_ref().ok().cloned())
+                .collect(),
+            existing_packages: self
+                .existing_packages
+                .iter()
+                .map(|(crate_ref, existing_package)| (crate_ref.clone(), existing_package.clone()))
+                .collect(),
+            graph: self.graph.clone(),
+            lock: self.lock.clone(),
+            pending_projects: self
+                .pending_projects
+                .iter()
+                .map(|project| project.iter().map(|source| source.state()).collect())
+                .collect(),
+            project: self.project,
+            requests: self.requests.to_requests(),
+        };
+        if let Err(err) = state.save(&self.data_dir().join(RUN_STATE_FILE)) {
+            print_error(&err);
+        }
+    }
+
+    /// Clone the git index if it's the registry in use and it hasn't been cloned yet, once a
+    /// crate of crates.io first needs to be resolved, so that runs without any never fetch it.
+    fn retrieve_git_index(&self) -> Result<(), CarguixError> {
+        if self.options.offline {
+            return Ok(());
+        }
+        match self.registry.git_index() {
+            Some(index) if index.exists().not() => self.update_index(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the resolution decisions of the run to the lock file, if any.
+    pub fn save_lock(&self) -> Result<(), CarguixError> {
+        match &self.options.lock_file {
+            Some(lock_file) => self.lock.save(lock_file),
+            None => Ok(()),
+        }
+    }
+
+    /// Version of a crate a requirement resolves to, preferring a matching package of the Guix
+    /// checkout, then the version pinned in the lock file.
+    pub fn resolve_requirement(
+        &mut self,
+        crate_name: &str,
+        requirement: &str,
+    ) -> Result<CrateRef, CarguixError> {
+        let package_name = CrateRef::new(crate_name, "").format_name();
+        if let Some(existing_package) = self.guix_packages.find(&package_name, requirement) {
+            let crate_ref = CrateRef::new(crate_name, &existing_package.version);
+            let source = format!("guix+{}", existing_package.variable);
+            self.existing_packages
+                .insert(crate_ref.clone(), existing_package.clone());
+            self.record_resolution(&crate_ref, Some(requirement), &source);
+            return Ok(crate_ref);
+        }
+        if let Some(vendored) = &self.vendored {
+            let crate_ref =
+                vendored.resolve(crate_name, requirement, self.options.strict_semver)?;
+            let source = format!("path+file://{}", vendored.crate_path(&crate_ref).display());
+            self.record_resolution(&crate_ref, Some(requirement), &source);
+            return Ok(crate_ref);
+        }
+        let pinned = self.pinned.resolution(crate_name, Some(requirement));
+        let (crate_ref, index_commit) = match pinned {
+            Some(pinned) => (
+                CrateRef::new(crate_name, &pinned.version),
+                pinned.index_commit.clone(),
+            ),
+            None if version::is_wildcard(requirement) => {
+                match self.locked_version(crate_name, requirement) {
+                    Some(crate_ref) => {
+                        log::info!(
+                            "wildcard requirement {} on crate {} resolved to version {} locked by the project",
+                            requirement,
+                            crate_name,
+                            crate_ref.version
+                        );
+                        (crate_ref, None)
+                    }
+                    None => {
+                        let crate_ref = RegistrySource::highest_matching_crate_version(
+                            self.registry_index(None)?,
+                            crate_name,
+                            requirement,
+                            &self.options,
+                        )?;
+                        let index_commit = self.pin_wildcard(&crate_ref, requirement);
+                        (crate_ref, index_commit)
+                    }
+                }
+            }
+            None => (
+                RegistrySource::highest_matching_crate_version(
+                    self.registry_index(None)?,
+                    crate_name,
+                    requirement,
+                    &self.options,
+                )?,
+                None,
+            ),
+        };
+        self.lock.record(Resolution {
+            crate_name: crate_ref.name.clone(),
+            requirement: Some(requirement.to_string()),
+            version: crate_ref.version.clone(),
+            source: REGISTRY_SOURCE.to_string(),
+            hash: None,
+            index_commit,
+        });
+        Ok(crate_ref)
+    }
+
+    /// Source of the copy of a crates.io crate the `[patch.crates-io]` sections of the projects
+    /// substitute for it, if its version matches the requirement like cargo demands.
+    #[allow(clippy::type_complexity)]
+    pub fn patched_source(
+        &mut self,
+        crate_name: &str,
+        requirement: &str,
+        features: &Features,
+    ) -> Result<Option<(CrateRef, Box<dyn Source>)>, CarguixError> {
+        let override_ = match self.patches.patch(crate_name) {
+            Some(override_) => override_.clone(),
+            None => return Ok(None),
+        };
+        let source = self.patches.source(
+            &override_,
+            crate_name,
+            features,
+            self.tmpdir.path(),
+            self.options.offline,
+        )?;
+        let crate_ref = source.crate_ref();
+        let strict = self.options.strict_semver;
+        let matches = version::parse_requirement(crate_name, requirement, strict)
+            .ok()
+            .zip(version::parse_version(crate_name, &crate_ref.version, strict).ok())
+            .is_none_or(|(requirement, version)| requirement.matches(&version));
+        if !matches {
+            log::warn!(
+                "patch of crate {} in version {} doesn't match requirement {}, ignoring it",
+                crate_name,
+                crate_ref.version,
+                requirement
+            );
+            return Ok(None);
+        }
+        Ok(Some((crate_ref, Box::new(source))))
+    }
+
+    /// Source of the copy of a crates.io crate version the `[replace]` sections of the projects
+    /// substitute for it, if any.
+    #[allow(clippy::type_complexity)]
+    pub fn replaced_source(
+        &mut self,
+        crate_ref: &CrateRef,
+        features: &Features,
+    ) -> Result<Option<(CrateRef, Box<dyn Source>)>, CarguixError> {
+        let override_ = match self.patches.replacement(crate_ref) {
+            Some(override_) => override_.clone(),
+            None => return Ok(None),
+        };
+        let source = self.patches.source(
+            &override_,
+            &crate_ref.name,
+            features,
+            self.tmpdir.path(),
+            self.options.offline,
+        )?;
+        if source.crate_ref() != *crate_ref {
+            log::warn!(
+                "replacement of crate {} in version {} is version {}",
+                crate_ref.name,
+                crate_ref.version,
+                source.crate_ref().version
+            );
+        }
+        Ok(Some((source.crate_ref(), Box::new(source))))
+    }
+
+    /// Version of a crate of an alternative registry a requirement resolves to, preferring the
+    /// version pinned in the lock file.
+    pub fn resolve_registry_requirement(
+        &mut self,
+        registry: &str,
+        crate_name: &str,
+        requirement: &str,
+    ) -> Result<CrateRef, CarguixError> {
+        if self.options.offline {
+            return Err(CarguixError::OfflineNetworkAccess(format!(
+                "resolving crate {} from registry {}",
+                crate_name, registry
+            )));
+        }
+        let alternative_registry = self.registries.get(registry)?;
+        let source = format!("registry+{}", alternative_registry.index_url);
+        let crate_ref = match self.pinned.resolution(crate_name, Some(requirement)) {
+            Some(pinned) if pinned.source == source => CrateRef::new(crate_name, &pinned.version),
+            _ => RegistrySource::highest_matching_crate_version(
+                alternative_registry.index()?,
+                crate_name,
+                requirement,
+                &self.options,
+            )?,
+        };
+        self.record_resolution(&crate_ref, Some(requirement), &source);
+        Ok(crate_ref)
+    }
+
+    /// Index of crates.io, or of the alternative registry of the given name.
+    pub fn registry_index(&self, registry: Option<&str>) -> Result<&Registry, CarguixError> {
+        match registry {
+            Some(registry) => self.registries.get(registry)?.index(),
+            None => {
+                self.retrieve_git_index()?;
+                Ok(&self.registry)
+            }
+        }
+    }
+
+    /// Highest version of a crates.io crate matching a requirement among the ones the
+    /// `Cargo.lock` files of the projects lock, the version cargo builds them with.
+    fn locked_version(&self, crate_name: &str, requirement: &str) -> Option<CrateRef> {
+        let version_req =
+            version::parse_requirement(crate_name, requirement, self.options.strict_semver).ok()?;
+        self.locked_hashes
+            .keys()
+            .filter(|(registry, crate_ref)| registry.is_none() && crate_ref.name == crate_name)
+            .filter_map(|(_, crate_ref)| {
+                Some((semver::Version::parse(&crate_ref.version).ok()?, crate_ref))
+            })
+            .filter(|(version, _)| version_req.matches(version))
+            .max_by(|(version, _), (other_version, _)| version.cmp(other_version))
+            .map(|(_, crate_ref)| crate_ref.clone())
+    }
+
+    /// Commit of the index a wildcard requirement is resolved against, recorded in the lock along
+    /// with the version. It is informational: the pinned version is what keeps the requirement
+    /// resolving the same in later runs, the index being neither checked out nor compared at that
+    /// commit.
+    fn pin_wildcard(&self, crate_ref: &CrateRef, requirement: &str) -> Option<String> {
+        let index_commit = self.index_commit();
+        match &index_commit {
+            Some(index_commit) => log::info!(
+                "wildcard requirement {} on crate {} pinned to version {} of index commit {}",
+                requirement,
+                crate_ref.name,
+                crate_ref.version,
+                index_commit
+            ),
+            None => self.options.warnings.warn(
+                WarningCode::UnpinnedWildcard,
+                format_args!(
+                    "wildcard requirement {} on crate {} resolved to the latest version {}, which only the lock file keeps from changing",
+                    requirement,
+                    crate_ref.name,
+                    crate_ref.version
+                ),
+            ),
+        }
+        index_commit
+    }
+
+    pub fn record_resolution(
+        &mut self,
+        crate_ref: &CrateRef,
+        requirement: Option<&str>,
+        source: &str,
+    ) {
+        self.lock.record(Resolution {
+            crate_name: crate_ref.name.clone(),
+            requirement: requirement.map(str::to_string),
+            version: crate_ref.version.clone(),
+            source: source.to_string(),
+            hash: None,
+            index_commit: None,
+        });
+    }
+
+    pub fn update_index(&self) -> Result<(), CarguixError> {
+        if self.options.offline {
+            return Err(CarguixError::OfflineNetworkAccess(
+                "updating the index".to_string(),
+            ));
+        }
+        let index = match self.registry.git_index() {
+            Some(index) => index,
+            None => {
+                log::info!("sparse index files are fetched up to date, nothing to update");
+                return Ok(());
+            }
+        };
+        log::info!("fetching crates.io index...");
+        let commit = index::update(
+            index,
+            self.options.allow_index_rewrite,
+            &*self.options.tooling,
+        )?;
+        log::info!("crates.io index verified at commit {}", commit);
+        Ok(())
+    }
+
+    /// Commit of the index the packages are resolved against, if it can be determined.
+    pub fn index_commit(&self) -> Option<String> {
+        index::head_commit(self.registry.git_index()?, &*self.options.tooling).ok()
+    }
+
+    /// Resolve the whole dependency graph and return the package of every crate in it.
+    pub fn packages(&mut self) -> Vec<Result<guix::Package, CarguixError>> {
+        loop {
+            while let Some(source) = self.crates.pop_front() {
+                self.process_crate(source);
+                self.checkpoint(false);
+            }
+            match self.pending_projects.pop_front() {
+                Some(project) => {
+                    self.crates.extend(project);
+                    self.project += 1;
+                }
+                None => break,
+            }
+        }
+        self.checkpoint(true);
+        self.report_merge_conflicts();
+        if !self.options.keep_duplicate_versions {
+            self.unify_versions();
+        }
+        self.report_duplicate_majors();
+        self.break_dependency_cycles();
+        self.limit_build_dependency_depth();
+        let missing = self.hash_crates(0);
+        self.replace_missing_tarballs(missing);
+        self.resolved_crates.clear();
+        if let Err(err) = RunState::remove(&self.data_dir().join(RUN_STATE_FILE)) {
+            print_error(&err);
+        }
+        let existing_packages = &self.existing_packages;
+        for package in self.packages.iter_mut().flatten() {
+            let existing_inputs = package
+                .cargo_inputs
+                .iter()
+                .chain(&package.cargo_development_inputs)
+                .filter_map(|input| Some((input.clone(), existing_packages.get(input)?.clone())))
+                .collect();
+            package.existing_inputs = existing_inputs;
+        }
+        if self.options.requirement_comments {
+            self.record_input_requirements();
+        }
+        self.mark_build_only_packages();
+        self.check_packages();
+        self.rename_colliding_variables();
+        self.sort_packages();
+        if self.packages.iter().all(Result::is_ok) {
+            self.freeze();
+        }
+        std::mem::take(&mut self.packages)
+    }
+
+    /// Order the packages like modules define them, see [`guix::dependency_order_positions`],
+    /// the crates which couldn't be packaged coming last by name and version, so that runs
+    /// resolving the same crates give the same packages in the same order.
+    fn sort_packages(&mut self) {
+        let packaged = self
+            .packages
+            .iter()
+            .enumerate()
+            .filter_map(|(position, package)| Some((position, package.as_ref().ok()?)))
+            .collect::<Vec<_>>();
+        let packages = packaged
+            .iter()
+            .map(|(_, package)| *package)
+            .collect::<Vec<_>>();
+        let mut order = guix::dependency_order_positions(&packages)
+            .into_iter()
+            .map(|index| packaged[index].0)
+            .collect::<Vec<_>>();
+        let mut failed = (0..self.packages.len())
+            .filter(|position| self.packages[*position].is_err())
+            .collect::<Vec<_>>();
+        failed.sort_by_key(|position| {
+            let run = &self.package_runs[*position];
+            (run.name.clone(), run.version.clone())
+        });
+        order.extend(failed);
+        let mut packages = mem::take(&mut self.packages)
+            .into_iter()
+            .zip(mem::take(&mut self.package_runs))
+            .map(Some)
+            .collect::<Vec<_>>();
+        (self.packages, self.package_runs) = order
+            .into_iter()
+            .filter_map(|position| packages[position].take())
+            .unzip();
+    }
+
+    /// Freeze the packages of a run whose crates could all be packaged, see
+    /// [`FrozenPackageSet`].
+    fn freeze(&self) {
+        let frozen = FrozenPackageSet::new(
+            self.index_commit(),
+            self.packages.iter().flatten(),
+            self.left_out_crates.clone(),
+        );
+        if let Err(err) = frozen.save(&self.data_dir().join(FROZEN_FILE)) {
+            print_error(&err);
+        }
+    }
+
+    /// Warn about the packages which don't meet the quality expected from a channel: the ones
+    /// without a license Guix knows, and the ones binding a system library without any input.
+    fn check_packages(&self) {
+        for package in self.packages.iter().flatten() {
+            let crate_ref = &package.crate_ref;
+            match &package.license {
+                Some(license) => {
+                    for identifier in license::unknown_identifiers(license) {
+                        self.options.warnings.warn(
+                            WarningCode::UnknownLicense,
+                            format_args!(
+                                "no Guix license matching SPDX identifier {} of crate {} v{}",
+                                identifier, crate_ref.name, crate_ref.version
+                            ),
+                        );
+                    }
+                }
+                None => self.options.warnings.warn(
+                    WarningCode::UnknownLicense,
+                    format_args!(
+                        "crate {} v{} declares no license",
+                        crate_ref.name, crate_ref.version
+                    ),
+                ),
+            }
+            if let (Some(links), true, true) = (
+                &package.links,
+                package.inputs.is_empty(),
+                package.native_inputs.is_empty(),
+            ) {
+                self.options.warnings.warn(
+                    WarningCode::LinksWithoutInputs,
+                    format_args!(
+                        "crate {} v{} links system library {} but has no input providing it, see [system-dependency] in carguix.toml",
+                        crate_ref.name, crate_ref.version, links
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Mark the procedural macros and the packages only they depend on, which builds skip with
+    /// [`Options::skip_proc_macro_builds`]. Root packages and their other dependencies are kept
+    /// even when they are procedural macros themselves.
+    fn mark_build_only_packages(&mut self) {
+        let packages = self
+            .packages
+            .iter()
+            .flatten()
+            .map(|package| (&package.crate_ref, package))
+            .collect::<HashMap<_, _>>();
+        let inputs = packages
+            .values()
+            .flat_map(|package| {
+                package
+                    .cargo_inputs
+                    .iter()
+                    .chain(&package.cargo_development_inputs)
+            })
+            .collect::<HashSet<_>>();
+        let roots = packages
+            .keys()
+            .filter(|crate_ref| !inputs.contains(*crate_ref))
+            .map(|crate_ref| (*crate_ref).clone())
+            .collect::<HashSet<_>>();
+        let mut queue = roots.iter().cloned().collect::<VecDeque<_>>();
+        let mut runtime = HashSet::new();
+        while let Some(crate_ref) = queue.pop_front() {
+            let package = match packages.get(&crate_ref) {
+                Some(package) if !package.proc_macro || roots.contains(&crate_ref) => package,
+                _ => continue,
+            };
+            if runtime.insert(crate_ref) {
+                queue.extend(
+                    package
+                        .cargo_inputs
+                        .iter()
+                        .chain(&package.cargo_development_inputs)
+                        .cloned(),
+                );
+            }
+        }
+        for package in self.packages.iter_mut().flatten() {
+            package.build_only = !runtime.contains(&package.crate_ref);
+            package.skip_build |= package.build_only && self.options.skip_proc_macro_builds;
+        }
+    }
+
+    /// Give every package the fewest build-dependency edges on the paths reaching it from the
+    /// root packages, and apply [`Options::deep_build_dependencies`] to the ones reached through
+    /// more than [`Options::max_build_dependency_depth`]. Packages only needed to compile build
+    /// scripts have a depth of at least 1.
+    fn limit_build_dependency_depth(&mut self) {
+        let mut weights = HashMap::<(&CrateRef, &CrateRef), usize>::new();
+        for edge in self.graph.edges() {
+            let weight = usize::from(edge.kind == DependencyKind::Build);
+            let pair_weight = weights
+                .entry((&edge.dependent, &edge.dependency))
+                .or_insert(weight);
+            *pair_weight = (*pair_weight).min(weight);
+        }
+        let packages = self
+            .packages
+            .iter()
+            .flatten()
+            .map(|package| (&package.crate_ref, package))
+            .collect::<HashMap<_, _>>();
+        let roots = self.root_crates();
+        // breadth-first search where build-dependency edges weigh 1 and the other ones 0
+        let mut depths = HashMap::<CrateRef, usize>::new();
+        let mut queue = roots
+            .iter()
+            .map(|crate_ref| (crate_ref.clone(), 0))
+            .collect::<VecDeque<_>>();
+        while let Some((crate_ref, depth)) = queue.pop_front() {
+            if depths.get(&crate_ref).is_some_and(|known| *known <= depth) {
+                continue;
+            }
+            depths.insert(crate_ref.clone(), depth);
+            let package = match packages.get(&crate_ref) {
+                Some(package) => package,
+                None => continue,
+            };
+            for input in package
+                .cargo_inputs
+                .iter()
+                .chain(&package.cargo_development_inputs)
+            {
+                match weights.get(&(&crate_ref, input)).copied().unwrap_or(0) {
+                    0 => queue.push_front((input.clone(), depth)),
+                    _ => queue.push_back((input.clone(), depth + 1)),
+                }
+            }
+        }
+        let max_depth = self.options.max_build_dependency_depth;
+        let collapse = self.options.deep_build_dependencies == DeepBuildDependencies::Collapse;
+        let (mut build_only, mut deep) = (0, 0);
+        for package in self.packages.iter_mut().flatten() {
+            package.build_dependency_depth = depths.get(&package.crate_ref).copied().unwrap_or(0);
+            package.deep_build_dependency =
+                max_depth.is_some_and(|max_depth| package.build_dependency_depth > max_depth);
+            build_only += usize::from(package.build_dependency_depth > 0);
+            deep += usize::from(package.deep_build_dependency);
+            if package.deep_build_dependency && collapse {
+                package.skip_build = true;
+                package.cargo_development_inputs.clear();
+            }
+        }
+        if build_only > 0 {
+            log::info!(
+                "{} packages are only needed to compile build scripts, at a build-dependency depth of {} at most",
+                build_only,
+                depths.values().max().copied().unwrap_or(0)
+            );
+        }
+        if let Some(max_depth) = max_depth.filter(|_| deep > 0) {
+            log::info!(
+                "{} packages are reached through more than {} build-dependency edges",
+                deep,
+                max_depth
+            );
+        }
+        if collapse && deep > 0 {
+            // the packages only the dropped development inputs pulled in aren't reached anymore
+            self.drop_unreachable_packages(&HashMap::new(), roots);
+        }
+    }
+
+    /// Define the packages whose variable is reserved or derived from several crates under a
+    /// variable of their own, see [`guix::renamed_variables`], and refer to them by it in their
+    /// dependents.
+    fn rename_colliding_variables(&mut self) {
+        let renamed = guix::renamed_variables(
+            self.packages
+                .iter()
+                .flatten()
+                .map(|package| &package.crate_ref),
+        );
+        for package in self.packages.iter_mut().flatten() {
+            package.variable = renamed.get(&package.crate_ref).cloned();
+            package.renamed_inputs = package
+                .cargo_inputs
+                .iter()
+                .chain(&package.cargo_development_inputs)
+                .filter_map(|input| Some((input.clone(), renamed.get(input)?.clone())))
+                .collect();
+        }
+    }
+
+    /// Give every package the requirements of its dependencies, as found in the dependency graph.
+    fn record_input_requirements(&mut self) {
+        let mut requirements = HashMap::<&CrateRef, BTreeMap<String, Vec<String>>>::new();
+        for edge in self.graph.edges() {
+            if let Some(requirement) = &edge.requirement {
+                let input_requirements = requirements
+                    .entry(&edge.dependent)
+                    .or_default()
+                    .entry(edge.dependency.format_name_version())
+                    .or_default();
+                if !input_requirements.contains(requirement) {
+                    input_requirements.push(requirement.clone());
+                }
+            }
+        }
+        for package in self.packages.iter_mut().flatten() {
+            if let Some(input_requirements) = requirements.remove(&package.crate_ref) {
+                package.input_requirements = input_requirements;
+            }
+        }
+    }
+
+    /// Dependencies between the crates packaged so far.
+    pub fn dependency_graph(&self) -> &DependencyGraph {
+        &self.graph
+    }
+
+    pub fn record_dependency(&mut self, edge: Edge) {
+        self.graph.add_edge(edge);
+    }
+
+    /// Origin of the hashes of the crates packaged so far.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats
+    }
+
+    /// What was done for each crate, in the order of the packages [`Self::packages`] returned.
+    pub fn package_runs(&self) -> &[PackageRun] {
+        &self.package_runs
+    }
+
+    /// Crates left out of the run, which packages may still refer to for other modules to define
+    /// them, see [`Self::enqueue_dependencies`].
+    pub fn left_out_crates(&self) -> &BTreeSet<String> {
+        &self.left_out_crates
+    }
+
+    /// Database the hashes of downloaded crates are cached in.
+    pub fn hash_database(&self) -> &HashDatabase {
+        &self.hashdb
+    }
+
+    /// Every published version of a crate of crates.io, or of the alternative registry
+    /// `registry`, in publication order.
+    pub fn published_versions(
+        &self,
+        crate_name: &str,
+        registry: Option<&str>,
+    ) -> Result<Vec<PublishedVersion>, CarguixError> {
+        let crate_ = self.registry_index(registry)?.crate_(crate_name)?;
+        let hash_name = match registry {
+            Some(registry) => format!("{}/{}", registry, crate_.name()),
+            None => crate_.name().to_string(),
+        };
+        crate_
+            .versions()
+            .iter()
+            .map(|crate_version| {
+                let version = crate_version.version().to_string();
+                Ok(PublishedVersion {
+                    hash: self
+                        .hashdb
+                        .get(&(hash_name.clone(), version.clone()), HashMethod::Tarball)?,
+                    version,
+                    yanked: crate_version.is_yanked(),
+                })
+            })
+            .collect()
+    }
+
+    /// Hashes of fresh downloads of the crates of hash database entries, in the same order, to
+    /// be compared with the cached ones.
+    pub fn verify_hashes(&self, entries: &[HashEntry]) -> Vec<Result<String, CarguixError>> {
+        let crates = entries
+            .iter()
+            .map(|entry| self.entry_download(entry))
+            .collect::<Vec<_>>();
+        let downloadable = crates
+            .iter()
+            .filter_map(|crate_| crate_.as_ref().ok().cloned())
+            .collect::<Vec<_>>();
+        let downloader = Downloader::new(
+            &self.hashdb,
+            self.tmpdir.path(),
+            &self.options,
+            &self.registries,
+        );
+        let mut hashes = downloader.downloaded_hashes(&downloadable).into_iter();
+        crates
+            .into_iter()
+            .map(|crate_| match crate_ {
+                Ok(_) => hashes.next().expect("every crate has been hashed"),
+                Err(err) => Err(err),
+            })
+            .collect()
+    }
+
+    /// Highest version of a crate of crates.io semver-compatible with the given one, the one
+    /// `cargo update` would pick.
+    pub fn compatible_update(&self, crate_ref: &CrateRef) -> Result<CrateRef, CarguixError> {
+        RegistrySource::highest_matching_crate_version(
+            self.registry_index(None)?,
+            &crate_ref.name,
+            &format!("^{}", crate_ref.version),
+            &self.options,
+        )
+    }
+
+    /// Hashes of crates of crates.io, in the same order, converted from the checksums of the
+    /// `Cargo.lock` files read when locked there, computed like the ones of packages otherwise or
+    /// with `options.verify_checksums`.
+    pub fn crates_io_hashes(&mut self, crates: &[CrateRef]) -> Vec<Result<String, CarguixError>> {
+        let verified = self.options.verify_checksums;
+        let pending = crates
+            .iter()
+            .filter(|crate_ref| {
+                verified
+                    || !self
+                        .locked_hashes
+                        .contains_key(&(None, (*crate_ref).clone()))
+            })
+            .map(|crate_ref| (crate_ref.clone(), None))
+            .collect::<Vec<_>>();
+        let checksums = pending
+            .iter()
+            .filter_map(|(crate_ref, _)| {
+                let checksum = self.expected_checksum(None, crate_ref)?;
+                Some(((None, crate_ref.clone()), checksum))
+            })
+            .collect();
+        let downloader = Downloader::new(
+            &self.hashdb,
+            self.tmpdir.path(),
+            &self.options,
+            &self.registries,
+        )
+        .with_checksums(checksums);
+        let mut hashes = downloader.crate_hashes(&pending).into_iter();
+        let downloader_stats = downloader.cache_stats();
+        self.cache_stats.cached += downloader_stats.cached;
+        self.cache_stats.mirrored += downloader_stats.mirrored;
+        self.cache_stats.stored += downloader_stats.stored;
+        self.cache_stats.downloaded += downloader_stats.downloaded;
+        if let Err(err) = self.hashdb.flush() {
+            print_error(&err);
+        }
+        crates
+            .iter()
+            .map(
+                |crate_ref| match self.locked_hashes.get(&(None, crate_ref.clone())) {
+                    Some(locked_hash) if !verified => {
+                        self.cache_stats.checksummed += 1;
+                        Ok(locked_hash.clone())
+                    }
+                    _ => hashes.next().expect("every pending crate has been hashed"),
+                },
+            )
+            .collect()
+    }
+
+    /// Hash the tarball of a crate must have, converted from its checksum in the `Cargo.lock`
+    /// files read or, failing that, in the index of its registry.
+    fn expected_checksum(&self, registry: Option<&str>, crate_ref: &CrateRef) -> Option<String> {
+        let locked_hash = self
+            .locked_hashes
+            .get(&(registry.map(str::to_string), crate_ref.clone()));
+        if let Some(locked_hash) = locked_hash {
+            return Some(locked_hash.clone());
+        }
+        let crate_ = self
+            .registry_index(registry)
+            .and_then(|index| index.crate_(&crate_ref.name))
+            .ok()?;
+        let crate_version = crate_
+            .versions()
+            .iter()
+            .find(|crate_version| crate_version.version() == crate_ref.version)?;
+        nix_hash::from_hex(crate_version.checksum())
+    }
+
+    /// Crate of a hash database entry, along with where it's downloaded from when it comes from
+    /// an alternative registry.
+    fn entry_download(
+        &self,
+        entry: &HashEntry,
+    ) -> Result<(CrateRef, Option<RegistryDownload>), CarguixError> {
+        let (registry, crate_name) = match entry.name.split_once('/') {
+            Some((registry, crate_name)) => (registry, crate_name),
+            None => return Ok((CrateRef::new(&entry.name, &entry.version), None)),
+        };
+        let crate_ = self.registry_index(Some(registry))?.crate_(crate_name)?;
+        let crate_version = crate_
+            .versions()
+            .iter()
+            .find(|crate_version| crate_version.version() == entry.version)
+            .ok_or_else(|| CarguixError::NoMatchingVersion {
+                name: crate_name.to_string(),
+                version: entry.version.clone(),
+            })?;
+        let registry_download = RegistryDownload {
+            registry: registry.to_string(),
+            url: self.registries.get(registry)?.download_url(
+                crate_name,
+                &entry.version,
+                crate_version.checksum(),
+            )?,
+        };
+        Ok((
+            CrateRef::new(crate_name, &entry.version),
+            Some(registry_download),
+        ))
+    }
+
+    /// Number of crates referenced from Guix instead of being defined.
+    pub fn existing_package_count(&self) -> usize {
+        self.existing_packages.len()
+    }
+
+    /// Directory of the index clone, the hash database, the run state and the git checkouts,
+    /// resolved by [`Generator::with_options`].
+    fn data_dir(&self) -> &Path {
+        self.options.data_dir.as_deref().unwrap_or(Path::new("."))
+    }
+
+    /// Source of the local crate at `path`, whose manifest is parsed once however many crates
+    /// depend on it.
+    pub fn path_source(
+        &mut self,
+        path: &Path,
+        workspace: Option<Arc<Workspace>>,
+        features: &Features,
+    ) -> Result<PathSource, CarguixError> {
+        PathSource::cached(path, workspace, features, &mut self.manifests)
+    }
+
+    /// Directory of a vendored crate, in offline mode.
+    pub fn vendored_crate_path(&self, crate_ref: &CrateRef) -> Option<PathBuf> {
+        Some(self.vendored.as_ref()?.crate_path(crate_ref))
+    }
+
+    /// Whether a crate lives in the vendor directory, its dev-dependencies being left out of
+    /// `Cargo.lock` then.
+    pub fn is_vendored(&self, path: &Path) -> bool {
+        self.vendored
+            .as_ref()
+            .is_some_and(|vendored| vendored.contains(path))
+    }
+
+    /// Versions of a package defined in the Guix checkout, whether a requirement matches them or
+    /// not.
+    pub fn guix_versions(&self, package_name: &str) -> Vec<String> {
+        self.guix_packages
+            .versions(package_name)
+            .iter()
+            .map(|package| package.version.clone())
+            .collect()
+    }
+
+    /// Whether a crate is packaged in Guix already, and must not be defined again.
+    pub fn is_existing(&self, crate_ref: &CrateRef) -> bool {
+        self.existing_packages.contains_key(crate_ref)
+    }
+
+    /// Key of a source, or the key of the root crate requested without a version, or with a
+    /// requirement, it is the resolved version of, so that a root crate reached again as a
+    /// dependency is packaged once.
+    fn processed_key(&self, source: &dyn Source) -> (String, Option<String>) {
+        let crate_key = source.crate_key();
+        let (name, version) = match &crate_key {
+            (name, Some(version)) => (name, version),
+            _ => return crate_key,
+        };
+        let mut root_keys = self
+            .roots
+            .iter()
+            .filter(|(root_name, root_version)| root_name == name && root_version.is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        root_keys.sort();
+        let state = source.state();
+        std::iter::once((name.clone(), None))
+            .chain(root_keys)
+            .find(|root_key| {
+                root_key != &crate_key
+                    && matches!(
+                        self.resolved_crates.get(root_key),
+                        Some((root_state, position))
+                            if merge::same_origin(root_state, &state)
+                                && matches!(
+                                    &self.packages[*position],
+                                    Ok(package) if package.crate_ref.version == *version
+                                )
+                    )
+            })
+            .unwrap_or(crate_key)
+    }
+
+    /// Package a crate, unless it has already been packaged with every feature it requires.
+    ///
+    /// Features are unified like cargo does: a crate reached again with additional features is
+    /// resolved once more with the union of both selections and its previous package replaced.
+    pub fn process_crate(&mut self, mut source: Box<dyn Source>) {
+        let crate_key = self.processed_key(source.as_ref());
+        let features = self
+            .options
+            .config
+            .features(&crate_key.0, crate_key.1.as_deref());
+        if !features.is_empty() {
+            source.merge_features(&Features::new(&features, false, false));
+        }
+        if self.projects.len() > 1 {
+            let state = source.state();
+            if let Some(first_request) = self.requests.record(&crate_key, self.project, &state) {
+                // The first project requiring the crate from another origin keeps it
+                if self.options.merge_strategy == Some(MergeStrategy::First)
+                    || !merge::same_origin(first_request, &state)
+                {
+                    return;
+                }
+            }
+        }
+        let position = match self.resolved_crates.get(&crate_key) {
+            Some((state, _)) if state.features().contains(source.features()) => return,
+            Some((state, position)) => {
+                source.merge_features(state.features());
+                *position
+            }
+            None => {
+                self.packages.push(Err(CarguixError::CratePackagingFailed {
+                    name: crate_key.0.clone(),
+                    version: crate_key.1.clone(),
+                }));
+                self.package_runs
+                    .push(PackageRun::new(&crate_key.0, crate_key.1.as_deref()));
+                self.packages.len() - 1
+            }
+        };
+        let is_root = self.roots.contains(&crate_key);
+        let depth = self.depths.get(&crate_key).copied().unwrap_or(0);
+        self.resolved_crates
+            .insert(crate_key, (source.state(), position));
+        let started = Instant::now();
+        self.packages[position] = source.resolve(self).map(|(package, dependencies)| {
+            self.enqueue_dependencies(dependencies, depth + 1);
+            self.graph.add_node(&package.crate_ref);
+            let mirrors = self
+                .options
+                .mirror_uris(&package.crate_ref.name, &package.crate_ref.version);
+            let build_system = self
+                .options
+                .config
+                .build_system(&package.crate_ref.name, &package.crate_ref.version);
+            let proc_macro = match package.origin {
+                Origin::Crate { .. } => self.options.config.is_proc_macro(&package.crate_ref.name),
+                _ => package.proc_macro,
+            };
+            let system_dependency = self.options.config.system_dependency(
+                &package.crate_ref.name,
+                &package.crate_ref.version,
+                package.links.as_deref(),
+            );
+            let package = package
+                .with_mirrors(mirrors, self.options.mirror.is_none())
+                .with_build_system(build_system)
+                .with_proc_macro(proc_macro)
+                .with_system_dependency(system_dependency)
+                .with_update_properties(self.options.update_properties)
+                .with_input_style(self.options.input_style);
+            if is_root {
+                package
+                    .with_inputs(&self.options.config.inputs)
+                    .with_install(&self.options.config.install)
+                    .with_search_paths(
+                        &self.options.config.native_search_paths,
+                        &self.options.config.search_paths,
+                    )
+            } else {
+                package.with_skip_build(self.options.skip_dependency_builds)
+            }
+        });
+        self.package_runs[position].duration += started.elapsed();
+    }
+
+    /// Enqueue the dependencies of a crate, which are at `depth` below the roots, leaving out the
+    /// ones [`Options::max_depth`], [`Options::excluded_crates`] or
+    /// [`Options::defined_variables`] prune. Packages still refer to those.
+    fn enqueue_dependencies(&mut self, dependencies: Vec<Box<dyn Source>>, depth: usize) {
+        if self
+            .options
+            .max_depth
+            .is_some_and(|max_depth| depth > max_depth)
+        {
+            self.left_out_crates.extend(
+                dependencies
+                    .iter()
+                    .map(|dependency| dependency.crate_key().0),
+            );
+            return;
+        }
+        for dependency in dependencies {
+            let crate_key = dependency.crate_key();
+            let (name, version) = &crate_key;
+            let defined = version.as_ref().is_some_and(|version| {
+                let variable = CrateRef::new(name, version).format_name_version();
+                self.options.defined_variables.contains(&variable)
+            });
+            if defined || self.options.excluded_crates.contains(name) {
+                self.left_out_crates.insert(name.clone());
+                continue;
+            }
+            let known_depth = self.depths.entry(crate_key).or_insert(depth);
+            *known_depth = (*known_depth).min(depth);
+            self.crates.push_back(dependency);
+        }
+    }
+
+    /// Replace the packages of the crates the merged projects require differently with errors
+    /// describing how each project requires them, unless a merge strategy resolves them.
+    fn report_merge_conflicts(&mut self) {
+        for conflict in self.requests.conflicts(&self.projects) {
+            let (name, version) = conflict.crate_key;
+            let provenances = conflict.provenances.join("; ");
+            if let Some(merge_strategy) = self.options.merge_strategy {
+                log::warn!(
+                    "version {:?} of crate {} packaged with merge strategy {:?}: {}",
+                    version,
+                    name,
+                    merge_strategy,
+                    provenances
+                );
+                continue;
+            }
+            let crate_key = (name.clone(), version.clone());
+            if let Some((_, position)) = self.resolved_crates.get(&crate_key) {
+                self.packages[*position] = Err(CarguixError::MergeConflict {
+                    name,
+                    version,
+                    provenances,
+                });
+            }
+        }
+    }
+
+    /// Break the cycles of the package inputs, which Guix can't evaluate, visiting the packages
+    /// in order so that the same cycles are broken every run. A cycle is broken by dropping its
+    /// last development input, the back-edge from a crate its dependents test with. Cycles of
+    /// regular inputs only can't be broken: the packages they go through are replaced with an
+    /// error naming the chain.
+    fn break_dependency_cycles(&mut self) {
+        while let Some(cycle) = self.dependency_cycle() {
+            // the last input of the cycle is the first dependent
+            let chain = cycle
+                .last()
+                .into_iter()
+                .chain(&cycle)
+                .map(|(_, input, _)| format!("{} v{}", input.name, input.version))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            match cycle.iter().rev().find(|(_, _, development)| *development) {
+                Some((position, input, _)) => {
+                    if let Ok(package) = &mut self.packages[*position] {
+                        self.options.warnings.warn(
+                            WarningCode::DependencyCycle,
+                            format_args!(
+                                "development input {} v{} of crate {} v{} left out, breaking the dependency cycle {}",
+                                input.name,
+                                input.version,
+                                package.crate_ref.name,
+                                package.crate_ref.version,
+                                chain
+                            ),
+                        );
+                        package
+                            .cargo_development_inputs
+                            .retain(|development_input| development_input != input);
+                    }
+                }
+                None => {
+                    for (position, _, _) in cycle {
+                        self.packages[position] = Err(CarguixError::DependencyCycle(chain.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// First cycle of the package inputs, as the inputs it goes through: the position of the
+    /// dependent, the input and whether it is a development input.
+    fn dependency_cycle(&self) -> Option<Vec<(usize, CrateRef, bool)>> {
+        fn visit(
+            position: usize,
+            packages: &[Result<guix::Package, CarguixError>],
+            positions: &HashMap<&CrateRef, usize>,
+            done: &mut HashSet<usize>,
+            path: &mut Vec<(usize, CrateRef, bool)>,
+        ) -> Option<Vec<(usize, CrateRef, bool)>> {
+            if done.contains(&position) {
+                return None;
+            }
+            let package = packages[position].as_ref().ok()?;
+            let inputs = package
+                .cargo_inputs
+                .iter()
+                .map(|input| (input, false))
+                .chain(
+                    package
+                        .cargo_development_inputs
+                        .iter()
+                        .map(|input| (input, true)),
+                );
+            for (input, development) in inputs {
+                let input_position = match positions.get(input) {
+                    Some(&input_position) => input_position,
+                    None => continue,
+                };
+                path.push((position, input.clone(), development));
+                if let Some(start) = path
+                    .iter()
+                    .position(|(dependent, _, _)| *dependent == input_position)
+                {
+                    return Some(path.split_off(start));
+                }
+                if let Some(cycle) = visit(input_position, packages, positions, done, path) {
+                    return Some(cycle);
+                }
+                path.pop();
+            }
+            done.insert(position);
+            None
+        }
+
+        let positions = self
+            .packages
+            .iter()
+            .enumerate()
+            .filter_map(|(position, package)| Some((&package.as_ref().ok()?.crate_ref, position)))
+            .collect::<HashMap<_, _>>();
+        let mut done = HashSet::new();
+        (0..self.packages.len()).find_map(|position| {
+            visit(
+                position,
+                &self.packages,
+                &positions,
+                &mut done,
+                &mut Vec::new(),
+            )
+        })
+    }
+
+    /// Warn about foundational crates packaged at several incompatible versions, naming the
+    /// dependents which require the older ones.
+    fn report_duplicate_majors(&self) {
+        let crate_refs = self
+            .packages
+            .iter()
+            .flatten()
+            .map(|package| &package.crate_ref)
+            .chain(self.existing_packages.keys());
+        let mut versions = BTreeMap::<&str, BTreeMap<_, Vec<&CrateRef>>>::new();
+        for crate_ref in crate_refs {
+            if !FOUNDATIONAL_CRATES.contains(&crate_ref.name.as_str()) {
+                continue;
+            }
+            if let Ok(version) = semver::Version::parse(&crate_ref.version) {
+                versions
+                    .entry(crate_ref.name.as_str())
+                    .or_default()
+                    .entry(version::compatibility_range(&version))
+                    .or_default()
+                    .push(crate_ref);
+            }
+        }
+        for (name, ranges) in versions {
+            if ranges.len() < 2 {
+                continue;
+            }
+            let packaged_versions = ranges
+                .values()
+                .flatten()
+                .map(|crate_ref| crate_ref.version.as_str())
+                .collect::<Vec<_>>();
+            let older_versions = ranges.values().rev().skip(1).flatten();
+            let dependents = older_versions
+                .map(|crate_ref| {
+                    let dependents = self
+                        .graph
+                        .edges()
+                        .filter(|edge| &edge.dependency == *crate_ref)
+                        .map(|edge| {
+                            format!(
+                                "{} v{} ({})",
+                                edge.dependent.name,
+                                edge.dependent.version,
+                                edge.requirement.as_deref().unwrap_or("path")
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    format!(
+                        "v{} is required by {}",
+                        crate_ref.version,
+                        dependents.join(", ")
+                    )
+                })
+                .collect::<Vec<_>>();
+            self.options.warnings.warn(
+                WarningCode::DuplicateMajor,
+                format_args!(
+                    "crate {} is packaged at incompatible versions {}, updating the dependents of the older ones would leave a single copy in the closure: {}",
+                    name,
+                    packaged_versions.join(", "),
+                    dependents.join("; ")
+                ),
+            );
+        }
+    }
+
+    /// Replace the versions of registry crates by a semver-compatible version packaged already
+    /// where possible, see [`unify::unified_versions`], leaving out the packages nothing depends
+    /// on anymore.
+    ///
+    /// The features of the replaced versions are enabled on their replacements, which are
+    /// packaged again when that enables new features.
+    fn unify_versions(&mut self) {
+        let mut merged_features = HashSet::new();
+        let replacements = loop {
+            let replacements = self.version_replacements();
+            let mut merged = false;
+            for (replaced, replacement) in replacements.values() {
+                let (replaced_state, _) = &self.resolved_crates[replaced];
+                let (replacement_state, _) = &self.resolved_crates[replacement];
+                if replacement_state
+                    .features()
+                    .contains(replaced_state.features())
+                    || !merged_features.insert((replaced.clone(), replacement.clone()))
+                {
+                    continue;
+                }
+                let registry = match replacement_state {
+                    SourceState::Registry { registry, .. } => registry.clone(),
+                    SourceState::Path { .. } => None,
+                };
+                let source =
+                    RegistrySource::new(&replacement.0, &replacement.1, replaced_state.features())
+                        .with_registry(registry);
+                self.crates.push_back(Box::new(source));
+                merged = true;
+            }
+            if !merged {
+                break replacements
+                    .into_iter()
+                    .map(|(replaced, (_, replacement))| {
+                        let (_, position) = self.resolved_crates[&replacement];
+                        let replacement = self.packages[position]
+                            .as_ref()
+                            .map(|package| package.crate_ref.clone());
+                        (replaced, replacement)
+                    })
+                    .filter_map(|(replaced, replacement)| Some((replaced, replacement.ok()?)))
+                    .collect::<HashMap<_, _>>();
+            }
+            while let Some(source) = self.crates.pop_front() {
+                self.process_crate(source);
+            }
+        };
+        if replacements.is_empty() {
+            return;
+        }
+        for (replaced, replacement) in &replacements {
+            log::info!(
+                "packaging crate {} v{} instead of v{}",
+                replaced.name,
+                replacement.version,
+                replaced.version
+            );
+            self.lock
+                .repin(&replaced.name, &replaced.version, &replacement.version);
+        }
+        let roots = self.root_crates();
+        self.drop_unreachable_packages(&replacements, roots);
+    }
+
+    /// Crates the other packages don't depend on, the roots the others are reached from.
+    fn root_crates(&self) -> Vec<CrateRef> {
+        let inputs = self
+            .packages
+            .iter()
+            .flatten()
+            .flat_map(|package| {
+                package
+                    .cargo_inputs
+                    .iter()
+                    .chain(&package.cargo_development_inputs)
+            })
+            .collect::<HashSet<_>>();
+        self.packages
+            .iter()
+            .flatten()
+            .map(|package| &package.crate_ref)
+            .filter(|crate_ref| !inputs.contains(crate_ref))
+            .cloned()
+            .collect()
+    }
+
+    /// Point the inputs on the replaced crates to their replacements, leaving out the packages
+    /// `roots`, found by [`Self::root_crates`] beforehand, don't depend on anymore, along with
+    /// the failed packages of the replaced crates.
+    fn drop_unreachable_packages(
+        &mut self,
+        replacements: &HashMap<CrateRef, CrateRef>,
+        roots: Vec<CrateRef>,
+    ) {
+        let mut queue = VecDeque::from(roots);
+        for package in self.packages.iter_mut().flatten() {
+            for inputs in [
+                &mut package.cargo_inputs,
+                &mut package.cargo_development_inputs,
+            ] {
+                for input in inputs.iter_mut() {
+                    if let Some(replacement) = replacements.get(input) {
+                        *input = replacement.clone();
+                    }
+                }
+                inputs.sort();
+                inputs.dedup();
+            }
+        }
+        let packages = self
+            .packages
+            .iter()
+            .flatten()
+            .map(|package| (&package.crate_ref, package))
+            .collect::<HashMap<_, _>>();
+        let mut kept = HashSet::new();
+        while let Some(crate_ref) = queue.pop_front() {
+            if replacements.contains_key(&crate_ref) || !kept.insert(crate_ref.clone()) {
+                continue;
+            }
+            if let Some(package) = packages.get(&crate_ref) {
+                queue.extend(
+                    package
+                        .cargo_inputs
+                        .iter()
+                        .chain(&package.cargo_development_inputs)
+                        .cloned(),
+                );
+            }
+        }
+        // Inputs packaged in Guix aren't packages of the run but are still depended on
+        kept.extend(self.existing_packages.keys().cloned());
+        (self.packages, self.package_runs) = std::mem::take(&mut self.packages)
+            .into_iter()
+            .zip(std::mem::take(&mut self.package_runs))
+            .filter(|(package, run)| match package {
+                Ok(package) => kept.contains(&package.crate_ref),
+                Err(_) => !replacements.keys().any(|replaced| {
+                    replaced.name == run.name && run.version.as_deref() == Some(&replaced.version)
+                }),
+            })
+            .unzip();
+        self.graph.replace_nodes(replacements, &kept);
+    }
+
+    /// Versions of registry crates packaged successfully which [`unify::unified_versions`]
+    /// replaces, along with the keys of the replaced crate and of its replacement.
+    #[allow(clippy::type_complexity)]
+    fn version_replacements(
+        &self,
+    ) -> HashMap<CrateRef, ((String, Option<String>), (String, Option<String>))> {
+        let mut crate_keys = HashMap::new();
+        let candidates = self
+            .resolved_crates
+            .iter()
+            .filter_map(|(crate_key, (state, position))| {
+                let registry = match state {
+                    SourceState::Registry { registry, .. } => registry.as_deref(),
+                    SourceState::Path { .. } => return None,
+                };
+                let crate_ref = &self.packages[*position].as_ref().ok()?.crate_ref;
+                crate_keys.insert(crate_ref, crate_key);
+                Some(unify::Candidate {
+                    crate_ref,
+                    registry,
+                    root: self.roots.contains(crate_key),
+                })
+            })
+            .collect::<Vec<_>>();
+        unify::unified_versions(&candidates, self.graph.edges())
+            .into_iter()
+            .map(|(replaced, replacement)| {
+                let crate_keys = (
+                    crate_keys[&replaced].clone(),
+                    crate_keys[&replacement].clone(),
+                );
+                (replaced, crate_keys)
+            })
+            .collect()
+    }
+
+    /// Fill in the hashes of the crates packaged from crates.io, downloading the ones neither
+    /// pinned nor cached concurrently, and return the crates.io crates whose tarball is missing
+    /// from the server. Hashed packages before position `from` are left as they are.
+    fn hash_crates(&mut self, from: usize) -> Vec<CrateRef> {
+        let mut pending = Vec::new();
+        for (position, package) in self.packages.iter_mut().enumerate() {
+            if let Ok(guix::Package {
+                crate_ref,
+                origin: Origin::Crate { hash, registry, .. },
+                ..
+            }) = package
+            {
+                if position < from && hash.is_some() {
+                    continue;
+                }
+                let refreshed = self.options.verify_checksums
+                    || self.options.refresh_hashes.contains(&crate_ref.name);
+                let registry_name = registry.as_ref().map(|registry| registry.registry.clone());
+                let locked_hash = self.locked_hashes.get(&(registry_name, crate_ref.clone()));
+                match (
+                    self.pinned.hash(&crate_ref.name, &crate_ref.version),
+                    locked_hash,
+                ) {
+                    // hashes are computed from the stored tarballs, missing ones being downloaded
+                    _ if self.options.tarball_dir.is_some() => {
+                        pending.push((position, (crate_ref.clone(), registry.clone())))
+                    }
+                    (Some(pinned_hash), _) if !refreshed => {
+                        *hash = Some(pinned_hash.to_string());
+                        self.cache_stats.pinned += 1;
+                        self.package_runs[position].hash_source = Some(HashSource::Pinned);
+                    }
+                    (None, Some(locked_hash)) if !refreshed && hash.is_none() => {
+                        *hash = Some(locked_hash.clone());
+                        self.cache_stats.checksummed += 1;
+                        self.package_runs[position].hash_source = Some(HashSource::Checksummed);
+                    }
+                    _ if hash.is_none() => {
+                        pending.push((position, (crate_ref.clone(), registry.clone())))
+                    }
+                    _ => (),
+                }
+            }
+        }
+        let crates = pending
+            .iter()
+            .map(|(_, crate_)| crate_.clone())
+            .collect::<Vec<_>>();
+        let checksums = crates
+            .iter()
+            .filter_map(|(crate_ref, registry)| {
+                let registry_name = registry.as_ref().map(|registry| registry.registry.clone());
+                let checksum = self.expected_checksum(registry_name.as_deref(), crate_ref)?;
+                Some(((registry_name, crate_ref.clone()), checksum))
+            })
+            .collect();
+        let downloader = Downloader::new(
+            &self.hashdb,
+            self.tmpdir.path(),
+            &self.options,
+            &self.registries,
+        )
+        .with_checksums(checksums);
+        let hashes = downloader.crate_hashes(&crates);
+        let downloader_stats = downloader.cache_stats();
+        self.cache_stats.cached += downloader_stats.cached;
+        self.cache_stats.mirrored += downloader_stats.mirrored;
+        self.cache_stats.stored += downloader_stats.stored;
+        self.cache_stats.downloaded += downloader_stats.downloaded;
+        let hash_runs = downloader.hash_runs();
+        for (position, (crate_ref, registry)) in &pending {
+            let registry_name = registry.as_ref().map(|registry| registry.registry.clone());
+            if let Some((hash_source, duration)) =
+                hash_runs.get(&(registry_name, crate_ref.clone()))
+            {
+                let package_run = &mut self.package_runs[*position];
+                package_run.hash_source = *hash_source;
+                package_run.duration += *duration;
+            }
+        }
+        let mut missing = Vec::new();
+        for ((position, (crate_ref, registry)), hash) in pending.into_iter().zip(hashes) {
+            match (&mut self.packages[position], hash) {
+                (
+                    Ok(guix::Package {
+                        origin: Origin::Crate { hash, .. },
+                        ..
+                    }),
+                    Ok(computed_hash),
+                ) => *hash = Some(computed_hash),
+                (package, Err(err)) => {
+                    if registry.is_none() && download::is_missing_tarball(&err) {
+                        missing.push(crate_ref);
+                    }
+                    *package = Err(err);
+                }
+                _ => (),
+            }
+        }
+        if let Err(err) = self.hashdb.flush() {
+            print_error(&err);
+        }
+        for package in self.packages.iter_mut().flatten() {
+            if let Origin::Crate {
+                hash: Some(hash),
+                registry,
+                ..
+            } = &package.origin
+            {
+                self.lock
+                    .record_hash(&package.crate_ref.name, &package.crate_ref.version, hash);
+                let registry_name = registry.as_ref().map(|registry| registry.registry.as_str());
+                if let Some(uri) = self.options.tarball_uri(
+                    &package.crate_ref.name,
+                    &package.crate_ref.version,
+                    registry_name,
+                ) {
+                    package.origin.fetch_from(uri);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Package the next-highest version matching the requirements on the crates.io crates whose
+    /// tarball is missing from the server, yanked server-side for instance, instead of failing
+    /// their dependents. Versions the lock file pins and crates resolved offline are kept.
+    fn replace_missing_tarballs(&mut self, mut missing: Vec<CrateRef>) {
+        if missing.is_empty() {
+            return;
+        }
+        // Positions are stale once versions are unified, the crates being matched by their runs
+        let positions = self
+            .package_runs
+            .iter()
+            .enumerate()
+            .map(|(position, run)| ((run.name.clone(), run.version.clone()), position))
+            .collect::<HashMap<_, _>>();
+        self.resolved_crates = mem::take(&mut self.resolved_crates)
+            .into_iter()
+            .filter_map(|(crate_key, (state, _))| {
+                let position = *positions.get(&crate_key)?;
+                Some((crate_key, (state, position)))
+            })
+            .collect();
+        let mut replacements = HashMap::<CrateRef, CrateRef>::new();
+        while !missing.is_empty() {
+            let from = self.packages.len();
+            for crate_ref in mem::take(&mut missing) {
+                let (replacement, source) = match self.missing_tarball_replacement(&crate_ref) {
+                    Some(replacement) => replacement,
+                    None => continue,
+                };
+                self.options.warnings.warn(
+                    WarningCode::MissingTarball,
+                    format_args!(
+                        "tarball of crate {} v{} is missing from the server, packaging v{} instead",
+                        crate_ref.name, crate_ref.version, replacement.version
+                    ),
+                );
+                let crate_key = (crate_ref.name.clone(), Some(crate_ref.version.clone()));
+                if let Some(depth) = self.depths.get(&crate_key).copied() {
+                    self.depths.insert(source.crate_key(), depth);
+                }
+                self.lock
+                    .repin(&crate_ref.name, &crate_ref.version, &replacement.version);
+                for previous in replacements.values_mut() {
+                    if *previous == crate_ref {
+                        *previous = replacement.clone();
+                    }
+                }
+                replacements.insert(crate_ref, replacement);
+                self.crates.push_back(source);
+            }
+            while let Some(source) = self.crates.pop_front() {
+                self.process_crate(source);
+            }
+            missing = self.hash_crates(from);
+        }
+        if !replacements.is_empty() {
+            let roots = self.root_crates();
+            self.drop_unreachable_packages(&replacements, roots);
+        }
+    }
+
+    /// Version to package instead of a crates.io crate whose tarball is missing, the highest one
+    /// below it matching the requirements of its dependents, along with its source.
+    fn missing_tarball_replacement(
+        &self,
+        crate_ref: &CrateRef,
+    ) -> Option<(CrateRef, Box<dyn Source>)> {
+        let crate_key = (crate_ref.name.clone(), Some(crate_ref.version.clone()));
+        if self.options.offline
+            || self.roots.contains(&crate_key)
+            || self.pinned.pins(&crate_ref.name, &crate_ref.version)
+        {
+            return None;
+        }
+        let (state, _) = self.resolved_crates.get(&crate_key)?;
+        let mut requirements = self
+            .graph
+            .edges()
+            .filter(|edge| edge.dependency == *crate_ref)
+            .filter_map(|edge| edge.requirement.clone())
+            .filter(|requirement| requirement.trim() != "*")
+            .collect::<Vec<_>>();
+        requirements.sort();
+        requirements.dedup();
+        requirements.push(format!("<{}", crate_ref.version));
+        let requirement = requirements.join(", ");
+        let replacement = self.registry_index(None).and_then(|registry| {
+            RegistrySource::highest_matching_crate_version(
+                registry,
+                &crate_ref.name,
+                &requirement,
+                &self.options,
+            )
+        });
+        match replacement {
+            Ok(replacement) => {
+                let source = RegistrySource::new(
+                    &replacement.name,
+                    &Some(replacement.version.clone()),
+                    state.features(),
+                );
+                Some((replacement, Box::new(source)))
+            }
+            Err(err) => {
+                log::warn!(
+                    "tarball of crate {} v{} is missing from the server and no other version matches {}: {}",
+                    crate_ref.name,
+                    crate_ref.version,
+                    requirement,
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Descriptive metadata of a crate, out of the enrichers selected by
+    /// [`Options::enrichers`].
+    pub fn crate_metadata(&mut self, target: &MetadataTarget) -> CrateMetadata {
+        enrichers::enrich(&mut self.enrichers, target)
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{crate_ref::CrateRef, source::DependencyKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Dependency of a package on another one, as declared in its manifest.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Edge {
+    pub dependent: CrateRef,
+    pub dependency: CrateRef,
+    /// Version requirement, `None` for path dependencies.
+    pub requirement: Option<String>,
+    pub kind: DependencyKind,
+    /// Name the dependent knows the dependency as, when renamed with `package = "..."`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+}
+
+/// Resolved dependency graph, explaining why each crate was pulled in.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DependencyGraph {
+    nodes: BTreeSet<CrateRef>,
+    edges: BTreeSet<Edge>,
+}
+
+impl DependencyGraph {
+    pub fn add_node(&mut self, crate_ref: &CrateRef) {
+        self.nodes.insert(crate_ref.clone());
+    }
+
+    pub fn add_edge(&mut self, edge: Edge) {
+        self.nodes.insert(edge.dependent.clone());
+        self.nodes.insert(edge.dependency.clone());
+        self.edges.insert(edge);
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter()
+    }
+
+    /// Point the edges to the crates of `replacements` to their replacements instead, then
+    /// forget the crates `kept` leaves out.
+    pub fn replace_nodes(
+        &mut self,
+        replacements: &HashMap<CrateRef, CrateRef>,
+        kept: &HashSet<CrateRef>,
+    ) {
+        self.edges = std::mem::take(&mut self.edges)
+            .into_iter()
+            .map(|edge| Edge {
+                dependency: replacements
+                    .get(&edge.dependency)
+                    .cloned()
+                    .unwrap_or(edge.dependency),
+                ..edge
+            })
+            .filter(|edge| kept.contains(&edge.dependent) && kept.contains(&edge.dependency))
+            .collect();
+        self.nodes.retain(|node| kept.contains(node));
+    }
+
+    /// Graphviz representation, nodes being named after the package variables and edges
+    /// labeled with the requirement and kind of the dependency, along with its alias if renamed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n");
+        for node in &self.nodes {
+            dot.push_str(&format!("    \"{}\";\n", node.format_name_version()));
+        }
+        for edge in &self.edges {
+            let alias = edge
+                .alias
+                .as_ref()
+                .map(|alias| format!(" as {}", alias))
+                .unwrap_or_default();
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{} ({}){}\"];\n",
+                edge.dependent.format_name_version(),
+                edge.dependency.format_name_version(),
+                edge.requirement.as_deref().unwrap_or("path"),
+                edge.kind.as_str(),
+                alias
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
@@ -0,0 +1,1934 @@
+use crate::{
+    config::{
+        BuildSystem, Input, InputKind, Install, SearchPath, SystemDependency, COPY_BUILD_SYSTEM,
+    },
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    guix_packages::ExistingPackage,
+    license,
+    metadata::{self, CrateMetadata},
+    source::DependencyKind,
+    template::{FieldData, InputData, ModuleData, ModulePackageData, PackageData, Templates},
+};
+use lexpr::sexp;
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+};
+
+/// Alternative registry a crate tarball is fetched from instead of crates.io.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryDownload {
+    /// Name of the registry in cargo configuration.
+    pub registry: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Origin {
+    /// Crate tarball fetched from crates.io with its Guix hash, unknown until it's downloaded,
+    /// or from the mirrors when crates.io fails, unless it comes from an alternative registry.
+    Crate {
+        hash: Option<String>,
+        /// Whether crates.io is tried before the mirrors, `false` when a mirror replaces it.
+        crates_io: bool,
+        mirrors: Vec<String>,
+        registry: Option<RegistryDownload>,
+    },
+    /// Commit of a git repository containing the crate, such as a crate overridden by a
+    /// `[patch]` section, with the Guix hash of the checkout.
+    Git {
+        url: String,
+        commit: String,
+        hash: String,
+        /// Directory of the crate in the repository, the build starting there, if not the root.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdir: Option<String>,
+    },
+    /// Local directory containing the crate sources.
+    Local {
+        path: PathBuf,
+        /// Files of the directory making up the crate, as `cargo package` selects them.
+        #[serde(default)]
+        files: FileSelection,
+    },
+}
+
+impl Origin {
+    /// Fetch the crate tarball from `uri` only, such as a copy of the tarball kept aside.
+    pub fn fetch_from(&mut self, uri: String) {
+        match self {
+            Origin::Crate {
+                registry: Some(registry),
+                ..
+            } => registry.url = uri,
+            Origin::Crate {
+                crates_io, mirrors, ..
+            } => {
+                *crates_io = false;
+                *mirrors = vec![uri];
+            }
+            Origin::Git { .. } | Origin::Local { .. } => (),
+        }
+    }
+}
+
+/// Form the packages take their crate dependencies in, which depends on the Guix version they
+/// target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputStyle {
+    /// Quasiquoted arguments, `` `(#:cargo-inputs (("rust-foo" ,rust-foo))) ``, of the Guix
+    /// versions predating the `list` argument forms.
+    LegacyAlist,
+    /// `(list #:cargo-inputs (list (list "rust-foo" rust-foo)))`.
+    #[default]
+    ListForms,
+    /// Crates listed in the `inputs` of the packages and development ones in their
+    /// `native-inputs`, like the `cargo-build-system` of recent Guix versions takes them.
+    Inputs,
+}
+
+impl InputStyle {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::str::FromStr for InputStyle {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        match style {
+            "legacy-alist" => Ok(InputStyle::LegacyAlist),
+            "list-forms" => Ok(InputStyle::ListForms),
+            "inputs" => Ok(InputStyle::Inputs),
+            _ => Err(format!(
+                "unknown style {}, expected legacy-alist, list-forms or inputs",
+                style
+            )),
+        }
+    }
+}
+
+/// Files of a local directory put in the store, the other ones not affecting its hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type", content = "paths")]
+pub enum FileSelection {
+    #[default]
+    All,
+    /// Every file but the ones below these paths, relative to the directory.
+    Excluding(Vec<String>),
+    /// These files only, relative to the directory, along with the directories leading to them.
+    Only(Vec<String>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Package {
+    pub crate_ref: CrateRef,
+    pub origin: Origin,
+    pub cargo_inputs: Vec<CrateRef>,
+    pub cargo_development_inputs: Vec<CrateRef>,
+    /// Inputs referring to packages defined in Guix rather than generated.
+    #[serde(serialize_with = "serialize_existing_inputs", skip_deserializing)]
+    pub existing_inputs: HashMap<CrateRef, ExistingPackage>,
+    /// Requirements the inputs were resolved from, by input variable, rendered as comments next
+    /// to them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub input_requirements: BTreeMap<String, Vec<String>>,
+    pub cargo_build_flags: Vec<String>,
+    /// Guix variables of the non-Rust inputs, `variable:output` selecting an output.
+    pub inputs: Vec<String>,
+    pub propagated_inputs: Vec<String>,
+    /// Guix variables of the tools run at build time, such as `pkg-config`.
+    #[serde(default)]
+    pub native_inputs: Vec<String>,
+    /// Modules defining the non-Rust inputs, e.g. `["gnu", "packages", "tls"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_modules: Vec<Vec<String>>,
+    /// System library the crate binds, as given by the `links` key of its manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<String>,
+    /// Extra files installed after the binaries.
+    pub install: Install,
+    pub native_search_paths: Vec<SearchPath>,
+    pub search_paths: Vec<SearchPath>,
+    /// Build system used instead of `cargo-build-system`.
+    pub build_system: Option<BuildSystem>,
+    pub home_page: Option<String>,
+    pub synopsis: Option<String>,
+    pub description: Option<String>,
+    /// SPDX license expression, translated to Guix licenses when rendered.
+    pub license: Option<String>,
+    /// Procedural macro, only ever run by the compiler.
+    #[serde(default)]
+    pub proc_macro: bool,
+    /// Procedural macro or dependency of procedural macros only, which never appears in the
+    /// runtime closure of its dependents.
+    #[serde(default)]
+    pub build_only: bool,
+    /// Leave the crate unbuilt, `#:skip-build? #t`, its dependents building it from its sources.
+    #[serde(default)]
+    pub skip_build: bool,
+    /// Fewest build-dependency edges on the paths from the root packages to the package, at
+    /// least 1 for the packages only needed to compile build scripts.
+    #[serde(default)]
+    pub build_dependency_depth: usize,
+    /// Reached through more build-dependency edges than the run allows, commented as such.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deep_build_dependency: bool,
+    /// Local crate whose manifest forbids publishing it, which no registry serves.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unpublished: bool,
+    /// Variable the package is defined as when the one derived from its crate is taken, see
+    /// [`Module::new`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variable: Option<String>,
+    /// Variables of the inputs defined under another name than the one derived from their crate.
+    #[serde(
+        serialize_with = "serialize_renamed_inputs",
+        skip_deserializing,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub renamed_inputs: HashMap<CrateRef, String>,
+    /// Properties update bots track the upstream releases of the package with, by name, see
+    /// [`Package::with_update_properties`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<(String, String)>,
+    /// Form the crate dependencies are listed in.
+    #[serde(default, skip_serializing_if = "InputStyle::is_default")]
+    pub input_style: InputStyle,
+}
+
+impl Package {
+    pub fn new(
+        crate_ref: CrateRef,
+        origin: Origin,
+        dependencies: &[(CrateRef, DependencyKind)],
+        cargo_build_flags: &[String],
+    ) -> Self {
+        let mut cargo_inputs = Vec::<CrateRef>::new();
+        let mut cargo_development_inputs = Vec::<CrateRef>::new();
+        for (crate_ref, kind) in dependencies {
+            let inputs = match kind {
+                DependencyKind::Normal | DependencyKind::Build => &mut cargo_inputs,
+                DependencyKind::Development => &mut cargo_development_inputs,
+            };
+            if !inputs.contains(crate_ref) {
+                inputs.push(crate_ref.clone());
+            }
+        }
+        cargo_development_inputs.retain(|crate_ref| !cargo_inputs.contains(crate_ref));
+        // listed by name and version rather than in the order of the manifest or of the index
+        cargo_inputs.sort();
+        cargo_development_inputs.sort();
+        Self {
+            crate_ref,
+            origin,
+            cargo_inputs,
+            cargo_development_inputs,
+            existing_inputs: HashMap::new(),
+            input_requirements: BTreeMap::new(),
+            cargo_build_flags: cargo_build_flags.to_vec(),
+            inputs: Vec::new(),
+            propagated_inputs: Vec::new(),
+            native_inputs: Vec::new(),
+            input_modules: Vec::new(),
+            links: None,
+            install: Install::default(),
+            native_search_paths: Vec::new(),
+            search_paths: Vec::new(),
+            build_system: None,
+            home_page: None,
+            synopsis: None,
+            description: None,
+            license: None,
+            proc_macro: false,
+            build_only: false,
+            skip_build: false,
+            build_dependency_depth: 0,
+            deep_build_dependency: false,
+            unpublished: false,
+            variable: None,
+            renamed_inputs: HashMap::new(),
+            properties: Vec::new(),
+            input_style: InputStyle::default(),
+        }
+    }
+
+    pub fn with_proc_macro(self, proc_macro: bool) -> Self {
+        Self { proc_macro, ..self }
+    }
+
+    pub fn with_skip_build(self, skip_build: bool) -> Self {
+        Self { skip_build, ..self }
+    }
+
+    pub fn with_input_style(self, input_style: InputStyle) -> Self {
+        Self {
+            input_style,
+            ..self
+        }
+    }
+
+    /// Add the properties `guix refresh` and other update bots track the upstream releases with,
+    /// when `enabled`: the `upstream-name` of crates whose name can't be told from the package
+    /// name, and the `release-monitoring-url` of the crates.io page or of the repository the
+    /// crate is fetched from. Local crates and crates of alternative registries have none.
+    pub fn with_update_properties(mut self, enabled: bool) -> Self {
+        if !enabled {
+            return self;
+        }
+        let name = &self.crate_ref.name;
+        if self.crate_ref.format_name() != format!("rust-{}", name) {
+            self.properties
+                .push(("upstream-name".to_string(), name.clone()));
+        }
+        let release_monitoring_url = match &self.origin {
+            Origin::Crate { registry: None, .. } => Some(metadata::crates_io_page(name)),
+            Origin::Git { url, .. } => Some(url.clone()),
+            _ => None,
+        };
+        if let Some(url) = release_monitoring_url {
+            self.properties
+                .push(("release-monitoring-url".to_string(), url));
+        }
+        self
+    }
+
+    pub fn with_unpublished(self, unpublished: bool) -> Self {
+        Self {
+            unpublished,
+            ..self
+        }
+    }
+
+    pub fn with_inputs(mut self, inputs: &[Input]) -> Self {
+        for input in inputs {
+            let package_inputs = match input.kind {
+                InputKind::Regular => &mut self.inputs,
+                InputKind::Propagated => &mut self.propagated_inputs,
+            };
+            if !package_inputs.contains(&input.package) {
+                package_inputs.push(input.package.clone());
+            }
+        }
+        self
+    }
+
+    pub fn with_links(self, links: Option<String>) -> Self {
+        Self { links, ..self }
+    }
+
+    /// Add the Guix packages a crate binding a system library needs to its inputs.
+    pub fn with_system_dependency(mut self, system_dependency: Option<SystemDependency>) -> Self {
+        let system_dependency = match system_dependency {
+            Some(system_dependency) => system_dependency,
+            None => return self,
+        };
+        for (variables, package_variables) in [
+            (&system_dependency.inputs, &mut self.inputs),
+            (&system_dependency.native_inputs, &mut self.native_inputs),
+        ] {
+            for variable in variables {
+                if !package_variables.contains(variable) {
+                    package_variables.push(variable.clone());
+                }
+            }
+        }
+        for module in system_dependency.modules() {
+            if !self.input_modules.contains(&module) {
+                self.input_modules.push(module);
+            }
+        }
+        self
+    }
+
+    pub fn with_install(self, install: &Install) -> Self {
+        Self {
+            install: install.clone(),
+            ..self
+        }
+    }
+
+    pub fn with_search_paths(
+        self,
+        native_search_paths: &[SearchPath],
+        search_paths: &[SearchPath],
+    ) -> Self {
+        Self {
+            native_search_paths: native_search_paths.to_vec(),
+            search_paths: search_paths.to_vec(),
+            ..self
+        }
+    }
+
+    pub fn with_build_system(self, build_system: Option<&BuildSystem>) -> Self {
+        Self {
+            build_system: build_system.cloned(),
+            ..self
+        }
+    }
+
+    /// Add mirror URIs to the origin of crates fetched from crates.io, which is left out of the
+    /// origin unless `crates_io` is set.
+    pub fn with_mirrors(mut self, uris: Vec<String>, crates_io: bool) -> Self {
+        if let Origin::Crate {
+            crates_io: origin_crates_io,
+            mirrors,
+            registry: None,
+            ..
+        } = &mut self.origin
+        {
+            *origin_crates_io = crates_io;
+            *mirrors = uris;
+        }
+        self
+    }
+
+    pub fn with_metadata(self, metadata: CrateMetadata) -> Self {
+        Self {
+            home_page: metadata.home_page,
+            synopsis: metadata.synopsis,
+            description: metadata.description,
+            license: metadata.license,
+            ..self
+        }
+    }
+
+    /// Append a paragraph telling which features the package is built with to its description.
+    pub fn with_feature_summary(self, summary: Option<String>) -> Self {
+        let description = match (self.description, summary) {
+            (Some(description), Some(summary)) => Some(format!("{}\n\n{}", description, summary)),
+            (description, summary) => description.or(summary),
+        };
+        Self {
+            description,
+            ..self
+        }
+    }
+
+    /// URL the package fetches its sources from first, `None` for local crates.
+    pub fn source_url(&self) -> Option<String> {
+        match &self.origin {
+            Origin::Crate {
+                registry: Some(registry),
+                ..
+            } => Some(registry.url.clone()),
+            Origin::Crate {
+                crates_io: true, ..
+            } => Some(format!(
+                "https://crates.io/api/v1/crates/{}/{}/download",
+                self.crate_ref.name, self.crate_ref.version
+            )),
+            Origin::Crate { mirrors, .. } => mirrors.first().cloned(),
+            Origin::Git { url, .. } => Some(url.clone()),
+            Origin::Local { .. } => None,
+        }
+    }
+
+    /// Guix hash of the package sources, `None` for local crates and crates not hashed yet.
+    pub fn hash(&self) -> Option<&str> {
+        match &self.origin {
+            Origin::Crate { hash, .. } => hash.as_deref(),
+            Origin::Git { hash, .. } => Some(hash),
+            Origin::Local { .. } => None,
+        }
+    }
+
+    /// Variable the package is defined as, e.g. `rust-serde-1.0.100`.
+    pub fn variable(&self) -> String {
+        self.variable
+            .clone()
+            .unwrap_or_else(|| self.crate_ref.format_name_version())
+    }
+
+    /// Name of the procedure returning the package built with a given rust compiler.
+    pub fn procedure_name(&self) -> String {
+        format!("make-{}", self.variable())
+    }
+
+    // unquoted expressions in `sexp!` need their parentheses
+    #[allow(unused_parens)]
+    pub fn to_sexpr(&self) -> lexpr::Value {
+        sexp!(
+            (#"define-public" ,(symbol(&self.variable()))
+                ,(self.package_sexpr(false)))
+        )
+    }
+
+    /// Procedure returning the package built with the rust compiler it's given, memoized so that
+    /// every dependent gets the same package, followed by its instantiation with the default
+    /// compiler.
+    #[allow(unused_parens)]
+    pub fn to_parameterized_sexprs(&self) -> Vec<lexpr::Value> {
+        let procedure_name = symbol(&self.procedure_name());
+        vec![
+            sexp!((
+                define,
+                (procedure_name.clone())(mlambda(rust), (self.package_sexpr(true)))
+            )),
+            sexp!(
+                (#"define-public" ,(symbol(&self.variable()))
+                    (,(procedure_name) rust))
+            ),
+        ]
+    }
+
+    /// `(package ...)` form, using the `rust` variable in scope as compiler and building the
+    /// inputs with it when `parameterized`.
+    fn package_sexpr(&self, parameterized: bool) -> lexpr::Value {
+        lexpr::Value::append(
+            vec![lexpr::Value::symbol("package")],
+            lexpr::Value::list(self.package_fields(parameterized)),
+        )
+    }
+
+    /// Fields of the `package` form, such as `(name "rust-foo")`.
+    #[allow(unused_parens)]
+    fn package_fields(&self, parameterized: bool) -> Vec<lexpr::Value> {
+        let mut fields = vec![
+            sexp!((name, (self.crate_ref.format_name()))),
+            sexp!((version, (self.crate_ref.version.clone()))),
+            sexp!((source, (self.origin_sexpr()))),
+        ];
+        match &self.build_system {
+            Some(build_system) => {
+                let name = symbol(&build_system.name);
+                fields.push(sexp!((#"build-system" ,(name))));
+                if let Some(arguments) = self.build_system_arguments_sexpr(build_system) {
+                    fields.push(sexp!((arguments, (arguments))));
+                }
+            }
+            None => {
+                fields.push(sexp!((#"build-system" #"cargo-build-system")));
+                if let Some(arguments) = self.arguments_sexpr(parameterized) {
+                    fields.push(sexp!((arguments, (arguments))));
+                }
+            }
+        }
+        // crates are only listed along with the other inputs in the `inputs` style
+        let crate_inputs = |inputs: &[CrateRef]| match self.input_style {
+            InputStyle::Inputs if self.build_system.is_none() => inputs
+                .iter()
+                .map(|input| self.input_sexpr(input, parameterized))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let native_inputs = crate_inputs(&self.cargo_development_inputs);
+        if !native_inputs.is_empty() || !self.native_inputs.is_empty() {
+            let native_inputs = inputs_list_sexpr(native_inputs, &self.native_inputs);
+            fields.push(sexp!((#"native-inputs" ,(native_inputs))));
+        }
+        let inputs = crate_inputs(&self.cargo_inputs);
+        if !inputs.is_empty() || !self.inputs.is_empty() {
+            fields.push(sexp!((inputs, (inputs_list_sexpr(inputs, &self.inputs)))));
+        }
+        if !self.propagated_inputs.is_empty() {
+            fields.push(sexp!((#"propagated-inputs" ,(variables_sexpr(&self.propagated_inputs)))));
+        }
+        if !self.native_search_paths.is_empty() {
+            fields.push(sexp!(
+                (#"native-search-paths" ,(search_paths_sexpr(&self.native_search_paths)))
+            ));
+        }
+        if !self.search_paths.is_empty() {
+            fields.push(sexp!((#"search-paths" ,(search_paths_sexpr(&self.search_paths)))));
+        }
+        fields.extend(vec![
+            sexp!((#"home-page" ,(string_or_false(&self.home_page)))),
+            sexp!((synopsis, (string_or_false(&self.synopsis)))),
+            sexp!((description, (string_or_false(&self.description)))),
+            sexp!((license, (self.license_sexpr()))),
+        ]);
+        if !self.properties.is_empty() {
+            let properties = lexpr::Value::list(self.properties.iter().map(|(name, value)| {
+                lexpr::Value::cons(symbol(name), lexpr::Value::string(value.as_str()))
+            }));
+            fields.push(sexp!((properties(quote, properties))));
+        }
+        fields
+    }
+
+    /// Values the package template renders the definitions of the package with.
+    pub fn template_data(&self, parameterized: bool) -> PackageData {
+        // Column of the fields in the default template, within `mlambda` when parameterized
+        let field_column = if parameterized { 6 } else { 4 };
+        let fields = self
+            .package_fields(parameterized)
+            .into_iter()
+            .filter_map(|field| match field.as_cons()?.clone().into_pair() {
+                (lexpr::Value::Symbol(name), lexpr::Value::Cons(value)) => Some(FieldData {
+                    name: name.into_string(),
+                    value: value.into_pair().0.to_string(),
+                    form: pretty_sexpr_at(&field, field_column),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let input_data = |inputs: &[CrateRef]| {
+            inputs
+                .iter()
+                .map(|input| InputData {
+                    variable: match self.existing_inputs.get(input) {
+                        Some(existing_package) => existing_package.variable.clone(),
+                        None => self.input_variable(input),
+                    },
+                    input: self.input_sexpr(input, parameterized).to_string(),
+                    requirements: self
+                        .input_requirements
+                        .get(&input.format_name_version())
+                        .map(|requirements| requirements.join(", ")),
+                })
+                .collect()
+        };
+        PackageData {
+            variable: self.variable(),
+            procedure: self.procedure_name(),
+            name: self.crate_ref.format_name(),
+            crate_name: self.crate_ref.name.clone(),
+            version: self.crate_ref.version.clone(),
+            parameterized,
+            field: fields
+                .iter()
+                .map(|field| (field.name.clone(), field.value.clone()))
+                .collect(),
+            fields,
+            cargo_inputs: input_data(&self.cargo_inputs),
+            cargo_development_inputs: input_data(&self.cargo_development_inputs),
+        }
+    }
+
+    /// Arguments of `cargo-build-system`, in the form of [`Package::input_style`], `None` when
+    /// the `inputs` style leaves none.
+    fn arguments_sexpr(&self, parameterized: bool) -> Option<lexpr::Value> {
+        let legacy = self.input_style == InputStyle::LegacyAlist;
+        // values are evaluated within the quasiquoted legacy arguments only once unquoted
+        let evaluated = |value: lexpr::Value| match legacy {
+            true => lexpr::Value::list(vec![lexpr::Value::symbol("unquote"), value]),
+            false => value,
+        };
+        let list = |values: Vec<lexpr::Value>| match legacy {
+            true => lexpr::Value::list(values),
+            false => lexpr::Value::append(
+                vec![lexpr::Value::symbol("list")],
+                lexpr::Value::list(values),
+            ),
+        };
+        let mut arguments = Vec::new();
+        if parameterized {
+            arguments.push(lexpr::Value::keyword("rust"));
+            arguments.push(evaluated(lexpr::Value::symbol("rust")));
+        }
+        if self.input_style != InputStyle::Inputs {
+            arguments.push(lexpr::Value::keyword("cargo-inputs"));
+            arguments.push(list(self.inputs_sexprs(&self.cargo_inputs, parameterized)));
+            if !self.cargo_development_inputs.is_empty() {
+                arguments.push(lexpr::Value::keyword("cargo-development-inputs"));
+                arguments.push(list(
+                    self.inputs_sexprs(&self.cargo_development_inputs, parameterized),
+                ));
+            }
+        }
+        if !self.cargo_build_flags.is_empty() {
+            arguments.push(lexpr::Value::keyword("cargo-build-flags"));
+            arguments.push(list(
+                self.cargo_build_flags
+                    .iter()
+                    .map(|flag| lexpr::Value::string(flag.as_str()))
+                    .collect(),
+            ));
+        }
+        if self.skip_build {
+            arguments.push(lexpr::Value::keyword("skip-build?"));
+            arguments.push(lexpr::Value::Bool(true));
+        }
+        if !self.install.is_empty() || self.source_subdir().is_some() {
+            arguments.push(lexpr::Value::keyword("phases"));
+            arguments.push(evaluated(self.phases_sexpr()));
+        }
+        match self.input_style {
+            InputStyle::Inputs if arguments.is_empty() => None,
+            InputStyle::LegacyAlist => Some(lexpr::Value::list(vec![
+                lexpr::Value::symbol("quasiquote"),
+                lexpr::Value::list(arguments),
+            ])),
+            _ => Some(list(arguments)),
+        }
+    }
+
+    /// Arguments of a build system replacing `cargo-build-system`, an install plan being
+    /// generated for `copy-build-system` unless arguments are given.
+    #[allow(unused_parens)]
+    fn build_system_arguments_sexpr(&self, build_system: &BuildSystem) -> Option<lexpr::Value> {
+        if build_system.arguments.is_some() || build_system.name != COPY_BUILD_SYSTEM {
+            return build_system.arguments.clone();
+        }
+        let default_plan = [(
+            ".".to_string(),
+            format!(
+                "share/cargo/src/{}-{}/",
+                self.crate_ref.name, self.crate_ref.version
+            ),
+        )];
+        let install_plan = if build_system.install_plan.is_empty() {
+            &default_plan[..]
+        } else {
+            &build_system.install_plan[..]
+        };
+        let install_plan = lexpr::Value::list(
+            install_plan
+                .iter()
+                .map(|(source, target)| sexp!((,(source.as_str()) ,(target.as_str())))),
+        );
+        Some(sexp!((list #:"install-plan" (quote ,(install_plan)))))
+    }
+
+    /// Directory of the crate in the git repository it is fetched from, if not the root.
+    fn source_subdir(&self) -> Option<&str> {
+        match &self.origin {
+            Origin::Git { subdir, .. } => subdir.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Standard phases, entering the directory of the crate after unpacking a repository it
+    /// lives in a subdirectory of, and followed by an `install-extras` phase which runs the
+    /// commands generating the extra files and copies them to their conventional location under
+    /// the output.
+    ///
+    /// `(gexp ...)` and `(ungexp ...)` are the long forms of `#~` and `#$`.
+    #[allow(unused_parens)]
+    fn phases_sexpr(&self) -> lexpr::Value {
+        let mut modifications = vec![
+            lexpr::Value::symbol("modify-phases"),
+            lexpr::Value::symbol("%standard-phases"),
+        ];
+        if let Some(subdir) = self.source_subdir() {
+            modifications.push(sexp!(
+                (#"add-after" (quote unpack) (quote #"chdir")
+                    (lambda _ (chdir ,(subdir))))
+            ));
+        }
+        if !self.install.is_empty() {
+            modifications.push(self.install_phase_sexpr());
+        }
+        sexp!((gexp, (lexpr::Value::list(modifications))))
+    }
+
+    /// `install-extras` phase of [`Self::phases_sexpr`].
+    #[allow(unused_parens)]
+    fn install_phase_sexpr(&self) -> lexpr::Value {
+        let install = &self.install;
+        let mut body = vec![lexpr::Value::symbol("lambda"), lexpr::Value::symbol("_")];
+        body.extend(install.commands.iter().map(|command| {
+            lexpr::Value::append(
+                vec![lexpr::Value::symbol("invoke")],
+                lexpr::Value::list(command.iter().map(|arg| lexpr::Value::string(arg.as_str()))),
+            )
+        }));
+        let files = install
+            .bash_completions
+            .iter()
+            .map(|file| {
+                let name = match file_extension(file) {
+                    Some("bash") => file_stem(file),
+                    _ => file_name(file),
+                };
+                (file, "share/bash-completion/completions".to_string(), name)
+            })
+            .chain(install.zsh_completions.iter().map(|file| {
+                let name = match file_name(file) {
+                    name if name.starts_with('_') => name,
+                    _ => format!("_{}", file_stem(file)),
+                };
+                (file, "share/zsh/site-functions".to_string(), name)
+            }))
+            .chain(install.fish_completions.iter().map(|file| {
+                let name = format!("{}.fish", file_stem(file));
+                (file, "share/fish/vendor_completions.d".to_string(), name)
+            }))
+            .chain(install.man_pages.iter().map(|file| {
+                let section = file_extension(file)
+                    .and_then(|extension| extension.get(0..1))
+                    .unwrap_or("1");
+                (file, format!("share/man/man{}", section), file_name(file))
+            }))
+            .chain(
+                install
+                    .desktop_files
+                    .iter()
+                    .map(|file| (file, "share/applications".to_string(), file_name(file))),
+            );
+        for (file, directory, name) in files {
+            body.push(sexp!(
+                (#"mkdir-p" (#"string-append" (ungexp output) ,(format!("/{}", directory))))
+            ));
+            body.push(sexp!(
+                (#"copy-file" ,(file.clone())
+                    (#"string-append" (ungexp output) ,(format!("/{}/{}", directory, name))))
+            ));
+        }
+        sexp!(
+            (#"add-after" (quote install) (quote #"install-extras")
+                ,(lexpr::Value::list(body)))
+        )
+    }
+
+    /// Inputs, the packages defined in Guix being referred to by their variable, which isn't
+    /// parameterized over the rust compiler.
+    fn inputs_sexprs(&self, inputs: &[CrateRef], parameterized: bool) -> Vec<lexpr::Value> {
+        inputs
+            .iter()
+            .map(|input| self.input_sexpr(input, parameterized))
+            .collect()
+    }
+
+    /// Entry of an input in the form of [`Package::input_style`], e.g.
+    /// `(list "rust-foo-1" rust-foo-1)`.
+    #[allow(unused_parens)]
+    fn input_sexpr(&self, input: &CrateRef, parameterized: bool) -> lexpr::Value {
+        let (name, package) = self.input_package_sexpr(input, parameterized);
+        match self.input_style {
+            InputStyle::LegacyAlist => sexp!((,(name) (unquote ,(package)))),
+            InputStyle::ListForms => sexp!((list, (name), (package))),
+            InputStyle::Inputs => package,
+        }
+    }
+
+    /// Name of an input and the expression evaluating to its package.
+    #[allow(unused_parens)]
+    fn input_package_sexpr(&self, input: &CrateRef, parameterized: bool) -> (String, lexpr::Value) {
+        match self.existing_inputs.get(input) {
+            Some(existing_package) => {
+                let variable = existing_package.variable.clone();
+                let package = symbol(&variable);
+                (variable, package)
+            }
+            None => match self.renamed_inputs.get(input) {
+                Some(variable) if parameterized => {
+                    let procedure_name = symbol(&format!("make-{}", variable));
+                    (variable.clone(), sexp!((,(procedure_name) rust)))
+                }
+                Some(variable) => (variable.clone(), symbol(variable)),
+                None if parameterized => {
+                    let procedure_name = symbol(&input.format_procedure_name());
+                    (input.format_name_version(), sexp!((,(procedure_name) rust)))
+                }
+                None => (
+                    input.format_name_version(),
+                    symbol(&input.format_name_version()),
+                ),
+            },
+        }
+    }
+
+    /// Variable an input generated along with the package is defined as.
+    fn input_variable(&self, input: &CrateRef) -> String {
+        self.renamed_inputs
+            .get(input)
+            .cloned()
+            .unwrap_or_else(|| input.format_name_version())
+    }
+
+    /// Definition with a comment after each input giving the requirements it was resolved from,
+    /// e.g. `; num-traits = "0.2"`, followed by a line break since comments run to the end of
+    /// the line, the rest of the line going on the next one under the input.
+    fn comment_requirements(&self, definition: String, parameterized: bool) -> String {
+        let mut commented = definition;
+        for input in self
+            .cargo_inputs
+            .iter()
+            .chain(&self.cargo_development_inputs)
+        {
+            let requirements = match self.input_requirements.get(&input.format_name_version()) {
+                Some(requirements) if !requirements.is_empty() => requirements,
+                _ => continue,
+            };
+            let entry = self.input_sexpr(input, parameterized).to_string();
+            let (start, end) = match find_laid_out(&commented, &entry) {
+                Some(span) => span,
+                None => continue,
+            };
+            let blanks = commented[end..].len() - commented[end..].trim_start_matches(' ').len();
+            let line_start = commented[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_break = match commented[end + blanks..].starts_with('\n') {
+                true => String::new(),
+                false => format!("\n{}", " ".repeat(start - line_start)),
+            };
+            let comment = format!(
+                " ; {} = {}{}",
+                input.name,
+                requirements
+                    .iter()
+                    .map(|requirement| format!("{:?}", requirement))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                line_break
+            );
+            commented.replace_range(end..end + blanks, &comment);
+        }
+        commented
+    }
+
+    fn license_sexpr(&self) -> lexpr::Value {
+        let licenses = self
+            .license
+            .as_deref()
+            .map(license::guix_licenses)
+            .unwrap_or_default();
+        match licenses.as_slice() {
+            [] => lexpr::Value::Bool(false),
+            [license] => symbol(license),
+            _ => lexpr::Value::append(
+                vec![lexpr::Value::symbol("list")],
+                lexpr::Value::list(licenses.iter().map(|license| symbol(license))),
+            ),
+        }
+    }
+
+    #[allow(unused_parens)]
+    /// `crate-uri` of the crate unless a mirror replaces crates.io, followed by its mirror URIs
+    /// if any.
+    #[allow(unused_parens)]
+    fn uri_sexpr(&self, crates_io: bool, mirrors: &[String]) -> lexpr::Value {
+        let mut uris = Vec::new();
+        if crates_io {
+            uris.push(sexp!((#"crate-uri" ,(self.crate_ref.name.clone()) version)));
+        }
+        uris.extend(
+            mirrors
+                .iter()
+                .map(|mirror| lexpr::Value::string(mirror.as_str())),
+        );
+        if uris.len() == 1 {
+            return uris.remove(0);
+        }
+        uris.insert(0, lexpr::Value::symbol("list"));
+        lexpr::Value::list(uris)
+    }
+
+    #[allow(unused_parens)]
+    fn origin_sexpr(&self) -> lexpr::Value {
+        match &self.origin {
+            Origin::Crate {
+                hash,
+                registry: Some(registry),
+                ..
+            } => sexp!(
+                (origin
+                    (method #"url-fetch")
+                    (#"uri" ,(registry.url.clone()))
+                    (#"file-name"
+                        (#"string-append" name "-" version ".tar.gz"))
+                    (sha256
+                        (base32 ,(hash.clone().unwrap_or_default()))))
+            ),
+            Origin::Crate {
+                hash,
+                crates_io,
+                mirrors,
+                ..
+            } => sexp!(
+                (origin
+                    (method #"url-fetch")
+                    (#"uri" ,(self.uri_sexpr(*crates_io, mirrors)))
+                    (#"file-name"
+                        (#"string-append" name "-" version ".tar.gz"))
+                    (sha256
+                        (base32 ,(hash.clone().unwrap_or_default()))))
+            ),
+            Origin::Git {
+                url, commit, hash, ..
+            } => sexp!(
+                (origin
+                    (method #"git-fetch")
+                    (#"uri" (#"git-reference"
+                        (url ,(url.clone()))
+                        (commit ,(commit.clone()))))
+                    (#"file-name" (#"git-file-name" name version))
+                    (sha256
+                        (base32 ,(hash.clone()))))
+            ),
+            Origin::Local { path, files } => {
+                let path = path.to_string_lossy().into_owned();
+                let name = format!("{}-checkout", self.crate_ref.format_name_version());
+                match select_sexpr(&path, files) {
+                    Some(select) => sexp!(
+                        (#"local-file" ,path ,name
+                            #:"recursive?" #t
+                            #:"select?" ,select)
+                    ),
+                    None => sexp!((#"local-file" ,path ,name #:"recursive?" #t)),
+                }
+            }
+        }
+    }
+}
+
+/// Predicate selecting the files of a local directory relative to `path`, if not all of them.
+#[allow(unused_parens)]
+fn select_sexpr(path: &str, files: &FileSelection) -> Option<lexpr::Value> {
+    let (excluding, paths) = match files {
+        FileSelection::All => return None,
+        FileSelection::Excluding(paths) => (true, paths),
+        FileSelection::Only(paths) => (false, paths),
+    };
+    let paths = lexpr::Value::list(paths.iter().map(|path| lexpr::Value::string(path.as_str())));
+    let member = sexp!(
+        (member (#"string-drop" file (#"string-length" ,(format!("{}/", path))))
+            (quote ,paths))
+    );
+    let member = if excluding {
+        sexp!((not, member))
+    } else {
+        member
+    };
+    Some(sexp!((lambda (file stat) ,member)))
+}
+
+fn string_or_false(value: &Option<String>) -> lexpr::Value {
+    value
+        .as_deref()
+        .map_or(lexpr::Value::Bool(false), lexpr::Value::string)
+}
+
+/// Existing inputs keyed by the name of the variable carguix would have defined for them, e.g.
+/// `rust-serde-1.0.100`, JSON objects having string keys only.
+fn serialize_existing_inputs<S: Serializer>(
+    existing_inputs: &HashMap<CrateRef, ExistingPackage>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    existing_inputs
+        .iter()
+        .map(|(crate_ref, existing_package)| (crate_ref.format_name_version(), existing_package))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Renamed inputs keyed by the name of the variable carguix would have defined for them.
+fn serialize_renamed_inputs<S: Serializer>(
+    renamed_inputs: &HashMap<CrateRef, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    renamed_inputs
+        .iter()
+        .map(|(crate_ref, variable)| (crate_ref.format_name_version(), variable))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Guix module defining a set of packages.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Module {
+    /// Module name, e.g. `["my", "channel", "rust-crates"]`.
+    pub name: Vec<String>,
+    /// Packages, each one after its inputs.
+    pub packages: Vec<Package>,
+    /// Commit of the crates.io index the packages were resolved against.
+    pub index_commit: Option<String>,
+    /// Define the packages as procedures parameterized over the rust compiler.
+    pub parameterized: bool,
+    /// Modules defining packages used as inputs.
+    pub imports: Vec<Vec<String>>,
+    /// Templates the module is rendered with by [`Module::to_scheme`].
+    #[serde(skip)]
+    pub templates: Templates,
+}
+
+impl Module {
+    pub fn new(
+        name: Vec<String>,
+        packages: Vec<Package>,
+        index_commit: Option<String>,
+        parameterized: bool,
+    ) -> Self {
+        Self {
+            name,
+            packages: dependency_order(packages),
+            index_commit,
+            parameterized,
+            imports: Vec::new(),
+            templates: Templates::default(),
+        }
+    }
+
+    pub fn with_imports(self, imports: Vec<Vec<String>>) -> Self {
+        Self { imports, ..self }
+    }
+
+    pub fn with_templates(self, templates: Templates) -> Self {
+        Self { templates, ..self }
+    }
+
+    /// Module name from the path of its file relative to the load path, e.g.
+    /// `my/channel/rust-crates.scm`.
+    pub fn name_from_path(path: &Path) -> Vec<String> {
+        path.with_extension("")
+            .components()
+            .filter_map(|component| match component {
+                std::path::Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Module name written either as `my channel rust-crates` or `(my channel rust-crates)`.
+    pub fn parse_name(name: &str) -> Vec<String> {
+        name.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// `define-module` form importing what the packages refer to.
+    fn header_sexpr(&self) -> lexpr::Value {
+        let mut header = vec![
+            lexpr::Value::symbol("define-module"),
+            lexpr::Value::list(self.name.iter().map(|part| symbol(part.as_str()))),
+        ];
+        for import in self.imports_sexprs() {
+            header.push(lexpr::Value::keyword("use-module"));
+            header.push(import);
+        }
+        lexpr::Value::list(header)
+    }
+
+    /// Specifications of the modules the packages refer to, e.g. `(guix packages)`.
+    fn imports_sexprs(&self) -> Vec<lexpr::Value> {
+        let mut imports = vec![
+            vec!["guix", "packages"],
+            vec!["guix", "build-system", "cargo"],
+        ];
+        let origins = self.packages.iter().map(|package| &package.origin);
+        if origins
+            .clone()
+            .any(|origin| matches!(origin, Origin::Crate { .. }))
+        {
+            imports.push(vec!["guix", "download"]);
+        }
+        if origins
+            .clone()
+            .any(|origin| matches!(origin, Origin::Git { .. }))
+        {
+            imports.push(vec!["guix", "git-download"]);
+        }
+        if origins
+            .clone()
+            .any(|origin| matches!(origin, Origin::Local { .. }))
+            || self
+                .packages
+                .iter()
+                .any(|package| !package.install.is_empty())
+        {
+            imports.push(vec!["guix", "gexp"]);
+        }
+        if self.parameterized {
+            imports.push(vec!["guix", "memoization"]);
+            imports.push(vec!["gnu", "packages", "rust"]);
+        }
+        let mut build_system_modules = self
+            .packages
+            .iter()
+            .filter_map(|package| package.build_system.as_ref())
+            .flat_map(BuildSystem::modules)
+            .collect::<Vec<_>>();
+        build_system_modules.sort();
+        build_system_modules.dedup();
+        let mut specifications = imports
+            .into_iter()
+            .map(|import| lexpr::Value::list(import.into_iter().map(lexpr::Value::symbol)))
+            .collect::<Vec<_>>();
+        let mut existing_modules = self
+            .packages
+            .iter()
+            .flat_map(|package| {
+                package
+                    .existing_inputs
+                    .values()
+                    .map(|existing_package| &existing_package.module)
+                    .chain(&package.input_modules)
+            })
+            .collect::<Vec<_>>();
+        existing_modules.sort();
+        existing_modules.dedup();
+        for import in existing_modules
+            .into_iter()
+            .chain(&build_system_modules)
+            .chain(&self.imports)
+        {
+            specifications.push(lexpr::Value::list(
+                import.iter().map(|part| symbol(part.as_str())),
+            ));
+        }
+        if self
+            .packages
+            .iter()
+            .any(|package| package.license.is_some())
+        {
+            specifications.push(lexpr::Value::list(vec![
+                lexpr::Value::list(vec![
+                    lexpr::Value::symbol("guix"),
+                    lexpr::Value::symbol("licenses"),
+                ]),
+                lexpr::Value::keyword("prefix"),
+                lexpr::Value::symbol("license:"),
+            ]));
+        }
+        specifications
+    }
+
+    pub fn to_scheme(&self) -> String {
+        self.templates.render_module(&self.module_data())
+    }
+
+    /// [`Self::to_scheme`] written to `writer`, sparing a copy of the module in memory.
+    pub fn write_scheme(&self, writer: &mut dyn Write) -> io::Result<()> {
+        self.templates.write_module(&self.module_data(), writer)
+    }
+
+    fn module_data(&self) -> ModuleData {
+        let packages = self
+            .packages
+            .iter()
+            .map(|package| ModulePackageData {
+                variable: package.variable(),
+                name: package.crate_ref.format_name(),
+                version: package.crate_ref.version.clone(),
+                definitions: package_definitions(package, self.parameterized, &self.templates),
+            })
+            .collect();
+        ModuleData {
+            header: pretty_sexpr(&self.header_sexpr()),
+            name: self.name.join(" "),
+            index_commit: self.index_commit.clone(),
+            packages,
+        }
+    }
+
+    /// Definitions of the packages without the module header, to complete an existing module.
+    pub fn definitions(&self) -> String {
+        self.packages
+            .iter()
+            .map(|package| package_definitions(package, self.parameterized, &self.templates))
+            .collect()
+    }
+}
+
+/// Bindings the package variables must not shadow: the core bindings of Guile and Guix the
+/// definitions refer to, and the compilers and tools of `(gnu packages rust)`, which parameterized
+/// modules import.
+pub const RESERVED_VARIABLES: &[&str] = &[
+    "arguments",
+    "cargo-build-system",
+    "crate-uri",
+    "define",
+    "git-fetch",
+    "inherit",
+    "lambda",
+    "license",
+    "list",
+    "local-file",
+    "make-rust-sysroot",
+    "mlambda",
+    "name",
+    "origin",
+    "package",
+    "rust",
+    "rust-analyzer",
+    "rust-bootstrap",
+    "rust-src",
+    "source",
+    "url-fetch",
+    "version",
+];
+
+/// Whether a package variable, or the procedure parameterized modules define along with it, would
+/// shadow one of the [`RESERVED_VARIABLES`].
+pub fn is_reserved_variable(variable: &str) -> bool {
+    let procedure_name = format!("make-{}", variable);
+    RESERVED_VARIABLES
+        .iter()
+        .any(|reserved| *reserved == variable || *reserved == procedure_name)
+}
+
+/// Variables of their own for the crates whose variable is reserved, see [`RESERVED_VARIABLES`],
+/// or derived from several crates, such as `foo_bar` and `foo-bar` from different registries.
+///
+/// Of the crates sharing a variable, the one spelled like it keeps it. The others are named after
+/// their crate as is, or suffixed with a counter if taken too.
+pub fn renamed_variables<'a>(
+    crate_refs: impl IntoIterator<Item = &'a CrateRef>,
+) -> HashMap<CrateRef, String> {
+    let mut crates = BTreeMap::<String, BTreeSet<&CrateRef>>::new();
+    for crate_ref in crate_refs {
+        crates
+            .entry(crate_ref.format_name_version())
+            .or_default()
+            .insert(crate_ref);
+    }
+    let mut taken = crates.keys().cloned().collect::<HashSet<_>>();
+    let mut renamed = HashMap::new();
+    for (variable, crate_refs) in &crates {
+        let reserved = is_reserved_variable(variable);
+        let kept = crate_refs
+            .iter()
+            .find(|crate_ref| crate_ref.format_name() == format!("rust-{}", crate_ref.name))
+            .or_else(|| crate_refs.iter().next())
+            .filter(|_| !reserved);
+        for crate_ref in crate_refs {
+            if Some(crate_ref) == kept {
+                continue;
+            }
+            let renamed_variable =
+                iter::once(format!("rust-{}-{}", crate_ref.name, crate_ref.version))
+                    .map(|candidate| mangle_symbol(&candidate).into_owned())
+                    .chain((2..).map(|counter| format!("{}-{}", variable, counter)))
+                    .find(|candidate| {
+                        !taken.contains(candidate) && !is_reserved_variable(candidate)
+                    })
+                    .expect("counters yield infinitely many variables");
+            log::warn!(
+                "crate {} v{} defined as {} since {} is {}",
+                crate_ref.name,
+                crate_ref.version,
+                renamed_variable,
+                variable,
+                if reserved {
+                    "reserved"
+                } else {
+                    "taken by another crate"
+                }
+            );
+            taken.insert(renamed_variable.clone());
+            renamed.insert((*crate_ref).clone(), renamed_variable);
+        }
+    }
+    renamed
+}
+
+/// Characters, besides alphanumeric ones, symbols may contain without being escaped.
+const SYMBOL_CHARACTERS: &str = "-_.+!$%&*/:<=>?^~@";
+
+/// `name` as a symbol Guile reads back whole: characters which would end it or start another
+/// datum, such as whitespace, parentheses, quotes or `#`, are replaced by `-`.
+pub fn mangle_symbol(name: &str) -> Cow<'_, str> {
+    let valid =
+        |character: char| character.is_alphanumeric() || SYMBOL_CHARACTERS.contains(character);
+    if name.is_empty() {
+        Cow::Borrowed("-")
+    } else if name.chars().all(valid) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(
+            name.chars()
+                .map(|character| if valid(character) { character } else { '-' })
+                .collect(),
+        )
+    }
+}
+
+/// Symbol of a name coming from crates or configuration, mangled by [`mangle_symbol`] so that
+/// unusual names don't break the module.
+pub fn symbol(name: &str) -> lexpr::Value {
+    lexpr::Value::symbol(mangle_symbol(name).into_owned())
+}
+
+/// Variables a module defines with `define-public`.
+pub fn defined_variables(scheme: &str) -> HashSet<String> {
+    scheme
+        .split("(define-public ")
+        .skip(1)
+        .filter_map(|definition| {
+            definition
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .next()
+        })
+        .filter(|variable| !variable.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Development environment of local crates: a `guix.scm` file evaluating to the package of the
+/// crate with development tools as native inputs, for `guix shell -D -f guix.scm`, or a
+/// `manifest.scm` file for `guix shell -m manifest.scm`, after the definitions of the
+/// dependencies.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    module: Module,
+    /// Variables of the local crates the environment is for, the ones no other package depends
+    /// on.
+    roots: Vec<String>,
+    manifest: bool,
+}
+
+impl Environment {
+    /// Environment of the local crates among `packages`, which must be a single one unless
+    /// writing a `manifest`, since a `guix.scm` file evaluates to a single package.
+    pub fn new(
+        packages: Vec<Package>,
+        index_commit: Option<String>,
+        manifest: bool,
+    ) -> Result<Self, CarguixError> {
+        let roots = root_packages(&packages)
+            .into_iter()
+            .filter(|package| matches!(package.origin, Origin::Local { .. }))
+            .map(Package::variable)
+            .collect::<Vec<_>>();
+        if roots.is_empty() {
+            return Err(CarguixError::NoEnvironmentRoot);
+        }
+        if roots.len() > 1 && !manifest {
+            return Err(CarguixError::EnvironmentRootsError(roots.join(", ")));
+        }
+        Ok(Self {
+            module: Module::new(Vec::new(), packages, index_commit, false),
+            roots,
+            manifest,
+        })
+    }
+
+    pub fn with_templates(self, templates: Templates) -> Self {
+        Self {
+            module: self.module.with_templates(templates),
+            ..self
+        }
+    }
+
+    #[allow(unused_parens)]
+    pub fn to_scheme(&self) -> String {
+        let mut imports = self.module.imports_sexprs();
+        let mut tool_modules = vec![
+            sexp!((gnu packages rust)),
+            sexp!((gnu packages #"pkg-config")),
+        ];
+        if self.manifest {
+            tool_modules.push(sexp!((guix profiles)));
+        }
+        for module in tool_modules {
+            if !imports.contains(&module) {
+                imports.push(module);
+            }
+        }
+        let mut scheme = format!(
+            "{}\n\n",
+            pretty_sexpr(&lexpr::Value::append(
+                vec![lexpr::Value::symbol("use-modules")],
+                lexpr::Value::list(imports)
+            ))
+        );
+        if let Some(index_commit) = &self.module.index_commit {
+            scheme.push_str(&format!(";; crates.io index commit {}\n\n", index_commit));
+        }
+        for package in &self.module.packages {
+            scheme.push_str(&package_definitions(package, false, &self.module.templates));
+        }
+        let tools = sexp!((list rust (list rust "cargo") #"pkg-config"));
+        let mut roots = self.roots.iter().map(|variable| symbol(variable.as_str()));
+        let environment = if self.manifest {
+            let mut manifests = vec![lexpr::Value::symbol("list")];
+            manifests.extend(roots.map(|root| sexp!((#"package->development-manifest" ,root))));
+            manifests.push(sexp!((#"packages->manifest" ,tools)));
+            sexp!((#"concatenate-manifests" ,(lexpr::Value::list(manifests))))
+        } else {
+            let root = roots
+                .next()
+                .expect("guix.scm environments have a single root");
+            sexp!((package (inherit ,root) (#"native-inputs" ,tools)))
+        };
+        scheme.push_str(&format!("{}\n", pretty_sexpr(&environment)));
+        scheme
+    }
+}
+
+/// Helper module exposing the generated packages to Scheme code through `(carguix-packages)`.
+#[derive(Debug, Clone)]
+pub struct ApiModule {
+    /// Module name, e.g. `["my", "channel", "rust-crates-api"]`.
+    pub name: Vec<String>,
+    /// Modules defining the packages.
+    pub imports: Vec<Vec<String>>,
+    /// Package variables, each one after its inputs.
+    pub variables: Vec<String>,
+}
+
+impl ApiModule {
+    pub fn new(name: Vec<String>, imports: Vec<Vec<String>>, packages: &[Package]) -> Self {
+        Self {
+            name,
+            imports,
+            variables: dependency_order(packages.to_vec())
+                .iter()
+                .map(Package::variable)
+                .collect(),
+        }
+    }
+
+    pub fn to_scheme(&self) -> String {
+        let mut header = vec![
+            lexpr::Value::symbol("define-module"),
+            lexpr::Value::list(self.name.iter().map(|part| symbol(part.as_str()))),
+        ];
+        for import in &self.imports {
+            header.push(lexpr::Value::keyword("use-module"));
+            header.push(lexpr::Value::list(
+                import.iter().map(|part| symbol(part.as_str())),
+            ));
+        }
+        let procedure = lexpr::Value::list(vec![
+            lexpr::Value::symbol("define-public"),
+            lexpr::Value::list(vec![lexpr::Value::symbol("carguix-packages")]),
+            variables_sexpr(&self.variables),
+        ]);
+        format!(
+            "{}\n\n;; Packages generated by carguix, each one after its inputs.\n{}\n",
+            pretty_sexpr(&lexpr::Value::list(header)),
+            pretty_sexpr(&procedure)
+        )
+    }
+}
+
+/// Manifest of the generated packages, for CI to fetch all their sources into the store with
+/// `guix build --sources=transitive -m`, so that later builds need no network access.
+#[derive(Debug, Clone)]
+pub struct PrefetchManifest {
+    /// Modules defining the packages.
+    pub imports: Vec<Vec<String>>,
+    /// Package variables, each one after its inputs.
+    pub variables: Vec<String>,
+}
+
+impl PrefetchManifest {
+    pub fn new(imports: Vec<Vec<String>>, packages: &[Package]) -> Self {
+        Self {
+            imports,
+            variables: dependency_order(packages.to_vec())
+                .iter()
+                .map(Package::variable)
+                .collect(),
+        }
+    }
+
+    pub fn to_scheme(&self) -> String {
+        let imports = self
+            .imports
+            .iter()
+            .map(|import| lexpr::Value::list(import.iter().map(|part| symbol(part.as_str()))))
+            .chain(iter::once(sexp!((guix profiles))));
+        let manifest = lexpr::Value::list(vec![
+            lexpr::Value::symbol("packages->manifest"),
+            variables_sexpr(&self.variables),
+        ]);
+        format!(
+            ";; Sources of the packages generated by carguix, fetched ahead of time with:\n\
+             ;;   guix build -L <channel> --sources=transitive -m <this file>\n{}\n\n{}\n",
+            pretty_sexpr(&lexpr::Value::append(
+                vec![lexpr::Value::symbol("use-modules")],
+                lexpr::Value::list(imports)
+            )),
+            pretty_sexpr(&manifest)
+        )
+    }
+}
+
+/// Definitions of a package rendered with the package template, by default each followed by a
+/// blank line.
+pub fn package_definitions(
+    package: &Package,
+    parameterized: bool,
+    templates: &Templates,
+) -> String {
+    let definitions = templates.render_package(&package.template_data(parameterized));
+    let definitions = package.comment_requirements(definitions, parameterized);
+    match (
+        package.deep_build_dependency,
+        package.build_dependency_depth,
+    ) {
+        (true, 1) => format!(
+            ";; Only needed to compile build scripts, a build-dependency away from the root\n\
+             ;; packages.\n{}",
+            definitions
+        ),
+        (true, depth) => format!(
+            ";; Only needed to compile build scripts, {} build-dependency edges away from the\n\
+             ;; root packages.\n{}",
+            depth, definitions
+        ),
+        (false, _) => definitions,
+    }
+}
+
+/// Packages no other package takes as input, the crates packaging was asked for.
+pub fn root_packages(packages: &[Package]) -> Vec<&Package> {
+    let inputs = packages
+        .iter()
+        .flat_map(|package| {
+            package
+                .cargo_inputs
+                .iter()
+                .chain(&package.cargo_development_inputs)
+        })
+        .collect::<HashSet<_>>();
+    packages
+        .iter()
+        .filter(|package| !inputs.contains(&package.crate_ref))
+        .collect()
+}
+
+/// Sort packages so that every package comes after the packages it takes as inputs, see
+/// [`dependency_order_positions`].
+fn dependency_order(packages: Vec<Package>) -> Vec<Package> {
+    let order = dependency_order_positions(&packages.iter().collect::<Vec<_>>());
+    let mut packages = packages.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|position| packages[position].take())
+        .collect()
+}
+
+/// Positions of the packages, each one after the packages it takes as inputs and the others by
+/// name and version, so that the order doesn't depend on the one the crates were reached in.
+/// Packages defined twice only keep their first position, and cycles, which dev-dependencies may
+/// introduce, are broken at the first package of the cycle in that order.
+pub fn dependency_order_positions(packages: &[&Package]) -> Vec<usize> {
+    fn visit(
+        position: usize,
+        packages: &[&Package],
+        positions: &BTreeMap<&CrateRef, usize>,
+        visited: &mut HashSet<usize>,
+        order: &mut Vec<usize>,
+    ) {
+        if !visited.insert(position) {
+            return;
+        }
+        let package = packages[position];
+        let inputs = package
+            .cargo_inputs
+            .iter()
+            .chain(&package.cargo_development_inputs)
+            .collect::<BTreeSet<_>>();
+        for input in inputs {
+            if let Some(&input_position) = positions.get(input) {
+                visit(input_position, packages, positions, visited, order);
+            }
+        }
+        order.push(position);
+    }
+
+    let mut positions = BTreeMap::new();
+    for (position, package) in packages.iter().enumerate() {
+        positions.entry(&package.crate_ref).or_insert(position);
+    }
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for &position in positions.values() {
+        visit(position, packages, &positions, &mut visited, &mut order);
+    }
+    order
+}
+
+/// `(list (search-path-specification ...) ...)`, leaving out the fields not set.
+#[allow(unused_parens)]
+fn search_paths_sexpr(search_paths: &[SearchPath]) -> lexpr::Value {
+    let specifications = search_paths.iter().map(|search_path| {
+        let mut fields = vec![
+            lexpr::Value::symbol("search-path-specification"),
+            sexp!((variable, (search_path.variable.clone()))),
+            sexp!((
+                files,
+                (lexpr::Value::append(
+                    vec![lexpr::Value::symbol("list")],
+                    lexpr::Value::list(
+                        search_path
+                            .files
+                            .iter()
+                            .map(|file| lexpr::Value::string(file.as_str()))
+                    ),
+                ))
+            )),
+        ];
+        if let Some(separator) = &search_path.separator {
+            let separator = match separator.as_str() {
+                "" => lexpr::Value::Bool(false),
+                separator => lexpr::Value::string(separator),
+            };
+            fields.push(sexp!((separator, (separator))));
+        }
+        if let Some(file_type) = &search_path.file_type {
+            fields.push(sexp!((#"file-type" (quote ,(symbol(file_type))))));
+        }
+        if let Some(file_pattern) = &search_path.file_pattern {
+            fields.push(sexp!((#"file-pattern" ,(file_pattern.clone()))));
+        }
+        lexpr::Value::list(fields)
+    });
+    lexpr::Value::append(
+        vec![lexpr::Value::symbol("list")],
+        lexpr::Value::list(specifications),
+    )
+}
+
+fn file_name(file: &str) -> String {
+    Path::new(file).file_name().map_or_else(
+        || file.to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+fn file_stem(file: &str) -> String {
+    Path::new(file).file_stem().map_or_else(
+        || file.to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    )
+}
+
+fn file_extension(file: &str) -> Option<&str> {
+    Path::new(file)
+        .extension()
+        .and_then(|extension| extension.to_str())
+}
+
+/// Form laid out like Guix sources: lists not fitting in 80 columns are broken the way the Emacs
+/// configuration of Guix indents them. Records such as `package` get their fields on lines of
+/// their own indented by two columns, definitions and procedures keeping their first argument on
+/// their line. Calls keep their first argument on their line when it fits there and align the
+/// others under it, or get them indented by one column otherwise. Keywords keep their argument.
+pub fn pretty_sexpr(value: &lexpr::Value) -> String {
+    pretty_sexpr_at(value, 0)
+}
+
+/// [`pretty_sexpr`] for a form starting at `column` of its first line, the following lines
+/// being indented accordingly.
+pub fn pretty_sexpr_at(value: &lexpr::Value, column: usize) -> String {
+    let mut pretty = String::new();
+    write_pretty_sexpr(value, column, &mut pretty);
+    pretty
+}
+
+const PRETTY_WIDTH: usize = 80;
+
+fn write_pretty_sexpr(value: &lexpr::Value, column: usize, pretty: &mut String) {
+    let flat = value.to_string();
+    let elements = match value.list_iter() {
+        Some(elements) if column + flat.len() > PRETTY_WIDTH => elements.collect::<Vec<_>>(),
+        _ => return pretty.push_str(&flat),
+    };
+    let (head, arguments) = match elements.split_first() {
+        Some(split) => split,
+        None => return pretty.push_str(&flat),
+    };
+    pretty.push('(');
+    write_pretty_sexpr(head, column + 1, pretty);
+    let mut arguments = arguments.iter().copied().peekable();
+    let head_end = column + 1 + head.to_string().len();
+    let indent = match head.as_symbol().map(distinguished_arguments) {
+        Some(Some(distinguished)) => {
+            for _ in 0..distinguished {
+                if arguments.peek().is_some() {
+                    pretty.push(' ');
+                    write_pretty_element(&mut arguments, head_end + 1, pretty);
+                }
+            }
+            column + 2
+        }
+        Some(None) => match arguments.peek() {
+            Some(first)
+                if !first.is_cons() || head_end + 1 + first.to_string().len() < PRETTY_WIDTH =>
+            {
+                pretty.push(' ');
+                write_pretty_element(&mut arguments, head_end + 1, pretty);
+                head_end + 1
+            }
+            _ => column + 1,
+        },
+        None => column + 1,
+    };
+    while arguments.peek().is_some() {
+        pretty.push('\n');
+        pretty.push_str(&" ".repeat(indent));
+        write_pretty_element(&mut arguments, indent, pretty);
+    }
+    pretty.push(')');
+}
+
+/// Write the next element of a list, along with its argument when it's a keyword.
+fn write_pretty_element<'a>(
+    elements: &mut iter::Peekable<impl Iterator<Item = &'a lexpr::Value>>,
+    column: usize,
+    pretty: &mut String,
+) {
+    let element = match elements.next() {
+        Some(element) => element,
+        None => return,
+    };
+    match elements.peek() {
+        Some(argument) if element.is_keyword() => {
+            let keyword = element.to_string();
+            pretty.push_str(&keyword);
+            pretty.push(' ');
+            write_pretty_sexpr(argument, column + keyword.len() + 1, pretty);
+            elements.next();
+        }
+        _ => write_pretty_sexpr(element, column, pretty),
+    }
+}
+
+/// Start and end of the first occurrence of a flat form in a text where it may have been laid
+/// out on several lines by [`pretty_sexpr`].
+fn find_laid_out(text: &str, flat: &str) -> Option<(usize, usize)> {
+    let mut parts = flat.split(' ');
+    let first = parts.next()?;
+    let parts = parts.collect::<Vec<_>>();
+    text.match_indices(first).find_map(|(start, _)| {
+        let mut end = start + first.len();
+        for part in &parts {
+            let rest = &text[end..];
+            let whitespace = rest.len() - rest.trim_start().len();
+            if whitespace == 0 || !rest[whitespace..].starts_with(part) {
+                return None;
+            }
+            end += whitespace + part.len();
+        }
+        Some((start, end))
+    })
+}
+
+/// Number of arguments of a special form kept on the line of its symbol, the other ones being
+/// indented by two columns, as the Emacs configuration of Guix declares them, `None` for calls.
+fn distinguished_arguments(symbol: &str) -> Option<usize> {
+    match symbol {
+        "package" | "origin" => Some(0),
+        "define" | "define-public" | "define-module" | "lambda" | "mlambda" | "let" | "let*"
+        | "modify-phases" => Some(1),
+        _ => None,
+    }
+}
+
+/// `(list a b ...)` referring to Guix variables, `variable:output` ones being referred to along
+/// with their output, e.g. `(list zstd "lib")`.
+fn variables_sexpr(variables: &[String]) -> lexpr::Value {
+    inputs_list_sexpr(Vec::new(), variables)
+}
+
+/// [`variables_sexpr`] preceded by the crate inputs listed in the `inputs` style.
+fn inputs_list_sexpr(crate_inputs: Vec<lexpr::Value>, variables: &[String]) -> lexpr::Value {
+    let variables = variables
+        .iter()
+        .map(|variable| match variable.split_once(':') {
+            Some((variable, output)) => lexpr::Value::list(vec![
+                lexpr::Value::symbol("list"),
+                symbol(variable),
+                lexpr::Value::string(output),
+            ]),
+            None => symbol(variable.as_str()),
+        });
+    lexpr::Value::append(
+        vec![lexpr::Value::symbol("list")],
+        lexpr::Value::list(crate_inputs.into_iter().chain(variables)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mangle_symbol_keeps_readable_names() {
+        for name in [
+            "rust-serde-1.0.188",
+            "rust-foo_bar-0.1.0+build.1",
+            "make-rust-a/b",
+        ] {
+            assert!(matches!(mangle_symbol(name), Cow::Borrowed(_)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn mangle_symbol_replaces_delimiters() {
+        assert_eq!(mangle_symbol("foo bar\tbaz\nqux"), "foo-bar-baz-qux");
+        assert_eq!(mangle_symbol("rust-foo(bar)-1.0.0"), "rust-foo-bar--1.0.0");
+        assert_eq!(mangle_symbol("#t"), "-t");
+        assert_eq!(mangle_symbol("rust-#:key-1.0.0"), "rust--:key-1.0.0");
+        assert_eq!(mangle_symbol("\"quoted\" 'a `b ,c"), "-quoted---a--b--c");
+        assert_eq!(mangle_symbol("a;comment|b[c]"), "a-comment-b-c-");
+    }
+
+    #[test]
+    fn mangle_symbol_names_empty_names() {
+        assert_eq!(mangle_symbol(""), "-");
+    }
+
+    #[test]
+    fn symbol_is_read_back_whole() {
+        for name in ["foo bar", "(x)", "#t", "\"q\"", "", "a;b"] {
+            let printed = lexpr::to_string(&symbol(name)).expect("symbols print");
+            let read = lexpr::from_str(&printed).expect("mangled symbols read back");
+            assert_eq!(read, symbol(name), "{:?} printed as {}", name, printed);
+            assert!(read.as_symbol().is_some(), "{:?} read as {}", name, read);
+        }
+    }
+
+    #[test]
+    fn crate_variables_are_mangled() {
+        let crate_ref = CrateRef::new("foo", "1.0.0 (x)");
+        assert_eq!(crate_ref.format_name_version(), "rust-foo-1.0.0--x-");
+        assert_eq!(crate_ref.format_procedure_name(), "make-rust-foo-1.0.0--x-");
+    }
+
+    #[test]
+    fn renamed_variables_keep_the_crate_spelled_like_the_variable() {
+        let crates = [
+            CrateRef::new("foo_bar", "1.0.0"),
+            CrateRef::new("foo-bar", "1.0.0"),
+        ];
+        let renamed = renamed_variables(&crates);
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed[&crates[0]], "rust-foo_bar-1.0.0");
+    }
+
+    #[test]
+    fn renamed_variables_avoid_mangled_collisions() {
+        // `foo bar` mangles to the variable of `foo-bar`, its own spelling being taken too
+        let crates = [
+            CrateRef::new("foo bar", "1.0.0"),
+            CrateRef::new("foo-bar", "1.0.0"),
+            CrateRef::new("foo(bar", "1.0.0"),
+        ];
+        let renamed = renamed_variables(&crates);
+        assert!(!renamed.contains_key(&crates[1]));
+        let mut variables = renamed.values().cloned().collect::<Vec<_>>();
+        variables.sort();
+        assert_eq!(
+            variables,
+            vec!["rust-foo-bar-1.0.0-2", "rust-foo-bar-1.0.0-3"]
+        );
+    }
+
+    #[test]
+    fn renamed_variables_avoid_taken_variables() {
+        // the counter skips the variable another crate already has
+        let crates = [
+            CrateRef::new("foo-bar", "1.0.0"),
+            CrateRef::new("foo bar", "1.0.0"),
+            CrateRef::new("foo-bar-1.0.0", "2"),
+        ];
+        let renamed = renamed_variables(&crates);
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed[&crates[1]], "rust-foo-bar-1.0.0-3");
+    }
+
+    #[test]
+    fn reserved_variables_are_detected() {
+        assert!(is_reserved_variable("list"));
+        assert!(is_reserved_variable("rust-sysroot"));
+        assert!(!is_reserved_variable("rust-list-1.0.0"));
+    }
+}
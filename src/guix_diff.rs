@@ -0,0 +1,231 @@
+//! Comparison of the packages a crate needs with the ones of a Guix checkout, for upstreaming.
+
+use crate::{
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    generator::Generator,
+    guix::{self, Package},
+    guix_packages::GuixPackages,
+};
+use std::{collections::BTreeMap, path::Path};
+
+/// Package of the Guix checkout a requirement was resolved to.
+#[derive(Debug, Clone)]
+pub struct CompatiblePackage {
+    pub crate_ref: CrateRef,
+    /// Variable of the package in Guix, e.g. `rust-serde-1`.
+    pub variable: String,
+    /// File of the module defining it, e.g. `gnu/packages/crates-io.scm`.
+    pub module_path: String,
+}
+
+/// Packages of a crate sorted by how they relate to a Guix checkout.
+#[derive(Debug, Clone, Default)]
+pub struct GuixDiff {
+    /// Crates resolved to a package of Guix at a compatible version.
+    pub compatible: Vec<CompatiblePackage>,
+    /// Packages defined in Guix at incompatible versions only, along with these versions.
+    pub bumps: Vec<(Package, Vec<String>)>,
+    /// Packages Guix doesn't define at any version.
+    pub missing: Vec<Package>,
+}
+
+impl GuixDiff {
+    /// Sort the packages generated with a Guix checkout, which left out the compatible ones.
+    pub fn new(generator: &Generator, packages: Vec<Package>) -> Self {
+        let mut compatible = BTreeMap::new();
+        for package in &packages {
+            for (crate_ref, existing_package) in &package.existing_inputs {
+                compatible.insert(
+                    crate_ref.clone(),
+                    CompatiblePackage {
+                        crate_ref: crate_ref.clone(),
+                        variable: existing_package.variable.clone(),
+                        module_path: format!("{}.scm", existing_package.module.join("/")),
+                    },
+                );
+            }
+        }
+        let mut diff = Self {
+            compatible: compatible.into_values().collect(),
+            ..Self::default()
+        };
+        for package in packages {
+            let guix_versions = generator.guix_versions(&package.crate_ref.format_name());
+            if guix_versions.is_empty() {
+                diff.missing.push(package);
+            } else {
+                diff.bumps.push((package, guix_versions));
+            }
+        }
+        diff.bumps
+            .sort_by(|(package, _), (other, _)| package.crate_ref.cmp(&other.crate_ref));
+        diff.missing
+            .sort_by(|package, other| package.crate_ref.cmp(&other.crate_ref));
+        diff
+    }
+
+    /// One line per package, e.g. `bump rust-syn 2.0.48 (Guix has 1.0.109)`.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for compatible in &self.compatible {
+            report.push_str(&format!(
+                "compatible {} {} ({} in {})\n",
+                compatible.crate_ref.format_name(),
+                compatible.crate_ref.version,
+                compatible.variable,
+                compatible.module_path
+            ));
+        }
+        for (package, guix_versions) in &self.bumps {
+            report.push_str(&format!(
+                "bump {} {} (Guix has {})\n",
+                package.crate_ref.format_name(),
+                package.crate_ref.version,
+                guix_versions.join(", ")
+            ));
+        }
+        for package in &self.missing {
+            report.push_str(&format!(
+                "missing {} {}\n",
+                package.crate_ref.format_name(),
+                package.crate_ref.version
+            ));
+        }
+        report
+    }
+
+    /// Definitions of the bumped and missing packages laid out like Guix sources, in
+    /// alphabetical order like `gnu/packages/crates-io.scm`, to be pasted into a Guix patch.
+    pub fn to_patch(&self) -> String {
+        let mut packages = self
+            .bumps
+            .iter()
+            .map(|(package, _)| package)
+            .chain(&self.missing)
+            .collect::<Vec<_>>();
+        packages.sort_by(|package, other| package.crate_ref.cmp(&other.crate_ref));
+        packages
+            .iter()
+            .map(|package| format!("{}\n", guix::pretty_sexpr(&package.to_sexpr())))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Hash of a generated package compared with the one of the Guix package of the same version.
+#[derive(Debug, Clone)]
+pub struct HashComparison {
+    pub crate_ref: CrateRef,
+    /// Variable of the package in Guix, e.g. `rust-serde-1`.
+    pub variable: String,
+    /// File of the module defining it, e.g. `gnu/packages/crates-io.scm`.
+    pub module_path: String,
+    pub hash: String,
+    /// Hash of the Guix package, `None` when its definition doesn't give it literally.
+    pub upstream_hash: Option<String>,
+    /// Fetch method of the Guix package when it isn't `url-fetch`, e.g. `git-fetch`.
+    pub upstream_method: Option<String>,
+    /// Whether the Guix package patches its origin or modifies it with a snippet.
+    pub upstream_patched: bool,
+}
+
+impl HashComparison {
+    /// Whether the hashes are known and differ.
+    pub fn is_mismatch(&self) -> bool {
+        self.upstream_hash
+            .as_ref()
+            .is_some_and(|upstream_hash| *upstream_hash != self.hash)
+    }
+}
+
+/// Hashes of the packages a crate needs compared with the ones of a Guix checkout defining the
+/// same versions, to catch the sources Guix fetches differently or patches.
+#[derive(Debug, Clone, Default)]
+pub struct HashReport {
+    pub comparisons: Vec<HashComparison>,
+}
+
+impl HashReport {
+    /// Compare the hashes of packages generated without reusing the ones of `guix_checkout`.
+    pub fn new(guix_checkout: &Path, packages: &[Package]) -> Result<Self, CarguixError> {
+        let guix_packages = GuixPackages::scan(guix_checkout)?;
+        let mut comparisons = packages
+            .iter()
+            .filter_map(|package| {
+                let hash = package.hash()?;
+                let crate_ref = &package.crate_ref;
+                let origin = guix_packages.origin(&crate_ref.format_name(), &crate_ref.version)?;
+                Some(HashComparison {
+                    crate_ref: crate_ref.clone(),
+                    variable: origin.package.variable.clone(),
+                    module_path: format!("{}.scm", origin.package.module.join("/")),
+                    hash: hash.to_string(),
+                    upstream_hash: origin.hash.clone(),
+                    upstream_method: origin.method.clone().filter(|method| method != "url-fetch"),
+                    upstream_patched: origin.patched,
+                })
+            })
+            .collect::<Vec<_>>();
+        comparisons.sort_by(|comparison, other| comparison.crate_ref.cmp(&other.crate_ref));
+        Ok(Self { comparisons })
+    }
+
+    pub fn mismatches(&self) -> usize {
+        self.comparisons
+            .iter()
+            .filter(|comparison| comparison.is_mismatch())
+            .count()
+    }
+
+    /// One line per package Guix defines at the same version, e.g.
+    /// `mismatch rust-foo 1.0.0: 0abc... in Guix, 1def... generated (Guix uses git-fetch)`.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for comparison in &self.comparisons {
+            let crate_ref = &comparison.crate_ref;
+            let line = match &comparison.upstream_hash {
+                None => format!(
+                    "unknown {} {} ({} in {} has no literal hash)",
+                    crate_ref.format_name(),
+                    crate_ref.version,
+                    comparison.variable,
+                    comparison.module_path
+                ),
+                Some(_) if !comparison.is_mismatch() => format!(
+                    "match {} {} ({} in {})",
+                    crate_ref.format_name(),
+                    crate_ref.version,
+                    comparison.variable,
+                    comparison.module_path
+                ),
+                Some(upstream_hash) => {
+                    let mut causes = Vec::new();
+                    if let Some(method) = &comparison.upstream_method {
+                        causes.push(format!("Guix uses {}", method));
+                    }
+                    if comparison.upstream_patched {
+                        causes.push("Guix modifies the origin".to_string());
+                    }
+                    let causes = match causes.is_empty() {
+                        true => String::new(),
+                        false => format!(" ({})", causes.join(", ")),
+                    };
+                    format!(
+                        "mismatch {} {}: {} in {} of {}, {} generated{}",
+                        crate_ref.format_name(),
+                        crate_ref.version,
+                        upstream_hash,
+                        comparison.variable,
+                        comparison.module_path,
+                        comparison.hash,
+                        causes
+                    )
+                }
+            };
+            report.push_str(&line);
+            report.push('\n');
+        }
+        report
+    }
+}
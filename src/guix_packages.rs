@@ -0,0 +1,152 @@
+use crate::errors::CarguixError;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Package already defined in Guix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExistingPackage {
+    /// Variable the package is bound to, e.g. `rust-serde-1`.
+    pub variable: String,
+    pub version: String,
+    /// Module exporting the variable, e.g. `["gnu", "packages", "crates-io"]`.
+    pub module: Vec<String>,
+}
+
+/// Origin of a package defined in Guix, as far as its definition tells with string literals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamOrigin {
+    pub package: ExistingPackage,
+    /// Fetch method, e.g. `url-fetch` or `git-fetch`.
+    pub method: Option<String>,
+    /// Base32 SHA256 hash of the origin.
+    pub hash: Option<String>,
+    /// Whether the origin is modified by patches or a snippet.
+    pub patched: bool,
+}
+
+/// Rust packages of a Guix checkout, by package name.
+#[derive(Debug, Clone, Default)]
+pub struct GuixPackages {
+    packages: HashMap<String, Vec<ExistingPackage>>,
+    /// Origins of the packages, by package name and version.
+    origins: HashMap<(String, String), UpstreamOrigin>,
+}
+
+impl GuixPackages {
+    /// Collect the `define-public` forms of the modules under `gnu/packages` whose package name
+    /// and version are string literals.
+    pub fn scan(checkout: &Path) -> Result<Self, CarguixError> {
+        let modules_path = checkout.join("gnu").join("packages");
+        let scan_error =
+            |err, path: &Path| CarguixError::GuixCheckoutError(err, path.to_path_buf());
+        let mut module_paths = fs::read_dir(&modules_path)
+            .map_err(|err| scan_error(err, &modules_path))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(|err| scan_error(err, &modules_path))?;
+        module_paths.retain(|path| path.extension().is_some_and(|extension| extension == "scm"));
+        module_paths.sort();
+        let mut guix_packages = Self::default();
+        for module_path in module_paths {
+            let content =
+                fs::read_to_string(&module_path).map_err(|err| scan_error(err, &module_path))?;
+            let module = module_path
+                .strip_prefix(checkout)
+                .unwrap_or(&module_path)
+                .with_extension("")
+                .iter()
+                .map(|part| part.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            for definition in content.split("(define-public ").skip(1) {
+                let variable = definition
+                    .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                    .next()
+                    .unwrap_or_default();
+                let fields = (
+                    string_field(definition, "name"),
+                    string_field(definition, "version"),
+                );
+                if let (Some(name), Some(version)) = fields {
+                    if name.starts_with("rust-") {
+                        let package = ExistingPackage {
+                            variable: variable.to_string(),
+                            version: version.to_string(),
+                            module: module.clone(),
+                        };
+                        let origin = UpstreamOrigin {
+                            package: package.clone(),
+                            method: symbol_field(definition, "method").map(str::to_string),
+                            hash: base32_hash(definition).map(str::to_string),
+                            patched: definition.contains("(patches ")
+                                || definition.contains("(snippet "),
+                        };
+                        guix_packages
+                            .origins
+                            .entry((name.to_string(), version.to_string()))
+                            .or_insert(origin);
+                        guix_packages
+                            .packages
+                            .entry(name.to_string())
+                            .or_default()
+                            .push(package);
+                    }
+                }
+            }
+        }
+        Ok(guix_packages)
+    }
+
+    /// Every version of a package, in the order of the modules defining them.
+    pub fn versions(&self, package_name: &str) -> &[ExistingPackage] {
+        self.packages
+            .get(package_name)
+            .map_or(&[], |packages| packages.as_slice())
+    }
+
+    /// Origin of a package at a given version, from the first module defining it.
+    pub fn origin(&self, package_name: &str, version: &str) -> Option<&UpstreamOrigin> {
+        self.origins
+            .get(&(package_name.to_string(), version.to_string()))
+    }
+
+    /// Highest version of a package matching a requirement.
+    pub fn find(&self, package_name: &str, requirement: &str) -> Option<&ExistingPackage> {
+        let requirement = VersionReq::parse(requirement).ok()?;
+        self.packages
+            .get(package_name)?
+            .iter()
+            .filter_map(|package| Some((Version::parse(&package.version).ok()?, package)))
+            .filter(|(version, _)| requirement.matches(version))
+            .max_by(|(version, _), (other_version, _)| version.cmp(other_version))
+            .map(|(_, package)| package)
+    }
+}
+
+/// Literal value of a `(<field> "<value>")` field appearing in a package definition.
+fn string_field<'a>(definition: &'a str, field: &str) -> Option<&'a str> {
+    let start = definition.find(&format!("({} \"", field))? + field.len() + 3;
+    let length = definition[start..].find('"')?;
+    Some(&definition[start..start + length])
+}
+
+/// Symbol of a `(<field> <symbol>)` field appearing in a package definition.
+fn symbol_field<'a>(definition: &'a str, field: &str) -> Option<&'a str> {
+    let start = definition.find(&format!("({} ", field))? + field.len() + 2;
+    definition[start..]
+        .split(|c: char| c.is_whitespace() || c == ')')
+        .next()
+        .filter(|symbol| !symbol.is_empty())
+}
+
+/// String of the `(base32 "<hash>")` form of a package definition, which Guix sources usually
+/// break after `base32`.
+fn base32_hash(definition: &str) -> Option<&str> {
+    let start = definition.find("(base32")? + "(base32".len();
+    let hash = definition[start..].trim_start().strip_prefix('"')?;
+    hash.split('"').next()
+}
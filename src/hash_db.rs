@@ -0,0 +1,461 @@
+use crate::errors::CarguixError;
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, Row, ToSql,
+};
+use std::{
+    convert::TryInto,
+    fmt, fs,
+    io::{self, ErrorKind},
+    mem,
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// SQLite database the hashes are cached in.
+pub const HASH_DATABASE_FILE: &str = "crates_hash.sqlite";
+/// Directory of the hash database, below the data directory.
+pub const HASHES_DIR: &str = "hashes";
+/// Database of the hashes written by former versions of carguix, migrated when found.
+const LEGACY_HASH_DATABASE_FILE: &str = "crates_hash.db";
+/// Number of hashes inserted at once, in a single transaction.
+const BATCH_SIZE: usize = 32;
+
+/// What a hash was computed over, hashes computed differently not being interchangeable even for
+/// the same crate version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashMethod {
+    /// SHA256 of the `.crate` tarball, as `url-fetch` origins check it.
+    Tarball,
+    /// SHA256 of the NAR serialization of the unpacked crate, as `guix hash -r` computes it.
+    Unpacked,
+    /// SHA256 of the NAR serialization of a git checkout, as `git-fetch` origins check it.
+    GitCheckout,
+}
+
+impl HashMethod {
+    /// Name of the method in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashMethod::Tarball => "tarball",
+            HashMethod::Unpacked => "unpacked",
+            HashMethod::GitCheckout => "git-checkout",
+        }
+    }
+}
+
+impl fmt::Display for HashMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromSql for HashMethod {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        let name = value.as_str()?;
+        [
+            HashMethod::Tarball,
+            HashMethod::Unpacked,
+            HashMethod::GitCheckout,
+        ]
+        .iter()
+        .copied()
+        .find(|method| method.as_str() == name)
+        .ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl ToSql for HashMethod {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+/// Hash recorded for a crate version, along with how and by which tool it was computed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredHash {
+    pub hash: String,
+    pub method: HashMethod,
+    /// Tool and version which computed the hash, e.g. `carguix 0.1.0`, `None` for the hashes
+    /// recorded by former versions of carguix.
+    pub tool: Option<String>,
+}
+
+impl StoredHash {
+    pub fn new(hash: &str, method: HashMethod, tool: &str) -> Self {
+        Self {
+            hash: hash.to_string(),
+            method,
+            tool: Some(tool.to_string()),
+        }
+    }
+
+    /// Hash of a tarball recorded by former versions of carguix, which only hashed tarballs.
+    fn legacy(hash: String) -> Self {
+        Self {
+            hash,
+            method: HashMethod::Tarball,
+            tool: None,
+        }
+    }
+}
+
+/// Hash of a crate version recorded in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashEntry {
+    /// Crate name, prefixed with `<registry>/` for crates of alternative registries.
+    pub name: String,
+    pub version: String,
+    pub hash: String,
+    pub method: HashMethod,
+    pub tool: Option<String>,
+}
+
+/// Hashes of the crates downloaded so far, by crate name and version.
+///
+/// The database is safe to share between threads and between carguix processes running in the
+/// same directory, SQLite locking it while it's written. Inserted hashes are written in batches.
+#[derive(Debug)]
+pub struct HashDatabase {
+    connection: Mutex<Connection>,
+    pending: Mutex<Vec<((String, String), StoredHash)>>,
+}
+
+impl HashDatabase {
+    /// Open the database in `dir`, creating it and migrating the legacy `crates_hash.db` of `dir`
+    /// into it if needed.
+    pub fn open(dir: &Path) -> Result<Self, CarguixError> {
+        let connection =
+            Connection::open(dir.join(HASH_DATABASE_FILE)).map_err(CarguixError::HashdbError)?;
+        let database = Self::with_connection(connection)?;
+        database.migrate(&dir.join(LEGACY_HASH_DATABASE_FILE))?;
+        Ok(database)
+    }
+
+    /// Open a database kept in memory, standing in until the one of the data directory is opened.
+    pub fn in_memory() -> Result<Self, CarguixError> {
+        Self::with_connection(Connection::open_in_memory().map_err(CarguixError::HashdbError)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, CarguixError> {
+        connection
+            .busy_timeout(Duration::from_secs(60))
+            .and_then(|()| connection.pragma_update(None, "journal_mode", "WAL"))
+            .and_then(|()| {
+                connection.execute_batch(
+                    "CREATE TABLE IF NOT EXISTS crate_hashes (
+                        name TEXT NOT NULL,
+                        version TEXT NOT NULL,
+                        hash TEXT NOT NULL,
+                        method TEXT NOT NULL DEFAULT 'tarball',
+                        tool TEXT,
+                        PRIMARY KEY (name, version)
+                    )",
+                )
+            })
+            .and_then(|()| add_hash_method_columns(&connection))
+            .map_err(CarguixError::HashdbError)?;
+        let database = Self {
+            connection: Mutex::new(connection),
+            pending: Mutex::new(Vec::new()),
+        };
+        Ok(database)
+    }
+
+    /// Import the hashes of a legacy rustbreak database, which is removed afterwards.
+    fn migrate(&self, legacy_path: &Path) -> Result<(), CarguixError> {
+        let migration_error = |err| CarguixError::HashdbMigrationError(err, legacy_path.into());
+        let content = match fs::read(legacy_path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(migration_error(err)),
+        };
+        let hashes = read_legacy_hashes(&content).map_err(migration_error)?;
+        let hashes = hashes
+            .into_iter()
+            .map(|(key, hash)| (key, StoredHash::legacy(hash)))
+            .collect::<Vec<_>>();
+        log::info!(
+            "migrating {} hashes from {:?} to {}",
+            hashes.len(),
+            legacy_path,
+            HASH_DATABASE_FILE
+        );
+        self.pending
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend(hashes);
+        self.flush()?;
+        match fs::remove_file(legacy_path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(migration_error(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Import the hashes former versions of carguix kept in the databases of `dir`, their working
+    /// directory, which are removed afterwards. The method and tool of the hashes are kept when
+    /// the database records them, the hashes being taken for tarball ones otherwise.
+    pub fn import_legacy(&self, dir: &Path) -> Result<(), CarguixError> {
+        self.migrate(&dir.join(LEGACY_HASH_DATABASE_FILE))?;
+        let legacy_path = dir.join(HASH_DATABASE_FILE);
+        if !legacy_path.is_file() {
+            return Ok(());
+        }
+        log::info!("importing the hashes of {:?}", legacy_path);
+        self.flush()?;
+        {
+            let connection = self
+                .connection
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            connection
+                .execute(
+                    "ATTACH DATABASE ?1 AS legacy",
+                    params![legacy_path.to_string_lossy()],
+                )
+                .and_then(|_| {
+                    let has_method = connection.query_row(
+                        "SELECT COUNT(*) FROM pragma_table_info('crate_hashes', 'legacy')
+                            WHERE name = 'method'",
+                        [],
+                        |row| row.get::<_, i64>(0),
+                    )? > 0;
+                    let insert = if has_method {
+                        "INSERT OR IGNORE INTO crate_hashes (name, version, hash, method, tool)
+                            SELECT name, version, hash, method, tool FROM legacy.crate_hashes"
+                    } else {
+                        "INSERT OR IGNORE INTO crate_hashes (name, version, hash)
+                            SELECT name, version, hash FROM legacy.crate_hashes"
+                    };
+                    connection.execute(insert, [])
+                })
+                .and_then(|_| connection.execute("DETACH DATABASE legacy", []))
+                .map_err(CarguixError::HashdbError)?;
+        }
+        for suffix in &["", "-wal", "-shm"] {
+            let path = dir.join(format!("{}{}", HASH_DATABASE_FILE, suffix));
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    return Err(CarguixError::HashdbMigrationError(err, path))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Hash of a crate computed with `method`, if it has been computed already. A hash computed
+    /// with another method is stale, the crate being hashed again and its entry replaced.
+    pub fn get(
+        &self,
+        key: &(String, String),
+        method: HashMethod,
+    ) -> Result<Option<String>, CarguixError> {
+        let pending = self.pending.lock().unwrap_or_else(|err| err.into_inner());
+        let stored = match pending
+            .iter()
+            .rev()
+            .find(|(pending_key, _)| pending_key == key)
+        {
+            Some((_, stored)) => Some(stored.clone()),
+            None => {
+                drop(pending);
+                self.connection
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .query_row(
+                        "SELECT hash, method, tool FROM crate_hashes
+                        WHERE name = ?1 AND version = ?2",
+                        params![key.0, key.1],
+                        |row| stored_hash(row, 0),
+                    )
+                    .optional()
+                    .map_err(|err| CarguixError::HashRetrieveFailed(err, key.clone()))?
+            }
+        };
+        match stored {
+            Some(stored) if stored.method != method => {
+                log::info!(
+                    "ignoring the {} hash of crate {} in version {}, a {} hash being expected",
+                    stored.method,
+                    key.0,
+                    key.1,
+                    method
+                );
+                Ok(None)
+            }
+            stored => Ok(stored.map(|stored| stored.hash)),
+        }
+    }
+
+    /// Every hash recorded, or the ones of the crate named `crate_name`, sorted by crate and
+    /// version.
+    pub fn entries(&self, crate_name: Option<&str>) -> Result<Vec<HashEntry>, CarguixError> {
+        self.flush()?;
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let mut statement = connection
+            .prepare(
+                "SELECT name, version, hash, method, tool FROM crate_hashes
+                WHERE ?1 IS NULL OR name = ?1 ORDER BY name, version",
+            )
+            .map_err(CarguixError::HashListFailed)?;
+        let entries = statement
+            .query_map(params![crate_name], |row| {
+                let stored = stored_hash(row, 2)?;
+                Ok(HashEntry {
+                    name: row.get(0)?,
+                    version: row.get(1)?,
+                    hash: stored.hash,
+                    method: stored.method,
+                    tool: stored.tool,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(CarguixError::HashListFailed)?;
+        Ok(entries)
+    }
+
+    /// Forget the hashes of a crate, in every version unless `version` is given, returning how
+    /// many were removed.
+    pub fn purge(&self, crate_name: &str, version: Option<&str>) -> Result<usize, CarguixError> {
+        self.flush()?;
+        self.connection
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .execute(
+                "DELETE FROM crate_hashes WHERE name = ?1 AND (?2 IS NULL OR version = ?2)",
+                params![crate_name, version],
+            )
+            .map_err(|err| CarguixError::HashPurgeFailed(err, crate_name.to_string()))
+    }
+
+    /// Record the hash of a crate, written along with the next batch.
+    pub fn insert(&self, key: &(String, String), hash: StoredHash) -> Result<(), CarguixError> {
+        let mut pending = self.pending.lock().unwrap_or_else(|err| err.into_inner());
+        pending.push((key.clone(), hash));
+        if pending.len() < BATCH_SIZE {
+            return Ok(());
+        }
+        let batch = mem::take(&mut *pending);
+        drop(pending);
+        self.write(batch)
+    }
+
+    /// Write the hashes inserted since the last batch.
+    pub fn flush(&self) -> Result<(), CarguixError> {
+        let batch = mem::take(&mut *self.pending.lock().unwrap_or_else(|err| err.into_inner()));
+        self.write(batch)
+    }
+
+    fn write(&self, batch: Vec<((String, String), StoredHash)>) -> Result<(), CarguixError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let transaction = connection
+            .transaction()
+            .map_err(CarguixError::HashDatabaseFlushFailed)?;
+        for ((name, version), stored) in &batch {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO crate_hashes (name, version, hash, method, tool)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![name, version, stored.hash, stored.method, stored.tool],
+                )
+                .map_err(|err| {
+                    CarguixError::HashInsertionFailed(err, (name.clone(), version.clone()))
+                })?;
+        }
+        transaction
+            .commit()
+            .map_err(CarguixError::HashDatabaseFlushFailed)
+    }
+}
+
+impl Drop for HashDatabase {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            crate::print_error(&err);
+        }
+    }
+}
+
+/// Stored hash of the `hash`, `method` and `tool` columns of a row, starting at column `first`.
+fn stored_hash(row: &Row, first: usize) -> rusqlite::Result<StoredHash> {
+    Ok(StoredHash {
+        hash: row.get(first)?,
+        method: row.get(first + 1)?,
+        tool: row.get(first + 2)?,
+    })
+}
+
+/// Add the `method` and `tool` columns to the table of databases written before hashes were
+/// recorded along with how they were computed, their hashes being the ones of tarballs.
+fn add_hash_method_columns(connection: &Connection) -> rusqlite::Result<()> {
+    let has_method = connection.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('crate_hashes') WHERE name = 'method'",
+        [],
+        |row| row.get::<_, i64>(0),
+    )? > 0;
+    if has_method {
+        return Ok(());
+    }
+    connection.execute_batch(
+        "ALTER TABLE crate_hashes ADD COLUMN method TEXT NOT NULL DEFAULT 'tarball';
+        ALTER TABLE crate_hashes ADD COLUMN tool TEXT;",
+    )
+}
+
+/// Hashes of a rustbreak database, a bincode encoded map from `(name, version)` keys to
+/// bincode encoded hashes.
+fn read_legacy_hashes(content: &[u8]) -> io::Result<Vec<((String, String), String)>> {
+    let mut reader = LegacyReader(content);
+    let mut hashes = Vec::new();
+    if content.is_empty() {
+        return Ok(hashes);
+    }
+    for _ in 0..reader.length()? {
+        let name = reader.string()?;
+        let version = reader.string()?;
+        let _value_length = reader.length()?;
+        let hash = reader.string()?;
+        hashes.push(((name, version), hash));
+    }
+    Ok(hashes)
+}
+
+/// Reader of bincode values, lengths being encoded as little-endian `u64`.
+struct LegacyReader<'a>(&'a [u8]);
+
+impl LegacyReader<'_> {
+    fn bytes(&mut self, count: usize) -> io::Result<&[u8]> {
+        if self.0.len() < count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "truncated hash database",
+            ));
+        }
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn length(&mut self) -> io::Result<usize> {
+        let bytes = self.bytes(8)?.try_into().expect("8 bytes were read");
+        Ok(u64::from_le_bytes(bytes) as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.length()?;
+        String::from_utf8(self.bytes(length)?.to_vec())
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+    }
+}
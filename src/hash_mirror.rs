@@ -0,0 +1,103 @@
+//! Read-only HTTP service sharing a hash database, so that a crate hashed by one member of a team
+//! isn't downloaded again by the others.
+//!
+//! The hash of a crate version is served as plain text at `<url>/<name>/<version>`, the name being
+//! prefixed with `<registry>/` for crates of alternative registries like in the database, and
+//! unknown versions answer `404 Not Found`.
+
+use crate::{
+    errors::CarguixError,
+    hash_db::{HashDatabase, HashMethod},
+};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+/// URL of the hash of a crate version on the mirror at `mirror`.
+pub fn hash_url(mirror: &str, key: &(String, String)) -> String {
+    format!("{}/{}/{}", mirror.trim_end_matches('/'), key.0, key.1)
+}
+
+/// Hash of a crate version served by the mirror at `mirror`, `None` if it doesn't know it.
+pub fn fetch(
+    client: &reqwest::Client,
+    mirror: &str,
+    key: &(String, String),
+) -> Result<Option<String>, reqwest::Error> {
+    let response = client.get(&hash_url(mirror, key)).send()?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let hash = response.error_for_status()?.text()?;
+    Ok(Some(hash.trim().to_string()))
+}
+
+/// Serve the hashes of `hashdb` on `address`, e.g. `0.0.0.0:8080`, until the process is killed.
+///
+/// Hashes inserted into the database by other carguix processes are served as soon as they are
+/// written.
+pub fn serve(hashdb: &HashDatabase, address: &str) -> Result<(), CarguixError> {
+    let listener = TcpListener::bind(address)
+        .map_err(|err| CarguixError::HashMirrorBindError(err, address.to_string()))?;
+    log::info!("serving hashes on {}", address);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(move || {
+                        if let Err(err) = respond(hashdb, stream) {
+                            log::warn!("could not answer a hash request: {}", err);
+                        }
+                    });
+                }
+                Err(err) => log::warn!("could not accept a hash request: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer a single `GET` request, the connection being closed afterwards.
+fn respond(hashdb: &HashDatabase, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // headers are irrelevant but read anyway, so that closing the connection doesn't reset it
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+    let mut request = request_line.split_whitespace();
+    let (status, body) = match (request.next(), request.next()) {
+        (Some("GET"), Some(path)) => match path.trim_start_matches('/').rsplit_once('/') {
+            Some((name, version)) if !name.is_empty() && !version.is_empty() => {
+                match hashdb.get(
+                    &(name.to_string(), version.to_string()),
+                    HashMethod::Tarball,
+                ) {
+                    Ok(Some(hash)) => ("200 OK", hash),
+                    Ok(None) => ("404 Not Found", format!("no hash of {} {}", name, version)),
+                    Err(err) => {
+                        crate::print_error(&err);
+                        ("500 Internal Server Error", err.to_string())
+                    }
+                }
+            }
+            _ => ("404 Not Found", "expected /<name>/<version>".to_string()),
+        },
+        _ => ("405 Method Not Allowed", "only GET is served".to_string()),
+    };
+    log::debug!("{} {}", request_line.trim(), status);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        body.len() + 1,
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,390 @@
+use crate::{download, errors::CarguixError, tooling::Tooling};
+use crates_index::Index;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+pub(crate) const INDEX_GIT_URL: &str = "https://github.com/rust-lang/crates.io-index";
+const SPARSE_INDEX_URL: &str = "https://index.crates.io";
+
+/// Dependency of a crate version as described in the index.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IndexedDependency {
+    /// Name the dependency is known as in the dependent crate, possibly renamed.
+    name: String,
+    req: String,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default = "default_true")]
+    default_features: bool,
+    #[serde(default)]
+    kind: Option<String>,
+    /// Actual crate name when the dependency is renamed.
+    #[serde(default)]
+    package: Option<String>,
+    /// Index URL of the registry the dependency comes from, when it isn't the registry of the
+    /// dependent crate.
+    #[serde(default)]
+    registry: Option<String>,
+    /// Target triple or `cfg(...)` expression the dependency is restricted to.
+    #[serde(default)]
+    target: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl IndexedDependency {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn crate_name(&self) -> &str {
+        self.package.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn requirement(&self) -> &str {
+        &self.req
+    }
+
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    pub fn has_default_features(&self) -> bool {
+        self.default_features
+    }
+
+    pub fn kind(&self) -> Option<&str> {
+        self.kind.as_deref()
+    }
+
+    pub fn registry(&self) -> Option<&str> {
+        self.registry.as_deref()
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+}
+
+/// Crate version as described by a line of its index file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IndexedVersion {
+    name: String,
+    vers: String,
+    #[serde(default)]
+    deps: Vec<IndexedDependency>,
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+    /// Features using the `dep:` and `?/` syntaxes, kept apart for older cargo versions.
+    #[serde(default)]
+    features2: BTreeMap<String, Vec<String>>,
+    /// SHA-256 checksum of the crate tarball.
+    #[serde(default)]
+    cksum: String,
+    /// Whether the version was yanked, new dependents being expected not to use it.
+    #[serde(default)]
+    yanked: bool,
+    /// System library the crate binds, such as `openssl` for `openssl-sys`.
+    #[serde(default)]
+    links: Option<String>,
+}
+
+impl IndexedVersion {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.vers
+    }
+
+    pub fn dependencies(&self) -> &[IndexedDependency] {
+        &self.deps
+    }
+
+    pub fn checksum(&self) -> &str {
+        &self.cksum
+    }
+
+    pub fn is_yanked(&self) -> bool {
+        self.yanked
+    }
+
+    pub fn links(&self) -> Option<&str> {
+        self.links.as_deref()
+    }
+
+    /// Feature table, including the `features2` entries.
+    pub fn features(&self) -> BTreeMap<String, Vec<String>> {
+        let mut features = self.features.clone();
+        features.extend(self.features2.clone());
+        features
+    }
+}
+
+/// Every published version of a crate, in publication order.
+#[derive(Debug, Clone)]
+pub struct IndexedCrate {
+    versions: Vec<IndexedVersion>,
+}
+
+impl IndexedCrate {
+    /// Versions listed by the index file of a crate, malformed lines being skipped with a
+    /// warning. Index files listing no valid version are an error.
+    fn parse(crate_name: &str, content: &str) -> Result<Self, CarguixError> {
+        let mut versions = Vec::new();
+        for (line_number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(version) => versions.push(version),
+                Err(err) => log::warn!(
+                    "skipping malformed line {} of the index file of crate {}: {}",
+                    line_number + 1,
+                    crate_name,
+                    err
+                ),
+            }
+        }
+        if versions.is_empty() {
+            return Err(CarguixError::EmptyIndexEntry(crate_name.to_string()));
+        }
+        Ok(Self { versions })
+    }
+
+    pub fn name(&self) -> &str {
+        self.latest_version().name()
+    }
+
+    pub fn versions(&self) -> &[IndexedVersion] {
+        &self.versions
+    }
+
+    /// Last published version, like `crates_index` defines it.
+    pub fn latest_version(&self) -> &IndexedVersion {
+        self.versions
+            .last()
+            .expect("parsing rejects index files without versions")
+    }
+
+    pub fn is_all_yanked(&self) -> bool {
+        self.versions.iter().all(IndexedVersion::is_yanked)
+    }
+}
+
+/// Backend crate versions and dependencies are looked up in.
+#[derive(Debug)]
+pub enum Registry {
+    /// Local clone of the git index, holding every crate.
+    Git(Index),
+    /// HTTP sparse index, whose crate files are fetched on demand and kept for the run, or a
+    /// `file://` directory laid out like one.
+    Sparse {
+        client: reqwest::Client,
+        /// Root of the index, without the `sparse+` prefix.
+        url: String,
+        /// Sent in the `Authorization` header, for registries requiring authentication.
+        token: Option<String>,
+        crates: Mutex<HashMap<String, IndexedCrate>>,
+    },
+}
+
+impl Registry {
+    pub fn git(path: &Path) -> Self {
+        Registry::Git(Index::new(path))
+    }
+
+    /// Sparse index of crates.io.
+    pub fn sparse() -> Self {
+        Self::sparse_with_url(SPARSE_INDEX_URL, None)
+    }
+
+    /// Sparse index rooted at `url`, such as the one of an alternative registry.
+    pub fn sparse_with_url(url: &str, token: Option<String>) -> Self {
+        Registry::Sparse {
+            client: download::http_client(),
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            crates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn git_index(&self) -> Option<&Index> {
+        match self {
+            Registry::Git(index) => Some(index),
+            Registry::Sparse { .. } => None,
+        }
+    }
+
+    /// Crate of the given name or, failing that, of the name with its `_` replaced by `-` or
+    /// the other way around, names being compared case-insensitively like crates.io does.
+    ///
+    /// The name of the crate returned is the one it's published under.
+    pub fn crate_(&self, crate_name: &str) -> Result<IndexedCrate, CarguixError> {
+        let not_found = match self.exact_crate(crate_name) {
+            Err(err @ CarguixError::CrateNotFound(_)) => err,
+            result => return result,
+        };
+        for normalized_name in [crate_name.replace('_', "-"), crate_name.replace('-', "_")] {
+            if normalized_name == crate_name {
+                continue;
+            }
+            match self.exact_crate(&normalized_name) {
+                Ok(crate_) => {
+                    log::warn!(
+                        "crate {} not found, using crate {} instead",
+                        crate_name,
+                        crate_.name()
+                    );
+                    return Ok(crate_);
+                }
+                Err(CarguixError::CrateNotFound(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Err(not_found)
+    }
+
+    fn exact_crate(&self, crate_name: &str) -> Result<IndexedCrate, CarguixError> {
+        let not_found = || CarguixError::CrateNotFound(crate_name.to_string());
+        // crate names are ASCII, which `index_file_path` relies on
+        if crate_name.is_empty() || !crate_name.is_ascii() {
+            return Err(not_found());
+        }
+        let crate_ = match self {
+            Registry::Git(index) => {
+                match fs::read_to_string(index.path().join(index_file_path(crate_name))) {
+                    Ok(content) => IndexedCrate::parse(crate_name, &content)?,
+                    Err(err) if err.kind() == ErrorKind::NotFound => return Err(not_found()),
+                    Err(err) => {
+                        return Err(CarguixError::IndexFileError(err, crate_name.to_string()))
+                    }
+                }
+            }
+            Registry::Sparse {
+                client,
+                url,
+                token,
+                crates,
+            } => {
+                let mut crates = crates.lock().unwrap_or_else(|err| err.into_inner());
+                if let Some(crate_) = crates.get(crate_name) {
+                    return Ok(crate_.clone());
+                }
+                let file_url = format!("{}/{}", url, index_file_path(crate_name).to_string_lossy());
+                let crate_ = fetch_sparse_index_file(client, &file_url, token, crate_name)?
+                    .ok_or_else(not_found)?;
+                crates.insert(crate_name.to_string(), crate_.clone());
+                crate_
+            }
+        };
+        Ok(crate_)
+    }
+}
+
+fn fetch_sparse_index_file(
+    client: &reqwest::Client,
+    url: &str,
+    token: &Option<String>,
+    crate_name: &str,
+) -> Result<Option<IndexedCrate>, CarguixError> {
+    log::debug!("fetching {}", url);
+    if let Some(path) = url.strip_prefix("file://") {
+        return match fs::read_to_string(path) {
+            Ok(content) => IndexedCrate::parse(crate_name, &content).map(Some),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(CarguixError::IndexFileError(err, crate_name.to_string())),
+        };
+    }
+    let fetch_error = |err| CarguixError::SparseIndexError(err, crate_name.to_string());
+    let mut request = client.get(url);
+    if let Some(token) = token {
+        request = request.header(reqwest::header::AUTHORIZATION, token.as_str());
+    }
+    let response = request.send().map_err(fetch_error)?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let content = response
+        .error_for_status()
+        .and_then(|mut response| response.text())
+        .map_err(fetch_error)?;
+    IndexedCrate::parse(crate_name, &content).map(Some)
+}
+
+/// Path of the file describing a crate, relative to the root of the index, for a non-empty ASCII
+/// name.
+pub fn index_file_path(crate_name: &str) -> PathBuf {
+    let name = crate_name.to_ascii_lowercase();
+    match name.len() {
+        1 => Path::new("1").to_path_buf(),
+        2 => Path::new("2").to_path_buf(),
+        3 => Path::new("3").join(&name[0..1]),
+        _ => Path::new(&name[0..2]).join(&name[2..4]),
+    }
+    .join(name)
+}
+
+/// Commit the index clone is checked out at.
+pub fn head_commit(index: &Index, tooling: &dyn Tooling) -> Result<String, CarguixError> {
+    tooling
+        .git_head(index.path())
+        .map_err(CarguixError::IndexGitError)
+}
+
+/// Clone or update the index and return the commit it is now checked out at.
+///
+/// Updates must fast-forward the current checkout so that a tampered history can't silently
+/// replace the checksums carguix already trusted, unless `allow_rewrite` is set (crates.io
+/// occasionally squashes the index history).
+pub fn update(
+    index: &Index,
+    allow_rewrite: bool,
+    tooling: &dyn Tooling,
+) -> Result<String, CarguixError> {
+    if !index.exists() {
+        return tooling
+            .git_clone(INDEX_GIT_URL, "HEAD", index.path())
+            .map_err(CarguixError::IndexUpdateError);
+    }
+    let previous_commit = head_commit(index, tooling)?;
+    let fetched_commit = tooling
+        .git_fetch(index.path(), INDEX_GIT_URL, "master")
+        .map_err(CarguixError::IndexGitError)?;
+    let fast_forward = fetched_commit == previous_commit
+        || tooling
+            .git_descends_from(index.path(), &fetched_commit, &previous_commit)
+            .map_err(CarguixError::IndexGitError)?;
+    if !fast_forward {
+        if !allow_rewrite {
+            return Err(CarguixError::IndexNotFastForward {
+                previous: previous_commit,
+                fetched: fetched_commit,
+            });
+        }
+        log::warn!(
+            "index history rewritten from {} to {}",
+            previous_commit,
+            fetched_commit
+        );
+    }
+    tooling
+        .git_reset(index.path(), &fetched_commit)
+        .map_err(CarguixError::IndexGitError)?;
+    Ok(fetched_commit)
+}
@@ -0,0 +1,321 @@
+use crate::{
+    cargo_lock::CargoLock,
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    features::Features,
+    path_source::{ManifestCache, PathSource},
+    registry_source::RegistrySource,
+    source::DependencyKind,
+    Generator,
+};
+use cargo_toml::{DepsSet, Manifest};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::Path,
+};
+
+/// Crate as carguix reads it from its manifest or from the index, with the features the selection
+/// enables and the dependencies it declares, nothing being resolved. A debugging aid for when the
+/// resolution goes wrong.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Inspection {
+    pub crate_ref: CrateRef,
+    /// Where the crate was read from, e.g. `path /src/app` or `crates.io`.
+    pub origin: String,
+    /// `[features]` table of the crate.
+    pub features: BTreeMap<String, Vec<String>>,
+    /// Features of the table the selection enables, directly or not.
+    pub enabled_features: BTreeSet<String>,
+    pub dependencies: Vec<DeclaredDependency>,
+}
+
+/// JSON document of `carguix inspect --json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct InspectionReport {
+    pub crates: Vec<Inspection>,
+}
+
+/// Dependency as declared by the manifest or the index, before its requirement is resolved.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeclaredDependency {
+    /// Name the dependent knows the dependency as.
+    pub name: String,
+    /// Actual name of the crate, differing from `name` when it is renamed.
+    pub crate_name: String,
+    /// Version requirement, `None` for path dependencies without one.
+    pub requirement: Option<String>,
+    pub kind: DependencyKind,
+    /// Where the dependency comes from, e.g. `crates.io`, `registry internal` or `path ../core`.
+    pub source: String,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+    /// Target triple or `cfg(...)` expression the dependency is restricted to.
+    pub target: Option<String>,
+    /// Whether the selected features enable it, optional dependencies being disabled otherwise.
+    pub enabled: bool,
+    /// Versions of the crate `Cargo.lock` lists, for local crates.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locked: Vec<String>,
+}
+
+impl Inspection {
+    /// Crate at `path`, or every member if `path` is a workspace root, along with the versions
+    /// the `Cargo.lock` file of the crate or of its workspace locks.
+    pub fn from_path(
+        path: &Path,
+        features: &Features,
+        manifests: &mut ManifestCache,
+    ) -> Result<Vec<Self>, CarguixError> {
+        PathSource::from_path_cached(path, features, manifests)?
+            .iter()
+            .map(|source| {
+                let lock_path = source
+                    .workspace
+                    .as_ref()
+                    .map_or(source.path.clone(), |workspace| workspace.root.clone())
+                    .join("Cargo.lock");
+                let lock = match lock_path.is_file() {
+                    true => CargoLock::open(&lock_path)?,
+                    false => CargoLock::default(),
+                };
+                Ok(Self::from_manifest(
+                    &source.manifest,
+                    &source.path,
+                    features,
+                    &lock,
+                ))
+            })
+            .collect()
+    }
+
+    fn from_manifest(
+        manifest: &Manifest,
+        path: &Path,
+        features: &Features,
+        lock: &CargoLock,
+    ) -> Self {
+        let package = manifest.package();
+        let crate_ref = CrateRef::new(&package.name, package.version());
+        let mut tables = vec![
+            (&manifest.dependencies, DependencyKind::Normal, None),
+            (&manifest.build_dependencies, DependencyKind::Build, None),
+            (
+                &manifest.dev_dependencies,
+                DependencyKind::Development,
+                None,
+            ),
+        ];
+        for (target, table) in &manifest.target {
+            tables.extend(vec![
+                (&table.dependencies, DependencyKind::Normal, Some(target)),
+                (
+                    &table.build_dependencies,
+                    DependencyKind::Build,
+                    Some(target),
+                ),
+                (
+                    &table.dev_dependencies,
+                    DependencyKind::Development,
+                    Some(target),
+                ),
+            ]);
+        }
+        let optional_dependencies = tables
+            .iter()
+            .flat_map(|(table, _, _)| table.iter())
+            .filter(|(_, dependency)| dependency.optional())
+            .map(|(name, _)| name.clone())
+            .collect::<HashSet<_>>();
+        let activation =
+            features.activate(&crate_ref.name, &manifest.features, &optional_dependencies);
+        let enabled = &activation;
+        let dependencies = tables
+            .into_iter()
+            .flat_map(|(table, kind, target): (&DepsSet, _, _)| {
+                table.iter().map(move |(name, dependency)| {
+                    let detail = dependency.detail();
+                    let crate_name = detail
+                        .and_then(|detail| detail.package.clone())
+                        .unwrap_or_else(|| name.clone());
+                    let source = match detail {
+                        Some(detail) if detail.path.is_some() => {
+                            format!("path {}", detail.path.as_deref().unwrap_or_default())
+                        }
+                        Some(detail) if detail.git.is_some() => {
+                            let revision = detail
+                                .rev
+                                .as_ref()
+                                .or(detail.tag.as_ref())
+                                .or(detail.branch.as_ref())
+                                .map(|revision| format!(" {}", revision))
+                                .unwrap_or_default();
+                            format!(
+                                "git {}{}",
+                                detail.git.as_deref().unwrap_or_default(),
+                                revision
+                            )
+                        }
+                        Some(detail) if detail.registry.is_some() => format!(
+                            "registry {}",
+                            detail.registry.as_deref().unwrap_or_default()
+                        ),
+                        _ => "crates.io".to_string(),
+                    };
+                    let requirement = match detail {
+                        Some(detail) if detail.path.is_some() || detail.git.is_some() => {
+                            detail.version.clone()
+                        }
+                        _ => Some(dependency.req().to_string()),
+                    };
+                    let locked = lock
+                        .package
+                        .iter()
+                        .filter(|locked| locked.name == crate_name)
+                        .map(|locked| locked.version.clone())
+                        .collect();
+                    DeclaredDependency {
+                        name: name.clone(),
+                        crate_name,
+                        requirement,
+                        kind,
+                        source,
+                        optional: dependency.optional(),
+                        default_features: detail.is_none_or(|detail| detail.default_features),
+                        features: dependency.req_features().to_vec(),
+                        target: target.cloned(),
+                        enabled: enabled.is_enabled(name, dependency.optional()),
+                        locked,
+                    }
+                })
+            })
+            .collect();
+        Self {
+            crate_ref,
+            origin: format!("path {}", path.display()),
+            features: manifest.features.clone(),
+            enabled_features: activation.features,
+            dependencies,
+        }
+    }
+
+    /// Version of a crate published in the index of crates.io or of an alternative registry, the
+    /// one a generation would pick if none is given.
+    pub fn from_index(
+        generator: &Generator,
+        crate_name: &str,
+        version: Option<&str>,
+        registry: Option<&str>,
+        features: &Features,
+    ) -> Result<Self, CarguixError> {
+        let crate_ = generator.registry_index(registry)?.crate_(crate_name)?;
+        let crate_version = match version {
+            Some(version) => crate_
+                .versions()
+                .iter()
+                .find(|crate_version| crate_version.version() == version)
+                .ok_or_else(|| CarguixError::NoMatchingVersion {
+                    name: crate_.name().to_string(),
+                    version: version.to_string(),
+                })?,
+            None => RegistrySource::default_version(&crate_, &generator.options)?,
+        };
+        let optional_dependencies = crate_version
+            .dependencies()
+            .iter()
+            .filter(|dependency| dependency.is_optional())
+            .map(|dependency| dependency.name().to_string())
+            .collect::<HashSet<_>>();
+        let feature_map = crate_version.features();
+        let activation = features.activate(crate_.name(), &feature_map, &optional_dependencies);
+        let dependencies = crate_version
+            .dependencies()
+            .iter()
+            .map(|dependency| DeclaredDependency {
+                name: dependency.name().to_string(),
+                crate_name: dependency.crate_name().to_string(),
+                requirement: Some(dependency.requirement().to_string()),
+                kind: DependencyKind::from_index(dependency.kind()),
+                source: match dependency.registry() {
+                    Some(registry) => format!("registry {}", registry),
+                    None => "crates.io".to_string(),
+                },
+                optional: dependency.is_optional(),
+                default_features: dependency.has_default_features(),
+                features: dependency.features().to_vec(),
+                target: dependency.target().map(str::to_string),
+                enabled: activation.is_enabled(dependency.name(), dependency.is_optional()),
+                locked: Vec::new(),
+            })
+            .collect();
+        Ok(Self {
+            crate_ref: CrateRef::new(crate_.name(), crate_version.version()),
+            origin: registry.map_or("crates.io".to_string(), |registry| {
+                format!("registry {}", registry)
+            }),
+            features: feature_map,
+            enabled_features: activation.features,
+            dependencies,
+        })
+    }
+
+    /// Human-readable report, one line per feature and per dependency.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{} v{} ({})\n",
+            self.crate_ref.name, self.crate_ref.version, self.origin
+        );
+        if !self.features.is_empty() {
+            text.push_str("features:\n");
+            for (feature, implied) in &self.features {
+                let enabled = match self.enabled_features.contains(feature) {
+                    true => " (enabled)",
+                    false => "",
+                };
+                text.push_str(&format!(
+                    "  {} = [{}]{}\n",
+                    feature,
+                    implied.join(", "),
+                    enabled
+                ));
+            }
+        }
+        if !self.dependencies.is_empty() {
+            text.push_str("dependencies:\n");
+        }
+        for dependency in &self.dependencies {
+            let mut line = format!("  {:<6} {}", dependency.kind.as_str(), dependency.name);
+            if dependency.crate_name != dependency.name {
+                line.push_str(&format!(" (crate {})", dependency.crate_name));
+            }
+            if let Some(requirement) = &dependency.requirement {
+                line.push_str(&format!(" {}", requirement));
+            }
+            line.push_str(&format!(" from {}", dependency.source));
+            if let Some(target) = &dependency.target {
+                line.push_str(&format!(", for {}", target));
+            }
+            if dependency.optional {
+                line.push_str(", optional");
+            }
+            if !dependency.default_features {
+                line.push_str(", no default features");
+            }
+            if !dependency.features.is_empty() {
+                line.push_str(&format!(", features {}", dependency.features.join(", ")));
+            }
+            if !dependency.locked.is_empty() {
+                line.push_str(&format!(", locked {}", dependency.locked.join(", ")));
+            }
+            if !dependency.enabled {
+                line.push_str(", disabled");
+            }
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+}
@@ -0,0 +1,292 @@
+//! Generation of Guix package definitions for Rust crates.
+//!
+//! [`generate`] turns a [`PackageSpec`] into a complete [`guix::Module`], while [`Generator`]
+//! gives access to the packages themselves as they are resolved.
+
+mod cargo_lock;
+mod cargo_metadata;
+mod carguix_lock;
+pub mod channel;
+pub mod config;
+pub mod crate_ref;
+mod download;
+pub mod enrichers;
+pub mod errors;
+pub mod example;
+pub mod features;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frozen;
+pub mod generator;
+pub mod graph;
+pub mod guix;
+pub mod guix_diff;
+mod guix_packages;
+pub mod hash_db;
+pub mod hash_mirror;
+pub mod index;
+pub mod inspect;
+mod license;
+pub mod license_report;
+pub mod merge;
+pub mod metadata;
+pub mod module_update;
+mod nix_hash;
+mod package_files;
+pub mod patches;
+pub mod path_source;
+pub mod platform;
+pub mod preview;
+pub mod registries;
+pub mod registry_source;
+pub mod run_report;
+mod run_state;
+pub mod schema;
+mod scheme_rewrite;
+pub mod self_test;
+pub mod source;
+pub mod stats;
+pub mod template;
+pub mod tooling;
+mod unify;
+pub mod validation;
+mod vendor;
+mod version;
+pub mod warnings;
+
+pub use crate::{
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    features::Features,
+    generator::{Generator, Options},
+    validation::Validator,
+};
+use std::{error::Error, path::PathBuf};
+
+/// Crates to generate packages for.
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// Crate published on crates.io, in its latest version if none is given.
+    Crate {
+        name: String,
+        version: Option<String>,
+    },
+    /// Local crate, or every member of a local workspace.
+    Path(PathBuf),
+    /// Most popular dependents of a crate published on crates.io.
+    ReverseDependencies { name: String, limit: usize },
+}
+
+/// Everything needed to generate a Guix module.
+#[derive(Debug, Clone)]
+pub struct PackageSpec {
+    pub target: Target,
+    /// Features activated on the root crates.
+    pub features: Features,
+    pub options: Options,
+    /// Module name, e.g. `["my", "channel", "rust-crates"]`.
+    pub module_name: Vec<String>,
+    /// Define the packages as procedures parameterized over the rust compiler.
+    pub parameterize_rust: bool,
+}
+
+/// Generate the module defining the packages of the target crates and of their dependencies.
+///
+/// Fails on the first crate which cannot be packaged, or whose package would not load in Guix,
+/// see [`Validator`]. The lock file of the options, if any, is
+/// written once the module is complete.
+pub fn generate(spec: PackageSpec) -> Result<guix::Module, CarguixError> {
+    let generator = match spec.target {
+        Target::Crate { name, version } => Generator::new(&name, &version, &spec.features)?,
+        Target::Path(path) => Generator::from_path(&path, &spec.features)?,
+        Target::ReverseDependencies { name, limit } => {
+            Generator::from_reverse_dependencies(&name, limit)?
+        }
+    };
+    let mut generator = generator.with_options(spec.options)?;
+    let packages = generator
+        .packages()
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let index_commit = generator.index_commit();
+    let validator =
+        Validator::new(&packages).with_left_out_crates(generator.left_out_crates().iter().cloned());
+    packages
+        .iter()
+        .try_for_each(|package| validator.validate(package))?;
+    generator.save_lock()?;
+    Ok(guix::Module::new(
+        spec.module_name,
+        packages,
+        index_commit,
+        spec.parameterize_rust,
+    ))
+}
+
+/// Log an error along with the chain of its causes.
+pub fn print_error(err: &dyn Error) {
+    log::error!("error: {}", err);
+    let mut cause = err.source();
+    while let Some(err) = cause {
+        log::error!("caused by: {}", err);
+        cause = err.source();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use std::{fs, path::Path};
+    use tempdir::TempDir;
+
+    /// Crate of a registry fixture: name, version and `(name, requirement)` dependencies.
+    type FixtureCrate<'a> = (&'a str, &'a str, &'a [(&'a str, &'a str)]);
+
+    /// Lay `crates` out in `dir` as a sparse index in `index` and their `.crate` files in
+    /// `crates`, each tarball holding a line naming the crate.
+    fn registry_fixture(dir: &Path, crates: &[FixtureCrate]) {
+        for (name, version, dependencies) in crates {
+            let tarball = format!("tarball of {} {}\n", name, version);
+            let crates_dir = dir.join("crates");
+            fs::create_dir_all(&crates_dir).unwrap();
+            fs::write(
+                crates_dir.join(format!("{}-{}.crate", name, version)),
+                &tarball,
+            )
+            .unwrap();
+            let checksum = Sha256::digest(tarball.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+            let deps = dependencies
+                .iter()
+                .map(|(dependency, requirement)| {
+                    format!(
+                        "{{\"name\":\"{}\",\"req\":\"{}\",\"features\":[],\"optional\":false,\"default_features\":true,\"target\":null,\"kind\":\"normal\"}}",
+                        dependency, requirement
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            let entry = format!(
+                "{{\"name\":\"{}\",\"vers\":\"{}\",\"deps\":[{}],\"cksum\":\"{}\",\"features\":{{}},\"yanked\":false}}\n",
+                name, version, deps, checksum
+            );
+            let index_file = dir
+                .join("index")
+                .join(&name[..2])
+                .join(&name[2..4])
+                .join(name);
+            fs::create_dir_all(index_file.parent().unwrap()).unwrap();
+            let mut content = fs::read_to_string(&index_file).unwrap_or_default();
+            content.push_str(&entry);
+            fs::write(&index_file, content).unwrap();
+        }
+    }
+
+    /// Spec of a run packaging `name` from the fixture of `dir` without any network access.
+    fn fixture_spec(dir: &Path, name: &str) -> PackageSpec {
+        PackageSpec {
+            target: Target::Crate {
+                name: name.to_string(),
+                version: None,
+            },
+            features: Features::default(),
+            options: Options {
+                index_url: Some(format!("file://{}", dir.join("index").display())),
+                tarball_dir: Some(dir.join("crates")),
+                data_dir: Some(dir.join("data")),
+                enrichers: Some(Vec::new()),
+                cargo_config_dirs: vec![dir.to_path_buf()],
+                // a closed port of the loopback interface, should anything need downloading
+                mirror: Some("http://127.0.0.1:9/{name}/{version}".to_string()),
+                ..Options::default()
+            },
+            module_name: vec!["fixture".to_string()],
+            parameterize_rust: false,
+        }
+    }
+
+    #[test]
+    fn generates_from_a_file_index_and_local_tarballs() {
+        let dir = TempDir::new("carguix-fixture").unwrap();
+        registry_fixture(
+            dir.path(),
+            &[
+                ("hello-lib", "0.2.0", &[]),
+                ("hello-lib", "0.2.1", &[]),
+                ("hello-lib", "0.3.0", &[]),
+                ("hello-app", "1.0.0", &[("hello-lib", "^0.2")]),
+            ],
+        );
+        let module = generate(fixture_spec(dir.path(), "hello-app")).unwrap();
+        let crates = module
+            .packages
+            .iter()
+            .map(|package| package.crate_ref.format_name_version())
+            .collect::<Vec<_>>();
+        assert_eq!(crates, ["rust-hello-lib-0.2.1", "rust-hello-app-1.0.0"]);
+        let app = &module.packages[1];
+        assert_eq!(app.cargo_inputs, vec![CrateRef::new("hello-lib", "0.2.1")]);
+        // the hashes are the ones of the local tarballs, which match the index checksums
+        let tarball = fs::read(dir.path().join("crates/hello-app-1.0.0.crate")).unwrap();
+        assert_eq!(
+            app.hash(),
+            Some(nix_hash::nix_base32(&Sha256::digest(&tarball)).as_str())
+        );
+        let scheme = module.to_scheme();
+        assert!(scheme.contains("(define-public rust-hello-app-1.0.0"));
+        assert!(scheme.contains("rust-hello-lib-0.2.1"));
+        assert!(!scheme.contains("rust-hello-lib-0.3.0"));
+    }
+
+    #[test]
+    fn generates_the_same_module_whatever_the_dependency_order() {
+        // the sources name the tarball directory, shared by both runs
+        let dir = TempDir::new("carguix-fixture").unwrap();
+        let mut crates: Vec<FixtureCrate> = vec![
+            ("leaf-lib", "1.0.0", &[]),
+            ("left-lib", "1.0.0", &[("leaf-lib", "^1")]),
+            ("right-lib", "1.0.0", &[("leaf-lib", "^1")]),
+            (
+                "top-app",
+                "1.0.0",
+                &[("left-lib", "^1"), ("right-lib", "^1")],
+            ),
+        ];
+        registry_fixture(dir.path(), &crates);
+        let first = generate(fixture_spec(dir.path(), "top-app"))
+            .unwrap()
+            .to_scheme();
+        fs::remove_dir_all(dir.path().join("index")).unwrap();
+        fs::remove_dir_all(dir.path().join("data")).unwrap();
+        crates.reverse();
+        crates[0].2 = &[("right-lib", "^1"), ("left-lib", "^1")];
+        registry_fixture(dir.path(), &crates);
+        let second = generate(fixture_spec(dir.path(), "top-app"))
+            .unwrap()
+            .to_scheme();
+        assert!(first == second, "{}\n differs from\n{}", first, second);
+        assert_eq!(
+            first.matches("(define-public rust-leaf-lib-1.0.0").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn rejects_local_tarballs_not_matching_the_index() {
+        let dir = TempDir::new("carguix-fixture").unwrap();
+        registry_fixture(dir.path(), &[("hello-lib", "0.2.0", &[])]);
+        fs::write(
+            dir.path().join("crates/hello-lib-0.2.0.crate"),
+            "tampered\n",
+        )
+        .unwrap();
+        // the tampered tarball is downloaded again, from a mirror which can't be reached
+        assert!(matches!(
+            generate(fixture_spec(dir.path(), "hello-lib")),
+            Err(CarguixError::CrateDownloadError(_, name)) if name == "hello-lib"
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{iter::Peekable, str::SplitWhitespace};
+
+/// Guix license variables (from `(guix licenses)`, imported with the `license:` prefix) matching
+/// SPDX identifiers.
+const SPDX_LICENSES: &[(&str, &str)] = &[
+    ("0BSD", "zero-bsd"),
+    ("AGPL-3.0", "agpl3"),
+    ("AGPL-3.0-only", "agpl3"),
+    ("AGPL-3.0-or-later", "agpl3+"),
+    ("Apache-2.0", "asl2.0"),
+    ("Artistic-2.0", "artistic2.0"),
+    ("BSD-2-Clause", "bsd-2"),
+    ("BSD-3-Clause", "bsd-3"),
+    ("BSD-4-Clause", "bsd-4"),
+    ("BSL-1.0", "boost1.0"),
+    ("CC-BY-4.0", "cc-by4.0"),
+    ("CC-BY-SA-4.0", "cc-by-sa4.0"),
+    ("CC0-1.0", "cc0"),
+    ("EPL-2.0", "epl2.0"),
+    ("GPL-2.0", "gpl2"),
+    ("GPL-2.0-only", "gpl2"),
+    ("GPL-2.0-or-later", "gpl2+"),
+    ("GPL-3.0", "gpl3"),
+    ("GPL-3.0-only", "gpl3"),
+    ("GPL-3.0-or-later", "gpl3+"),
+    ("ISC", "isc"),
+    ("LGPL-2.0", "lgpl2.0"),
+    ("LGPL-2.0-or-later", "lgpl2.0+"),
+    ("LGPL-2.1", "lgpl2.1"),
+    ("LGPL-2.1-only", "lgpl2.1"),
+    ("LGPL-2.1-or-later", "lgpl2.1+"),
+    ("LGPL-3.0", "lgpl3"),
+    ("LGPL-3.0-only", "lgpl3"),
+    ("LGPL-3.0-or-later", "lgpl3+"),
+    ("MIT", "expat"),
+    ("MIT-0", "expat-0"),
+    ("MPL-1.1", "mpl1.1"),
+    ("MPL-2.0", "mpl2.0"),
+    ("OpenSSL", "openssl"),
+    ("Unicode-3.0", "unicode"),
+    ("Unicode-DFS-2016", "unicode"),
+    ("Unlicense", "unlicense"),
+    ("WTFPL", "wtfpl2"),
+    ("X11", "x11"),
+    ("Zlib", "zlib"),
+];
+
+/// Guix license symbols for the licenses mentioned in an SPDX expression.
+///
+/// Operators are not kept: like in `(gnu packages crates-io)`, every license of an expression is
+/// listed. Licenses without a Guix counterpart, see [`unknown_identifiers`], are left out.
+pub fn guix_licenses(spdx_expression: &str) -> Vec<String> {
+    let mut licenses = Vec::new();
+    for license in spdx_identifiers(spdx_expression)
+        .into_iter()
+        .filter_map(guix_license)
+    {
+        if !licenses.contains(&license) {
+            licenses.push(license);
+        }
+    }
+    licenses
+}
+
+/// License identifiers of an SPDX expression without a Guix counterpart.
+pub fn unknown_identifiers(spdx_expression: &str) -> Vec<&str> {
+    spdx_identifiers(spdx_expression)
+        .into_iter()
+        .filter(|identifier| guix_license(identifier).is_none())
+        .map(|identifier| identifier.trim_end_matches('+'))
+        .collect()
+}
+
+/// Guix license symbol of an SPDX license identifier, `+` standing for later versions.
+fn guix_license(identifier: &str) -> Option<String> {
+    let plus = identifier.ends_with('+');
+    let identifier = identifier.trim_end_matches('+');
+    SPDX_LICENSES
+        .iter()
+        .find(|(spdx, _)| spdx.eq_ignore_ascii_case(identifier))
+        .map(|(_, guix)| {
+            if plus && !guix.ends_with('+') {
+                format!("license:{}+", guix)
+            } else {
+                format!("license:{}", guix)
+            }
+        })
+}
+
+/// License identifiers of an SPDX expression, skipping operators and exceptions.
+///
+/// The obsolete `/` separator used by old crates is understood as `OR`.
+pub fn spdx_identifiers(spdx_expression: &str) -> Vec<&str> {
+    let mut identifiers = Vec::new();
+    let mut exception = false;
+    for token in spdx_expression
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+    {
+        match token {
+            "OR" | "AND" | "or" | "and" => (),
+            "WITH" | "with" => exception = true,
+            _ if exception => exception = false,
+            _ => identifiers.push(token),
+        }
+    }
+    identifiers
+}
+
+/// Alternatives of an SPDX expression, each one listing licenses that all apply, e.g.
+/// `MIT AND (Apache-2.0 OR Zlib)` as `[["MIT", "Apache-2.0"], ["MIT", "Zlib"]]`.
+///
+/// Exceptions are left out and, like in [`spdx_identifiers`], `/` is understood as `OR`.
+pub fn spdx_alternatives(spdx_expression: &str) -> Vec<Vec<String>> {
+    let spaced = spdx_expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('/', " OR ");
+    parse_or(&mut spaced.split_whitespace().peekable())
+}
+
+fn parse_or(tokens: &mut Peekable<SplitWhitespace>) -> Vec<Vec<String>> {
+    let mut alternatives = parse_and(tokens);
+    while tokens
+        .next_if(|token| token.eq_ignore_ascii_case("OR"))
+        .is_some()
+    {
+        alternatives.extend(parse_and(tokens));
+    }
+    alternatives
+}
+
+fn parse_and(tokens: &mut Peekable<SplitWhitespace>) -> Vec<Vec<String>> {
+    let mut alternatives = parse_license(tokens);
+    while tokens
+        .next_if(|token| token.eq_ignore_ascii_case("AND"))
+        .is_some()
+    {
+        let right = parse_license(tokens);
+        alternatives = alternatives
+            .iter()
+            .flat_map(|left| {
+                right
+                    .iter()
+                    .map(move |right| left.iter().chain(right).cloned().collect())
+            })
+            .collect();
+    }
+    alternatives
+}
+
+fn parse_license(tokens: &mut Peekable<SplitWhitespace>) -> Vec<Vec<String>> {
+    match tokens.next() {
+        Some("(") => {
+            let alternatives = parse_or(tokens);
+            tokens.next_if_eq(&")");
+            alternatives
+        }
+        Some(identifier) => {
+            if tokens
+                .next_if(|token| token.eq_ignore_ascii_case("WITH"))
+                .is_some()
+            {
+                tokens.next();
+            }
+            vec![vec![identifier.to_string()]]
+        }
+        None => vec![Vec::new()],
+    }
+}
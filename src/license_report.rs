@@ -0,0 +1,233 @@
+use crate::{guix, license};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// What redistributing a work including code under a license requires, from the least to the
+/// most demanding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LicenseFamily {
+    /// Keeping the notices, such as MIT or Apache-2.0.
+    Permissive,
+    /// Keeping the licensed files themselves free, such as MPL-2.0 or LGPL-3.0.
+    WeakCopyleft,
+    /// Licensing the whole work under the same terms, such as GPL-3.0.
+    StrongCopyleft,
+    /// Undeclared or unrecognized license.
+    Unknown,
+}
+
+impl LicenseFamily {
+    fn of(identifier: &str) -> Self {
+        let identifier = identifier.to_ascii_uppercase();
+        let starts_with = |prefixes: &[&str]| {
+            prefixes
+                .iter()
+                .any(|prefix| identifier.starts_with(&prefix.to_ascii_uppercase()))
+        };
+        if starts_with(&["LGPL-", "MPL-", "EPL-", "CDDL-", "CC-BY-SA-"]) {
+            LicenseFamily::WeakCopyleft
+        } else if starts_with(&["GPL-", "AGPL-"]) {
+            LicenseFamily::StrongCopyleft
+        } else if starts_with(&[
+            "0BSD",
+            "Apache-",
+            "Artistic-2.0",
+            "BSD-",
+            "BSL-1.0",
+            "CC-BY-",
+            "CC0-",
+            "ISC",
+            "MIT",
+            "OpenSSL",
+            "Unicode-",
+            "Unlicense",
+            "WTFPL",
+            "X11",
+            "Zlib",
+        ]) {
+            LicenseFamily::Permissive
+        } else {
+            LicenseFamily::Unknown
+        }
+    }
+}
+
+const GPL_2_ONLY: &[&str] = &["GPL-2.0", "GPL-2.0-only"];
+const VERSION_3: &[&str] = &[
+    "GPL-3.0",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "LGPL-3.0",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "AGPL-3.0",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+];
+const GPL: &[&str] = &[
+    "GPL-2.0",
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "AGPL-3.0",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+];
+
+/// Pairs of licenses whose code can't be redistributed together, with the reason why.
+const INCOMPATIBILITIES: &[(&[&str], &[&str], &str)] = &[
+    (
+        GPL_2_ONLY,
+        &["Apache-2.0"],
+        "the patent terms of Apache-2.0 are restrictions GPL-2.0 forbids",
+    ),
+    (
+        GPL_2_ONLY,
+        VERSION_3,
+        "GPL-2.0-only code can't be relicensed under version 3",
+    ),
+    (
+        GPL,
+        &["BSD-4-Clause"],
+        "the advertising clause of BSD-4-Clause is a restriction the GPL forbids",
+    ),
+    (
+        GPL,
+        &["OpenSSL"],
+        "the advertising clause of OpenSSL is a restriction the GPL forbids",
+    ),
+    (
+        GPL,
+        &["MPL-1.1", "EPL-1.0", "EPL-2.0", "CDDL-1.0"],
+        "weak copyleft licenses without GPL compatibility clause can't be combined with the GPL",
+    ),
+];
+
+/// Licenses of the closure which can't be redistributed together.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Conflict {
+    pub license: String,
+    pub other_license: String,
+    pub reason: String,
+    /// Packages under `license`, named after their variables.
+    pub packages: Vec<String>,
+    /// Packages under `other_license`.
+    pub other_packages: Vec<String>,
+}
+
+/// Closure partitioned by license family, along with the license combinations which are
+/// problematic for redistribution.
+///
+/// Out of the alternatives of an SPDX expression, e.g. `MIT OR Apache-2.0`, the most permissive
+/// one not conflicting with the copyleft licenses of the closure is retained.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LicenseReport {
+    pub groups: BTreeMap<LicenseFamily, Vec<String>>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl LicenseReport {
+    pub fn new(packages: &[guix::Package]) -> Self {
+        let candidates = packages
+            .iter()
+            .map(|package| {
+                let alternatives = package
+                    .license
+                    .as_deref()
+                    .map(license::spdx_alternatives)
+                    .unwrap_or_default();
+                let family = |alternative: &Vec<String>| {
+                    alternative
+                        .iter()
+                        .map(|identifier| LicenseFamily::of(&normalize(identifier)))
+                        .max()
+                        .unwrap_or(LicenseFamily::Unknown)
+                };
+                let least_family = alternatives
+                    .iter()
+                    .map(family)
+                    .min()
+                    .unwrap_or(LicenseFamily::Unknown);
+                let alternatives = alternatives
+                    .iter()
+                    .filter(|alternative| family(alternative) == least_family)
+                    .map(|alternative| alternative.iter().map(|id| normalize(id)).collect())
+                    .collect::<Vec<Vec<_>>>();
+                (package, least_family, alternatives)
+            })
+            .collect::<Vec<_>>();
+        let copyleft_licenses = candidates
+            .iter()
+            .filter(|(_, family, _)| *family == LicenseFamily::StrongCopyleft)
+            .filter_map(|(_, _, alternatives)| alternatives.first())
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut groups = BTreeMap::<_, Vec<_>>::new();
+        let mut packages_by_license = BTreeMap::<_, Vec<_>>::new();
+        for (package, family, alternatives) in candidates {
+            let name = package.variable();
+            let retained = alternatives
+                .iter()
+                .find(|alternative| {
+                    alternative.iter().all(|license| {
+                        copyleft_licenses
+                            .iter()
+                            .all(|copyleft| incompatibility(license, copyleft).is_none())
+                    })
+                })
+                .or_else(|| alternatives.first());
+            for license in retained.into_iter().flatten() {
+                packages_by_license
+                    .entry(license.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+            groups.entry(family).or_default().push(name);
+        }
+        let mut conflicts = Vec::new();
+        for (license, packages) in &packages_by_license {
+            for (other_license, other_packages) in &packages_by_license {
+                if let Some(reason) = incompatibility(license, other_license) {
+                    conflicts.push(Conflict {
+                        license: license.clone(),
+                        other_license: other_license.clone(),
+                        reason: reason.to_string(),
+                        packages: packages.clone(),
+                        other_packages: other_packages.clone(),
+                    });
+                }
+            }
+        }
+        Self { groups, conflicts }
+    }
+}
+
+/// Reason why code under two licenses can't be redistributed together, if it can't.
+fn incompatibility(license: &str, other_license: &str) -> Option<&'static str> {
+    let is_one_of =
+        |license: &str, licenses: &[&str]| licenses.iter().any(|l| l.eq_ignore_ascii_case(license));
+    INCOMPATIBILITIES
+        .iter()
+        .find(|(licenses, other_licenses, _)| {
+            is_one_of(license, licenses) && is_one_of(other_license, other_licenses)
+        })
+        .map(|(_, _, reason)| *reason)
+}
+
+/// Identifier with its obsolete `+` suffix spelled as `-or-later`, e.g. `GPL-2.0+` as
+/// `GPL-2.0-or-later`.
+fn normalize(identifier: &str) -> String {
+    match identifier.strip_suffix('+') {
+        Some(identifier) if !identifier.ends_with("-or-later") => {
+            format!("{}-or-later", identifier)
+        }
+        Some(identifier) => identifier.to_string(),
+        None => identifier.to_string(),
+    }
+}
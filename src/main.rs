@@ -1,388 +1,2106 @@
-use crates_index::{Crate, Dependency, Index};
-use err_derive::Error;
-use heck::KebabCase;
-use lexpr::sexp;
-use rustbreak::Database;
-use semver::{Version, VersionReq};
-use shellfn::shell;
+use carguix::{
+    channel::Channel,
+    config::Config,
+    enrichers::EnricherKind,
+    example::Example,
+    frozen::{FrozenPackageSet, FROZEN_FILE},
+    generator::{default_data_dir, open_hash_database, DeepBuildDependencies},
+    guix,
+    guix_diff::{GuixDiff, HashReport},
+    hash_db::{HashDatabase, HashMethod},
+    hash_mirror,
+    inspect::{Inspection, InspectionReport},
+    license_report::LicenseReport,
+    merge::MergeStrategy,
+    module_update, patches,
+    path_source::ManifestCache,
+    platform::Platform,
+    preview::Preview,
+    print_error,
+    run_report::RunReport,
+    schema::Versioned,
+    self_test,
+    stats::{RunStats, Stats},
+    template::Templates,
+    tooling::SharedTooling,
+    validation::Validator,
+    warnings::{WarningCode, Warnings},
+    CarguixError, CrateRef, Features, Generator, Options,
+};
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{hash_map::RandomState, BTreeSet, HashMap, HashSet},
     error::Error,
-    fs::File,
-    io::copy,
-    ops::Not,
+    fs::{self, File},
+    hash::BuildHasher,
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Instant,
 };
 use structopt::StructOpt;
-use tempdir::TempDir;
+
+/// Size in MiB above which downloading a crate is warned about, unless `--max-crate-size` sets it.
+const DEFAULT_MAX_CRATE_SIZE: u64 = 10;
+
+/// Representation of the generated packages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Guix package definitions.
+    Sexpr,
+    /// Resolved package set, for scripts.
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "sexpr" => Ok(Format::Sexpr),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format {}, expected sexpr or json", format)),
+        }
+    }
+}
 
 #[derive(Debug, StructOpt)]
-#[structopt(about = "Generate Guix package definition for Rust crates")]
+enum Command {
+    #[structopt(
+        name = "crate",
+        about = "Package a crate of the registry, like `carguix <crate_name>`"
+    )]
+    Crate {
+        #[structopt(help = "Crate to package, `name@version` for a given version")]
+        crate_name: String,
+        #[structopt(
+            short,
+            long,
+            help = "Generate package definition for specific version of the crate, or for the highest one matching a requirement such as ^1.2 or \">=0.10, <0.12\" (default: latest)"
+        )]
+        version: Option<String>,
+    },
+    #[structopt(
+        name = "path",
+        about = "Package local crates or workspaces, like `carguix --path <dir>`"
+    )]
+    Path {
+        #[structopt(
+            parse(from_os_str),
+            raw(required = "true"),
+            help = "Local crate or workspace to package, several ones being packaged in a single module"
+        )]
+        dirs: Vec<PathBuf>,
+    },
+    #[structopt(
+        name = "lock",
+        about = "Package the crates of a Cargo.lock file and its vendor directory without network access, like `carguix --path <dir> --offline`"
+    )]
+    Lock {
+        #[structopt(
+            parse(from_os_str),
+            help = "Cargo.lock file next to the manifest of the crate or workspace"
+        )]
+        cargo_lock: PathBuf,
+        #[structopt(
+            long = "root",
+            value_name = "name",
+            help = "Only package this crate of the workspace"
+        )]
+        root: Option<String>,
+        #[structopt(
+            long = "vendor-dir",
+            parse(from_os_str),
+            help = "Directory `cargo vendor` unpacked the crates into (default: vendor next to the Cargo.lock file)"
+        )]
+        vendor_dir: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "git",
+        about = "Package a crate, or the members of a workspace, from a git repository, fetched from its commit"
+    )]
+    Git {
+        #[structopt(help = "URL of the repository")]
+        url: String,
+        #[structopt(
+            long,
+            value_name = "sha",
+            raw(conflicts_with_all = r#"&["tag", "branch"]"#),
+            help = "Commit to package"
+        )]
+        rev: Option<String>,
+        #[structopt(
+            long,
+            value_name = "tag",
+            conflicts_with = "branch",
+            help = "Tag to package"
+        )]
+        tag: Option<String>,
+        #[structopt(
+            long,
+            value_name = "name",
+            help = "Branch to package (default: the default branch of the repository)"
+        )]
+        branch: Option<String>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            value_name = "path",
+            help = "Directory of the crate or workspace in the repository (default: its root)"
+        )]
+        subdir: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "reverse-deps",
+        about = "Package the most popular dependents of a crate of crates.io, by number of dependents of their own"
+    )]
+    ReverseDeps {
+        #[structopt(help = "Crate whose dependents to package")]
+        crate_name: String,
+        #[structopt(
+            long,
+            value_name = "count",
+            default_value = "10",
+            help = "Number of dependents to package"
+        )]
+        limit: usize,
+    },
+    #[structopt(
+        name = "cache",
+        about = "Inspect, maintain and serve the hash database",
+        raw(alias = r#""hash-db""#)
+    )]
+    Cache {
+        #[structopt(subcommand)]
+        command: CacheCommand,
+    },
+    #[structopt(
+        name = "versions",
+        about = "List the published versions of a crate, marking the yanked and cached ones"
+    )]
+    Versions {
+        crate_name: String,
+        #[structopt(
+            long,
+            value_name = "name",
+            help = "Look the crate up in this alternative registry instead of crates.io"
+        )]
+        registry: Option<String>,
+    },
+    #[structopt(
+        name = "preview",
+        about = "Print the first levels of the dependency tree of a crate of the registry, with the estimated size of its closure, without downloading anything"
+    )]
+    Preview {
+        #[structopt(help = "Crate to preview, `name@version` for a given version")]
+        crate_name: String,
+        #[structopt(
+            long,
+            value_name = "depth",
+            default_value = "2",
+            help = "Number of levels of the dependency tree to print"
+        )]
+        depth: usize,
+    },
+    #[structopt(
+        name = "inspect",
+        about = "Print the manifest or index data of a crate as carguix reads it, without resolving its dependencies"
+    )]
+    Inspect {
+        #[structopt(
+            help = "Directory of a local crate or workspace, or crate of the registry as `name[@version]`"
+        )]
+        target: String,
+        #[structopt(
+            long,
+            value_name = "name",
+            help = "Look the crate up in this alternative registry instead of crates.io"
+        )]
+        registry: Option<String>,
+        #[structopt(long, help = "Print the data as JSON")]
+        json: bool,
+    },
+    #[structopt(
+        name = "render",
+        about = "Write the packages of the last run which packaged every crate again, in the format and to the outputs of the options"
+    )]
+    Render {
+        #[structopt(
+            long = "frozen",
+            help = "Render the package set the run froze in the data directory, without resolving anything"
+        )]
+        frozen: bool,
+    },
+    #[structopt(
+        name = "update",
+        about = "Move the crates of a module generated by carguix to their latest compatible versions, in place"
+    )]
+    Update {
+        #[structopt(parse(from_os_str), help = "Module file to update")]
+        module: PathBuf,
+        #[structopt(
+            long = "cargo-lock",
+            parse(from_os_str),
+            help = "Take the versions from this Cargo.lock file instead of the index"
+        )]
+        cargo_lock: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "regen",
+        about = "Package a crate of a module generated by carguix and its dependencies again, leaving the other packages as they are"
+    )]
+    Regen {
+        #[structopt(parse(from_os_str), help = "Module file to regenerate the packages of")]
+        module: PathBuf,
+        #[structopt(
+            long = "root",
+            value_name = "crate",
+            help = "Crate whose package and dependencies are regenerated, `name@version` when the module packages several versions of it"
+        )]
+        root: String,
+    },
+    #[structopt(
+        name = "env",
+        about = "Write a guix.scm development environment for local crates, for `guix shell -D -f guix.scm`"
+    )]
+    Env {
+        #[structopt(
+            long = "path",
+            parse(from_os_str),
+            raw(number_of_values = "1"),
+            help = "Local crate or workspace to make the environment for, repeatable (default: the current directory)"
+        )]
+        path: Vec<PathBuf>,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "File to write the environment to instead of printing it"
+        )]
+        output: Option<PathBuf>,
+        #[structopt(
+            long = "manifest",
+            help = "Write a manifest for `guix shell -m`, which can gather several local crates"
+        )]
+        manifest: bool,
+    },
+    #[structopt(
+        name = "diff",
+        about = "Sort the crates a crate needs by whether a Guix checkout packages them, and define the ones to add or bump"
+    )]
+    Diff {
+        #[structopt(help = "Crate to compare, `name@version` for a given version")]
+        crate_name: String,
+        #[structopt(
+            long = "guix-checkout",
+            parse(from_os_str),
+            help = "Guix checkout to compare with"
+        )]
+        guix_checkout: PathBuf,
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            help = "File to write the definitions to instead of printing them after the report"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "check-hashes",
+        about = "Package a crate without reusing Guix packages and compare the hashes with the ones of the Guix packages of the same versions"
+    )]
+    CheckHashes {
+        #[structopt(help = "Crate to check, `name@version` for a given version")]
+        crate_name: String,
+        #[structopt(
+            long = "guix-checkout",
+            parse(from_os_str),
+            help = "Guix checkout to compare with"
+        )]
+        guix_checkout: PathBuf,
+    },
+    #[structopt(
+        name = "channel",
+        about = "Scaffold a Guix channel serving generated packages"
+    )]
+    Channel {
+        #[structopt(subcommand)]
+        command: ChannelCommand,
+    },
+    #[structopt(
+        name = "example",
+        about = "Scaffold a tiny Rust project along with its guix.scm file and a channel defining its packages"
+    )]
+    Example {
+        #[structopt(
+            raw(possible_values = "carguix::example::EXAMPLE_TEMPLATES"),
+            help = "Project to scaffold"
+        )]
+        template: String,
+        #[structopt(parse(from_os_str), help = "Directory of the project")]
+        dir: PathBuf,
+        #[structopt(long = "force", help = "Overwrite the existing files")]
+        force: bool,
+    },
+    #[structopt(
+        name = "self-test",
+        about = "Package an embedded crate served on the loopback interface, to check that carguix works in this environment"
+    )]
+    SelfTest,
+}
+
+#[derive(Debug, StructOpt)]
+enum ChannelCommand {
+    #[structopt(
+        name = "init",
+        about = "Write a channel defining a crate and its dependencies, ready to be published"
+    )]
+    Init {
+        #[structopt(parse(from_os_str), help = "Directory of the channel")]
+        dir: PathBuf,
+        #[structopt(
+            long = "name",
+            help = "Name of the channel, prefix of its modules (default: the directory name)"
+        )]
+        name: Option<String>,
+        #[structopt(
+            long = "path",
+            parse(from_os_str),
+            raw(number_of_values = "1"),
+            conflicts_with = "crate_name",
+            help = "Local crate or workspace to package, repeatable (default: the current directory)"
+        )]
+        path: Vec<PathBuf>,
+        #[structopt(
+            long = "crate",
+            value_name = "name",
+            help = "Crate to package from the registry, `name@version` for a given version"
+        )]
+        crate_name: Option<String>,
+        #[structopt(long = "force", help = "Overwrite the existing channel files")]
+        force: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum CacheCommand {
+    #[structopt(name = "list", about = "List the cached hashes")]
+    List {
+        #[structopt(
+            help = "Only list the hashes of this crate, `<registry>/<crate>` for crates of alternative registries"
+        )]
+        crate_name: Option<String>,
+    },
+    #[structopt(
+        name = "verify",
+        about = "Download crates again and compare their hashes with the cached ones"
+    )]
+    Verify {
+        #[structopt(help = "Only verify the hashes of this crate")]
+        crate_name: Option<String>,
+        #[structopt(
+            long,
+            value_name = "count",
+            help = "Only verify this many hashes picked at random"
+        )]
+        sample: Option<usize>,
+    },
+    #[structopt(
+        name = "purge",
+        about = "Remove the hashes of a crate from the database"
+    )]
+    Purge {
+        #[structopt(
+            help = "Crate whose hashes are removed, `<registry>/<crate>` for crates of alternative registries, `<crate>@<version>` for a single version"
+        )]
+        crate_name: String,
+        #[structopt(short, long, help = "Only remove the hash of this version")]
+        version: Option<String>,
+    },
+    #[structopt(
+        name = "serve",
+        about = "Serve the hash database read-only over HTTP, for --hash-mirror"
+    )]
+    Serve {
+        #[structopt(
+            long,
+            default_value = "127.0.0.1:8080",
+            help = "Address to listen on, e.g. 0.0.0.0:8080 to serve other hosts"
+        )]
+        address: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    about = "Generate Guix package definition for Rust crates",
+    raw(setting = "structopt::clap::AppSettings::SubcommandsNegateReqs")
+)]
 struct Cli {
-    crate_name: String,
-    #[structopt(short, long, help = "Update crates.io index")]
-    update: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    #[structopt(
+        raw(required_unless_one = r#"&["path", "explicit_crate", "from_file"]"#),
+        help = "Crate to package, `name@version` for a given version, directory of a local crate or workspace, or URL of a git repository"
+    )]
+    crate_name: Option<String>,
+    #[structopt(
+        name = "crates",
+        help = "Further crates of the registry to package along with the first one in a single module, as `name` or `name@version`"
+    )]
+    more_crates: Vec<String>,
+    #[structopt(
+        long = "from-file",
+        value_name = "file",
+        parse(from_os_str),
+        conflicts_with = "path",
+        help = "Package the crates of the registry listed in this file, one `name` or `name@version` per line, along with the other crates given (- for the standard input)"
+    )]
+    from_file: Option<PathBuf>,
+    #[structopt(
+        long = "crate",
+        value_name = "name",
+        conflicts_with = "crate_name",
+        help = "Crate to package from the registry even if a directory has the same name"
+    )]
+    explicit_crate: Option<String>,
+    // Named apart from the `update` subcommand, which clap would report as present otherwise
+    #[structopt(short = "u", long = "update", help = "Update crates.io index")]
+    update_index: bool,
     #[structopt(
         short,
         long,
-        help = "Generate package definition for specific version of the crate (default: earliest)"
+        help = "Generate package definition for specific version of the crate, or for the highest one matching a requirement such as ^1.2 or \">=0.10, <0.12\" (default: latest)"
     )]
     version: Option<String>,
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        raw(conflicts_with_all = r#"&["crate_name", "explicit_crate"]"#),
+        raw(number_of_values = "1"),
+        help = "Generate package definitions for the local crate or workspace at this path, repeat it to package several projects in a single module"
+    )]
+    path: Vec<PathBuf>,
+    #[structopt(
+        long = "merge-conflicts",
+        value_name = "strategy",
+        raw(possible_values = r#"&["unify", "first"]"#),
+        help = "Package the crates several --path projects require from different origins or with different features by unifying their features (unify) or like the first project does (first)"
+    )]
+    merge_conflicts: Option<MergeStrategy>,
+    #[structopt(
+        long,
+        help = "Space or comma separated list of features to activate on the root crates"
+    )]
+    features: Vec<String>,
+    #[structopt(
+        long = "no-default-features",
+        help = "Do not activate the `default` feature of the root crates"
+    )]
+    no_default_features: bool,
+    #[structopt(
+        long = "all-features",
+        help = "Activate all available features of the root crates"
+    )]
+    all_features: bool,
+    #[structopt(
+        long = "max-crate-size",
+        value_name = "MiB",
+        help = "Ask for confirmation before downloading crates bigger than this size, failing without a terminal to ask on unless --assume-yes is given (default: only warn above 10 MiB)"
+    )]
+    max_crate_size: Option<u64>,
+    #[structopt(
+        short = "y",
+        long = "assume-yes",
+        help = "Download crates above the size threshold without asking for confirmation"
+    )]
+    assume_yes: bool,
+    #[structopt(
+        long = "skip-dev-dependencies",
+        help = "Do not package dev-dependencies nor list them as development inputs"
+    )]
+    skip_dev_dependencies: bool,
+    #[structopt(
+        long = "target",
+        value_name = "triple",
+        help = "Package the target-specific dependencies of this target triple (default: the host target)"
+    )]
+    target: Option<String>,
+    #[structopt(
+        long = "all-targets",
+        conflicts_with = "target",
+        help = "Package the target-specific dependencies of every target"
+    )]
+    all_targets: bool,
+    #[structopt(
+        long = "strict-semver",
+        help = "Fail on versions and requirements which are not valid semver instead of repairing them"
+    )]
+    strict_semver: bool,
+    #[structopt(
+        long = "allow-yanked",
+        help = "Resolve requirements to yanked versions too instead of only when no other version matches"
+    )]
+    allow_yanked: bool,
+    #[structopt(
+        long = "allow-prerelease",
+        help = "Resolve requirements and crates without a version to pre-releases too instead of only when a requirement mentions one"
+    )]
+    allow_prerelease: bool,
+    #[structopt(
+        long = "no-prerelease",
+        conflicts_with = "allow_prerelease",
+        help = "Prefer the highest stable release, the default"
+    )]
+    no_prerelease: bool,
+    #[structopt(
+        long = "keep-duplicate-versions",
+        help = "Package every version requirements resolve to instead of a single version per semver-compatible range of each crate"
+    )]
+    keep_duplicate_versions: bool,
+    #[structopt(
+        long = "requirement-comments",
+        help = "Comment each cargo input with the requirements it was resolved from"
+    )]
+    requirement_comments: bool,
+    #[structopt(
+        long = "update-properties",
+        help = "Give the packages the upstream-name and release-monitoring-url properties update bots track releases with"
+    )]
+    update_properties: bool,
+    #[structopt(
+        long = "style",
+        value_name = "style",
+        default_value = "list-forms",
+        raw(possible_values = r#"&["legacy-alist", "list-forms", "inputs"]"#),
+        help = "List the crate dependencies in the quasiquoted #:cargo-inputs of older Guix versions (legacy-alist), in #:cargo-inputs list forms (list-forms) or in the inputs of the packages (inputs)"
+    )]
+    style: guix::InputStyle,
+    #[structopt(
+        long = "feature-summaries",
+        help = "Append to each description which features of the crate the package is built with and without"
+    )]
+    feature_summaries: bool,
+    #[structopt(
+        long = "skip-proc-macro-builds",
+        help = "Leave the procedural macros and the crates only they depend on unbuilt, as they never appear in runtime closures"
+    )]
+    skip_proc_macro_builds: bool,
+    #[structopt(
+        long = "skip-build",
+        help = "Leave every package but the root ones unbuilt, as Guix builds the dependencies of a crate along with it"
+    )]
+    skip_build: bool,
+    #[structopt(
+        long = "enrichers",
+        value_name = "enricher",
+        raw(use_delimiter = "true"),
+        raw(possible_values = r#"&["overrides", "manifest", "crates-io", "lib-rs"]"#),
+        help = "Sources of the synopses, descriptions, home pages and licenses, the first ones taking precedence, comma separated (default: overrides,manifest,crates-io,lib-rs)"
+    )]
+    enrichers: Vec<EnricherKind>,
+    #[structopt(
+        long = "allow",
+        value_name = "code",
+        raw(use_delimiter = "true"),
+        help = "Silence the warnings of these categories, such as W001, comma separated and repeatable"
+    )]
+    allow: Vec<WarningCode>,
+    #[structopt(
+        long = "deny",
+        value_name = "code",
+        raw(use_delimiter = "true"),
+        help = "Fail once the packages are generated if warnings of these categories, such as W002, were emitted, comma separated and repeatable"
+    )]
+    deny: Vec<WarningCode>,
+    #[structopt(
+        long = "proc-macro-module",
+        parse(from_os_str),
+        raw(requires = r#""output""#),
+        conflicts_with = "format",
+        help = "Write the procedural macros and the crates only they depend on to this module, which the --output module imports"
+    )]
+    proc_macro_module: Option<PathBuf>,
+    #[structopt(
+        long = "sparse-index",
+        help = "Fetch the crates needed from the crates.io HTTP index instead of cloning the git index"
+    )]
+    sparse_index: bool,
+    #[structopt(
+        long = "index-url",
+        value_name = "url",
+        conflicts_with = "offline",
+        help = "Look crates up in this index instead of the one of crates.io, a sparse index served over HTTP or a file:// directory laid out like an index, such as a test fixture"
+    )]
+    index_url: Option<String>,
+    #[structopt(
+        long = "offline",
+        requires = "path",
+        raw(conflicts_with_all = r#"&["sparse_index", "update_index"]"#),
+        help = "Resolve every crate from the Cargo.lock and vendor directory of the path, without network access"
+    )]
+    offline: bool,
+    #[structopt(
+        long = "cargo-lock",
+        parse(from_os_str),
+        requires = "offline",
+        help = "Cargo.lock file the versions are taken from in offline mode (default: in the workspace root of the path)"
+    )]
+    cargo_lock: Option<PathBuf>,
+    #[structopt(
+        long = "vendor-dir",
+        parse(from_os_str),
+        requires = "offline",
+        help = "Directory `cargo vendor` unpacked the crates into (default: vendor in the workspace root of the path)"
+    )]
+    vendor_dir: Option<PathBuf>,
+    #[structopt(
+        long = "cargo-metadata",
+        help = "Read the manifests of local crates with `cargo metadata`, falling back to carguix's own parser when cargo can't be found"
+    )]
+    cargo_metadata: bool,
+    #[structopt(
+        long = "allow-index-rewrite",
+        help = "Accept index updates which are not fast-forwards of the current checkout"
+    )]
+    allow_index_rewrite: bool,
+    #[structopt(
+        long = "lock-file",
+        parse(from_os_str),
+        default_value = "carguix.lock",
+        help = "File pinning the versions and hashes chosen by previous runs"
+    )]
+    lock_file: PathBuf,
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "Write a complete Guix module to this file instead of printing the definitions"
+    )]
+    output: Option<PathBuf>,
+    #[structopt(
+        long = "output-dir",
+        parse(from_os_str),
+        conflicts_with = "output",
+        help = "Write one Guix module per package, named after the package, in this directory"
+    )]
+    output_dir: Option<PathBuf>,
+    #[structopt(
+        long = "format",
+        conflicts_with = "output_dir",
+        raw(possible_values = r#"&["sexpr", "json"]"#),
+        help = "Print or write Guix definitions (sexpr) or the resolved package set as JSON (json) (default: sexpr)"
+    )]
+    format: Option<Format>,
+    #[structopt(
+        long,
+        requires = "output_dir",
+        help = "Overwrite the module files which already exist in the output directory"
+    )]
+    force: bool,
+    #[structopt(
+        long = "module-name",
+        help = "Name of the module written with --output, or prefix of the modules written with --output-dir, e.g. \"my channel rust-crates\" (default: from the path)"
+    )]
+    module_name: Option<String>,
+    #[structopt(
+        long = "parameterize-rust",
+        help = "Define each package with a procedure taking the rust compiler to build it with"
+    )]
+    parameterize_rust: bool,
+    #[structopt(
+        long = "template",
+        parse(from_os_str),
+        help = "Mustache template of the module files, instead of the default one"
+    )]
+    template: Option<PathBuf>,
+    #[structopt(
+        long = "package-template",
+        parse(from_os_str),
+        help = "Mustache template of the definitions of each package, instead of the default one"
+    )]
+    package_template: Option<PathBuf>,
+    #[structopt(
+        long = "config",
+        parse(from_os_str),
+        default_value = "carguix.toml",
+        help = "File declaring the inputs, extra files and search paths of the root packages"
+    )]
+    config: PathBuf,
+    #[structopt(
+        long = "preset",
+        value_name = "name",
+        raw(
+            possible_values = "carguix::config::PRESET_NAMES",
+            number_of_values = "1"
+        ),
+        help = "Add the system libraries, features and procedural macros carguix ships for an ecosystem to the configuration, which takes precedence, repeatable"
+    )]
+    presets: Vec<String>,
+    #[structopt(
+        long = "refresh-lock",
+        help = "Resolve versions again instead of reusing the ones pinned in the lock file"
+    )]
+    refresh_lock: bool,
+    #[structopt(
+        long = "resume",
+        help = "Pick up where an interrupted run for the same crates left off"
+    )]
+    resume: bool,
+    #[structopt(
+        short,
+        long,
+        default_value = "8",
+        help = "Number of crates downloaded and hashed at the same time"
+    )]
+    jobs: usize,
+    #[structopt(
+        long = "download-retries",
+        value_name = "count",
+        default_value = "3",
+        help = "Download a crate this many more times after a network error, a server error or a tarball not matching its checksum"
+    )]
+    download_retries: usize,
+    #[structopt(
+        long = "guix-checkout",
+        parse(from_os_str),
+        help = "Reference the crates packaged in this Guix checkout instead of defining them again"
+    )]
+    guix_checkout: Option<PathBuf>,
+    #[structopt(
+        long = "mirror",
+        help = "Fetch crates from a mirror instead of crates.io, `{name}` and `{version}` being replaced in the URL template"
+    )]
+    mirror: Option<String>,
+    #[structopt(
+        long = "hash-mirror",
+        value_name = "url",
+        help = "Ask this hash mirror, served by `carguix cache serve`, for the hashes missing from the hash database before downloading crates"
+    )]
+    hash_mirror: Option<String>,
+    #[structopt(
+        long = "data-dir",
+        parse(from_os_str),
+        help = "Keep the clone of the crates.io index, the hash database and the git checkouts in this directory instead of $CARGUIX_HOME or $XDG_CACHE_HOME/carguix"
+    )]
+    data_dir: Option<PathBuf>,
+    #[structopt(
+        long = "tarball-dir",
+        parse(from_os_str),
+        help = "Keep the crate tarballs in this directory, downloading each one once, and fetch them from there instead of their registry"
+    )]
+    tarball_dir: Option<PathBuf>,
+    #[structopt(
+        long = "tarball-url",
+        value_name = "url",
+        raw(requires = r#""tarball_dir""#),
+        help = "URL the tarballs of --tarball-dir are published at, e.g. on an artifact server, instead of file:// URIs of the directory"
+    )]
+    tarball_url: Option<String>,
+    #[structopt(
+        long = "guix-hash",
+        help = "Compute hashes by calling `guix hash` instead of natively"
+    )]
+    guix_hash: bool,
+    #[structopt(
+        long = "refresh-hashes",
+        value_name = "crate",
+        raw(number_of_values = "1"),
+        help = "Download this crate again to compute its hash, ignoring the lock file and the hash database (repeatable)"
+    )]
+    refresh_hashes: Vec<String>,
+    #[structopt(
+        long = "verify",
+        conflicts_with = "offline",
+        help = "Download every crate to hash it, failing if its tarball doesn't match the checksum of Cargo.lock"
+    )]
+    verify: bool,
+    #[structopt(
+        long = "stats-out",
+        parse(from_os_str),
+        help = "Append statistics about the generated packages to this JSON file"
+    )]
+    stats_out: Option<PathBuf>,
+    #[structopt(
+        long = "scheme-api",
+        parse(from_os_str),
+        conflicts_with = "format",
+        help = "Write a module exporting `(carguix-packages)`, the list of the generated packages, to this file"
+    )]
+    scheme_api: Option<PathBuf>,
+    #[structopt(
+        long = "prefetch-manifest",
+        parse(from_os_str),
+        conflicts_with = "format",
+        help = "Write a manifest of the generated packages to this file, for CI to fetch their sources ahead of time with `guix build --sources=transitive -m`"
+    )]
+    prefetch_manifest: Option<PathBuf>,
+    #[structopt(
+        long = "emit-graph",
+        parse(from_os_str),
+        help = "Write the resolved dependency graph to this Graphviz DOT file"
+    )]
+    emit_graph: Option<PathBuf>,
+    #[structopt(
+        long = "license-report",
+        parse(from_os_str),
+        help = "Write the packages grouped by license family and the license conflicts to this JSON file"
+    )]
+    license_report: Option<PathBuf>,
+    #[structopt(
+        long = "report",
+        parse(from_os_str),
+        help = "Write the outcome of every crate, with its source, hash and duration or its error, and the run totals to this JSON file"
+    )]
+    report: Option<PathBuf>,
+    #[structopt(
+        long = "depth",
+        value_name = "levels",
+        help = "Only package this many levels of dependencies below the root crates, the next level being referred to as inputs to supply from another module"
+    )]
+    depth: Option<usize>,
+    #[structopt(
+        long = "max-build-depth",
+        value_name = "edges",
+        help = "Comment, or collapse with --deep-build-deps collapse, the packages reached through more than this many build-dependency edges, 1 for the build-dependencies of build-dependencies"
+    )]
+    max_build_depth: Option<usize>,
+    #[structopt(
+        long = "deep-build-deps",
+        value_name = "policy",
+        raw(possible_values = r#"&["annotate", "collapse"]"#),
+        raw(requires = r#""max_build_depth""#),
+        help = "Comment the packages beyond --max-build-depth (annotate) or leave them unbuilt without their development inputs (collapse) (default: annotate)"
+    )]
+    deep_build_deps: Option<DeepBuildDependencies>,
+    #[structopt(
+        long = "exclude",
+        value_name = "crate",
+        raw(use_delimiter = "true"),
+        help = "Refer to these crates as inputs without packaging them nor their dependencies, comma separated and repeatable"
+    )]
+    exclude: Vec<String>,
+    #[structopt(
+        long = "exclude-from",
+        parse(from_os_str),
+        help = "Exclude the crates listed in this file, one per line, like --exclude"
+    )]
+    exclude_from: Option<PathBuf>,
+    #[structopt(
+        long = "only-missing",
+        conflicts_with = "format",
+        help = "Only package the crates the --output module, or the modules of --output-dir, don't define yet, appending them to the --output module"
+    )]
+    only_missing: bool,
+    #[structopt(
+        long = "no-validate",
+        help = "Write the packages even when they fail the checks of what Guix would reject when loading them"
+    )]
+    no_validate: bool,
 }
 
-#[derive(Debug, Error)]
-pub enum CarguixError {
-    #[error(display = "could not create temporary directory")]
-    TmpdirError(#[error(cause)] std::io::Error),
-    #[error(display = "could not open hash database (crates_hash.db)")]
-    HashdbError(#[error(cause)] rustbreak::BreakError),
-    #[error(display = "could not update index")]
-    IndexUpdateError(#[error(cause)] crates_index::Error),
-    #[error(display = "could not package version {:?} of crate {}", version, name)]
-    CratePackagingFailed {
-        name: String,
-        version: Option<String>,
-    },
-    #[error(display = "could not find crate {}", _0)]
-    CrateNotFound(String),
-    #[error(display = "failure while retrieving key {:?} in hash database", _0)]
-    HashRetrieveFailed(#[error(cause)] rustbreak::BreakError, (String, String)),
-    #[error(display = "could not download crate {}", _0)]
-    CrateDownloadError(#[error(cause)] reqwest::Error, String),
-    #[error(display = "could not create crate {} destination file", _0)]
-    FileCreationFailed(#[error(cause)] std::io::Error, String),
-    #[error(display = "failure while inserting key {:?} in hash database", _0)]
-    HashInsertionFailed(#[error(cause)] rustbreak::BreakError, (String, String)),
-    #[error(display = "could not flush hash database")]
-    HashDatabaseFlushFailed(#[error(cause, no_from)] rustbreak::BreakError),
-    #[error(display = "could not compute hash of crate {}", _0)]
-    GuixHashError(
-        #[error(cause)] shellfn::Error<std::convert::Infallible>,
-        String,
-    ),
-    #[error(display = "could not copy crate {} source to destination", _0)]
-    CopyError(#[error(cause)] std::io::Error, String),
-    #[error(display = "no version of crate {} matching {} found", name, version)]
-    NoMatchingVersion { name: String, version: String },
-    #[error(
-        display = "no version of crate {} matching requirement {} found",
-        name,
-        requirement
-    )]
-    NoVersionMatchingRequirement { name: String, requirement: String },
-    #[error(display = "parsing of version {} for crate {} failed", _1, _1)]
-    VersionParsingError(#[error(cause)] semver::SemVerError, String, String),
-    #[error(display = "parsing of requirement {} for crate {} failed", _1, _0)]
-    RequirementParsingError(#[error(cause)] semver::ReqParseError, String, String),
-    #[error(
-        display = "could not process a dependency of crate {} in version {}",
-        _0,
-        _1
-    )]
-    DependencyProcessingFailed(#[error(cause)] Box<CarguixError>, String, String),
-}
-
-#[derive(Debug)]
-pub struct Carguix {
-    crates: VecDeque<(String, Option<String>)>,
-    already_added_crates: HashSet<(String, Option<String>)>,
-    index: Index,
-    tmpdir: TempDir,
-    hashdb: Database<(String, String)>,
-}
-
-impl Carguix {
-    pub fn new(crate_name: &str, crate_version: &Option<String>) -> Result<Self, CarguixError> {
-        let mut carguix = Carguix {
-            crates: VecDeque::new(),
-            already_added_crates: HashSet::new(),
-            index: Index::new("_index"),
-            tmpdir: TempDir::new(env!("CARGO_PKG_NAME")).map_err(CarguixError::TmpdirError)?,
-            hashdb: Database::open("crates_hash.db").map_err(CarguixError::HashdbError)?,
-        };
-        carguix
-            .crates
-            .push_back((crate_name.to_string(), crate_version.clone()));
-        if carguix.index.exists().not() {
-            carguix.update_index()?;
-        }
-        Ok(carguix)
-    }
-
-    pub fn update_index(&self) -> Result<(), CarguixError> {
-        log::info!("fetching crates.io index...");
-        self.index
-            .retrieve_or_update()
-            .map_err(CarguixError::IndexUpdateError)
-    }
-
-    pub fn process_crate(
-        &mut self,
-        crate_name: &str,
-        crate_version: &Option<String>,
-    ) -> Result<lexpr::Value, CarguixError> {
-        let crate_index = &self
-            .index
-            .crate_(&crate_name)
-            .ok_or_else(|| CarguixError::CrateNotFound(crate_name.to_string()))?;
-        let crate_package = self
-            .crate_package(crate_index, &crate_version)
-            .map_err(|_| CarguixError::CratePackagingFailed {
-                name: crate_name.to_string(),
-                version: crate_version.clone(),
-            })?;
-        for dependency in &crate_package.dependencies {
-            self.crates
-                .push_back((dependency.name.clone(), Some(dependency.version.clone())));
-        }
-        self.already_added_crates
-            .insert((crate_name.to_string(), crate_version.clone()));
-        Ok(crate_package.to_package_sexpr())
-    }
-
-    pub fn get_crate_hash(
-        &mut self,
-        crate_name: &str,
-        version: &str,
-    ) -> Result<String, CarguixError> {
-        let key = &(crate_name.to_string(), version.to_string());
-        match self.hashdb.retrieve::<String, _>(key) {
-            Ok(hash) => return Ok(hash),
-            Err(rustbreak::BreakError::NotFound) => (), // cache miss
-            Err(err) => Err(CarguixError::HashRetrieveFailed(err, key.clone()))?,
-        }
-        let url = format!(
-            "https://crates.io/api/v1/crates/{}/{}/download",
-            crate_name, version
-        );
-        let mut download_request = reqwest::get(&url)
-            .map_err(|err| CarguixError::CrateDownloadError(err, crate_name.to_string()))?;
-        let downloaded_crate_path = self
-            .tmpdir
-            .path()
-            .join(format!("{}-{}.tar.gz", crate_name, version));
-        let mut downloaded_crate = File::create(downloaded_crate_path.clone())
-            .map_err(|err| CarguixError::FileCreationFailed(err, crate_name.to_string()))?;
-        copy(&mut download_request, &mut downloaded_crate)
-            .map_err(|err| CarguixError::CopyError(err, crate_name.to_string()))?;
-        let hash = Self::guix_hash(&downloaded_crate_path.to_string_lossy())
-            .map_err(|err| CarguixError::GuixHashError(err, crate_name.to_string()))?;
-        self.hashdb
-            .insert(key, hash.clone())
-            .map_err(|err| CarguixError::HashInsertionFailed(err, key.clone()))?;
-        self.hashdb
-            .flush()
-            .map_err(CarguixError::HashDatabaseFlushFailed)?;
-        Ok(hash)
-    }
-
-    pub fn guix_hash(file_path: &str) -> Result<String, shellfn::Error<std::convert::Infallible>> {
-        #[shell]
-        fn guix_hash_(file_path: &str) -> Result<String, shellfn::Error<std::convert::Infallible>> {
-            "guix hash $FILE_PATH"
-        }
-        Ok(guix_hash_(file_path)?.trim().to_string())
-    }
-
-    pub fn crate_package(
-        &mut self,
-        crate_: &Crate,
-        version: &Option<String>,
-    ) -> Result<CratePackage, CarguixError> {
-        let version = version
-            .as_ref()
-            .map(String::as_str)
-            .unwrap_or_else(|| crate_.latest_version().version());
-        let crate_version = crate_
-            .versions()
-            .iter()
-            .find(|crate_version| crate_version.version() == version)
-            .ok_or(CarguixError::NoMatchingVersion {
-                name: crate_.name().to_string(),
-                version: version.to_string(),
-            })?;
-        let dependencies = crate_version
-            .dependencies()
-            .iter()
-            .map(|dependency| self.dependency_crate_ref(dependency))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|err| {
-                CarguixError::DependencyProcessingFailed(
-                    Box::new(err),
-                    crate_.name().to_string(),
-                    version.to_string(),
-                )
-            })?;
-        let hash = self.get_crate_hash(crate_.name(), version)?;
-        Ok(CratePackage::new(
-            crate_.name(),
-            version,
-            &hash,
-            &dependencies,
-        ))
-    }
-
-    pub fn dependency_crate_ref(
-        &mut self,
-        dependency: &Dependency,
-    ) -> Result<CrateRef, CarguixError> {
-        let crate_name = dependency.crate_name();
-        let crate_ = self
-            .index
-            .crate_(crate_name)
-            .ok_or_else(|| CarguixError::CrateNotFound(crate_name.to_string()))?;
-        let mut crate_versions = crate_
-            .versions()
-            .iter()
-            .map(|crate_version| Version::parse(crate_version.version()))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|err| {
-                CarguixError::VersionParsingError(
-                    err,
-                    crate_name.to_string(),
-                    dependency.requirement().to_string(),
+fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    let started = Instant::now();
+    let mut args = Cli::from_args();
+    let root = read_source_command(&mut args);
+    read_positional(&mut args);
+    if args.scheme_api.is_some() && args.output.is_none() && args.output_dir.is_none() {
+        structopt::clap::Error::with_description(
+            "--scheme-api requires the packages to be written to modules with --output or --output-dir",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if args.prefetch_manifest.is_some() && args.output.is_none() && args.output_dir.is_none() {
+        structopt::clap::Error::with_description(
+            "--prefetch-manifest requires the packages to be written to modules with --output or --output-dir",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if args.only_missing && args.output.is_none() && args.output_dir.is_none() {
+        structopt::clap::Error::with_description(
+            "--only-missing completes the modules of --output or --output-dir",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    if args.offline && args.path.len() > 1 {
+        structopt::clap::Error::with_description(
+            "--offline resolves crates from the Cargo.lock file of a single --path",
+            structopt::clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    match &args.command {
+        Some(Command::Cache { command }) => return Ok(run_cache_command(&args, command)?),
+        Some(Command::Versions {
+            crate_name,
+            registry,
+        }) => return Ok(print_versions(&args, crate_name, registry.as_deref())?),
+        Some(Command::Preview { crate_name, depth }) => {
+            return Ok(preview(&args, crate_name, *depth)?)
+        }
+        Some(Command::Inspect {
+            target,
+            registry,
+            json,
+        }) => return Ok(inspect(&args, target, registry.as_deref(), *json)?),
+        Some(Command::Env {
+            path,
+            output,
+            manifest,
+        }) => {
+            return Ok(write_environment(
+                &args,
+                path,
+                output.as_deref(),
+                *manifest,
+            )?)
+        }
+        Some(Command::Render { frozen }) => {
+            if !frozen {
+                structopt::clap::Error::with_description(
+                    "render only renders the frozen package set, pass --frozen",
+                    structopt::clap::ErrorKind::MissingRequiredArgument,
                 )
-            })?;
-        crate_versions.sort();
-        let version_req = VersionReq::parse(dependency.requirement()).map_err(|err| {
-            CarguixError::RequirementParsingError(
-                err,
-                crate_name.to_string(),
-                dependency.requirement().to_string(),
-            )
-        })?;
-        let highest_matching_version = crate_versions
-            .iter()
-            .rev()
-            .find(|version| version_req.matches(&version))
-            .ok_or(CarguixError::NoVersionMatchingRequirement {
-                name: crate_name.to_string(),
-                requirement: dependency.requirement().to_string(),
-            })?;
-        Ok(CrateRef::new(
+                .exit();
+            }
+            return Ok(render_frozen(&args)?);
+        }
+        Some(Command::Update { module, cargo_lock }) => {
+            return Ok(update_module(&args, module, cargo_lock.as_deref())?)
+        }
+        Some(Command::Regen { module, root }) => {
+            return Ok(regenerate_subtree(&args, module, root)?)
+        }
+        Some(Command::Diff {
             crate_name,
-            &highest_matching_version.to_string(),
-        ))
+            guix_checkout,
+            output,
+        }) => {
+            return Ok(diff_guix_checkout(
+                &args,
+                crate_name,
+                guix_checkout,
+                output.as_deref(),
+            )?)
+        }
+        Some(Command::CheckHashes {
+            crate_name,
+            guix_checkout,
+        }) => return Ok(check_upstream_hashes(&args, crate_name, guix_checkout)?),
+        Some(Command::Example {
+            template,
+            dir,
+            force,
+        }) => return Ok(write_example(&args, template, dir, *force)?),
+        Some(Command::SelfTest) => return Ok(self_test()?),
+        Some(Command::Channel {
+            command:
+                ChannelCommand::Init {
+                    dir,
+                    name,
+                    path,
+                    crate_name,
+                    force,
+                },
+        }) => {
+            return Ok(init_channel(
+                &args,
+                dir,
+                name.as_deref(),
+                path,
+                crate_name.as_deref(),
+                *force,
+            )?)
+        }
+        Some(Command::Crate { .. }) | Some(Command::Path { .. }) | Some(Command::Lock { .. }) => {
+            unreachable!("source subcommands are turned into options")
+        }
+        Some(Command::Git { .. }) | Some(Command::ReverseDeps { .. }) | None => (),
+    }
+    let templates = Templates::open(args.template.as_deref(), args.package_template.as_deref())?;
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let crates = crate_list(&args)?;
+    let generator = match (args.path.as_slice(), &args.crate_name) {
+        _ if !crates.is_empty() => Generator::from_crates(&crates, &features)?,
+        ([path], _) if root.is_some() => {
+            Generator::from_path_member(path, root.as_deref().unwrap_or_default(), &features)?
+        }
+        ([_, ..], _) => Generator::from_paths(&args.path, &features)?,
+        ([], Some(crate_name)) => Generator::new(crate_name, &args.version, &features)?,
+        ([], None) => match &args.command {
+            Some(Command::Git {
+                url,
+                rev,
+                tag,
+                branch,
+                subdir,
+            }) => Generator::from_git(
+                url,
+                &patches::git_revision(rev.as_deref(), tag.as_deref(), branch.as_deref()),
+                subdir.as_deref(),
+                &features,
+            )?,
+            Some(Command::ReverseDeps { crate_name, limit }) => {
+                Generator::from_reverse_dependencies(crate_name, *limit)?
+            }
+            _ => unreachable!("structopt requires either a crate name or a path"),
+        },
+    };
+    let options = options(&args)?;
+    let warnings = options.warnings.clone();
+    let mut generator = generator.with_options(options)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let results = generator.packages();
+    let index_commit = generator.index_commit();
+    if let Some(report) = &args.report {
+        let run_report = RunReport::new(
+            &results,
+            generator.package_runs(),
+            generator.cache_stats(),
+            started.elapsed(),
+        );
+        let content = serde_json::to_string_pretty(&Versioned::new(run_report))
+            .map_err(CarguixError::JsonSerializationError)?;
+        fs::write(report, content + "\n")
+            .map_err(|err| CarguixError::RunReportError(err, report.clone()))?;
+    }
+    let denied_warnings = warnings.denied_warnings();
+    if !denied_warnings.is_empty() {
+        return Err(CarguixError::DeniedWarnings(denied_warnings.len()).into());
+    }
+    let mut packages = Vec::new();
+    for package in results {
+        match package {
+            Ok(package) => packages.push(package),
+            Err(err) => print_error(&err),
+        }
+    }
+    if let Some(emit_graph) = &args.emit_graph {
+        fs::write(emit_graph, generator.dependency_graph().to_dot())
+            .map_err(|err| CarguixError::GraphError(err, emit_graph.clone()))?;
     }
+    if let Some(license_report) = &args.license_report {
+        let report = LicenseReport::new(&packages);
+        for conflict in &report.conflicts {
+            log::warn!(
+                "{} ({}) and {} ({}) can't be redistributed together: {}",
+                conflict.license,
+                conflict.packages.join(", "),
+                conflict.other_license,
+                conflict.other_packages.join(", "),
+                conflict.reason
+            );
+        }
+        let content = serde_json::to_string_pretty(&Versioned::new(report))
+            .map_err(CarguixError::JsonSerializationError)?;
+        fs::write(license_report, content + "\n")
+            .map_err(|err| CarguixError::LicenseReportError(err, license_report.clone()))?;
+    }
+    if let Some(stats_out) = &args.stats_out {
+        let run_stats = RunStats::new(
+            &packages,
+            generator.existing_package_count(),
+            generator.cache_stats(),
+            index_commit.clone(),
+        );
+        Stats::append(stats_out, run_stats)?;
+    }
+    write_packages(
+        &args,
+        packages,
+        index_commit,
+        generator.left_out_crates(),
+        templates,
+    )?;
+    generator.save_lock()?;
+    Ok(())
+}
+
+/// Write the packages of the frozen package set again, see [`FrozenPackageSet`].
+fn render_frozen(args: &Cli) -> Result<(), CarguixError> {
+    let data_dir = args.data_dir.clone().unwrap_or_else(default_data_dir);
+    let frozen = FrozenPackageSet::open(&data_dir.join(FROZEN_FILE))?;
+    let templates = Templates::open(args.template.as_deref(), args.package_template.as_deref())?;
+    let index_commit = frozen.index_commit.clone();
+    let left_out_crates = frozen.left_out_crates.clone();
+    write_packages(
+        args,
+        frozen.into_packages(),
+        index_commit,
+        &left_out_crates,
+        templates,
+    )
 }
 
-impl Iterator for Carguix {
-    type Item = Result<lexpr::Value, CarguixError>;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((crate_name, crate_version)) = self.crates.pop_front() {
-            if self
-                .already_added_crates
-                .contains(&(crate_name.clone(), crate_version.clone()))
-            {
-                continue;
+/// Write the packages in the format and to the outputs the options select, once they pass the
+/// checks of [`Validator`] unless `--no-validate` is given.
+fn write_packages(
+    args: &Cli,
+    packages: Vec<guix::Package>,
+    index_commit: Option<String>,
+    left_out_crates: &BTreeSet<String>,
+    templates: Templates,
+) -> Result<(), CarguixError> {
+    if !args.no_validate {
+        let validator = Validator::new(&packages)
+            .with_defined_variables(defined_variables(args)?)
+            .with_left_out_crates(left_out_crates.iter().cloned());
+        let errors = validator.validate_all(&packages);
+        for err in &errors {
+            print_error(err);
+        }
+        if !errors.is_empty() {
+            return Err(CarguixError::InvalidPackages(errors.len()));
+        }
+    }
+    let module_name = |path: &Path| match &args.module_name {
+        Some(module_name) => guix::Module::parse_name(module_name),
+        None => guix::Module::name_from_path(path),
+    };
+    // modules the packages are written to, imported by the API module and the prefetch manifest
+    let package_modules = || match (&args.output, &args.output_dir) {
+        (Some(output), _) => vec![module_name(output)],
+        (None, Some(output_dir)) => {
+            let prefix = module_name(output_dir);
+            packages
+                .iter()
+                .map(|package| {
+                    let mut name = prefix.clone();
+                    name.push(package.variable());
+                    name
+                })
+                .collect()
+        }
+        (None, None) => {
+            unreachable!("--scheme-api and --prefetch-manifest require --output or --output-dir")
+        }
+    };
+    if let Some(scheme_api) = &args.scheme_api {
+        let api_module = guix::ApiModule::new(
+            guix::Module::name_from_path(scheme_api),
+            package_modules(),
+            &packages,
+        );
+        fs::write(scheme_api, api_module.to_scheme())
+            .map_err(|err| CarguixError::OutputError(err, scheme_api.clone()))?;
+    }
+    if let Some(prefetch_manifest) = &args.prefetch_manifest {
+        let manifest = guix::PrefetchManifest::new(package_modules(), &packages);
+        fs::write(prefetch_manifest, manifest.to_scheme())
+            .map_err(|err| CarguixError::OutputError(err, prefetch_manifest.clone()))?;
+    }
+    match (
+        args.format.unwrap_or(Format::Sexpr),
+        &args.output,
+        &args.output_dir,
+    ) {
+        (Format::Json, output, _) => {
+            let module_name = match (&args.module_name, output) {
+                (Some(module_name), _) => guix::Module::parse_name(module_name),
+                (None, Some(output)) => guix::Module::name_from_path(output),
+                (None, None) => Vec::new(),
+            };
+            let module =
+                guix::Module::new(module_name, packages, index_commit, args.parameterize_rust);
+            match output {
+                Some(output) => {
+                    let output_error = |err| CarguixError::OutputError(err, output.clone());
+                    let mut writer = BufWriter::new(File::create(output).map_err(output_error)?);
+                    serde_json::to_writer_pretty(&mut writer, &Versioned::new(module))
+                        .map_err(CarguixError::JsonSerializationError)?;
+                    writeln!(writer)
+                        .and_then(|()| writer.flush())
+                        .map_err(output_error)?;
+                }
+                None => {
+                    let json = serde_json::to_string_pretty(&Versioned::new(module))
+                        .map_err(CarguixError::JsonSerializationError)?;
+                    println!("{}", json);
+                }
+            }
+        }
+        (Format::Sexpr, Some(output), _) => {
+            let module_name = module_name(output);
+            let (packages, imports) = match &args.proc_macro_module {
+                Some(proc_macro_module) => write_proc_macro_module(
+                    proc_macro_module,
+                    &module_name,
+                    packages,
+                    &index_commit,
+                    args.parameterize_rust,
+                    &templates,
+                )?,
+                None => (packages, Vec::new()),
+            };
+            let existing_module = match args.only_missing {
+                true => read_existing_module(output)?,
+                false => None,
+            };
+            let mut packages = packages;
+            if let Some(existing_module) = &existing_module {
+                let defined_variables = guix::defined_variables(existing_module);
+                packages.retain(|package| !defined_variables.contains(&package.variable()));
+            }
+            let module =
+                guix::Module::new(module_name, packages, index_commit, args.parameterize_rust)
+                    .with_imports(imports)
+                    .with_templates(templates);
+            let output_error = |err| CarguixError::OutputError(err, output.clone());
+            match existing_module {
+                Some(existing_module) => fs::write(
+                    output,
+                    format!("{}\n\n{}", existing_module.trim_end(), module.definitions()),
+                )
+                .map_err(output_error)?,
+                None => {
+                    let mut writer = BufWriter::new(File::create(output).map_err(output_error)?);
+                    module
+                        .write_scheme(&mut writer)
+                        .and_then(|()| writer.flush())
+                        .map_err(output_error)?;
+                }
+            }
+        }
+        (Format::Sexpr, None, Some(output_dir)) => write_package_modules(
+            output_dir,
+            &module_name(output_dir),
+            &packages,
+            &index_commit,
+            args.parameterize_rust,
+            &templates,
+            args.force,
+        )?,
+        (Format::Sexpr, None, None) => {
+            if let Some(index_commit) = index_commit {
+                println!(";; crates.io index commit {}\n", index_commit);
+            }
+            for package in packages {
+                print!(
+                    "{}",
+                    guix::package_definitions(&package, args.parameterize_rust, &templates)
+                );
             }
-            return Some(self.process_crate(&crate_name, &crate_version));
         }
-        None
     }
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
-pub struct CratePackage {
-    pub crate_ref: CrateRef,
-    pub hash: String,
-    pub dependencies: Vec<CrateRef>,
+/// Turn the `crate`, `path` and `lock` subcommands into the options they stand for, returning
+/// the crate `lock --root` selects. The `git` and `reverse-deps` subcommands are only checked
+/// against the options.
+fn read_source_command(args: &mut Cli) -> Option<String> {
+    let exit = |description: &str, kind| {
+        structopt::clap::Error::with_description(description, kind).exit()
+    };
+    let command = match args.command.take() {
+        Some(command @ Command::Crate { .. })
+        | Some(command @ Command::Path { .. })
+        | Some(command @ Command::Lock { .. }) => command,
+        // the checkout is packaged by the generator, which knows its repository
+        Some(command @ Command::Git { .. }) => {
+            if args.crate_name.is_some() || args.explicit_crate.is_some() || !args.path.is_empty() {
+                exit(
+                    "the git subcommand gives the crates to package by itself",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            if args.version.is_some() || args.offline {
+                exit(
+                    "--version only applies to crates of the registry, and --offline forbids cloning",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            args.command = Some(command);
+            return None;
+        }
+        // the dependents are looked up by the generator, in the index
+        Some(command @ Command::ReverseDeps { .. }) => {
+            if args.crate_name.is_some() || args.explicit_crate.is_some() || !args.path.is_empty() {
+                exit(
+                    "the reverse-deps subcommand gives the crates to package by itself",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            if args.version.is_some() || args.offline {
+                exit(
+                    "--version doesn't apply to dependents, and --offline forbids reading the index",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            args.command = Some(command);
+            return None;
+        }
+        command => {
+            args.command = command;
+            return None;
+        }
+    };
+    if args.crate_name.is_some() || args.explicit_crate.is_some() || !args.path.is_empty() {
+        exit(
+            "the crate, path and lock subcommands give the crates to package by themselves",
+            structopt::clap::ErrorKind::ArgumentConflict,
+        );
+    }
+    match command {
+        Command::Crate {
+            crate_name,
+            version,
+        } => {
+            if version.is_some() && args.version.is_some() {
+                exit(
+                    "--version is given twice",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            args.explicit_crate = Some(crate_name);
+            args.version = version.or_else(|| args.version.take());
+            None
+        }
+        Command::Path { dirs } => {
+            if args.version.is_some() {
+                exit(
+                    "--version only applies to crates of the registry",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            args.path = dirs;
+            None
+        }
+        Command::Lock {
+            cargo_lock,
+            root,
+            vendor_dir,
+        } => {
+            if args.version.is_some() {
+                exit(
+                    "--version only applies to crates of the registry",
+                    structopt::clap::ErrorKind::ArgumentConflict,
+                );
+            }
+            let dir = match cargo_lock.parent() {
+                Some(dir) if dir.as_os_str().is_empty() => PathBuf::from("."),
+                Some(dir) => dir.to_path_buf(),
+                None => PathBuf::from("."),
+            };
+            args.offline = true;
+            args.vendor_dir = vendor_dir.or_else(|| Some(dir.join("vendor")));
+            args.cargo_lock = Some(cargo_lock);
+            args.path = vec![dir];
+            root
+        }
+        _ => unreachable!("only source subcommands are read"),
+    }
 }
 
-impl CratePackage {
-    pub fn new(name: &str, version: &str, hash: &str, dependencies: &[CrateRef]) -> Self {
-        Self {
-            crate_ref: CrateRef::new(name, version),
-            hash: hash.to_string(),
-            dependencies: dependencies.to_vec(),
+/// Make sense of the positional argument: a directory with a `Cargo.toml` is packaged like with
+/// `--path`, a git URL like with `carguix git <url>` and `name@version` stands for
+/// `name --version version`. `--crate` and `--path` name a crate or a directory explicitly
+/// instead.
+fn read_positional(args: &mut Cli) {
+    let exit = |description: &str, kind| {
+        structopt::clap::Error::with_description(description, kind).exit()
+    };
+    let (argument, explicit) = match (args.explicit_crate.take(), &args.crate_name) {
+        (Some(crate_name), _) => (crate_name, true),
+        (None, Some(argument)) => (argument.clone(), false),
+        (None, None) => return,
+    };
+    args.crate_name = Some(argument.clone());
+    if !explicit && Path::new(&argument).join("Cargo.toml").is_file() {
+        if !args.more_crates.is_empty() || args.from_file.is_some() {
+            exit(
+                "only crates of the registry are packaged together, pass --path to package the directory",
+                structopt::clap::ErrorKind::ArgumentConflict,
+            );
+        }
+        log::info!("packaging the local crate or workspace at {}", argument);
+        args.path = vec![PathBuf::from(argument)];
+        args.crate_name = None;
+        if args.version.is_some() {
+            exit(
+                "--version only applies to crates of the registry, pass --crate to package the crate named like the directory",
+                structopt::clap::ErrorKind::ArgumentConflict,
+            );
         }
+        return;
     }
+    if !explicit && (argument.contains("://") || argument.starts_with("git@")) {
+        if !args.more_crates.is_empty() || args.from_file.is_some() {
+            exit(
+                "only crates of the registry are packaged together, package the repository on its own",
+                structopt::clap::ErrorKind::ArgumentConflict,
+            );
+        }
+        if args.version.is_some() || args.offline {
+            exit(
+                "--version only applies to crates of the registry, and --offline forbids cloning",
+                structopt::clap::ErrorKind::ArgumentConflict,
+            );
+        }
+        // packaged like `carguix git <url>`, at the default branch of the repository
+        log::info!("packaging the git repository at {}", argument);
+        args.command = Some(Command::Git {
+            url: argument,
+            rev: None,
+            tag: None,
+            branch: None,
+            subdir: None,
+        });
+        args.crate_name = None;
+        return;
+    }
+    let (crate_name, version) = crate_version(&argument, &args.version);
+    args.crate_name = Some(crate_name.to_string());
+    args.version = version;
+}
 
-    pub fn to_package_sexpr(&self) -> lexpr::Value {
-        let dependencies_sexpr = self
-            .dependencies
-            .iter()
-            .map(CrateRef::to_dependency_sexpr)
-            .collect::<Vec<_>>();
-        sexp!(
-            (#"define-public" ,(lexpr::Value::symbol(self.crate_ref.format_name_version()))
-                (package
-                    (name ,(self.crate_ref.format_name()))
-                    (version ,(self.crate_ref.version.clone()))
-                    (source
-                        (origin
-                            (method #"url-fetch")
-                            (#"uri" (#"crate-uri" ,(self.crate_ref.name.clone()) version))
-                            (#"file-name"
-                                (#"string-append" name "-" version ".tar.gz"))
-                            (sha256
-                                (base32 ,(self.hash.clone())))))
-                    (#"build-system" #"cargo-build-system")
-                    (arguments
-                        (list #:"cargo-inputs"
-                            ,(lexpr::Value::append(
-                                vec![lexpr::Value::symbol("list")],
-                                lexpr::Value::list(dependencies_sexpr)))))
-                    (#"home-page" #f)
-                    (synopsis #f)
-                    (description #f)
-                    (license #f)))
+/// Name and version of the crate `spec` names, either as `name@version` or as a bare name
+/// whose version is `version_option`.
+fn crate_version<'a>(spec: &'a str, version_option: &Option<String>) -> (&'a str, Option<String>) {
+    match CrateRef::split_version(spec) {
+        (crate_name, Some(version)) => (
+            crate_name,
+            Some(shorthand_version(version, version_option).to_string()),
+        ),
+        (crate_name, None) => (crate_name, version_option.clone()),
+    }
+}
+
+/// Version of the `name@version` shorthand, which mustn't be empty nor given along with
+/// `--version`.
+fn shorthand_version<'a>(version: &'a str, version_option: &Option<String>) -> &'a str {
+    if version.is_empty() || version_option.is_some() {
+        structopt::clap::Error::with_description(
+            "`name@version` needs a version, and no --version besides",
+            structopt::clap::ErrorKind::InvalidValue,
         )
+        .exit();
     }
+    version
 }
 
-#[derive(Debug, Clone)]
-pub struct CrateRef {
-    pub name: String,
-    pub version: String,
+/// Settings of the generator, from the command line.
+fn options(args: &Cli) -> Result<Options, CarguixError> {
+    Ok(Options {
+        max_crate_size: Some(args.max_crate_size.unwrap_or(DEFAULT_MAX_CRATE_SIZE) * 1024 * 1024),
+        confirm_large_crates: args.max_crate_size.is_some(),
+        assume_yes: args.assume_yes,
+        skip_dev_dependencies: args.skip_dev_dependencies,
+        platform: match (&args.target, args.all_targets) {
+            (_, true) => Platform::All,
+            (Some(triple), false) => Platform::new(triple)?,
+            (None, false) => Platform::host(),
+        },
+        strict_semver: args.strict_semver,
+        allow_yanked: args.allow_yanked,
+        allow_prerelease: args.allow_prerelease && !args.no_prerelease,
+        keep_duplicate_versions: args.keep_duplicate_versions,
+        requirement_comments: args.requirement_comments,
+        update_properties: args.update_properties,
+        input_style: args.style,
+        feature_summaries: args.feature_summaries,
+        skip_proc_macro_builds: args.skip_proc_macro_builds,
+        skip_dependency_builds: args.skip_build,
+        enrichers: (!args.enrichers.is_empty()).then(|| args.enrichers.clone()),
+        warnings: Warnings::new(&args.allow, &args.deny),
+        max_depth: args.depth,
+        max_build_dependency_depth: args.max_build_depth,
+        deep_build_dependencies: args.deep_build_deps.unwrap_or_default(),
+        excluded_crates: excluded_crates(args)?,
+        defined_variables: defined_variables(args)?,
+        offline: args.offline,
+        cargo_config_dirs: args.path.clone(),
+        cargo_lock: args.cargo_lock.clone(),
+        vendor_dir: args.vendor_dir.clone(),
+        cargo_metadata: args.cargo_metadata,
+        sparse_index: args.sparse_index,
+        index_url: args.index_url.clone(),
+        data_dir: args.data_dir.clone(),
+        allow_index_rewrite: args.allow_index_rewrite,
+        lock_file: Some(args.lock_file.clone()),
+        refresh_lock: args.refresh_lock,
+        resume: args.resume,
+        jobs: args.jobs,
+        download_retries: args.download_retries,
+        guix_hash: args.guix_hash,
+        merge_strategy: args.merge_conflicts,
+        refresh_hashes: args.refresh_hashes.clone(),
+        verify_checksums: args.verify,
+        mirror: args.mirror.clone(),
+        hash_mirror: args.hash_mirror.clone(),
+        tarball_dir: args.tarball_dir.clone(),
+        tarball_url: args.tarball_url.clone(),
+        guix_checkout: args.guix_checkout.clone(),
+        tooling: SharedTooling::default(),
+        config: Config::open(&args.config)?.with_presets(&args.presets)?,
+    })
 }
 
-impl CrateRef {
-    pub fn new(name: &str, version: &str) -> Self {
-        Self {
-            name: name.to_string(),
-            version: version.to_string(),
+/// Crates of the registry packaged together when several are given, on the command line or in the
+/// `--from-file` list, whose lines starting with `#` are comments, the first one in the version of
+/// `--version`. Empty for a single crate given on the command line.
+fn crate_list(args: &Cli) -> Result<Vec<(String, Option<String>)>, CarguixError> {
+    if args.more_crates.is_empty() && args.from_file.is_none() {
+        return Ok(Vec::new());
+    }
+    let mut specs = args.more_crates.clone();
+    if let Some(from_file) = &args.from_file {
+        let content = if from_file == Path::new("-") {
+            io::read_to_string(io::stdin())
+        } else {
+            fs::read_to_string(from_file)
         }
+        .map_err(|err| CarguixError::CrateListError(err, from_file.clone()))?;
+        specs.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
     }
+    let mut crates = args
+        .crate_name
+        .iter()
+        .map(|crate_name| (crate_name.clone(), args.version.clone()))
+        .collect::<Vec<_>>();
+    for spec in &specs {
+        let (crate_name, version) = crate_version(spec, &None);
+        let crate_ = (crate_name.to_string(), version);
+        if !crates.contains(&crate_) {
+            crates.push(crate_);
+        }
+    }
+    Ok(crates)
+}
 
-    pub fn to_dependency_sexpr(&self) -> lexpr::Value {
-        let formatted_name = self.format_name_version();
-        sexp!((
-            list,
-            (formatted_name.clone()),
-            (lexpr::Value::symbol(formatted_name))
-        ))
+/// Crates given with `--exclude` and listed in the `--exclude-from` file, whose lines starting
+/// with `#` are comments.
+fn excluded_crates(args: &Cli) -> Result<Vec<String>, CarguixError> {
+    let mut excluded_crates = args.exclude.clone();
+    if let Some(exclude_from) = &args.exclude_from {
+        let content = fs::read_to_string(exclude_from)
+            .map_err(|err| CarguixError::ExclusionListError(err, exclude_from.clone()))?;
+        excluded_crates.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string),
+        );
     }
+    Ok(excluded_crates)
+}
 
-    pub fn format_name(&self) -> String {
-        format!("rust-{}", self.name.to_kebab_case())
+/// Variables of the packages `--only-missing` leaves out: the ones the `--output` module
+/// defines, or the ones the modules of `--output-dir` are named after.
+fn defined_variables(args: &Cli) -> Result<HashSet<String>, CarguixError> {
+    if !args.only_missing {
+        return Ok(HashSet::new());
+    }
+    match (&args.output, &args.output_dir) {
+        (Some(output), _) => Ok(read_existing_module(output)?
+            .map(|module| guix::defined_variables(&module))
+            .unwrap_or_default()),
+        (None, Some(output_dir)) if output_dir.is_dir() => {
+            let entries = fs::read_dir(output_dir)
+                .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+                .map_err(|err| CarguixError::InvalidPath(err, output_dir.clone()))?;
+            Ok(entries
+                .iter()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "scm"))
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+                .collect())
+        }
+        _ => Ok(HashSet::new()),
     }
+}
 
-    pub fn format_name_version(&self) -> String {
-        format!("rust-{}-{}", self.name.to_kebab_case(), self.version)
+/// Content of a module file `--only-missing` completes, `None` if it doesn't exist yet.
+fn read_existing_module(path: &Path) -> Result<Option<String>, CarguixError> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(CarguixError::ModuleIoError(err, path.to_path_buf())),
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-    let args = Cli::from_args();
-    let carguix = Carguix::new(&args.crate_name, &args.version)?;
-    if args.update {
-        carguix.update_index()?;
-    }
-    for crate_sexpr in carguix {
-        match crate_sexpr {
-            Ok(crate_sexpr) => println!("{}\n", crate_sexpr),
+/// Hash database of `--data-dir`, or of the default data directory.
+fn hash_database(args: &Cli) -> Result<HashDatabase, CarguixError> {
+    open_hash_database(&args.data_dir.clone().unwrap_or_else(default_data_dir))
+}
+
+/// Packages the generator produced, the crates which failed being reported on stderr.
+fn collect_packages(generator: &mut Generator) -> Vec<guix::Package> {
+    let mut packages = Vec::new();
+    for package in generator.packages() {
+        match package {
+            Ok(package) => packages.push(package),
             Err(err) => print_error(&err),
         }
     }
+    packages
+}
+
+fn run_cache_command(args: &Cli, command: &CacheCommand) -> Result<(), CarguixError> {
+    match command {
+        CacheCommand::List { crate_name } => {
+            let hashdb = hash_database(args)?;
+            for entry in hashdb.entries(crate_name.as_deref())? {
+                println!(
+                    "{} {} {} {} ({})",
+                    entry.name,
+                    entry.version,
+                    entry.hash,
+                    entry.method,
+                    entry.tool.as_deref().unwrap_or("unknown tool")
+                );
+            }
+        }
+        CacheCommand::Verify { crate_name, sample } => {
+            // Crates are downloaded without looking them up in the crates.io index, which
+            // doesn't need to be cloned
+            let generator = Generator::with_sources(Vec::new())?.with_options(Options {
+                sparse_index: true,
+                ..options(args)?
+            })?;
+            let mut entries = generator.hash_database().entries(crate_name.as_deref())?;
+            // only the hashes of tarballs can be compared with the ones of fresh downloads
+            entries.retain(|entry| entry.method == HashMethod::Tarball);
+            if let Some(sample) = sample {
+                let random_state = RandomState::new();
+                entries.sort_by_cached_key(|entry| {
+                    random_state.hash_one((&entry.name, &entry.version))
+                });
+                entries.truncate(*sample);
+            }
+            let hashes = generator.verify_hashes(&entries);
+            let mut mismatches = 0;
+            for (entry, hash) in entries.iter().zip(hashes) {
+                match hash {
+                    Ok(hash) if hash == entry.hash => {
+                        log::info!("hash of crate {} v{} verified", entry.name, entry.version)
+                    }
+                    Ok(hash) => {
+                        mismatches += 1;
+                        println!(
+                            "{} {}: cached hash {} but downloaded {}",
+                            entry.name, entry.version, entry.hash, hash
+                        );
+                    }
+                    Err(err) => print_error(&err),
+                }
+            }
+            if mismatches > 0 {
+                return Err(CarguixError::HashMismatches(mismatches));
+            }
+        }
+        CacheCommand::Purge {
+            crate_name,
+            version,
+        } => {
+            let (crate_name, version) = crate_version(crate_name, version);
+            let hashdb = hash_database(args)?;
+            let purged = hashdb.purge(crate_name, version.as_deref())?;
+            println!("purged {} hashes of crate {}", purged, crate_name);
+        }
+        CacheCommand::Serve { address } => {
+            let hashdb = hash_database(args)?;
+            hash_mirror::serve(&hashdb, address)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the published versions of a crate, one per line, followed by `yanked` and `cached`
+/// markers.
+fn print_versions(
+    args: &Cli,
+    crate_name: &str,
+    registry: Option<&str>,
+) -> Result<(), CarguixError> {
+    let generator = Generator::with_sources(Vec::new())?.with_options(options(args)?)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    for published in generator.published_versions(crate_name, registry)? {
+        let mut line = published.version;
+        if published.yanked {
+            line.push_str(" yanked");
+        }
+        if published.hash.is_some() {
+            line.push_str(" cached");
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn preview(args: &Cli, crate_name: &str, depth: usize) -> Result<(), CarguixError> {
+    let (crate_name, version) = crate_version(crate_name, &args.version);
+    let mut generator = Generator::with_sources(Vec::new())?.with_options(options(args)?)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let preview = Preview::new(&mut generator, crate_name, &version)?;
+    print!("{}", preview.to_tree(depth));
+    Ok(())
+}
+
+fn inspect(
+    args: &Cli,
+    target: &str,
+    registry: Option<&str>,
+    json: bool,
+) -> Result<(), CarguixError> {
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let inspections = if Path::new(target).is_dir() {
+        let mut manifests = match args.cargo_metadata {
+            true => ManifestCache::cargo_metadata(),
+            false => ManifestCache::default(),
+        };
+        Inspection::from_path(Path::new(target), &features, &mut manifests)?
+    } else {
+        let generator = Generator::with_sources(Vec::new())?.with_options(options(args)?)?;
+        if args.update_index {
+            generator.update_index()?;
+        }
+        let (crate_name, version) = crate_version(target, &args.version);
+        vec![Inspection::from_index(
+            &generator,
+            crate_name,
+            version.as_deref(),
+            registry,
+            &features,
+        )?]
+    };
+    if json {
+        let content = serde_json::to_string_pretty(&Versioned::new(InspectionReport {
+            crates: inspections,
+        }))
+        .map_err(CarguixError::JsonSerializationError)?;
+        println!("{}", content);
+    } else {
+        let reports = inspections
+            .iter()
+            .map(Inspection::to_text)
+            .collect::<Vec<_>>();
+        print!("{}", reports.join("\n"));
+    }
+    Ok(())
+}
+
+fn update_module(args: &Cli, module: &Path, cargo_lock: Option<&Path>) -> Result<(), CarguixError> {
+    let mut generator = Generator::with_sources(Vec::new())?.with_options(options(args)?)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let updated_packages = module_update::update_module(&mut generator, module, cargo_lock)?;
+    for updated in &updated_packages {
+        println!("{} {} -> {}", updated.crate_name, updated.from, updated.to);
+    }
+    log::info!("{} packages updated", updated_packages.len());
     Ok(())
 }
 
-fn print_error(err: &dyn Error) {
-    log::error!("error: {}", err);
-    let mut cause = err.source();
-    while let Some(err) = cause {
-        log::error!("caused by: {}", err);
-        cause = err.source();
+/// Print the outcome of every step of the self-test, failing if any step failed.
+fn self_test() -> Result<(), CarguixError> {
+    let checks = self_test::run()?;
+    for check in &checks {
+        match &check.outcome {
+            self_test::Outcome::Passed(detail) => println!("ok      {}: {}", check.step, detail),
+            self_test::Outcome::Skipped(reason) => {
+                println!("skipped {}: {}", check.step, reason)
+            }
+            self_test::Outcome::Failed(reason) => println!("FAILED  {}: {}", check.step, reason),
+        }
+    }
+    match checks.iter().filter(|check| check.failed()).count() {
+        0 => Ok(()),
+        failures => Err(CarguixError::SelfTestFailed(failures)),
+    }
+}
+
+/// Package the root crate of a subtree of a module again, along with its dependencies, and
+/// replace the definitions of the subtree with them.
+fn regenerate_subtree(args: &Cli, module: &Path, root: &str) -> Result<(), CarguixError> {
+    let templates = Templates::open(None, args.package_template.as_deref())?;
+    let subtree = module_update::Subtree::open(module, root)?;
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let generator = match subtree.root_path() {
+        Some(path) => Generator::from_path_member(path, &subtree.root().name, &features)?,
+        None => Generator::new(
+            &subtree.root().name,
+            &Some(subtree.root().version.clone()),
+            &features,
+        )?,
+    };
+    let mut generator = generator.with_options(options(args)?)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let packages = collect_packages(&mut generator);
+    let regenerated = subtree.regenerate(packages, &templates)?;
+    for variable in &regenerated.updated {
+        println!("updated {}", variable);
+    }
+    for variable in &regenerated.added {
+        println!("added {}", variable);
+    }
+    for variable in &regenerated.removed {
+        println!("removed {}", variable);
+    }
+    generator.save_lock()
+}
+
+/// Write the development environment of local crates, defining their dependencies.
+fn write_environment(
+    args: &Cli,
+    paths: &[PathBuf],
+    output: Option<&Path>,
+    manifest: bool,
+) -> Result<(), CarguixError> {
+    let templates = Templates::open(None, args.package_template.as_deref())?;
+    let paths = match paths {
+        [] => vec![PathBuf::from(".")],
+        paths => paths.to_vec(),
+    };
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let mut generator = Generator::from_paths(&paths, &features)?.with_options(options(args)?)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let packages = collect_packages(&mut generator);
+    let index_commit = generator.index_commit();
+    let environment =
+        guix::Environment::new(packages, index_commit, manifest)?.with_templates(templates);
+    match output {
+        Some(output) => fs::write(output, environment.to_scheme())
+            .map_err(|err| CarguixError::OutputError(err, output.to_path_buf()))?,
+        None => print!("{}", environment.to_scheme()),
+    }
+    generator.save_lock()
+}
+
+/// Report how the packages of a crate relate to a Guix checkout and define the ones it lacks.
+fn diff_guix_checkout(
+    args: &Cli,
+    crate_name: &str,
+    guix_checkout: &Path,
+    output: Option<&Path>,
+) -> Result<(), CarguixError> {
+    let (crate_name, version) = crate_version(crate_name, &args.version);
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let mut generator = Generator::new(crate_name, &version, &features)?.with_options(Options {
+        guix_checkout: Some(guix_checkout.to_path_buf()),
+        ..options(args)?
+    })?;
+    if args.update_index {
+        generator.update_index()?;
     }
+    let packages = collect_packages(&mut generator);
+    let diff = GuixDiff::new(&generator, packages);
+    print!("{}", diff.report());
+    match output {
+        Some(output) => fs::write(output, diff.to_patch())
+            .map_err(|err| CarguixError::OutputError(err, output.to_path_buf()))?,
+        None => print!("\n{}", diff.to_patch()),
+    }
+    generator.save_lock()
+}
+
+/// Compare the hashes of the packages of a crate with the ones of the Guix packages of the
+/// same versions, failing on mismatches.
+fn check_upstream_hashes(
+    args: &Cli,
+    crate_name: &str,
+    guix_checkout: &Path,
+) -> Result<(), CarguixError> {
+    let (crate_name, version) = crate_version(crate_name, &args.version);
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    // every crate is packaged, none being replaced by the Guix package it is compared with
+    let mut generator = Generator::new(crate_name, &version, &features)?.with_options(Options {
+        guix_checkout: None,
+        ..options(args)?
+    })?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let packages = collect_packages(&mut generator);
+    let report = HashReport::new(guix_checkout, &packages)?;
+    print!("{}", report.report());
+    generator.save_lock()?;
+    match report.mismatches() {
+        0 => Ok(()),
+        mismatches => Err(CarguixError::UpstreamHashMismatches(mismatches)),
+    }
+}
+
+/// Write a channel packaging either local crates or a crate of the registry to `dir`.
+fn init_channel(
+    args: &Cli,
+    dir: &Path,
+    name: Option<&str>,
+    paths: &[PathBuf],
+    crate_name: Option<&str>,
+    force: bool,
+) -> Result<(), CarguixError> {
+    let name = match name.or_else(|| dir.file_name().and_then(|name| name.to_str())) {
+        Some(name) => name.to_string(),
+        None => structopt::clap::Error::with_description(
+            "the channel directory has no usable name, pass --name",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    let templates = Templates::open(args.template.as_deref(), args.package_template.as_deref())?;
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let generator = match (paths, crate_name) {
+        ([], Some(crate_name)) => {
+            let (crate_name, version) = crate_version(crate_name, &None);
+            Generator::new(crate_name, &version, &features)?
+        }
+        ([], None) => Generator::from_paths(&[PathBuf::from(".")], &features)?,
+        (paths, _) => Generator::from_paths(paths, &features)?,
+    };
+    let mut generator = generator.with_options(options(args)?)?;
+    if args.update_index {
+        generator.update_index()?;
+    }
+    let packages = collect_packages(&mut generator);
+    let index_commit = generator.index_commit();
+    let channel = Channel::new(&name, packages, index_commit, args.parameterize_rust)
+        .with_templates(templates);
+    for path in channel.write(dir, force)? {
+        println!("{}", path.display());
+    }
+    generator.save_lock()
+}
+
+/// Scaffold the example project of `template` in `dir`, along with the `guix.scm` file of its
+/// development environment and a channel defining its packages in `dir/channel`.
+fn write_example(args: &Cli, template: &str, dir: &Path, force: bool) -> Result<(), CarguixError> {
+    for path in Example::new(template)?.write(dir, force)? {
+        println!("{}", path.display());
+    }
+    let features = Features::new(&args.features, !args.no_default_features, args.all_features);
+    let mut generator =
+        Generator::from_paths(&[dir.to_path_buf()], &features)?.with_options(options(args)?)?;
+    let packages = collect_packages(&mut generator);
+    let index_commit = generator.index_commit();
+    let guix_scm = dir.join("guix.scm");
+    if !force && guix_scm.exists() {
+        return Err(CarguixError::ExampleFileExists(guix_scm));
+    }
+    let environment = guix::Environment::new(packages.clone(), index_commit.clone(), false)?
+        .with_templates(Templates::open(None, args.package_template.as_deref())?);
+    fs::write(&guix_scm, environment.to_scheme())
+        .map_err(|err| CarguixError::OutputError(err, guix_scm.clone()))?;
+    println!("{}", guix_scm.display());
+    let templates = Templates::open(args.template.as_deref(), args.package_template.as_deref())?;
+    let channel = Channel::new("example", packages, index_commit, args.parameterize_rust)
+        .with_templates(templates);
+    for path in channel.write(&dir.join("channel"), force)? {
+        println!("{}", path.display());
+    }
+    generator.save_lock()
+}
+
+/// Write the build-only packages, procedural macros and their exclusive dependencies, to their own
+/// module, importing the main module for the packages they share with the runtime closure.
+/// Return the other packages and the imports of the main module.
+fn write_proc_macro_module(
+    path: &Path,
+    main_module: &[String],
+    packages: Vec<guix::Package>,
+    index_commit: &Option<String>,
+    parameterized: bool,
+    templates: &Templates,
+) -> Result<(Vec<guix::Package>, Vec<Vec<String>>), CarguixError> {
+    let (build_only, packages): (Vec<_>, Vec<_>) =
+        packages.into_iter().partition(|package| package.build_only);
+    let runtime = packages
+        .iter()
+        .map(|package| &package.crate_ref)
+        .collect::<HashSet<_>>();
+    let imports = if build_only.iter().any(|package| {
+        package
+            .cargo_inputs
+            .iter()
+            .chain(&package.cargo_development_inputs)
+            .any(|input| runtime.contains(input))
+    }) {
+        vec![main_module.to_vec()]
+    } else {
+        Vec::new()
+    };
+    let module_name = guix::Module::name_from_path(path);
+    let module = guix::Module::new(
+        module_name.clone(),
+        build_only,
+        index_commit.clone(),
+        parameterized,
+    )
+    .with_imports(imports)
+    .with_templates(templates.clone());
+    fs::write(path, module.to_scheme())
+        .map_err(|err| CarguixError::OutputError(err, path.to_path_buf()))?;
+    Ok((packages, vec![module_name]))
+}
+
+/// Write every package to its own module, named `<prefix> rust-foo-1.2.3`, importing the modules
+/// of its inputs. Existing files are left untouched unless `force` is set.
+fn write_package_modules(
+    output_dir: &Path,
+    prefix: &[String],
+    packages: &[guix::Package],
+    index_commit: &Option<String>,
+    parameterized: bool,
+    templates: &Templates,
+    force: bool,
+) -> Result<(), CarguixError> {
+    fs::create_dir_all(output_dir)
+        .map_err(|err| CarguixError::OutputDirError(err, output_dir.to_path_buf()))?;
+    let module_name = |variable: String| {
+        let mut name = prefix.to_vec();
+        name.push(variable);
+        name
+    };
+    let packaged = packages
+        .iter()
+        .map(|package| (&package.crate_ref, package.variable()))
+        .collect::<HashMap<_, _>>();
+    for package in packages {
+        let path = output_dir.join(format!("{}.scm", package.variable()));
+        if path.exists() && !force {
+            log::info!("skipping existing module file {:?}", path);
+            continue;
+        }
+        let mut imports = package
+            .cargo_inputs
+            .iter()
+            .chain(&package.cargo_development_inputs)
+            .filter_map(|input| packaged.get(input).cloned())
+            .map(module_name)
+            .collect::<Vec<_>>();
+        imports.sort();
+        imports.dedup();
+        let module = guix::Module::new(
+            module_name(package.variable()),
+            vec![package.clone()],
+            index_commit.clone(),
+            parameterized,
+        )
+        .with_imports(imports)
+        .with_templates(templates.clone());
+        fs::write(&path, module.to_scheme())
+            .map_err(|err| CarguixError::OutputError(err, path.clone()))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,192 @@
+use crate::{features::Features, source::SourceState};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+/// How a crate several projects require from different origins or with different features is
+/// packaged when they are merged into a single module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Features are unified like cargo does within a workspace, the origin being the one of the
+    /// first project requiring the crate.
+    Unify,
+    /// The crate is packaged the way the first project requiring it does.
+    First,
+}
+
+impl std::str::FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy {
+            "unify" => Ok(MergeStrategy::Unify),
+            "first" => Ok(MergeStrategy::First),
+            _ => Err(format!(
+                "unknown merge strategy {}, expected unify or first",
+                strategy
+            )),
+        }
+    }
+}
+
+/// Crate required by a project, with the features of every dependent of the project merged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateRequest {
+    pub crate_key: (String, Option<String>),
+    /// Position of the project in the merged ones.
+    pub project: usize,
+    pub source: SourceState,
+}
+
+/// Crate required differently by several projects.
+#[derive(Debug, Clone)]
+pub struct MergeConflict {
+    pub crate_key: (String, Option<String>),
+    /// How every project requiring the crate requires it, e.g. `"svc-a" requires it from
+    /// crates.io with features ["std"]`.
+    pub provenances: Vec<String>,
+}
+
+/// How each of the merged projects requires the crates of the closure.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectRequests {
+    requests: HashMap<(String, Option<String>), BTreeMap<usize, SourceState>>,
+}
+
+impl ProjectRequests {
+    pub fn from_requests(requests: Vec<CrateRequest>) -> Self {
+        let mut project_requests = Self::default();
+        for request in requests {
+            project_requests
+                .requests
+                .entry(request.crate_key)
+                .or_default()
+                .insert(request.project, request.source);
+        }
+        project_requests
+    }
+
+    pub fn to_requests(&self) -> Vec<CrateRequest> {
+        self.requests
+            .iter()
+            .flat_map(|(crate_key, sources)| {
+                sources.iter().map(move |(project, source)| CrateRequest {
+                    crate_key: crate_key.clone(),
+                    project: *project,
+                    source: source.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Record that `project` requires a crate from `source`, returning how the first project
+    /// requiring it does if that's another project.
+    pub fn record(
+        &mut self,
+        crate_key: &(String, Option<String>),
+        project: usize,
+        source: &SourceState,
+    ) -> Option<&SourceState> {
+        let sources = self.requests.entry(crate_key.clone()).or_default();
+        match sources.get_mut(&project) {
+            Some(requested) if same_origin(requested, source) => {
+                requested.merge_features(source.features())
+            }
+            Some(_) => (),
+            None => {
+                sources.insert(project, source.clone());
+            }
+        }
+        sources
+            .iter()
+            .next()
+            .filter(|(first_project, _)| **first_project != project)
+            .map(|(_, first_source)| first_source)
+    }
+
+    /// Crates which the projects named after `projects` don't all require from the same origin
+    /// with the same features.
+    pub fn conflicts(&self, projects: &[PathBuf]) -> Vec<MergeConflict> {
+        let mut conflicts = self
+            .requests
+            .iter()
+            .filter(|(_, sources)| {
+                let mut sources = sources.values();
+                let first = sources.next();
+                sources.any(|source| {
+                    first.is_some_and(|first| {
+                        !same_origin(first, source) || first.features() != source.features()
+                    })
+                })
+            })
+            .map(|(crate_key, sources)| MergeConflict {
+                crate_key: crate_key.clone(),
+                provenances: sources
+                    .iter()
+                    .map(|(project, source)| {
+                        format!(
+                            "{:?} requires it from {} with {}",
+                            projects[*project],
+                            origin(source),
+                            describe_features(source.features())
+                        )
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        conflicts.sort_by(|conflict, other| conflict.crate_key.cmp(&other.crate_key));
+        conflicts
+    }
+}
+
+/// Whether two sources denote the same crate tarball or directory, whatever their features.
+pub fn same_origin(source: &SourceState, other: &SourceState) -> bool {
+    match (source, other) {
+        (
+            SourceState::Registry { registry, .. },
+            SourceState::Registry {
+                registry: other_registry,
+                ..
+            },
+        ) => registry == other_registry,
+        (
+            SourceState::Path { path, .. },
+            SourceState::Path {
+                path: other_path, ..
+            },
+        ) => path == other_path,
+        _ => false,
+    }
+}
+
+fn describe_features(features: &Features) -> String {
+    if features.all_features {
+        return "all features".to_string();
+    }
+    let mut names = features
+        .features
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if features.default_features {
+        names.insert(0, "default");
+    }
+    match names.as_slice() {
+        [] => "no features".to_string(),
+        names => format!("features {}", names.join(", ")),
+    }
+}
+
+fn origin(source: &SourceState) -> String {
+    match source {
+        SourceState::Registry { registry: None, .. } => "crates.io".to_string(),
+        SourceState::Registry {
+            registry: Some(registry),
+            ..
+        } => format!("registry {}", registry),
+        SourceState::Path { path, .. } => format!("path {:?}", path),
+    }
+}
@@ -0,0 +1,182 @@
+use cargo_toml::Manifest;
+use serde_json::Value;
+
+/// Descriptive fields of a package.
+#[derive(Debug, Clone, Default)]
+pub struct CrateMetadata {
+    pub home_page: Option<String>,
+    pub synopsis: Option<String>,
+    pub description: Option<String>,
+    /// SPDX license expression.
+    pub license: Option<String>,
+}
+
+impl CrateMetadata {
+    pub fn new(description: Option<&str>, home_page: Option<&str>, license: Option<&str>) -> Self {
+        let description = description
+            .map(|description| description.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|description| !description.is_empty())
+            .map(|description| texinfo(&description));
+        Self {
+            home_page: home_page.map(str::to_string),
+            synopsis: description.as_deref().map(synopsis),
+            description: description.map(|description| {
+                if description.ends_with('.') {
+                    description
+                } else {
+                    format!("{}.", description)
+                }
+            }),
+            license: license.map(str::to_string),
+        }
+    }
+
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        let package = manifest.package();
+        Self::new(
+            package.description(),
+            package.homepage().or_else(|| package.repository()),
+            package.license(),
+        )
+    }
+
+    /// Whether every field is filled.
+    pub fn is_complete(&self) -> bool {
+        self.home_page.is_some()
+            && self.synopsis.is_some()
+            && self.description.is_some()
+            && self.license.is_some()
+    }
+
+    /// Fill the empty fields with the ones of `other`.
+    pub fn fill(&mut self, other: CrateMetadata) {
+        self.home_page = self.home_page.take().or(other.home_page);
+        self.synopsis = self.synopsis.take().or(other.synopsis);
+        self.description = self.description.take().or(other.description);
+        self.license = self.license.take().or(other.license);
+    }
+
+    /// Metadata of a crate without any information but its crates.io page.
+    pub fn from_crate_name(crate_name: &str) -> Self {
+        Self::new(None, Some(&crates_io_page(crate_name)), None)
+    }
+
+    /// Metadata from the response of the `https://crates.io/api/v1/crates/<name>` endpoint.
+    pub fn from_crates_io(crate_name: &str, version: &str, response: &Value) -> Self {
+        let crate_ = &response["crate"];
+        let license = response["versions"]
+            .as_array()
+            .and_then(|versions| versions.iter().find(|entry| entry["num"] == version))
+            .and_then(|entry| entry["license"].as_str());
+        let home_page = crate_["homepage"]
+            .as_str()
+            .or_else(|| crate_["repository"].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| crates_io_page(crate_name));
+        Self::new(crate_["description"].as_str(), Some(&home_page), license)
+    }
+}
+
+/// Page of a crate on crates.io.
+pub fn crates_io_page(crate_name: &str) -> String {
+    format!("https://crates.io/crates/{}", crate_name)
+}
+
+/// Description with the Markdown constructs crate descriptions commonly use turned into Texinfo
+/// markup, which Guix renders descriptions from: code spans become `@code`, links and URLs `@url`
+/// and strong emphasis `@strong`. Characters Texinfo gives a meaning to are escaped.
+fn texinfo(markdown: &str) -> String {
+    let mut texinfo = String::new();
+    let mut rest = markdown;
+    while let Some(character) = rest.chars().next() {
+        if let Some((code, after)) = code_span(rest) {
+            texinfo.push_str(&format!("@code{{{}}}", escape(code)));
+            rest = after;
+        } else if let Some((label, url, after)) = link(rest) {
+            texinfo.push_str(&format!(
+                "@url{{{}, {}}}",
+                escape(url),
+                escape(label).replace(',', "@comma{}")
+            ));
+            rest = after;
+        } else if let Some((url, after)) = autolink(rest) {
+            texinfo.push_str(&format!("@url{{{}}}", escape(url)));
+            rest = after;
+        } else if let Some((strong, after)) = delimited(rest, "**", "**") {
+            texinfo.push_str(&format!("@strong{{{}}}", escape(strong)));
+            rest = after;
+        } else {
+            texinfo.push_str(&escape(&character.to_string()));
+            rest = &rest[character.len_utf8()..];
+        }
+    }
+    texinfo
+}
+
+fn escape(text: &str) -> String {
+    text.replace('@', "@@")
+        .replace('{', "@{")
+        .replace('}', "@}")
+}
+
+/// Content between `open` and the next `close` at the start of `text`, and what follows.
+fn delimited<'a>(text: &'a str, open: &str, close: &str) -> Option<(&'a str, &'a str)> {
+    let text = text.strip_prefix(open)?;
+    let end = text.find(close)?;
+    Some((&text[..end], &text[end + close.len()..])).filter(|(content, _)| !content.is_empty())
+}
+
+/// Code span at the start of `text`, delimited by runs of backticks of the same length.
+fn code_span(text: &str) -> Option<(&str, &str)> {
+    let fence = &text[..text.len() - text.trim_start_matches('`').len()];
+    if fence.is_empty() {
+        return None;
+    }
+    delimited(text, fence, fence).map(|(code, after)| (code.trim(), after))
+}
+
+/// `[label](url)` link at the start of `text`.
+fn link(text: &str) -> Option<(&str, &str, &str)> {
+    let (label, after) = delimited(text, "[", "](")?;
+    let end = after.find(')')?;
+    let url = &after[..end];
+    Some((label, url, &after[end + 1..]))
+        .filter(|_| is_url(url) && !url.contains(char::is_whitespace))
+}
+
+/// `<url>` autolink or bare URL at the start of `text`, the punctuation ending a sentence being
+/// left out of the latter.
+fn autolink(text: &str) -> Option<(&str, &str)> {
+    if let Some((url, after)) = delimited(text, "<", ">") {
+        return Some((url, after)).filter(|_| is_url(url));
+    }
+    if !is_url(text) {
+        return None;
+    }
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let url = text[..end].trim_end_matches(['.', ',', ';', ':', ')', '!', '?']);
+    Some((url, &text[url.len()..]))
+}
+
+fn is_url(text: &str) -> bool {
+    text.starts_with("https://") || text.starts_with("http://")
+}
+
+/// First sentence of a description, without leading article nor final period as `guix lint`
+/// expects.
+fn synopsis(description: &str) -> String {
+    let sentence = description
+        .split(". ")
+        .next()
+        .unwrap_or(description)
+        .trim_end_matches('.');
+    let sentence = ["A ", "An ", "The "]
+        .iter()
+        .find_map(|article| sentence.strip_prefix(article))
+        .unwrap_or(sentence);
+    let mut chars = sentence.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,565 @@
+//! Refresh of a module generated by carguix, re-resolving its crates without regenerating it,
+//! or regenerating the subtree of a single crate.
+
+use crate::{
+    cargo_lock::CargoLock,
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    generator::Generator,
+    guix::{self, Package},
+    print_error, registries,
+    scheme_rewrite::{self, parse, Kind, Node, Rewriter},
+    template::Templates,
+    version,
+};
+use semver::Version;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Package of a module whose crate was moved to another version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdatedPackage {
+    pub crate_name: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Update the crates of crates.io packaged by the module file `module`, written by carguix, to
+/// the highest semver-compatible version of the index, or to the version locked in `cargo_lock`.
+///
+/// Only the `version`, `uri` and `sha256` fields of the updated packages are rewritten, along
+/// with the variables named after their version and every reference to them, the rest of the
+/// file being left as it is. Packages of local crates and alternative registries are skipped.
+pub fn update_module(
+    generator: &mut Generator,
+    module: &Path,
+    cargo_lock: Option<&Path>,
+) -> Result<Vec<UpdatedPackage>, CarguixError> {
+    let content = fs::read_to_string(module)
+        .map_err(|err| CarguixError::ModuleIoError(err, module.to_path_buf()))?;
+    let forms = parse(&content)
+        .map_err(|err| CarguixError::ModuleParsingError(err, module.to_path_buf()))?;
+    let locked = match cargo_lock {
+        Some(cargo_lock) => {
+            generator.read_locked_hashes(cargo_lock)?;
+            Some(CargoLock::open(cargo_lock)?)
+        }
+        None => None,
+    };
+    let mut packages = Vec::new();
+    let mut defined = HashSet::new();
+    for form in &forms {
+        form.visit(&mut |node| match node.head(&content) {
+            Some("package") => packages.extend(CratePackage::new(&content, node)),
+            Some("define-public") => {
+                defined.extend(node.item(1).map(|name| name.text(&content).to_string()))
+            }
+            _ => (),
+        });
+    }
+    let mut updates = Vec::new();
+    for package in packages {
+        let crate_ref = CrateRef::new(&package.crate_name, &package.version);
+        let updated = match &locked {
+            Some(locked) => locked_update(locked, &crate_ref),
+            None => generator.compatible_update(&crate_ref).map(Some),
+        };
+        let updated = match updated {
+            Ok(Some(updated)) if updated.version != crate_ref.version => updated,
+            Ok(_) => continue,
+            Err(err) => {
+                print_error(&err);
+                continue;
+            }
+        };
+        let variable = updated.format_name_version();
+        if !defined.insert(variable.clone()) {
+            log::warn!(
+                "{} is defined already, leaving {} as is",
+                variable,
+                crate_ref.format_name_version()
+            );
+            continue;
+        }
+        updates.push((package, crate_ref, updated));
+    }
+    let crates = updates
+        .iter()
+        .map(|(_, _, updated)| updated.clone())
+        .collect::<Vec<_>>();
+    let hashes = generator.crates_io_hashes(&crates);
+    let mut rewriter = Rewriter::new(&content);
+    let mut renamed = Vec::new();
+    let mut updated_packages = Vec::new();
+    for ((package, crate_ref, updated), hash) in updates.into_iter().zip(hashes) {
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(err) => {
+                print_error(&err);
+                continue;
+            }
+        };
+        rewriter.replace(package.version_span, quoted(&updated.version));
+        rewriter.replace(package.hash_span, quoted(&hash));
+        for uri in package.uri_spans {
+            let uri_text = &content[uri.clone()];
+            let replaced = replace_version(uri_text, &crate_ref.version, &updated.version);
+            if replaced != uri_text {
+                rewriter.replace(uri, replaced);
+            }
+        }
+        renamed.push((
+            crate_ref.format_name_version(),
+            updated.format_name_version(),
+        ));
+        updated_packages.push(UpdatedPackage {
+            crate_name: crate_ref.name,
+            from: crate_ref.version,
+            to: updated.version,
+        });
+    }
+    if updated_packages.is_empty() {
+        return Ok(updated_packages);
+    }
+    for form in &forms {
+        form.visit_leaves(&mut |node| {
+            let text = node.text(&content);
+            for (from, to) in &renamed {
+                let replacement = match node.kind {
+                    Kind::Atom if text == from => to.clone(),
+                    Kind::Atom if text.strip_prefix("make-") == Some(from) => {
+                        format!("make-{}", to)
+                    }
+                    Kind::String if text == quoted(from) => quoted(to),
+                    _ => continue,
+                };
+                rewriter.replace(node.span.clone(), replacement);
+            }
+        });
+    }
+    fs::write(module, rewriter.finish())
+        .map_err(|err| CarguixError::ModuleIoError(err, module.to_path_buf()))?;
+    Ok(updated_packages)
+}
+
+/// Definitions of a module written by carguix under a root crate, to be replaced with the ones of
+/// the root crate packaged again.
+#[derive(Debug)]
+pub struct Subtree {
+    module: PathBuf,
+    content: String,
+    definitions: Vec<Definition>,
+    root: CrateRef,
+    /// Variable of the package of the root crate.
+    root_variable: String,
+    /// Directory of the root crate when it's a local crate.
+    root_path: Option<PathBuf>,
+}
+
+/// Packages of a module changed by [`Subtree::regenerate`], by variable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegeneratedSubtree {
+    /// Packages of the subtree whose definitions changed.
+    pub updated: Vec<String>,
+    /// Packages the subtree didn't have, defined before the root package.
+    pub added: Vec<String>,
+    /// Packages of the subtree no longer needed by it nor by the other packages.
+    pub removed: Vec<String>,
+}
+
+impl Subtree {
+    /// Subtree of the module file `module` under the package of `root`, a crate name or
+    /// `name@version` when the module packages several versions of the crate.
+    pub fn open(module: &Path, root: &str) -> Result<Self, CarguixError> {
+        let content = fs::read_to_string(module)
+            .map_err(|err| CarguixError::ModuleIoError(err, module.to_path_buf()))?;
+        let forms = parse(&content)
+            .map_err(|err| CarguixError::ModuleParsingError(err, module.to_path_buf()))?;
+        let definitions = Definition::all(&content, forms);
+        let (root_name, root_version) = CrateRef::split_version(root);
+        let package_name = CrateRef::new(root_name, "").format_name();
+        let mut candidates = Vec::new();
+        for definition in &definitions {
+            definition.form.visit(&mut |node| {
+                if node.head(&content) != Some("package") {
+                    return;
+                }
+                let field = |name| node.field(&content, name)?.item(1)?.string(&content);
+                let version = match (field("name"), field("version")) {
+                    (Some(name), Some(version)) if name == package_name => version,
+                    _ => return,
+                };
+                if root_version.is_some_and(|root_version| root_version != version) {
+                    return;
+                }
+                let mut path = None;
+                if let Some(source) = node.field(&content, "source") {
+                    source.visit(&mut |node| {
+                        if node.head(&content) == Some("local-file") {
+                            path = node.item(1).and_then(|path| path.string(&content));
+                        }
+                    });
+                }
+                candidates.push((definition.variable.clone(), version, path));
+            });
+        }
+        candidates.sort();
+        candidates.dedup();
+        let (root_variable, version, path) = match candidates.len() {
+            0 => {
+                return Err(CarguixError::SubtreeRootNotFound(
+                    root.to_string(),
+                    module.to_path_buf(),
+                ))
+            }
+            1 => candidates.remove(0),
+            _ => {
+                return Err(CarguixError::AmbiguousSubtreeRoot(
+                    root.to_string(),
+                    module.to_path_buf(),
+                    candidates
+                        .iter()
+                        .map(|(_, version, _)| format!("{}@{}", root_name, version))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ))
+            }
+        };
+        Ok(Self {
+            module: module.to_path_buf(),
+            content,
+            definitions,
+            root: CrateRef::new(root_name, &version),
+            root_variable,
+            root_path: path.map(PathBuf::from),
+        })
+    }
+
+    /// Crate whose package and dependencies are regenerated.
+    pub fn root(&self) -> &CrateRef {
+        &self.root
+    }
+
+    /// Directory of the root crate when it's a local crate rather than a crate of the registry.
+    pub fn root_path(&self) -> Option<&Path> {
+        self.root_path.as_deref()
+    }
+
+    /// Whether the packages are defined as procedures parameterized over the rust compiler.
+    pub fn parameterized(&self) -> bool {
+        self.definitions
+            .iter()
+            .any(|definition| definition.name != definition.variable)
+    }
+
+    /// Replace the definitions of the subtree with the ones of `packages`, the root crate and its
+    /// dependencies packaged again, and write the module.
+    ///
+    /// The definitions of the other packages are left as they are, even when the root crate now
+    /// depends on them, and so are the packages of the subtree they refer to. The other packages
+    /// of the subtree are removed, and new ones are defined before the root package.
+    pub fn regenerate(
+        self,
+        packages: Vec<Package>,
+        templates: &Templates,
+    ) -> Result<RegeneratedSubtree, CarguixError> {
+        let parameterized = self.parameterized();
+        let variables = self
+            .definitions
+            .iter()
+            .map(|definition| definition.variable.as_str())
+            .collect::<HashSet<_>>();
+        let references = self
+            .definitions
+            .iter()
+            .map(|definition| {
+                definition
+                    .references
+                    .iter()
+                    .filter(|reference| variables.contains(reference.as_str()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let module = guix::Module::new(Vec::new(), packages, None, parameterized);
+        let regenerated_variables = module
+            .packages
+            .iter()
+            .map(guix::Package::variable)
+            .collect::<HashSet<_>>();
+        // Packages reached through the ones regenerated only, whose references are replaced
+        let reachable = |starts: Vec<&str>, regenerated: &HashSet<String>| {
+            let mut reached = HashSet::new();
+            let mut pending = starts;
+            while let Some(variable) = pending.pop() {
+                if !reached.insert(variable.to_string()) || regenerated.contains(variable) {
+                    continue;
+                }
+                for (definition, references) in self.definitions.iter().zip(&references) {
+                    if definition.variable == variable {
+                        pending.extend(references.iter().map(|reference| reference.as_str()));
+                    }
+                }
+            }
+            reached
+        };
+        let subtree = reachable(vec![self.root_variable.as_str()], &HashSet::new());
+        let kept = reachable(
+            variables
+                .iter()
+                .copied()
+                .filter(|variable| !subtree.contains(*variable))
+                .collect(),
+            &regenerated_variables
+                .intersection(&subtree)
+                .cloned()
+                .collect(),
+        );
+        let mut regenerated = HashMap::new();
+        let mut added_definitions = String::new();
+        let mut regenerated_subtree = RegeneratedSubtree::default();
+        for package in &module.packages {
+            let variable = package.variable();
+            let definitions = guix::package_definitions(package, parameterized, templates);
+            if subtree.contains(&variable) {
+                regenerated.insert(variable, definitions);
+            } else if !variables.contains(variable.as_str()) {
+                added_definitions.push_str(&definitions);
+                regenerated_subtree.added.push(variable);
+            }
+        }
+        let root_start = self
+            .definitions
+            .iter()
+            .find(|definition| definition.variable == self.root_variable)
+            .expect("the root package is defined")
+            .form
+            .span
+            .start;
+        let mut rewriter = Rewriter::new(&self.content);
+        rewriter.insert(root_start, added_definitions);
+        let mut replaced = HashSet::new();
+        for definition in &self.definitions {
+            let variable = &definition.variable;
+            if !subtree.contains(variable) {
+                continue;
+            }
+            match regenerated.get(variable) {
+                Some(definitions) if replaced.insert(variable) => {
+                    let old_forms = self
+                        .definitions
+                        .iter()
+                        .filter(|definition| definition.variable == *variable)
+                        .map(|definition| &definition.form)
+                        .collect::<Vec<_>>();
+                    let new_forms = parse(definitions).map_err(|err| {
+                        CarguixError::PackageTemplateRenderingError(
+                            templates.package_template_name(),
+                            variable.clone(),
+                            err,
+                        )
+                    })?;
+                    let tokens = |forms: Vec<&Node>, content| {
+                        forms
+                            .iter()
+                            .map(|form| form.tokens(content))
+                            .collect::<Vec<_>>()
+                    };
+                    if tokens(old_forms.clone(), &self.content)
+                        != tokens(new_forms.iter().collect(), definitions)
+                    {
+                        regenerated_subtree.updated.push(variable.clone());
+                    }
+                    if old_forms.len() == new_forms.len() {
+                        for (old_form, new_form) in old_forms.into_iter().zip(&new_forms) {
+                            scheme_rewrite::splice(&mut rewriter, old_form, new_form, definitions);
+                        }
+                    } else {
+                        rewriter.insert(definition.form.span.start, definitions.clone());
+                        for old_form in old_forms {
+                            rewriter.remove(old_form.span.clone());
+                        }
+                    }
+                }
+                Some(_) => (),
+                None if kept.contains(variable) => (),
+                None => {
+                    if !regenerated_subtree.removed.contains(variable) {
+                        regenerated_subtree.removed.push(variable.clone());
+                    }
+                    rewriter.remove(definition.form.span.clone());
+                }
+            }
+        }
+        fs::write(&self.module, rewriter.finish())
+            .map_err(|err| CarguixError::ModuleIoError(err, self.module.clone()))?;
+        Ok(regenerated_subtree)
+    }
+}
+
+/// Top-level `define` or `define-public` form of a module.
+#[derive(Debug)]
+struct Definition {
+    /// Name defined by the form.
+    name: String,
+    /// Variable of the package the form defines, `rust-foo-1.0.0` for both the package and its
+    /// `make-rust-foo-1.0.0` procedure.
+    variable: String,
+    form: Node,
+    /// Atoms of the form, among which the variables of the packages it refers to.
+    references: HashSet<String>,
+}
+
+impl Definition {
+    /// Definitions among the top-level forms, in the same order, other forms being left out.
+    fn all(content: &str, forms: Vec<Node>) -> Vec<Self> {
+        let mut definitions = Vec::new();
+        for form in forms {
+            let name = match form.head(content) {
+                Some("define") | Some("define-public") => form.item(1).and_then(|name| {
+                    match name.kind {
+                        Kind::Atom => Some(name.text(content)),
+                        // Procedure definitions such as `(define (f x) ...)`
+                        _ => name.head(content),
+                    }
+                }),
+                _ => None,
+            };
+            let name = match name {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let mut references = HashSet::new();
+            for item in form.items.iter().skip(2) {
+                item.visit_leaves(&mut |node| {
+                    if node.kind == Kind::Atom {
+                        let text = node.text(content);
+                        references.insert(text.strip_prefix("make-").unwrap_or(text).to_string());
+                    }
+                });
+            }
+            definitions.push(Self {
+                variable: name.clone(),
+                name,
+                form,
+                references,
+            });
+        }
+        let public = definitions
+            .iter()
+            .map(|definition| definition.name.clone())
+            .collect::<HashSet<_>>();
+        for definition in &mut definitions {
+            if let Some(variable) = definition.name.strip_prefix("make-") {
+                if public.contains(variable) {
+                    definition.variable = variable.to_string();
+                }
+            }
+        }
+        definitions
+    }
+}
+
+/// Version of a crate of crates.io locked in `Cargo.lock`, the highest one semver-compatible with
+/// the current version if several are, `None` if the crate is locked in none of them.
+fn locked_update(
+    cargo_lock: &CargoLock,
+    crate_ref: &CrateRef,
+) -> Result<Option<CrateRef>, CarguixError> {
+    let parse = |version_str: &str| {
+        Version::parse(version_str).map_err(|err| {
+            CarguixError::VersionParsingError(err, crate_ref.name.clone(), version_str.to_string())
+        })
+    };
+    let range = version::compatibility_range(&parse(&crate_ref.version)?);
+    let mut locked_versions = Vec::new();
+    for package in &cargo_lock.package {
+        let from_crates_io = package.source.as_deref().is_some_and(|source| {
+            registries::is_crates_io_index(source.strip_prefix("registry+").unwrap_or(source))
+        });
+        if package.name != crate_ref.name || !from_crates_io {
+            continue;
+        }
+        let locked_version = parse(&package.version)?;
+        if version::compatibility_range(&locked_version) == range {
+            locked_versions.push(locked_version);
+        }
+    }
+    Ok(locked_versions
+        .into_iter()
+        .max()
+        .map(|locked_version| CrateRef::new(&crate_ref.name, &locked_version.to_string())))
+}
+
+/// Replace the occurrences of `from` standing for a whole version in a URI string, e.g. in
+/// `foo-1.0.1.crate` but not in `foo-1.0.10.crate`.
+fn replace_version(text: &str, from: &str, to: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(from) {
+        let (before, after) = (&rest[..start], &rest[start + from.len()..]);
+        let joined_before = before.ends_with(|c: char| c.is_ascii_digit() || c == '.');
+        let mut next = after.chars();
+        let joined_after = match (next.next(), next.next()) {
+            (Some(c), _) if c.is_ascii_digit() => true,
+            (Some('.'), Some(c)) => c.is_ascii_digit(),
+            _ => false,
+        };
+        replaced.push_str(before);
+        replaced.push_str(if joined_before || joined_after {
+            from
+        } else {
+            to
+        });
+        rest = after;
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+fn quoted(text: &str) -> String {
+    format!("{:?}", text)
+}
+
+/// Package of a crate of crates.io, whose origin refers to `crate-uri`, with the spans of the
+/// fields an update rewrites.
+#[derive(Debug)]
+struct CratePackage {
+    crate_name: String,
+    version: String,
+    version_span: Range<usize>,
+    /// Strings of the `uri` field, the crates.io one being built by `crate-uri`.
+    uri_spans: Vec<Range<usize>>,
+    hash_span: Range<usize>,
+}
+
+impl CratePackage {
+    fn new(content: &str, package: &Node) -> Option<Self> {
+        let version = package.field(content, "version")?.item(1)?;
+        let origin = package.field(content, "source")?.item(1)?;
+        let uri = origin.field(content, "uri")?.item(1)?;
+        let hash = origin.field(content, "sha256")?.item(1)?.item(1)?;
+        let mut crate_name = None;
+        let mut uri_spans = Vec::new();
+        uri.visit(&mut |node| {
+            if node.head(content) == Some("crate-uri") {
+                crate_name = node.item(1).and_then(|name| name.string(content));
+            }
+        });
+        uri.visit_leaves(&mut |node| {
+            if node.kind == Kind::String {
+                uri_spans.push(node.span.clone());
+            }
+        });
+        Some(Self {
+            crate_name: crate_name?,
+            version: version.string(content)?,
+            version_span: version.span.clone(),
+            uri_spans,
+            hash_span: hash.span.clone(),
+        })
+    }
+}
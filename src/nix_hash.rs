@@ -0,0 +1,265 @@
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+/// Alphabet of the base32 variant used by Nix and Guix, which omits `e`, `o`, `t` and `u`.
+const NIX_BASE32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Hash of a file or directory as printed by `guix hash`, or `guix hash -r` for directories.
+pub fn hash(path: &Path) -> io::Result<String> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        nar_hash(path)
+    } else {
+        file_hash(path)
+    }
+}
+
+/// Nix-base32 SHA256 of the content of a file.
+pub fn file_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(nix_base32(&hasher.finalize()))
+}
+
+/// Writer hashing everything written through it to `inner`, so that a download is hashed as it
+/// is received.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Nix-base32 SHA256 of what was written, the same as [`file_hash`] of a file of that content.
+    pub fn finish(self) -> String {
+        nix_base32(&self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Nix-base32 SHA256 of the NAR serialization of a file tree.
+pub fn nar_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    write_nar_string(&mut hasher, b"nix-archive-1")?;
+    write_nar(&mut hasher, path)?;
+    Ok(nix_base32(&hasher.finalize()))
+}
+
+/// Nix-base32 form of a SHA256 written in hexadecimal, such as the checksums of `Cargo.lock` and
+/// index entries, which are the ones of crate tarballs.
+pub fn from_hex(hex: &str) -> Option<String> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    Some(nix_base32(&bytes))
+}
+
+/// Whether `hash` is a SHA256 in Nix-base32, as `(sha256 (base32 ...))` fields expect: 52
+/// characters of the alphabet, the first one only holding the most significant bit.
+pub fn is_sha256(hash: &str) -> bool {
+    hash.len() == 52
+        && hash.bytes().all(|byte| NIX_BASE32_ALPHABET.contains(&byte))
+        && hash.starts_with(['0', '1'])
+}
+
+/// Encode bytes the way Nix does: least significant bits first, starting from the last character.
+pub fn nix_base32(bytes: &[u8]) -> String {
+    let length = (bytes.len() * 8).div_ceil(5);
+    (0..length)
+        .rev()
+        .map(|n| {
+            let bit = n * 5;
+            let (i, j) = (bit / 8, bit % 8);
+            let low = bytes[i] >> j;
+            let high = bytes
+                .get(i + 1)
+                .map_or(0, |byte| byte.checked_shl(8 - j as u32).unwrap_or(0));
+            NIX_BASE32_ALPHABET[usize::from((low | high) & 0x1f)] as char
+        })
+        .collect()
+}
+
+fn write_nar(writer: &mut impl Write, path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    write_nar_string(writer, b"(")?;
+    write_nar_string(writer, b"type")?;
+    if metadata.file_type().is_symlink() {
+        write_nar_string(writer, b"symlink")?;
+        write_nar_string(writer, b"target")?;
+        let target = fs::read_link(path)?;
+        write_nar_string(writer, target.to_string_lossy().as_bytes())?;
+    } else if metadata.is_dir() {
+        write_nar_string(writer, b"directory")?;
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            write_nar_string(writer, b"entry")?;
+            write_nar_string(writer, b"(")?;
+            write_nar_string(writer, b"name")?;
+            write_nar_string(writer, entry.to_string_lossy().as_bytes())?;
+            write_nar_string(writer, b"node")?;
+            write_nar(writer, &path.join(entry))?;
+            write_nar_string(writer, b")")?;
+        }
+    } else {
+        write_nar_string(writer, b"regular")?;
+        if metadata.permissions().mode() & 0o100 != 0 {
+            write_nar_string(writer, b"executable")?;
+            write_nar_string(writer, b"")?;
+        }
+        write_nar_string(writer, b"contents")?;
+        writer.write_all(&metadata.len().to_le_bytes())?;
+        io::copy(&mut File::open(path)?, writer)?;
+        write_nar_padding(writer, metadata.len())?;
+    }
+    write_nar_string(writer, b")")
+}
+
+/// NAR strings are prefixed by their length and padded with zeros to a multiple of 8 bytes.
+fn write_nar_string(writer: &mut impl Write, string: &[u8]) -> io::Result<()> {
+    writer.write_all(&(string.len() as u64).to_le_bytes())?;
+    writer.write_all(string)?;
+    write_nar_padding(writer, string.len() as u64)
+}
+
+fn write_nar_padding(writer: &mut impl Write, length: u64) -> io::Result<()> {
+    let padding = (8 - length % 8) % 8;
+    writer.write_all(&[0; 8][..padding as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempdir::TempDir;
+
+    /// Bytes of a Nix-base32 string, reading it the way [`nix_base32`] writes it.
+    fn decode_nix_base32(hash: &str, length: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; length];
+        for (n, character) in hash.bytes().rev().enumerate() {
+            let digit = NIX_BASE32_ALPHABET
+                .iter()
+                .position(|&letter| letter == character)
+                .expect("a character of the alphabet") as u16;
+            let (i, j) = (n * 5 / 8, n * 5 % 8);
+            bytes[i] |= (digit << j) as u8;
+            if let Some(byte) = bytes.get_mut(i + 1) {
+                *byte |= (digit >> (8 - j)) as u8;
+            }
+        }
+        bytes
+    }
+
+    fn write_file(path: &Path, content: &[u8], mode: u32) {
+        fs::write(path, content).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn hashes_the_empty_input_like_guix() {
+        let dir = TempDir::new("carguix-nix-hash").unwrap();
+        let path = dir.path().join("empty");
+        write_file(&path, b"", 0o644);
+        let expected = "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73";
+        assert_eq!(file_hash(&path).unwrap(), expected);
+        assert_eq!(HashingWriter::new(io::sink()).finish(), expected);
+        assert_eq!(
+            from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855").as_deref(),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn hashes_a_small_file_like_guix() {
+        let dir = TempDir::new("carguix-nix-hash").unwrap();
+        let path = dir.path().join("abc");
+        write_file(&path, b"abc", 0o644);
+        let expected = "1b8m03r63zqhnjf7l5wnldhh7c134ap5vpj0850ymkq1iyzicy5s";
+        assert_eq!(hash(&path).unwrap(), expected);
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(writer.finish(), expected);
+    }
+
+    #[test]
+    fn hashes_a_directory_like_guix_hash_recursive() {
+        let dir = TempDir::new("carguix-nix-hash").unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        write_file(&tree.join("hello"), b"hello\n", 0o644);
+        write_file(&tree.join("sub/run.sh"), b"#!/bin/sh\n", 0o755);
+        symlink("hello", tree.join("link")).unwrap();
+        assert_eq!(
+            hash(&tree).unwrap(),
+            "0wxvvb823splz6rsbjwiqvfxpglxc44dr3fdq7xjkiz9nrxgfas7"
+        );
+        // a regular file serialized on its own, as `guix hash -r` does for files
+        assert_eq!(
+            nar_hash(&tree.join("hello")).unwrap(),
+            "04zwf782yjwnh3q6hz5izfd6jyip8kgw6g6yj43fiqhbyhdd0dqw"
+        );
+        // the executable bit and the symlink are part of the hash
+        fs::set_permissions(tree.join("sub/run.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+        assert_ne!(
+            hash(&tree).unwrap(),
+            "0wxvvb823splz6rsbjwiqvfxpglxc44dr3fdq7xjkiz9nrxgfas7"
+        );
+    }
+
+    #[test]
+    fn nix_base32_round_trips() {
+        let digests = vec![
+            Sha256::digest(b"").to_vec(),
+            Sha256::digest(b"abc").to_vec(),
+            vec![0; 32],
+            vec![0xff; 32],
+            (0..32).collect(),
+        ];
+        for digest in digests {
+            let hash = nix_base32(&digest);
+            assert!(is_sha256(&hash), "{}", hash);
+            assert_eq!(decode_nix_base32(&hash, digest.len()), digest);
+        }
+        assert_eq!(nix_base32(&[]), "");
+        assert_eq!(decode_nix_base32(&nix_base32(&[0xa5]), 1), vec![0xa5]);
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_sha256() {
+        assert!(!is_sha256(""));
+        assert!(!is_sha256(
+            "2mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        ));
+        assert!(!is_sha256(
+            "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c7e"
+        ));
+        assert!(from_hex("not hexadecimal").is_none());
+    }
+}
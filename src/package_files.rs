@@ -0,0 +1,131 @@
+use crate::{errors::CarguixError, guix::FileSelection};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use std::{fs, path::Path};
+
+/// Files of the crate in `dir` which `cargo package` would put in its tarball, according to the
+/// `include` and `exclude` patterns of its manifest.
+///
+/// As with cargo, `include` takes precedence over `exclude`, `Cargo.toml` is always kept and the
+/// `target` directory, hidden directories and nested packages are left out.
+pub fn select(
+    dir: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<FileSelection, CarguixError> {
+    let mut walk = Walk {
+        dir,
+        include: (!include.is_empty())
+            .then(|| patterns(dir, include))
+            .transpose()?,
+        exclude: patterns(dir, exclude)?,
+        selected: Vec::new(),
+    };
+    walk.walk(dir)?;
+    let mut selected = walk.selected;
+    selected.sort();
+    Ok(match walk.include {
+        Some(_) => FileSelection::Only(selected),
+        None if selected.is_empty() => FileSelection::All,
+        None => FileSelection::Excluding(selected),
+    })
+}
+
+fn patterns(dir: &Path, patterns: &[String]) -> Result<Gitignore, CarguixError> {
+    let pattern_error = |err, pattern: &str| {
+        CarguixError::PackageFilePatternError(err, pattern.to_string(), dir.join("Cargo.toml"))
+    };
+    let mut builder = GitignoreBuilder::new(dir);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|err| pattern_error(err, pattern))?;
+    }
+    builder
+        .build()
+        .map_err(|err| pattern_error(err, &patterns.join(", ")))
+}
+
+/// Traversal of a crate directory collecting the files selected with `include`, or else the
+/// topmost paths left out.
+struct Walk<'a> {
+    dir: &'a Path,
+    include: Option<Gitignore>,
+    exclude: Gitignore,
+    selected: Vec<String>,
+}
+
+impl Walk<'_> {
+    /// Walk the directory `path`, returning whether any file below it is packaged.
+    fn walk(&mut self, path: &Path) -> Result<bool, CarguixError> {
+        let list_error = |err| CarguixError::PackageFilesError(err, path.to_path_buf());
+        let mut entries = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(list_error)?;
+        entries.sort();
+        let mut any_packaged = false;
+        for entry in entries {
+            let relative = entry
+                .strip_prefix(self.dir)
+                .expect("entries are below the crate");
+            let walked = entry.is_dir() && !self.skipped_dir(relative, &entry);
+            let packaged = if walked {
+                self.walk(&entry)?
+            } else {
+                !entry.is_dir() && self.is_packaged(relative)
+            };
+            any_packaged |= packaged;
+            // Directories walked through have their left out content listed already
+            let listed = match self.include {
+                Some(_) => packaged,
+                None => !packaged && !walked,
+            };
+            if listed {
+                self.selected.push(relative_path(relative));
+            }
+        }
+        Ok(any_packaged)
+    }
+
+    /// Whether a directory is left out whatever its content, its files being excluded otherwise.
+    fn skipped_dir(&self, relative: &Path, path: &Path) -> bool {
+        let hidden = relative
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        hidden
+            || relative == Path::new("target")
+            || path.join("Cargo.toml").exists()
+            || (self.include.is_none() && self.exclude.matched(relative, true).is_ignore())
+    }
+
+    fn is_packaged(&self, relative: &Path) -> bool {
+        if relative == Path::new("Cargo.toml") {
+            return true;
+        }
+        match &self.include {
+            Some(include) => matches!(
+                include.matched_path_or_any_parents(relative, false),
+                Match::Ignore(_)
+            ),
+            None => !self
+                .exclude
+                .matched_path_or_any_parents(relative, false)
+                .is_ignore(),
+        }
+    }
+}
+
+/// Path relative to the crate directory, with `/` separators as in Guix.
+fn relative_path(relative: &Path) -> String {
+    relative
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
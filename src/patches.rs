@@ -0,0 +1,234 @@
+//! Overrides of crates.io crates by the `[patch.crates-io]` and `[replace]` sections of the root
+//! manifests, which cargo builds instead of the published crates.
+//!
+//! Overrides pointing at a directory are packaged as local crates. Overrides pointing at a git
+//! repository are checked out below `git` in the data directory and fetched from their commit by
+//! the packages, as are the crates of the `git` subcommand.
+
+use crate::{
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    features::Features,
+    path_source::{self, GitCheckout, PathSource},
+    tooling::SharedTooling,
+};
+use cargo_toml::Dependency;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Directory the git overrides are checked out in, next to the clone of the index.
+const CHECKOUTS_DIR: &str = "git";
+
+/// Copy of a crate overriding the published one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Override {
+    Path(PathBuf),
+    Git {
+        url: String,
+        /// Revision of the repository, as understood by `git rev-parse`.
+        revision: String,
+    },
+}
+
+impl Override {
+    /// Override declared like a dependency, `None` for declarations naming no directory nor
+    /// repository, such as the ones of another registry.
+    fn from_dependency(project: &Path, dependency: &Dependency) -> Option<Self> {
+        let detail = dependency.detail()?;
+        if let Some(path) = &detail.path {
+            return Some(Override::Path(project.join(path)));
+        }
+        let url = detail.git.clone()?;
+        let revision = git_revision(
+            detail.rev.as_deref(),
+            detail.tag.as_deref(),
+            detail.branch.as_deref(),
+        );
+        Some(Override::Git { url, revision })
+    }
+}
+
+/// Revision of a clone pointed at by the `rev`, `tag` or `branch` of a git dependency, as
+/// understood by `git rev-parse`, the default branch if none is given.
+pub fn git_revision(rev: Option<&str>, tag: Option<&str>, branch: Option<&str>) -> String {
+    match (rev, tag, branch) {
+        (Some(rev), _, _) => rev.to_string(),
+        (None, Some(tag), _) => format!("refs/tags/{}", tag),
+        (None, None, Some(branch)) => format!("origin/{}", branch),
+        (None, None, None) => "HEAD".to_string(),
+    }
+}
+
+/// Overrides declared by the projects being packaged.
+#[derive(Debug, Default)]
+pub struct Patches {
+    /// `[patch.crates-io]` entries, by crate name.
+    patched: HashMap<String, Override>,
+    /// `[replace]` entries, by crate.
+    replaced: HashMap<CrateRef, Override>,
+    /// Checkouts of the git overrides so far, by repository and revision.
+    checkouts: HashMap<(String, String), (PathBuf, GitCheckout)>,
+    /// Directory containing the checkouts directory, the current directory if `None`.
+    data_dir: Option<PathBuf>,
+    /// Clones and checks the repositories out.
+    tooling: SharedTooling,
+}
+
+impl Patches {
+    /// Add the overrides of the root manifest of the project at `project`, the ones read first
+    /// taking precedence.
+    pub fn read(&mut self, project: &Path) -> Result<(), CarguixError> {
+        let manifest = path_source::read_manifest(project, None)?;
+        let patches = manifest.patch.get("crates-io").into_iter().flatten();
+        for (name, dependency) in patches {
+            let crate_name = dependency.package().unwrap_or(name);
+            match Override::from_dependency(project, dependency) {
+                Some(override_) => {
+                    self.patched
+                        .entry(crate_name.to_string())
+                        .or_insert(override_);
+                }
+                None => log::warn!(
+                    "ignoring patch of crate {} in {:?}, which is neither a path nor a git dependency",
+                    crate_name,
+                    project
+                ),
+            }
+        }
+        // cargo still honors `[replace]`, which it deprecates in favor of `[patch]`
+        #[allow(deprecated)]
+        let replacements = &manifest.replace;
+        for (spec, dependency) in replacements {
+            let crate_ref = match spec.split_once(':').or_else(|| spec.split_once('@')) {
+                Some((name, version)) => CrateRef::new(name, version),
+                None => {
+                    log::warn!(
+                        "ignoring replacement {} in {:?}, which doesn't name a version",
+                        spec,
+                        project
+                    );
+                    continue;
+                }
+            };
+            match Override::from_dependency(project, dependency) {
+                Some(override_) => {
+                    self.replaced.entry(crate_ref).or_insert(override_);
+                }
+                None => log::warn!(
+                    "ignoring replacement {} in {:?}, which is neither a path nor a git dependency",
+                    spec,
+                    project
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the repositories out below `git` in `data_dir` rather than in the current
+    /// directory.
+    pub fn set_checkouts_dir(&mut self, data_dir: &Path) {
+        self.data_dir = Some(data_dir.to_path_buf());
+    }
+
+    /// Clone and check the repositories out with `tooling`.
+    pub fn set_tooling(&mut self, tooling: SharedTooling) {
+        self.tooling = tooling;
+    }
+
+    /// Override of a crate by `[patch.crates-io]`, whatever its version.
+    pub fn patch(&self, crate_name: &str) -> Option<&Override> {
+        self.patched.get(crate_name)
+    }
+
+    /// Override of a crate version by `[replace]`.
+    pub fn replacement(&self, crate_ref: &CrateRef) -> Option<&Override> {
+        self.replaced.get(crate_ref)
+    }
+
+    /// Source of the copy of `crate_name` an override points at, the git repositories being
+    /// cloned into `tmpdir` unless `offline` forbids it.
+    pub fn source(
+        &mut self,
+        override_: &Override,
+        crate_name: &str,
+        features: &Features,
+        tmpdir: &Path,
+        offline: bool,
+    ) -> Result<PathSource, CarguixError> {
+        let (url, revision) = match override_ {
+            Override::Path(path) => return PathSource::new(path, None, features),
+            Override::Git { url, revision } => (url, revision),
+        };
+        if offline {
+            return Err(CarguixError::OfflineNetworkAccess(format!(
+                "cloning {} to patch crate {}",
+                url, crate_name
+            )));
+        }
+        let (checkout_dir, git) = self.checkout(url, revision, tmpdir)?;
+        PathSource::from_git_checkout(&checkout_dir, &git, None, features)?
+            .1
+            .into_iter()
+            .find(|source| source.manifest.package().name == crate_name)
+            .ok_or_else(|| CarguixError::NoPathMember(crate_name.to_string(), checkout_dir))
+    }
+
+    /// Directory `revision` of the repository at `url` is checked out in, along with its commit.
+    pub fn checkout(
+        &mut self,
+        url: &str,
+        revision: &str,
+        tmpdir: &Path,
+    ) -> Result<(PathBuf, GitCheckout), CarguixError> {
+        let key = (url.to_string(), revision.to_string());
+        if let Some(checkout) = self.checkouts.get(&key) {
+            return Ok(checkout.clone());
+        }
+        let git_error = |err| CarguixError::PatchGitError(err, url.to_string());
+        let clone_dir = tmpdir.join(format!("git-{}", self.checkouts.len()));
+        log::info!("cloning {}", url);
+        let commit_id = self
+            .tooling
+            .git_clone(url, revision, &clone_dir)
+            .map_err(git_error)?;
+        let repository_name = url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let checkout_name = format!("{}-{}", repository_name, &commit_id[..12]);
+        let checkouts_dir = match &self.data_dir {
+            Some(data_dir) => data_dir.join(CHECKOUTS_DIR),
+            None => PathBuf::from(CHECKOUTS_DIR),
+        };
+        let checkout_error =
+            |err| CarguixError::PatchCheckoutError(err, checkouts_dir.join(&checkout_name));
+        fs::create_dir_all(&checkouts_dir).map_err(checkout_error)?;
+        // libgit2 resolves relative checkout directories against the repository
+        let checkouts_dir = fs::canonicalize(&checkouts_dir).map_err(checkout_error)?;
+        let checkout_dir = checkouts_dir.join(&checkout_name);
+        if !checkout_dir.is_dir() {
+            // checked out aside first, so that an interrupted checkout isn't taken for a complete one
+            let partial_dir = checkouts_dir.join(format!("{}.partial", checkout_name));
+            if partial_dir.exists() {
+                fs::remove_dir_all(&partial_dir).map_err(checkout_error)?;
+            }
+            self.tooling
+                .git_checkout(&clone_dir, &commit_id, &partial_dir)
+                .map_err(git_error)?;
+            fs::rename(&partial_dir, &checkout_dir).map_err(checkout_error)?;
+        }
+        let git = GitCheckout {
+            url: url.to_string(),
+            commit: commit_id,
+            subdir: None,
+        };
+        self.checkouts
+            .insert(key, (checkout_dir.clone(), git.clone()));
+        Ok((checkout_dir, git))
+    }
+}
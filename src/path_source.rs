@@ -0,0 +1,694 @@
+use crate::{
+    cargo_metadata::CargoManifests,
+    crate_ref::CrateRef,
+    enrichers::MetadataTarget,
+    errors::CarguixError,
+    features::Features,
+    graph::Edge,
+    guix::{self, Origin},
+    nix_hash, package_files,
+    registry_source::RegistrySource,
+    source::{DependencyKind, Source, SourceState},
+    Generator,
+};
+use cargo_toml::{Dependency, Inheritable, Manifest, Publish};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Version of the packages whose manifest doesn't set one, the default of cargo, so
+/// crates only referenced by path still get a version to name and render their package.
+pub const PLACEHOLDER_VERSION: &str = "0.0.0";
+
+/// Workspace root along with the location of each of its members.
+#[derive(Debug)]
+pub struct Workspace {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+    pub members: BTreeMap<String, PathBuf>,
+}
+
+impl Workspace {
+    /// Workspace whose root manifest lives in `root`.
+    pub fn open(root: &Path) -> Result<Self, CarguixError> {
+        Self::new(root, read_manifest(root, None)?)
+    }
+
+    pub fn new(root: &Path, manifest: Manifest) -> Result<Self, CarguixError> {
+        let mut members = BTreeMap::new();
+        if let Some(package) = &manifest.package {
+            members.insert(package.name.clone(), root.to_path_buf());
+        }
+        let workspace = manifest
+            .workspace
+            .as_ref()
+            .ok_or_else(|| CarguixError::NoPackageInManifest(root.join("Cargo.toml")))?;
+        let excluded = workspace
+            .exclude
+            .iter()
+            .map(|exclude| root.join(exclude))
+            .collect::<Vec<_>>();
+        for pattern in &workspace.members {
+            let member_paths = glob::glob(&root.join(pattern).to_string_lossy())
+                .map_err(|err| {
+                    CarguixError::WorkspaceMemberPatternError(
+                        err,
+                        pattern.clone(),
+                        root.join("Cargo.toml"),
+                    )
+                })?
+                .filter_map(Result::ok)
+                .filter(|path| path.join("Cargo.toml").is_file())
+                .filter(|path| !excluded.iter().any(|exclude| path.starts_with(exclude)));
+            for member_path in member_paths {
+                let member_path = canonicalize(&member_path)?;
+                let member_manifest = read_manifest(&member_path, Some((&manifest, root)))?;
+                let member_package = member_manifest.package.ok_or_else(|| {
+                    CarguixError::NoPackageInManifest(member_path.join("Cargo.toml"))
+                })?;
+                members.insert(member_package.name, member_path);
+            }
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+            manifest,
+            members,
+        })
+    }
+}
+
+/// Commit of a git repository a local directory was checked out from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitCheckout {
+    pub url: String,
+    pub commit: String,
+    /// Directory of the repository the local directory is, if not its root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<PathBuf>,
+}
+
+/// Crate living in a local directory, possibly as a member of a workspace.
+#[derive(Debug, Clone)]
+pub struct PathSource {
+    pub path: PathBuf,
+    pub manifest: Arc<Manifest>,
+    pub workspace: Option<Arc<Workspace>>,
+    pub features: Features,
+    /// Commit the directory is a checkout of, the package fetching it rather than the directory.
+    pub git: Option<GitCheckout>,
+}
+
+/// Manifests of the local crates parsed so far, by directory and workspace root, shared by the
+/// sources of the crates depending on them instead of being parsed again for each dependent.
+#[derive(Debug, Default)]
+pub struct ManifestCache {
+    manifests: HashMap<(PathBuf, Option<PathBuf>), Arc<Manifest>>,
+    workspaces: HashMap<PathBuf, Arc<Workspace>>,
+    /// Workspace each crate read without one is a member of, found among its ancestors.
+    enclosing_workspaces: HashMap<PathBuf, Option<Arc<Workspace>>>,
+    /// Manifests as `cargo metadata` reports them, when reading them with cargo, see
+    /// [`crate::generator::Options::cargo_metadata`].
+    cargo: Option<CargoManifests>,
+}
+
+impl ManifestCache {
+    /// Cache reading the manifests with `cargo metadata`, falling back to carguix's own parser
+    /// when cargo can't be found or fails on a crate carguix can read.
+    pub fn cargo_metadata() -> Self {
+        Self {
+            cargo: Some(CargoManifests::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Workspace whose root manifest lives in `path`, read once, `None` if `path` is the
+    /// directory of a crate which isn't a workspace root.
+    pub fn workspace(&mut self, path: &Path) -> Result<Option<Arc<Workspace>>, CarguixError> {
+        if let Some(workspace) = self.workspaces.get(path) {
+            return Ok(Some(workspace.clone()));
+        }
+        if let Some(cargo) = self.cargo.as_mut() {
+            match cargo.workspace(path) {
+                Ok(workspace) if !cargo.missing => {
+                    if let Some(workspace) = &workspace {
+                        self.workspaces
+                            .insert(path.to_path_buf(), workspace.clone());
+                    }
+                    return Ok(workspace);
+                }
+                Ok(_) => self.check_cargo(),
+                Err(err) => log::warn!("{}, reading the workspace without cargo", err),
+            }
+        }
+        if !is_workspace_root(path) {
+            return Ok(None);
+        }
+        let workspace = Arc::new(Workspace::new(path, read_manifest(path, None)?)?);
+        self.workspaces
+            .insert(path.to_path_buf(), workspace.clone());
+        Ok(Some(workspace))
+    }
+
+    /// Workspace the crate at `path` is a member of without being its root, the first ancestor
+    /// declaring a `[workspace]` like cargo looks for it, so that the fields and dependencies
+    /// the crate inherits from it can be read when it is given on its own.
+    pub fn enclosing_workspace(&mut self, path: &Path) -> Option<Arc<Workspace>> {
+        if let Some(workspace) = self.enclosing_workspaces.get(path) {
+            return workspace.clone();
+        }
+        let root = path.ancestors().skip(1).find(|dir| is_workspace_root(dir));
+        let workspace = match root.map(|root| self.workspace(root)) {
+            Some(Ok(workspace)) => workspace
+                .filter(|workspace| workspace.members.values().any(|member| member == path)),
+            Some(Err(err)) => {
+                log::debug!("{}, reading {:?} without its workspace", err, path);
+                None
+            }
+            None => None,
+        };
+        self.enclosing_workspaces
+            .insert(path.to_path_buf(), workspace.clone());
+        workspace
+    }
+
+    fn read(
+        &mut self,
+        path: &Path,
+        workspace: Option<&Workspace>,
+    ) -> Result<Arc<Manifest>, CarguixError> {
+        let parsed = read_manifest(
+            path,
+            workspace.map(|workspace| (&workspace.manifest, workspace.root.as_path())),
+        );
+        match self
+            .cargo
+            .as_mut()
+            .map(|cargo| cargo.manifest(path, parsed.as_ref().ok()))
+        {
+            Some(Ok(Some(manifest))) => return Ok(manifest),
+            Some(Err(err)) if parsed.is_ok() => {
+                log::warn!("{}, reading the manifest without cargo", err)
+            }
+            Some(Err(err)) => return Err(err),
+            _ => self.check_cargo(),
+        }
+        parsed.map(Arc::new)
+    }
+
+    /// Stop reading the manifests with cargo if it can't be found.
+    fn check_cargo(&mut self) {
+        if self.cargo.as_ref().is_some_and(|cargo| cargo.missing) {
+            log::warn!("cargo not found, reading the manifests without cargo metadata");
+            self.cargo = None;
+        }
+    }
+}
+
+impl PathSource {
+    pub fn new(
+        path: &Path,
+        workspace: Option<Arc<Workspace>>,
+        features: &Features,
+    ) -> Result<Self, CarguixError> {
+        Self::cached(path, workspace, features, &mut ManifestCache::default())
+    }
+
+    /// [`Self::new`] reusing the manifest of the crate `cache` holds, the manifest being added
+    /// to it otherwise.
+    pub fn cached(
+        path: &Path,
+        workspace: Option<Arc<Workspace>>,
+        features: &Features,
+        cache: &mut ManifestCache,
+    ) -> Result<Self, CarguixError> {
+        let path = canonicalize(path)?;
+        let workspace = match workspace.filter(|workspace| path.starts_with(&workspace.root)) {
+            Some(workspace) => Some(workspace),
+            None => cache.enclosing_workspace(&path),
+        };
+        let key = (
+            path.clone(),
+            workspace.as_ref().map(|workspace| workspace.root.clone()),
+        );
+        let manifest = match cache.manifests.get(&key) {
+            Some(manifest) => manifest.clone(),
+            None => {
+                let manifest = cache.read(&path, workspace.as_deref())?;
+                if manifest.package.is_none() {
+                    return Err(CarguixError::NoPackageInManifest(path.join("Cargo.toml")));
+                }
+                cache.manifests.insert(key, manifest.clone());
+                manifest
+            }
+        };
+        Ok(Self {
+            path,
+            manifest,
+            workspace,
+            features: features.clone(),
+            git: None,
+        })
+    }
+
+    /// Fetch the crate from the commit the directory is a checkout of, if any.
+    pub fn with_git(self, git: Option<GitCheckout>) -> Self {
+        Self { git, ..self }
+    }
+
+    /// Root of the checkout the crate is fetched from, if any.
+    fn git_root(&self) -> Option<&Path> {
+        let depth = self
+            .git
+            .as_ref()?
+            .subdir
+            .as_ref()
+            .map_or(0, |subdir| subdir.components().count());
+        self.path.ancestors().nth(depth)
+    }
+
+    /// Checkout of the repository `path` belongs to when it is the directory of this crate or
+    /// below the same checkout, `None` for directories out of the checkout.
+    fn git_checkout_of(&self, path: &Path) -> Option<GitCheckout> {
+        let git = self.git.as_ref()?;
+        let root = self.git_root()?;
+        let subdir = path.strip_prefix(root).ok()?;
+        Some(GitCheckout {
+            subdir: (subdir != Path::new("")).then(|| subdir.to_path_buf()),
+            ..git.clone()
+        })
+    }
+
+    /// Sources for the crates at `subdir` of the checkout `git` of a repository, `checkout`
+    /// being its directory, along with the project they belong to: the checkout itself if
+    /// its root is a workspace the crates are members of, `subdir` otherwise.
+    pub fn from_git_checkout(
+        checkout: &Path,
+        git: &GitCheckout,
+        subdir: Option<&Path>,
+        features: &Features,
+    ) -> Result<(PathBuf, Vec<Self>), CarguixError> {
+        let checkout = canonicalize(checkout)?;
+        let dir = canonicalize(&checkout.join(subdir.unwrap_or_else(|| Path::new(""))))?;
+        let root_workspace = dir != checkout
+            && checkout.join("Cargo.toml").is_file()
+            && read_manifest(&checkout, None)?.workspace.is_some();
+        let members = if root_workspace {
+            Self::from_path(&checkout, features)?
+                .into_iter()
+                .filter(|member| member.path.starts_with(&dir))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let (project, sources) = if members.is_empty() {
+            (dir.clone(), Self::from_path(&dir, features)?)
+        } else {
+            (checkout.clone(), members)
+        };
+        let sources = sources
+            .into_iter()
+            .map(|source| {
+                let subdir = source
+                    .path
+                    .strip_prefix(&checkout)
+                    .ok()
+                    .filter(|subdir| *subdir != Path::new(""))
+                    .map(Path::to_path_buf);
+                source.with_git(Some(GitCheckout {
+                    subdir,
+                    ..git.clone()
+                }))
+            })
+            .collect();
+        Ok((project, sources))
+    }
+
+    /// Sources for the crate at `path`, or for every member if `path` is a workspace root.
+    pub fn from_path(path: &Path, features: &Features) -> Result<Vec<Self>, CarguixError> {
+        Self::from_path_cached(path, features, &mut ManifestCache::default())
+    }
+
+    /// [`Self::from_path`] reading the manifests through `cache`.
+    pub fn from_path_cached(
+        path: &Path,
+        features: &Features,
+        cache: &mut ManifestCache,
+    ) -> Result<Vec<Self>, CarguixError> {
+        let path = canonicalize(path)?;
+        let workspace = match cache.workspace(&path)? {
+            Some(workspace) => workspace,
+            None => return Ok(vec![Self::cached(&path, None, features, cache)?]),
+        };
+        workspace
+            .members
+            .values()
+            .map(|member_path| Self::cached(member_path, Some(workspace.clone()), features, cache))
+            .collect()
+    }
+
+    pub fn crate_ref(&self) -> CrateRef {
+        let package = self.manifest.package();
+        CrateRef::new(&package.name, package.version())
+    }
+
+    /// Whether the manifest lets the crate be published, internal crates setting `publish = false`
+    /// or an empty list of registries.
+    pub fn is_published(&self) -> bool {
+        match self.manifest.package().publish.get() {
+            Ok(Publish::Flag(publish)) => *publish,
+            Ok(Publish::Registry(registries)) => !registries.is_empty(),
+            Err(_) => true,
+        }
+    }
+
+    /// Name of the alternative registry of a dependency, manifests read with `cargo metadata`
+    /// giving the index of the registry rather than its name.
+    fn dependency_registry(
+        generator: &Generator,
+        dependency: &Dependency,
+    ) -> Result<Option<String>, CarguixError> {
+        let detail = match dependency.detail() {
+            Some(detail) => detail,
+            None => return Ok(None),
+        };
+        match (&detail.registry, &detail.registry_index) {
+            (Some(registry), _) => Ok(Some(registry.clone())),
+            (None, Some(index_url)) => Ok(generator
+                .registries
+                .name_by_index_url(index_url.strip_prefix("registry+").unwrap_or(index_url))?
+                .map(str::to_string)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn dependency_source(
+        &self,
+        generator: &mut Generator,
+        name: &str,
+        dependency: &Dependency,
+        features: &Features,
+    ) -> Result<(CrateRef, Box<dyn Source>), CarguixError> {
+        let crate_name = dependency.package().unwrap_or(name);
+        let member_path = self
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.members.get(crate_name));
+        let dependency_path = dependency.detail().and_then(|detail| detail.path.as_ref());
+        // crates of the same checkout are fetched from the same commit
+        let with_git = |source: Self| {
+            let git = self.git_checkout_of(&source.path);
+            source.with_git(git)
+        };
+        if let (Some(member_path), Some(_)) = (member_path, dependency_path) {
+            let source =
+                with_git(generator.path_source(member_path, self.workspace.clone(), features)?);
+            Ok((source.crate_ref(), Box::new(source)))
+        } else if let Some(dependency_path) = dependency_path {
+            let source = with_git(generator.path_source(
+                &self.path.join(dependency_path),
+                self.workspace.clone(),
+                features,
+            )?);
+            Ok((source.crate_ref(), Box::new(source)))
+        } else if let Some(member) = member_path
+            .map(|member_path| generator.path_source(member_path, self.workspace.clone(), features))
+            .transpose()?
+            .filter(|member| !member.is_published())
+            .map(with_git)
+        {
+            // no registry serves the crate, the requirement can only mean the workspace member
+            log::info!(
+                "dependency {} of crate {} is the unpublished workspace member at {:?}",
+                name,
+                self.manifest.package().name,
+                member.path
+            );
+            Ok((member.crate_ref(), Box::new(member)))
+        } else if dependency.is_crates_io() {
+            if let Some(patched) =
+                generator.patched_source(crate_name, dependency.req(), features)?
+            {
+                return Ok(patched);
+            }
+            let crate_ref = generator.resolve_requirement(crate_name, dependency.req())?;
+            if let Some(replaced) = generator.replaced_source(&crate_ref, features)? {
+                return Ok(replaced);
+            }
+            if let Some(vendored_path) = generator.vendored_crate_path(&crate_ref) {
+                if !vendored_path.join("Cargo.toml").is_file() {
+                    return Err(CarguixError::CrateNotVendored(
+                        crate_ref.name,
+                        crate_ref.version,
+                        vendored_path,
+                    ));
+                }
+                let source = generator.path_source(&vendored_path, None, features)?;
+                if source.crate_ref() != crate_ref {
+                    return Err(CarguixError::CrateNotVendored(
+                        crate_ref.name,
+                        crate_ref.version,
+                        vendored_path,
+                    ));
+                }
+                return Ok((crate_ref, Box::new(source)));
+            }
+            let source =
+                RegistrySource::new(&crate_ref.name, &Some(crate_ref.version.clone()), features);
+            Ok((crate_ref, Box::new(source)))
+        } else if let Some(registry) = Self::dependency_registry(generator, dependency)? {
+            let crate_ref =
+                generator.resolve_registry_requirement(&registry, crate_name, dependency.req())?;
+            let source =
+                RegistrySource::new(&crate_ref.name, &Some(crate_ref.version.clone()), features)
+                    .with_registry(Some(registry));
+            Ok((crate_ref, Box::new(source)))
+        } else {
+            Err(CarguixError::UnsupportedDependency(
+                name.to_string(),
+                self.manifest.package().name.clone(),
+            ))
+        }
+    }
+}
+
+impl Source for PathSource {
+    fn crate_key(&self) -> (String, Option<String>) {
+        let crate_ref = self.crate_ref();
+        (crate_ref.name, Some(crate_ref.version))
+    }
+
+    fn state(&self) -> SourceState {
+        SourceState::Path {
+            path: self.path.clone(),
+            workspace: self
+                .workspace
+                .as_ref()
+                .map(|workspace| workspace.root.clone()),
+            features: self.features.clone(),
+            git: self.git.clone(),
+        }
+    }
+
+    fn features(&self) -> &Features {
+        &self.features
+    }
+
+    fn merge_features(&mut self, features: &Features) {
+        self.features.merge(features);
+    }
+
+    fn resolve(
+        &self,
+        generator: &mut Generator,
+    ) -> Result<(guix::Package, Vec<Box<dyn Source>>), CarguixError> {
+        let crate_ref = self.crate_ref();
+        let source = match &self.git {
+            Some(git) => format!("git+{}#{}", git.url, git.commit),
+            None => format!("path+file://{}", self.path.display()),
+        };
+        generator.record_resolution(&crate_ref, None, &source);
+        let target_tables = self.manifest.target.values();
+        let optional_dependencies =
+            self.manifest
+                .dependencies
+                .iter()
+                .chain(&self.manifest.build_dependencies)
+                .chain(target_tables.clone().flat_map(|target| {
+                    target.dependencies.iter().chain(&target.build_dependencies)
+                }))
+                .filter(|(_, dependency)| dependency.optional())
+                .map(|(name, _)| name.clone())
+                .collect::<HashSet<_>>();
+        let activation = self.features.activate(
+            &crate_ref.name,
+            &self.manifest.features,
+            &optional_dependencies,
+        );
+        // Tables of the platforms left out are skipped, see `Options::platform`
+        let platform_targets = self
+            .manifest
+            .target
+            .iter()
+            .filter(|(target, _)| generator.options.platform.includes(Some(target)))
+            .map(|(_, target)| target);
+        let mut dependency_tables = vec![
+            (&self.manifest.dependencies, DependencyKind::Normal),
+            (&self.manifest.build_dependencies, DependencyKind::Build),
+            (&self.manifest.dev_dependencies, DependencyKind::Development),
+        ];
+        dependency_tables.extend(platform_targets.flat_map(|target| {
+            vec![
+                (&target.dependencies, DependencyKind::Normal),
+                (&target.build_dependencies, DependencyKind::Build),
+                (&target.dev_dependencies, DependencyKind::Development),
+            ]
+        }));
+        let skip_dev_dependencies =
+            generator.options.skip_dev_dependencies || generator.is_vendored(&self.path);
+        let (dependencies, sources): (Vec<_>, Vec<_>) = dependency_tables
+            .iter()
+            .filter(|(_, kind)| !(skip_dev_dependencies && *kind == DependencyKind::Development))
+            .flat_map(|(table, kind)| {
+                table
+                    .iter()
+                    .map(move |(name, dependency)| (name, dependency, *kind))
+            })
+            .filter(|(name, dependency, _)| activation.is_enabled(name, dependency.optional()))
+            .map(|(name, dependency, kind)| {
+                let features = activation.dependency_features(
+                    name,
+                    dependency.req_features(),
+                    dependency
+                        .detail()
+                        .is_none_or(|detail| detail.default_features),
+                );
+                let (crate_ref, source) =
+                    self.dependency_source(generator, name, dependency, &features)?;
+                let requirement = dependency
+                    .detail()
+                    .map_or(Some(dependency.req()), |detail| {
+                        detail.path.is_none().then(|| dependency.req())
+                    });
+                generator.record_dependency(Edge {
+                    dependent: self.crate_ref(),
+                    dependency: crate_ref.clone(),
+                    requirement: requirement.map(str::to_string),
+                    kind,
+                    alias: (crate_ref.name != *name).then(|| name.clone()),
+                });
+                Ok(((crate_ref, kind), source))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                CarguixError::DependencyProcessingFailed(
+                    Box::new(err),
+                    crate_ref.name.clone(),
+                    crate_ref.version.clone(),
+                )
+            })?
+            .into_iter()
+            .unzip();
+        let sources = sources
+            .into_iter()
+            .zip(&dependencies)
+            .filter(|(_, (crate_ref, _))| !generator.is_existing(crate_ref))
+            .map(|(source, _)| source)
+            .collect();
+        let origin = match &self.git {
+            Some(git) => Origin::Git {
+                url: git.url.clone(),
+                commit: git.commit.clone(),
+                hash: nix_hash::nar_hash(self.git_root().unwrap_or(&self.path))
+                    .map_err(|err| CarguixError::HashError(err, crate_ref.name.clone()))?,
+                subdir: git
+                    .subdir
+                    .as_ref()
+                    .map(|subdir| subdir.to_string_lossy().into_owned()),
+            },
+            None => Origin::Local {
+                path: self.path.clone(),
+                files: package_files::select(
+                    &self.path,
+                    self.manifest.package().include(),
+                    self.manifest.package().exclude(),
+                )?,
+            },
+        };
+        let metadata = generator.crate_metadata(&MetadataTarget {
+            crate_ref: &crate_ref,
+            registry: None,
+            local: Some((&self.path, &self.manifest)),
+        });
+        let package = guix::Package::new(
+            crate_ref,
+            origin,
+            &dependencies,
+            &self.features.cargo_build_flags(),
+        )
+        .with_metadata(metadata)
+        .with_feature_summary(
+            generator
+                .options
+                .feature_summaries
+                .then(|| activation.summary(&self.manifest.features))
+                .flatten(),
+        )
+        .with_proc_macro(self.manifest.lib.as_ref().is_some_and(|lib| lib.proc_macro))
+        .with_unpublished(!self.is_published())
+        .with_links(self.manifest.package().links().map(str::to_string));
+        Ok((package, sources))
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, CarguixError> {
+    path.canonicalize()
+        .map_err(|err| CarguixError::InvalidPath(err, path.to_path_buf()))
+}
+
+/// Whether the manifest of `dir` declares a workspace.
+fn is_workspace_root(dir: &Path) -> bool {
+    fs::read(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|content| toml::from_slice::<toml::Value>(&content).ok())
+        .is_some_and(|manifest| manifest.get("workspace").is_some())
+}
+
+pub fn read_manifest(
+    path: &Path,
+    workspace: Option<(&Manifest, &Path)>,
+) -> Result<Manifest, CarguixError> {
+    let manifest_path = path.join("Cargo.toml");
+    let parsing_error = |err| CarguixError::ManifestParsingError(err, manifest_path.clone());
+    let content = fs::read(&manifest_path).map_err(|err| parsing_error(err.into()))?;
+    let mut value =
+        toml::from_slice::<toml::Value>(&content).map_err(|err| parsing_error(err.into()))?;
+    if let Some(package) = value.get_mut("package").and_then(toml::Value::as_table_mut) {
+        if !package.contains_key("version") {
+            package.insert(
+                "version".to_string(),
+                toml::Value::String(PLACEHOLDER_VERSION.to_string()),
+            );
+        }
+    }
+    let mut manifest = value
+        .try_into::<Manifest>()
+        .map_err(|err| parsing_error(err.into()))?;
+    manifest
+        .complete_from_path_and_workspace(&manifest_path, workspace)
+        .map_err(parsing_error)?;
+    if let Some(package) = manifest.package.as_mut() {
+        if package.version.get().is_err() {
+            log::warn!(
+                "{} inherits its version from a workspace that doesn't set it, using {}",
+                manifest_path.display(),
+                PLACEHOLDER_VERSION
+            );
+            package.version = Inheritable::Set(PLACEHOLDER_VERSION.to_string());
+        }
+    }
+    Ok(manifest)
+}
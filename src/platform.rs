@@ -0,0 +1,70 @@
+use crate::errors::CarguixError;
+use cfg_expr::{targets::TargetInfo, Expression, Predicate};
+
+/// Platforms whose target-specific dependencies are packaged, i.e. the ones of
+/// `[target.'cfg(...)'.dependencies]` tables and of index entries with a `target` key.
+#[derive(Debug, Clone)]
+pub enum Platform {
+    /// Every target-specific dependency, whatever the platform it's for.
+    All,
+    /// Dependencies of the given target, and the ones which aren't target-specific.
+    Target(&'static TargetInfo),
+}
+
+impl Platform {
+    /// Platform of the target triple `triple`, e.g. `aarch64-unknown-linux-gnu`.
+    pub fn new(triple: &str) -> Result<Self, CarguixError> {
+        cfg_expr::targets::get_builtin_target_by_triple(triple)
+            .map(Platform::Target)
+            .ok_or_else(|| CarguixError::UnknownTargetTriple(triple.to_string()))
+    }
+
+    /// Platform carguix runs on, which Guix builds the packages for unless cross-compiling.
+    pub fn host() -> Self {
+        let arch = match std::env::consts::ARCH {
+            "riscv64" => "riscv64gc",
+            "powerpc64" if cfg!(target_endian = "little") => "powerpc64le",
+            arch => arch,
+        };
+        let triple = format!("{}-unknown-{}-gnu", arch, std::env::consts::OS);
+        Self::new(&triple).unwrap_or_else(|_| {
+            log::warn!(
+                "unknown host target {}, including dependencies of every target",
+                triple
+            );
+            Platform::All
+        })
+    }
+
+    /// Whether a dependency restricted to `target`, a target triple or a `cfg(...)` expression,
+    /// is packaged, dependencies with no target being packaged for every platform.
+    pub fn includes(&self, target: Option<&str>) -> bool {
+        let (info, target) = match (self, target) {
+            (Platform::Target(info), Some(target)) => (info, target),
+            _ => return true,
+        };
+        if !target.starts_with("cfg(") {
+            return info.triple.as_str() == target;
+        }
+        match Expression::parse(target) {
+            Ok(expression) => expression.eval(|predicate| match predicate {
+                Predicate::Target(target_predicate) => target_predicate.matches(*info),
+                _ => false,
+            }),
+            Err(err) => {
+                log::warn!(
+                    "could not parse target {}, including its dependencies: {}",
+                    target,
+                    err
+                );
+                true
+            }
+        }
+    }
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Self::host()
+    }
+}
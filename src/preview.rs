@@ -0,0 +1,171 @@
+use crate::{
+    crate_ref::CrateRef, errors::CarguixError, features::Features, registry_source::RegistrySource,
+    Generator,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+/// Dependency tree of a crate looked up in the index only, nothing being downloaded, to gauge
+/// the effort of packaging it.
+///
+/// Every crate is taken with its default features and dev-dependencies are left out, so the
+/// closure is an estimate of the one a full generation would package.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub root: CrateRef,
+    /// Dependencies of every crate of the closure, crates packaged in Guix not being expanded.
+    pub dependencies: BTreeMap<CrateRef, Vec<CrateRef>>,
+    /// Crates of the closure packaged in Guix already.
+    pub existing: BTreeSet<CrateRef>,
+}
+
+impl Preview {
+    pub fn new(
+        generator: &mut Generator,
+        crate_name: &str,
+        version: &Option<String>,
+    ) -> Result<Self, CarguixError> {
+        let crate_ = generator.registry_index(None)?.crate_(crate_name)?;
+        let version = match version {
+            Some(version) => version.clone(),
+            None => RegistrySource::default_version(&crate_, &generator.options)?
+                .version()
+                .to_string(),
+        };
+        let root = CrateRef::new(crate_.name(), &version);
+        let mut dependencies = BTreeMap::new();
+        let mut existing = BTreeSet::new();
+        let mut queue = VecDeque::from(vec![root.clone()]);
+        while let Some(crate_ref) = queue.pop_front() {
+            if dependencies.contains_key(&crate_ref) || existing.contains(&crate_ref) {
+                continue;
+            }
+            if generator.is_existing(&crate_ref) {
+                existing.insert(crate_ref);
+                continue;
+            }
+            let crate_dependencies = Self::crate_dependencies(generator, &crate_ref)?;
+            queue.extend(crate_dependencies.iter().cloned());
+            dependencies.insert(crate_ref, crate_dependencies);
+        }
+        Ok(Self {
+            root,
+            dependencies,
+            existing,
+        })
+    }
+
+    /// Normal and build dependencies of a crate of crates.io with its default features.
+    fn crate_dependencies(
+        generator: &mut Generator,
+        crate_ref: &CrateRef,
+    ) -> Result<Vec<CrateRef>, CarguixError> {
+        let crate_ = generator.registry_index(None)?.crate_(&crate_ref.name)?;
+        let crate_version = crate_
+            .versions()
+            .iter()
+            .find(|crate_version| crate_version.version() == crate_ref.version)
+            .ok_or_else(|| CarguixError::NoMatchingVersion {
+                name: crate_ref.name.clone(),
+                version: crate_ref.version.clone(),
+            })?;
+        let optional_dependencies = crate_version
+            .dependencies()
+            .iter()
+            .filter(|dependency| dependency.is_optional())
+            .map(|dependency| dependency.name().to_string())
+            .collect::<HashSet<_>>();
+        let activation = Features::default().activate(
+            crate_.name(),
+            &crate_version.features(),
+            &optional_dependencies,
+        );
+        let mut crate_dependencies = Vec::new();
+        for dependency in crate_version.dependencies() {
+            if dependency.kind() == Some("dev")
+                || dependency.registry().is_some()
+                || !generator.options.platform.includes(dependency.target())
+                || !activation.is_enabled(dependency.name(), dependency.is_optional())
+            {
+                continue;
+            }
+            let dependency =
+                generator.resolve_requirement(dependency.crate_name(), dependency.requirement())?;
+            if !crate_dependencies.contains(&dependency) {
+                crate_dependencies.push(dependency);
+            }
+        }
+        Ok(crate_dependencies)
+    }
+
+    /// Number of crates in the closure, including the ones packaged in Guix already.
+    pub fn closure_size(&self) -> usize {
+        self.dependencies.len() + self.existing.len()
+    }
+
+    /// First `depth` levels of the tree, drawn like `cargo tree` does, each crate being marked
+    /// as missing or packaged in Guix, followed by the estimated size of the closure.
+    pub fn to_tree(&self, depth: usize) -> String {
+        let mut tree = String::new();
+        let mut expanded = HashSet::new();
+        self.draw(&self.root, "", "", depth, &mut expanded, &mut tree);
+        tree.push_str(&format!(
+            "\nestimated closure: {} crates, {} to package, {} packaged in Guix already\n",
+            self.closure_size(),
+            self.dependencies.len(),
+            self.existing.len()
+        ));
+        tree
+    }
+
+    fn draw(
+        &self,
+        crate_ref: &CrateRef,
+        prefix: &str,
+        child_prefix: &str,
+        depth: usize,
+        expanded: &mut HashSet<CrateRef>,
+        tree: &mut String,
+    ) {
+        let marker = if self.existing.contains(crate_ref) {
+            "packaged"
+        } else {
+            "missing"
+        };
+        let dependencies = self
+            .dependencies
+            .get(crate_ref)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let suffix = if dependencies.is_empty() {
+            ""
+        } else if expanded.contains(crate_ref) {
+            " (*)"
+        } else if depth == 0 {
+            " ..."
+        } else {
+            ""
+        };
+        tree.push_str(&format!(
+            "{}{} v{} [{}]{}\n",
+            prefix, crate_ref.name, crate_ref.version, marker, suffix
+        ));
+        if depth == 0 || !expanded.insert(crate_ref.clone()) {
+            return;
+        }
+        for (position, dependency) in dependencies.iter().enumerate() {
+            let (branch, indent) = if position + 1 == dependencies.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            self.draw(
+                dependency,
+                &format!("{}{}", child_prefix, branch),
+                &format!("{}{}", child_prefix, indent),
+                depth - 1,
+                expanded,
+                tree,
+            );
+        }
+    }
+}
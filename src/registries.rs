@@ -0,0 +1,239 @@
+use crate::{
+    download,
+    errors::CarguixError,
+    index::{self, Registry},
+};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Index URLs cargo knows crates.io by.
+const CRATES_IO_INDEX_URLS: &[&str] = &[
+    "https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io",
+];
+
+/// Whether an index URL, as found in cargo configuration or `Cargo.lock` sources, is the one of
+/// crates.io.
+pub fn is_crates_io_index(index_url: &str) -> bool {
+    CRATES_IO_INDEX_URLS.contains(&index_url.trim_end_matches('/'))
+}
+
+/// Subset of a cargo configuration or credentials file.
+#[derive(Debug, Default, Deserialize)]
+struct CargoConfig {
+    #[serde(default)]
+    registries: HashMap<String, RegistryEntry>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RegistryEntry {
+    index: Option<String>,
+    token: Option<String>,
+}
+
+/// `config.json` file at the root of an index.
+#[derive(Debug, Deserialize)]
+struct IndexConfig {
+    /// Download URL, or template of it.
+    dl: String,
+}
+
+/// Registry other than crates.io, declared in the `[registries]` table of cargo configuration.
+#[derive(Debug)]
+pub struct AlternativeRegistry {
+    pub name: String,
+    /// Index URL as declared, e.g. `sparse+https://cargo.example.org/index/`.
+    pub index_url: String,
+    /// Only sparse indexes are supported, git ones being `None`.
+    index: Option<Registry>,
+    token: Option<String>,
+    /// `dl` field of the index configuration, fetched when the first crate is downloaded.
+    download_template: OnceCell<String>,
+}
+
+impl AlternativeRegistry {
+    fn new(name: &str, index_url: &str, token: Option<String>) -> Self {
+        let index = index_url
+            .strip_prefix("sparse+")
+            .map(|url| Registry::sparse_with_url(url, token.clone()));
+        Self {
+            name: name.to_string(),
+            index_url: index_url.to_string(),
+            index,
+            token,
+            download_template: OnceCell::new(),
+        }
+    }
+
+    pub fn index(&self) -> Result<&Registry, CarguixError> {
+        self.index.as_ref().ok_or_else(|| {
+            CarguixError::UnsupportedRegistry(self.name.clone(), self.index_url.clone())
+        })
+    }
+
+    /// Token sent in the `Authorization` header of index and download requests.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// URL a crate is downloaded from, following the template of the index configuration.
+    pub fn download_url(
+        &self,
+        crate_name: &str,
+        version: &str,
+        checksum: &str,
+    ) -> Result<String, CarguixError> {
+        let template = self
+            .download_template
+            .get_or_try_init(|| self.fetch_download_template())?;
+        if ![
+            "{crate}",
+            "{version}",
+            "{prefix}",
+            "{lowerprefix}",
+            "{sha256-checksum}",
+        ]
+        .iter()
+        .any(|marker| template.contains(marker))
+        {
+            return Ok(format!(
+                "{}/{}/{}/download",
+                template.trim_end_matches('/'),
+                crate_name,
+                version
+            ));
+        }
+        let prefix = index::index_file_path(crate_name)
+            .parent()
+            .map(|prefix| prefix.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(template
+            .replace("{crate}", crate_name)
+            .replace("{version}", version)
+            .replace("{lowerprefix}", &prefix.to_lowercase())
+            .replace("{prefix}", &prefix)
+            .replace("{sha256-checksum}", checksum))
+    }
+
+    fn fetch_download_template(&self) -> Result<String, CarguixError> {
+        let url = match &self.index {
+            Some(Registry::Sparse { url, .. }) => format!("{}/config.json", url),
+            _ => {
+                return Err(CarguixError::UnsupportedRegistry(
+                    self.name.clone(),
+                    self.index_url.clone(),
+                ))
+            }
+        };
+        let fetch_error = |err| CarguixError::RegistryConfigError(err, self.name.clone());
+        let mut request = download::http_client().get(&url);
+        if let Some(token) = &self.token {
+            request = request.header(reqwest::header::AUTHORIZATION, token.as_str());
+        }
+        let config = request
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| response.json::<IndexConfig>())
+            .map_err(fetch_error)?;
+        Ok(config.dl)
+    }
+}
+
+/// Alternative registries declared in cargo configuration, by name.
+#[derive(Debug, Default)]
+pub struct Registries {
+    registries: HashMap<String, AlternativeRegistry>,
+}
+
+impl Registries {
+    /// Read the registries declared in the `.cargo/config.toml` files of `dir` and its ancestors
+    /// then of `$CARGO_HOME`, the closest declaration taking precedence, with tokens from
+    /// `$CARGO_HOME/credentials.toml`. `CARGO_REGISTRIES_<NAME>_INDEX` and
+    /// `CARGO_REGISTRIES_<NAME>_TOKEN` environment variables override both.
+    pub fn load(dir: &Path) -> Result<Self, CarguixError> {
+        let cargo_home = env::var_os("CARGO_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".cargo")));
+        let mut config_files = dir
+            .ancestors()
+            .map(|ancestor| ancestor.join(".cargo"))
+            .collect::<Vec<_>>();
+        config_files.extend(cargo_home.clone());
+        let config_files = config_files
+            .into_iter()
+            .flat_map(|cargo_dir| vec![cargo_dir.join("config.toml"), cargo_dir.join("config")]);
+        let credential_files = cargo_home
+            .iter()
+            .flat_map(|cargo_home| {
+                vec![
+                    cargo_home.join("credentials.toml"),
+                    cargo_home.join("credentials"),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let mut entries = HashMap::<String, RegistryEntry>::new();
+        for path in config_files.chain(credential_files) {
+            for (name, entry) in read_cargo_config(&path)?.registries {
+                let merged = entries.entry(name).or_default();
+                merged.index = merged.index.take().or(entry.index);
+                merged.token = merged.token.take().or(entry.token);
+            }
+        }
+        let registries = entries
+            .into_iter()
+            .filter_map(|(name, entry)| {
+                let variable = |field: &str| {
+                    let name = name.to_uppercase().replace('-', "_");
+                    env::var(format!("CARGO_REGISTRIES_{}_{}", name, field)).ok()
+                };
+                let index_url = variable("INDEX").or(entry.index)?;
+                let token = variable("TOKEN").or(entry.token);
+                let registry = AlternativeRegistry::new(&name, &index_url, token);
+                Some((name, registry))
+            })
+            .collect();
+        Ok(Self { registries })
+    }
+
+    /// Add the registries of `other` which are not declared already.
+    pub fn merge(&mut self, other: Registries) {
+        for (name, registry) in other.registries {
+            self.registries.entry(name).or_insert(registry);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<&AlternativeRegistry, CarguixError> {
+        self.registries
+            .get(name)
+            .ok_or_else(|| CarguixError::UnknownRegistry(name.to_string()))
+    }
+
+    /// Name of the registry with the given index URL, `None` standing for crates.io.
+    pub fn name_by_index_url(&self, index_url: &str) -> Result<Option<&str>, CarguixError> {
+        if is_crates_io_index(index_url) {
+            return Ok(None);
+        }
+        let index_url = index_url.trim_end_matches('/');
+        self.registries
+            .values()
+            .find(|registry| registry.index_url.trim_end_matches('/') == index_url)
+            .map(|registry| Some(registry.name.as_str()))
+            .ok_or_else(|| CarguixError::UnknownRegistry(index_url.to_string()))
+    }
+}
+
+/// Read a cargo configuration file, a missing file declaring nothing.
+fn read_cargo_config(path: &Path) -> Result<CargoConfig, CarguixError> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content)
+            .map_err(|err| CarguixError::CargoConfigParsingError(err, path.to_path_buf())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(CargoConfig::default()),
+        Err(err) => Err(CarguixError::CargoConfigIoError(err, path.to_path_buf())),
+    }
+}
@@ -0,0 +1,436 @@
+use crate::{
+    carguix_lock::REGISTRY_SOURCE,
+    crate_ref::CrateRef,
+    enrichers::MetadataTarget,
+    errors::CarguixError,
+    features::Features,
+    graph::Edge,
+    guix::{self, Origin, RegistryDownload},
+    index::{IndexedCrate, IndexedVersion, Registry},
+    source::{DependencyKind, Source, SourceState},
+    version,
+    warnings::WarningCode,
+    Generator, Options,
+};
+use crates_index::Index;
+use std::collections::{HashMap, HashSet};
+
+/// Crate published on crates.io or an alternative registry, identified by name and optionally version (default: latest).
+#[derive(Debug, Clone)]
+pub struct RegistrySource {
+    pub name: String,
+    pub version: Option<String>,
+    pub features: Features,
+    /// Alternative registry the crate is published on instead of crates.io.
+    pub registry: Option<String>,
+}
+
+impl RegistrySource {
+    pub fn new(name: &str, version: &Option<String>, features: &Features) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.clone(),
+            features: features.clone(),
+            registry: None,
+        }
+    }
+
+    pub fn with_registry(self, registry: Option<String>) -> Self {
+        Self { registry, ..self }
+    }
+
+    /// Version requirement the crate was requested with, such as `^1.2` or `>=0.10, <0.12`, the
+    /// highest version matching it being packaged. Versions published as such, as well as the
+    /// ones semver accepts, are taken as exact versions instead.
+    pub fn requirement<'a>(&'a self, crate_: &IndexedCrate) -> Option<&'a str> {
+        let version = self.version.as_deref()?;
+        let published = crate_
+            .versions()
+            .iter()
+            .any(|crate_version| crate_version.version() == version);
+        (!published && semver::Version::parse(version).is_err()).then_some(version)
+    }
+
+    /// Version of a crate packaged when none is given: the last published one, yanked versions
+    /// being skipped unless `options.allow_yanked` is set and pre-releases unless
+    /// `options.allow_prerelease` is set or no stable version is left.
+    pub fn default_version<'a>(
+        crate_: &'a IndexedCrate,
+        options: &Options,
+    ) -> Result<&'a IndexedVersion, CarguixError> {
+        let mut candidates = crate_
+            .versions()
+            .iter()
+            .rev()
+            .filter(|crate_version| options.allow_yanked || !crate_version.is_yanked())
+            .peekable();
+        let latest = *candidates
+            .peek()
+            .ok_or_else(|| CarguixError::AllVersionsYanked(crate_.name().to_string()))?;
+        if options.allow_prerelease {
+            return Ok(latest);
+        }
+        let stable = candidates.find(|crate_version| {
+            semver::Version::parse(crate_version.version())
+                .map_or(true, |version| !version.is_prerelease())
+        });
+        Ok(stable.unwrap_or_else(|| {
+            options.warnings.warn(
+                WarningCode::PreRelease,
+                format_args!(
+                    "crate {} has only pre-release versions, using {}",
+                    crate_.name(),
+                    latest.version()
+                ),
+            );
+            latest
+        }))
+    }
+
+    /// Version of a crates.io dependency, along with the source of the copy overriding it when
+    /// the projects patch or replace it.
+    fn crates_io_dependency(
+        generator: &mut Generator,
+        crate_name: &str,
+        requirement: &str,
+        features: &Features,
+    ) -> Result<(CrateRef, Option<Box<dyn Source>>), CarguixError> {
+        if let Some((crate_ref, source)) =
+            generator.patched_source(crate_name, requirement, features)?
+        {
+            return Ok((crate_ref, Some(source)));
+        }
+        let crate_ref = generator.resolve_requirement(crate_name, requirement)?;
+        Ok(match generator.replaced_source(&crate_ref, features)? {
+            Some((crate_ref, source)) => (crate_ref, Some(source)),
+            None => (crate_ref, None),
+        })
+    }
+
+    /// Highest version of a crate matching a requirement, versions and requirement semver
+    /// rejects being repaired unless `options.strict_semver` is set.
+    ///
+    /// Yanked versions are only picked when `options.allow_yanked` is set or when no other
+    /// version matches. Pre-releases are left out unless `options.allow_prerelease` is set or
+    /// the requirement mentions one.
+    pub fn highest_matching_crate_version(
+        registry: &Registry,
+        crate_name: &str,
+        requirement: &str,
+        options: &Options,
+    ) -> Result<CrateRef, CarguixError> {
+        let strict = options.strict_semver;
+        let allow_prerelease =
+            options.allow_prerelease || version::mentions_prerelease(requirement);
+        let crate_ = registry.crate_(crate_name)?;
+        if !options.allow_yanked && crate_.is_all_yanked() {
+            return Err(CarguixError::AllVersionsYanked(crate_name.to_string()));
+        }
+        let mut crate_versions = crate_
+            .versions()
+            .iter()
+            .map(|crate_version| {
+                let version = crate_version.version();
+                version::parse_version(crate_name, version, strict)
+                    .map(|parsed| (parsed, version, crate_version.is_yanked()))
+                    .map_err(|err| {
+                        CarguixError::VersionParsingError(
+                            err,
+                            crate_name.to_string(),
+                            version.to_string(),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        crate_versions.sort();
+        let version_req =
+            version::parse_requirement(crate_name, requirement, strict).map_err(|err| {
+                CarguixError::RequirementParsingError(
+                    err,
+                    crate_name.to_string(),
+                    requirement.to_string(),
+                )
+            })?;
+        let mut matching_versions = crate_versions
+            .iter()
+            .rev()
+            .filter(|(version, _, _)| allow_prerelease || !version.is_prerelease())
+            .filter(|(version, _, _)| version_req.matches(version))
+            .peekable();
+        let (_, highest_yanked_version, _) =
+            *matching_versions
+                .peek()
+                .ok_or(CarguixError::NoVersionMatchingRequirement {
+                    name: crate_name.to_string(),
+                    requirement: requirement.to_string(),
+                })?;
+        let highest_matching_version =
+            match matching_versions.find(|(_, _, yanked)| options.allow_yanked || !yanked) {
+                Some((_, version, _)) => version,
+                None => {
+                    options.warnings.warn(
+                        WarningCode::YankedVersion,
+                        format_args!(
+                            "only yanked versions of crate {} match requirement {}, using {}",
+                            crate_name, requirement, highest_yanked_version
+                        ),
+                    );
+                    highest_yanked_version
+                }
+            };
+        Ok(CrateRef::new(crate_.name(), highest_matching_version))
+    }
+
+    /// Dependents of a crate ranked by how many crates of the index depend on them in turn.
+    ///
+    /// Only the latest version of each crate is considered and dev-dependencies are ignored.
+    pub fn popular_dependents(
+        index: &Index,
+        crate_name: &str,
+        limit: usize,
+    ) -> Result<Vec<Self>, CarguixError> {
+        let crate_ = index
+            .crate_(crate_name)
+            .ok_or_else(|| CarguixError::CrateNotFound(crate_name.to_string()))?;
+        log::info!("scanning index for dependents of {}...", crate_.name());
+        let mut dependent_counts = HashMap::<String, usize>::new();
+        let mut dependents = Vec::new();
+        for indexed_crate in index.crates() {
+            let latest_version = indexed_crate.latest_version();
+            for dependency in latest_version.dependencies() {
+                if dependency.kind() == Some("dev") {
+                    continue;
+                }
+                *dependent_counts
+                    .entry(dependency.crate_name().to_string())
+                    .or_default() += 1;
+                if dependency.crate_name() == crate_.name() {
+                    dependents.push(latest_version.name().to_string());
+                }
+            }
+        }
+        dependents.sort();
+        dependents.dedup();
+        dependents.sort_by_key(|dependent| {
+            std::cmp::Reverse(dependent_counts.get(dependent).copied().unwrap_or_default())
+        });
+        Ok(dependents
+            .iter()
+            .take(limit)
+            .map(|dependent| Self::new(dependent, &None, &Features::default()))
+            .collect())
+    }
+
+    fn package(
+        &self,
+        generator: &mut Generator,
+    ) -> Result<(guix::Package, Vec<Box<dyn Source>>), CarguixError> {
+        if generator.options.offline {
+            return Err(CarguixError::OfflineNetworkAccess(format!(
+                "packaging crate {} from {}",
+                self.name,
+                self.registry.as_deref().unwrap_or("crates.io")
+            )));
+        }
+        let crate_ = generator
+            .registry_index(self.registry.as_deref())?
+            .crate_(&self.name)?;
+        let requirement = self.requirement(&crate_);
+        let version = match (requirement, &self.version) {
+            (Some(requirement), _) => {
+                match generator.pinned.version(crate_.name(), Some(requirement)) {
+                    Some(version) => version.to_string(),
+                    None => {
+                        Self::highest_matching_crate_version(
+                            generator.registry_index(self.registry.as_deref())?,
+                            crate_.name(),
+                            requirement,
+                            &generator.options,
+                        )?
+                        .version
+                    }
+                }
+            }
+            (None, Some(version)) => version.clone(),
+            (None, None) => match generator.pinned.version(crate_.name(), None) {
+                Some(version) => version.to_string(),
+                None => Self::default_version(&crate_, &generator.options)?
+                    .version()
+                    .to_string(),
+            },
+        };
+        let version = version.as_str();
+        let crate_version = crate_
+            .versions()
+            .iter()
+            .find(|crate_version| crate_version.version() == version)
+            .ok_or(CarguixError::NoMatchingVersion {
+                name: crate_.name().to_string(),
+                version: version.to_string(),
+            })?;
+        let optional_dependencies = crate_version
+            .dependencies()
+            .iter()
+            .filter(|dependency| dependency.is_optional())
+            .map(|dependency| dependency.name().to_string())
+            .collect::<HashSet<_>>();
+        let activation = self.features.activate(
+            crate_.name(),
+            &crate_version.features(),
+            &optional_dependencies,
+        );
+        let skip_dev_dependencies = generator.options.skip_dev_dependencies;
+        let platform = generator.options.platform.clone();
+        let dependencies = crate_version
+            .dependencies()
+            .iter()
+            .filter(|dependency| activation.is_enabled(dependency.name(), dependency.is_optional()))
+            .filter(|dependency| platform.includes(dependency.target()))
+            .map(|dependency| (dependency, DependencyKind::from_index(dependency.kind())))
+            .filter(|(_, kind)| !(skip_dev_dependencies && *kind == DependencyKind::Development))
+            .map(|(dependency, kind)| {
+                let registry = match dependency.registry() {
+                    Some(index_url) => generator
+                        .registries
+                        .name_by_index_url(index_url)?
+                        .map(str::to_string),
+                    None => self.registry.clone(),
+                };
+                let features = activation.dependency_features(
+                    dependency.name(),
+                    dependency.features(),
+                    dependency.has_default_features(),
+                );
+                let (crate_ref, overridden) = match &registry {
+                    Some(registry) => (
+                        generator.resolve_registry_requirement(
+                            registry,
+                            dependency.crate_name(),
+                            dependency.requirement(),
+                        )?,
+                        None,
+                    ),
+                    None => Self::crates_io_dependency(
+                        generator,
+                        dependency.crate_name(),
+                        dependency.requirement(),
+                        &features,
+                    )?,
+                };
+                generator.record_dependency(Edge {
+                    dependent: CrateRef::new(crate_.name(), version),
+                    dependency: crate_ref.clone(),
+                    requirement: Some(dependency.requirement().to_string()),
+                    kind,
+                    alias: (dependency.name() != dependency.crate_name())
+                        .then(|| dependency.name().to_string()),
+                });
+                Ok((crate_ref, kind, (features, overridden), registry))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                CarguixError::DependencyProcessingFailed(
+                    Box::new(err),
+                    crate_.name().to_string(),
+                    version.to_string(),
+                )
+            })?;
+        let (source, registry_download) = match &self.registry {
+            Some(registry) => {
+                let alternative_registry = generator.registries.get(registry)?;
+                let registry_download = RegistryDownload {
+                    registry: registry.clone(),
+                    url: alternative_registry.download_url(
+                        crate_.name(),
+                        version,
+                        crate_version.checksum(),
+                    )?,
+                };
+                let source = format!("registry+{}", alternative_registry.index_url);
+                (source, Some(registry_download))
+            }
+            None => (REGISTRY_SOURCE.to_string(), None),
+        };
+        let crate_ref = CrateRef::new(crate_.name(), version);
+        let metadata = generator.crate_metadata(&MetadataTarget {
+            crate_ref: &crate_ref,
+            registry: self.registry.as_deref(),
+            local: None,
+        });
+        if self.version.is_none() || requirement.is_some() {
+            generator.record_resolution(&crate_ref, requirement, &source);
+        }
+        let package = guix::Package::new(
+            crate_ref,
+            Origin::Crate {
+                hash: None,
+                crates_io: true,
+                mirrors: Vec::new(),
+                registry: registry_download,
+            },
+            &dependencies
+                .iter()
+                .map(|(crate_ref, kind, _, _)| (crate_ref.clone(), *kind))
+                .collect::<Vec<_>>(),
+            &self.features.cargo_build_flags(),
+        )
+        .with_metadata(metadata)
+        .with_feature_summary(
+            generator
+                .options
+                .feature_summaries
+                .then(|| activation.summary(&crate_version.features()))
+                .flatten(),
+        )
+        .with_links(crate_version.links().map(str::to_string));
+        let dependencies = dependencies
+            .into_iter()
+            .filter(|(crate_ref, _, _, _)| !generator.is_existing(crate_ref))
+            .map(|(crate_ref, _, (features, overridden), registry)| {
+                overridden.unwrap_or_else(|| {
+                    Box::new(
+                        Self::new(&crate_ref.name, &Some(crate_ref.version), &features)
+                            .with_registry(registry),
+                    )
+                })
+            })
+            .collect();
+        Ok((package, dependencies))
+    }
+}
+
+impl Source for RegistrySource {
+    fn crate_key(&self) -> (String, Option<String>) {
+        (self.name.clone(), self.version.clone())
+    }
+
+    fn state(&self) -> SourceState {
+        SourceState::Registry {
+            name: self.name.clone(),
+            version: self.version.clone(),
+            features: self.features.clone(),
+            registry: self.registry.clone(),
+        }
+    }
+
+    fn features(&self) -> &Features {
+        &self.features
+    }
+
+    fn merge_features(&mut self, features: &Features) {
+        self.features.merge(features);
+    }
+
+    fn resolve(
+        &self,
+        generator: &mut Generator,
+    ) -> Result<(guix::Package, Vec<Box<dyn Source>>), CarguixError> {
+        self.package(generator).map_err(|err| {
+            log::warn!("skipping crate {}: {}", self.name, err);
+            CarguixError::CratePackagingFailed {
+                name: self.name.clone(),
+                version: self.version.clone(),
+            }
+        })
+    }
+}
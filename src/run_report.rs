@@ -0,0 +1,141 @@
+use crate::{errors::CarguixError, guix, schema::ErrorReport, stats::CacheStats};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Where the hash of a crate tarball came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashSource {
+    /// Pinned in the lock file.
+    Pinned,
+    /// Converted from the checksum of a `Cargo.lock` file.
+    Checksummed,
+    /// Found in the hash database.
+    Cached,
+    /// Fetched from the hash mirror.
+    Mirrored,
+    /// Computed from the tarball of the tarball directory.
+    Stored,
+    /// Computed from a fresh download.
+    Downloaded,
+}
+
+/// What a run did for one crate of its graph, whether it could be packaged or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageRun {
+    pub name: String,
+    /// Version resolved, or requested when the crate couldn't be resolved, `None` when the
+    /// latest version was requested.
+    pub version: Option<String>,
+    /// Time spent resolving and hashing the crate, downloads included.
+    pub duration: Duration,
+    /// `None` for crates not packaged from a registry tarball.
+    pub hash_source: Option<HashSource>,
+}
+
+impl PackageRun {
+    pub fn new(name: &str, version: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.map(str::to_string),
+            duration: Duration::ZERO,
+            hash_source: None,
+        }
+    }
+}
+
+/// Outcome of a crate in the run report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateReport {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Whether the crate was packaged.
+    pub generated: bool,
+    /// URL the package fetches the crate sources from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_source: Option<HashSource>,
+    /// Fewest build-dependency edges reaching the package from the root packages, for the
+    /// packages only needed to compile build scripts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_dependency_depth: Option<usize>,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+}
+
+/// Totals of the run report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunTotals {
+    pub crates: usize,
+    pub generated: usize,
+    pub failed: usize,
+    pub cache: CacheStats,
+    pub duration_ms: u128,
+}
+
+/// Outcome of every crate of a run and its totals, written with `--report` so that CI jobs can
+/// fail on partial errors and follow how runs evolve.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    pub crates: Vec<CrateReport>,
+    pub totals: RunTotals,
+}
+
+impl RunReport {
+    /// Report of the packages of [`crate::Generator::packages`], `runs` being the matching
+    /// [`crate::Generator::package_runs`].
+    pub fn new(
+        packages: &[Result<guix::Package, CarguixError>],
+        runs: &[PackageRun],
+        cache: CacheStats,
+        duration: Duration,
+    ) -> Self {
+        let crates = packages
+            .iter()
+            .zip(runs)
+            .map(|(package, run)| match package {
+                Ok(package) => CrateReport {
+                    name: package.crate_ref.name.clone(),
+                    version: Some(package.crate_ref.version.clone()),
+                    generated: true,
+                    source_url: package.source_url(),
+                    hash: package.hash().map(str::to_string),
+                    hash_source: run.hash_source,
+                    build_dependency_depth: Some(package.build_dependency_depth)
+                        .filter(|depth| *depth > 0),
+                    duration_ms: run.duration.as_millis(),
+                    error: None,
+                },
+                Err(err) => CrateReport {
+                    name: run.name.clone(),
+                    version: run.version.clone(),
+                    generated: false,
+                    source_url: None,
+                    hash: None,
+                    hash_source: None,
+                    build_dependency_depth: None,
+                    duration_ms: run.duration.as_millis(),
+                    error: Some(ErrorReport::from_error(err)),
+                },
+            })
+            .collect::<Vec<_>>();
+        let generated = crates.iter().filter(|report| report.generated).count();
+        Self {
+            totals: RunTotals {
+                crates: crates.len(),
+                generated,
+                failed: crates.len() - generated,
+                cache,
+                duration_ms: duration.as_millis(),
+            },
+            crates,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{
+    carguix_lock::CarguixLock, crate_ref::CrateRef, errors::CarguixError, graph::DependencyGraph,
+    guix, guix_packages::ExistingPackage, merge::CrateRequest, source::SourceState,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fs, io::ErrorKind, path::Path};
+
+/// File the progress of a run is persisted to, alongside `crates_hash.sqlite`.
+pub const RUN_STATE_FILE: &str = "carguix_state.json";
+
+/// Crate packaged by a run, along with the position of its package.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResolvedCrate {
+    pub crate_key: (String, Option<String>),
+    /// Source with the features of every dependent merged.
+    pub source: SourceState,
+    pub position: usize,
+}
+
+/// Progress of a run, from which `--resume` picks up an interrupted run where it left off.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunState {
+    /// Keys of the root crates, telling which invocation the state belongs to.
+    pub roots: BTreeSet<(String, Option<String>)>,
+    /// Sources waiting to be packaged.
+    pub queue: Vec<SourceState>,
+    pub resolved_crates: Vec<ResolvedCrate>,
+    /// Packages generated so far, `None` standing for crates which could not be packaged.
+    pub packages: Vec<Option<guix::Package>>,
+    pub existing_packages: Vec<(CrateRef, ExistingPackage)>,
+    pub graph: DependencyGraph,
+    pub lock: CarguixLock,
+    /// Root sources of the merged projects which are not being packaged yet.
+    #[serde(default)]
+    pub pending_projects: Vec<Vec<SourceState>>,
+    /// Position of the merged project being packaged.
+    #[serde(default)]
+    pub project: usize,
+    #[serde(default)]
+    pub requests: Vec<CrateRequest>,
+}
+
+impl RunState {
+    /// Read the state of an interrupted run, `None` if there is none.
+    pub fn open(path: &Path) -> Result<Option<Self>, CarguixError> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|err| CarguixError::RunStateParsingError(err, path.to_path_buf())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(CarguixError::RunStateIoError(err, path.to_path_buf())),
+        }
+    }
+
+    /// Write the state through a temporary file, so that being interrupted while saving leaves
+    /// the previous state intact.
+    pub fn save(&self, path: &Path) -> Result<(), CarguixError> {
+        let content = serde_json::to_string(self)
+            .map_err(|err| CarguixError::RunStateParsingError(err, path.to_path_buf()))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, content)
+            .and_then(|()| fs::rename(&tmp_path, path))
+            .map_err(|err| CarguixError::RunStateIoError(err, path.to_path_buf()))
+    }
+
+    /// Remove the state of a run which completed, if any.
+    pub fn remove(path: &Path) -> Result<(), CarguixError> {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(CarguixError::RunStateIoError(err, path.to_path_buf()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
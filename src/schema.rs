@@ -0,0 +1,57 @@
+//! Versioning of the JSON documents carguix produces: the module of `--format json` and of the C
+//! interface, the errors of the C interface, the license report and the statistics file.
+//!
+//! Every document carries the version of its schema, which is bumped whenever a field is renamed,
+//! removed or changes meaning. Adding a field keeps the version, so consumers should ignore the
+//! fields they don't know.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// Version of the schema of the JSON documents.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Document preceded by the version of its schema, its fields being serialized alongside
+/// `schema-version`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub document: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(document: T) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            document,
+        }
+    }
+}
+
+/// Failure of a request, along with the chain of its causes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReport {
+    pub error: String,
+    pub causes: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(error: &str, causes: Vec<String>) -> Self {
+        Self {
+            error: error.to_string(),
+            causes,
+        }
+    }
+
+    pub fn from_error(err: &dyn Error) -> Self {
+        let mut causes = Vec::new();
+        let mut cause = err.source();
+        while let Some(err) = cause {
+            causes.push(err.to_string());
+            cause = err.source();
+        }
+        Self::new(&err.to_string(), causes)
+    }
+}
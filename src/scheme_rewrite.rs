@@ -0,0 +1,433 @@
+//! Rewriting of Scheme files in place, such as modules generated by carguix and edited since,
+//! the data being located by their spans so that the comments and layout of the parts left
+//! untouched are kept.
+
+use std::ops::Range;
+
+/// Edits of a Scheme file, applied at once so that the spans they are given by stay valid.
+#[derive(Debug)]
+pub struct Rewriter<'a> {
+    content: &'a str,
+    edits: Vec<(Range<usize>, String)>,
+}
+
+impl<'a> Rewriter<'a> {
+    pub fn new(content: &'a str) -> Self {
+        Self {
+            content,
+            edits: Vec::new(),
+        }
+    }
+
+    pub fn replace(&mut self, span: Range<usize>, text: impl Into<String>) {
+        self.edits.push((span, text.into()));
+    }
+
+    /// Insert text at a position, after the text inserted there before.
+    pub fn insert(&mut self, position: usize, text: impl Into<String>) {
+        self.edits.push((position..position, text.into()));
+    }
+
+    /// Remove a datum along with the whitespace following it, or preceding it on its line when
+    /// it's the last item of a list, so that neither blank lines nor blanks before the closing
+    /// parenthesis pile up.
+    pub fn remove(&mut self, span: Range<usize>) {
+        let after = &self.content[span.end..];
+        let trailing_whitespace = after.len() - after.trim_start().len();
+        let span = if after.trim_start().starts_with([')', ']']) {
+            let before = &self.content[..span.start];
+            let leading_blanks = before.len() - before.trim_end_matches([' ', '\t']).len();
+            span.start - leading_blanks..span.end
+        } else {
+            span.start..span.end + trailing_whitespace
+        };
+        self.edits.push((span, String::new()));
+    }
+
+    /// Content with the edits applied, which mustn't overlap.
+    pub fn finish(mut self) -> String {
+        self.edits.sort_by_key(|(span, _)| (span.start, span.end));
+        let mut rewritten = String::with_capacity(self.content.len());
+        let mut position = 0;
+        for (span, replacement) in self.edits {
+            rewritten.push_str(&self.content[position..span.start]);
+            rewritten.push_str(&replacement);
+            position = span.end;
+        }
+        rewritten.push_str(&self.content[position..]);
+        rewritten
+    }
+}
+
+/// Rewrite `old`, a datum of the content of the rewriter, into `new`, a datum of `new_content`,
+/// only replacing the parts which differ so that the comments and layout of the others are kept.
+///
+/// Lists starting with the same symbol are rewritten item by item. The items of records such as
+/// `(package (name ...) ...)` are matched by the symbol they start with, so that adding or
+/// removing a field leaves the other ones as they are.
+pub fn splice(rewriter: &mut Rewriter, old: &Node, new: &Node, new_content: &str) {
+    let content = rewriter.content;
+    if old.tokens(content) == new.tokens(new_content) {
+        return;
+    }
+    let head = old.head(content);
+    if head.is_none() || head != new.head(new_content) {
+        rewriter.replace(old.span.clone(), new.text(new_content));
+        return;
+    }
+    match (old.fields(content), new.fields(new_content)) {
+        (Some(old_fields), Some(new_fields)) => {
+            let mut previous = &old.items[0];
+            for (name, new_field) in new_fields {
+                match old_fields.iter().find(|(old_name, _)| *old_name == name) {
+                    Some((_, old_field)) => {
+                        splice(rewriter, old_field, new_field, new_content);
+                        previous = old_field;
+                    }
+                    None => insert_after(rewriter, previous, new_field.text(new_content)),
+                }
+            }
+            for (name, old_field) in old_fields {
+                if !new
+                    .items
+                    .iter()
+                    .any(|item| item.head(new_content) == Some(name))
+                {
+                    rewriter.remove(old_field.span.clone());
+                }
+            }
+        }
+        _ if old.items.len() == new.items.len() => {
+            for (old_item, new_item) in old.items.iter().zip(&new.items).skip(1) {
+                splice(rewriter, old_item, new_item, new_content);
+            }
+        }
+        _ => rewriter.replace(old.span.clone(), new.text(new_content)),
+    }
+}
+
+/// Insert an item after another one of a list, on its own line if the other one is, after the
+/// comment ending the line if any.
+fn insert_after(rewriter: &mut Rewriter, previous: &Node, text: &str) {
+    let content = rewriter.content;
+    let line_start = content[..previous.span.start]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let indentation = &content[line_start..previous.span.start];
+    if !indentation.trim().is_empty() {
+        rewriter.insert(previous.span.end, format!(" {}", text));
+        return;
+    }
+    let rest_of_line = &content[previous.span.end..];
+    let line_end = rest_of_line.find('\n').unwrap_or(rest_of_line.len());
+    let position = match rest_of_line[..line_end].trim_start().starts_with(';') {
+        true => previous.span.end + line_end,
+        false => previous.span.end,
+    };
+    rewriter.insert(position, format!("\n{}{}", indentation, text));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    List,
+    Atom,
+    String,
+}
+
+/// Datum of a Scheme file, located by its span in the file so that it can be rewritten in place.
+#[derive(Debug)]
+pub struct Node {
+    pub kind: Kind,
+    pub span: Range<usize>,
+    pub items: Vec<Node>,
+}
+
+impl Node {
+    pub fn text<'a>(&self, content: &'a str) -> &'a str {
+        &content[self.span.clone()]
+    }
+
+    /// Content of a string without escape sequences, which carguix never writes in names and
+    /// versions.
+    pub fn string(&self, content: &str) -> Option<String> {
+        let text = self.text(content);
+        match self.kind {
+            Kind::String if !text.contains('\\') => Some(text[1..text.len() - 1].to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn item(&self, position: usize) -> Option<&Node> {
+        self.items.get(position)
+    }
+
+    /// Symbol a list starts with.
+    pub fn head<'a>(&self, content: &'a str) -> Option<&'a str> {
+        match self.items.first() {
+            Some(head) if head.kind == Kind::Atom => Some(head.text(content)),
+            _ => None,
+        }
+    }
+
+    /// Item of a record such as `(package ...)` which is a list starting with `name`.
+    pub fn field(&self, content: &str, name: &str) -> Option<&Node> {
+        self.items
+            .iter()
+            .find(|item| item.head(content) == Some(name))
+    }
+
+    /// Items of a record such as `(package (name ...) ...)` by the symbol they start with, `None`
+    /// if this list isn't a record.
+    pub fn fields<'a>(&'a self, content: &'a str) -> Option<Vec<(&'a str, &'a Node)>> {
+        let fields = self
+            .items
+            .iter()
+            .skip(1)
+            .map(|item| Some((item.head(content)?, item)))
+            .collect::<Option<Vec<_>>>()?;
+        let mut names = fields.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        match !fields.is_empty() && names.len() == fields.len() {
+            true => Some(fields),
+            false => None,
+        }
+    }
+
+    /// Datum without its comments and layout, to compare data written differently.
+    pub fn tokens(&self, content: &str) -> String {
+        match self.kind {
+            Kind::List => format!(
+                "({})",
+                self.items
+                    .iter()
+                    .map(|item| item.tokens(content))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            _ => self.text(content).to_string(),
+        }
+    }
+
+    /// Call `visit` with this list and every list within it.
+    pub fn visit<'a>(&'a self, visit: &mut impl FnMut(&'a Node)) {
+        if self.kind == Kind::List {
+            visit(self);
+            for item in &self.items {
+                item.visit(visit);
+            }
+        }
+    }
+
+    /// Call `visit` with every atom and string within this datum.
+    pub fn visit_leaves<'a>(&'a self, visit: &mut impl FnMut(&'a Node)) {
+        match self.kind {
+            Kind::List => self.items.iter().for_each(|item| item.visit_leaves(visit)),
+            _ => visit(self),
+        }
+    }
+}
+
+/// Top-level data of a Scheme file, comments and quote characters aside.
+pub fn parse(content: &str) -> Result<Vec<Node>, String> {
+    let bytes = content.as_bytes();
+    let mut stack = vec![Vec::new()];
+    let mut starts = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let start = position;
+        match bytes[position] {
+            b';' => {
+                while position < bytes.len() && bytes[position] != b'\n' {
+                    position += 1;
+                }
+                continue;
+            }
+            b'(' | b'[' => {
+                starts.push(position);
+                stack.push(Vec::new());
+                position += 1;
+                continue;
+            }
+            b')' | b']' => {
+                let items = stack.pop().filter(|_| !stack.is_empty());
+                let (items, start) = match (items, starts.pop()) {
+                    (Some(items), Some(start)) => (items, start),
+                    _ => return Err(format!("unbalanced parenthesis at byte {}", position)),
+                };
+                position += 1;
+                let list = Node {
+                    kind: Kind::List,
+                    span: start..position,
+                    items,
+                };
+                stack.last_mut().expect("lists are nested").push(list);
+                continue;
+            }
+            b'"' => {
+                position += 1;
+                while position < bytes.len() && bytes[position] != b'"' {
+                    position += if bytes[position] == b'\\' { 2 } else { 1 };
+                }
+                if position >= bytes.len() {
+                    return Err(format!("unterminated string at byte {}", start));
+                }
+                position += 1;
+                stack.last_mut().expect("lists are nested").push(Node {
+                    kind: Kind::String,
+                    span: start..position,
+                    items: Vec::new(),
+                });
+                continue;
+            }
+            b'\'' | b'`' | b',' | b'@' => {
+                position += 1;
+                continue;
+            }
+            byte if byte.is_ascii_whitespace() => {
+                position += 1;
+                continue;
+            }
+            _ => (),
+        }
+        // Character literals such as `#\(` end after their first character
+        if content[position..].starts_with("#\\") {
+            position += 3;
+        }
+        while position < bytes.len()
+            && !bytes[position].is_ascii_whitespace()
+            && !b"()[]\";".contains(&bytes[position])
+        {
+            position += 1;
+        }
+        if content[start..].starts_with('#') && bytes.get(position) == Some(&b'(') {
+            // Vector prefix, the vector being read as a list
+            continue;
+        }
+        stack.last_mut().expect("lists are nested").push(Node {
+            kind: Kind::Atom,
+            span: start..position,
+            items: Vec::new(),
+        });
+    }
+    match (stack.pop(), stack.is_empty()) {
+        (Some(forms), true) => Ok(forms),
+        _ => Err("unbalanced parenthesis at end of file".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = r#";;; Generated by carguix, edited since
+(define-module (crates))
+
+;; pinned on purpose
+(define-public rust-foo-1.0.0
+  (package
+    (name "rust-foo")   ; keep the name
+    (version "1.0.0")
+    (build-system cargo-build-system)
+    (arguments
+     `(#:cargo-inputs (("rust-bar" ,rust-bar-0.1.0))))
+    (license license:expat)))
+
+(define-public rust-bar-0.1.0
+  (package
+    (name "rust-bar")
+    (version "0.1.0")  ; not bumped yet
+    (license #f)))
+"#;
+
+    /// `MODULE` with its top-level form at `position` spliced into `new`.
+    fn splice_form(position: usize, new: &str) -> String {
+        let old_forms = parse(MODULE).unwrap();
+        let new_forms = parse(new).unwrap();
+        let mut rewriter = Rewriter::new(MODULE);
+        splice(&mut rewriter, &old_forms[position], &new_forms[0], new);
+        rewriter.finish()
+    }
+
+    #[test]
+    fn splice_keeps_comments_and_layout_outside_the_rewritten_parts() {
+        let rewritten = splice_form(
+            1,
+            r#"(define-public rust-foo-1.0.0 (package (name "rust-foo") (version "1.0.1")
+                (build-system cargo-build-system)
+                (arguments `(#:cargo-inputs (("rust-bar" ,rust-bar-0.1.0))))
+                (license license:expat)))"#,
+        );
+        assert_eq!(
+            rewritten,
+            MODULE.replacen(r#"(version "1.0.0")"#, r#"(version "1.0.1")"#, 1)
+        );
+    }
+
+    #[test]
+    fn splice_matches_record_fields_by_name() {
+        let rewritten = splice_form(
+            2,
+            r#"(define-public rust-bar-0.1.0 (package (name "rust-bar") (version "0.1.0")
+                (synopsis "Bar") (license license:expat)))"#,
+        );
+        let expected = MODULE
+            .replacen(
+                "    (version \"0.1.0\")  ; not bumped yet\n",
+                "    (version \"0.1.0\")  ; not bumped yet\n    (synopsis \"Bar\")\n",
+                1,
+            )
+            .replacen("(license #f)", "(license license:expat)", 1);
+        assert_eq!(rewritten, expected);
+        let rewritten = splice_form(
+            2,
+            r#"(define-public rust-bar-0.1.0 (package (name "rust-bar") (version "0.1.0")))"#,
+        );
+        assert_eq!(rewritten, MODULE.replacen("    (license #f)))", "))", 1));
+    }
+
+    #[test]
+    fn splice_leaves_identical_forms_untouched() {
+        // the same datum written differently, without its comments
+        let rewritten = splice_form(
+            2,
+            r#"(define-public rust-bar-0.1.0
+                 (package (name "rust-bar") (version "0.1.0") (license #f)))"#,
+        );
+        assert_eq!(rewritten, MODULE);
+        let forms = parse(MODULE).unwrap();
+        assert_eq!(Rewriter::new(MODULE).finish(), MODULE);
+        assert_eq!(forms.len(), 3);
+    }
+
+    #[test]
+    fn splice_replaces_forms_whose_head_differs() {
+        let rewritten = splice_form(2, "(define rust-bar #f)");
+        let start = MODULE.find("(define-public rust-bar").unwrap();
+        assert_eq!(
+            rewritten,
+            format!("{}(define rust-bar #f)\n", &MODULE[..start])
+        );
+    }
+
+    #[test]
+    fn parse_locates_data_and_skips_comments() {
+        let forms = parse(MODULE).unwrap();
+        let foo = &forms[1];
+        assert_eq!(foo.head(MODULE), Some("define-public"));
+        let package = foo.item(2).unwrap();
+        let name = package.field(MODULE, "name").unwrap();
+        assert_eq!(
+            name.item(1).unwrap().string(MODULE).as_deref(),
+            Some("rust-foo")
+        );
+        assert_eq!(
+            forms[2].tokens(MODULE),
+            r#"(define-public rust-bar-0.1.0 (package (name "rust-bar") (version "0.1.0") (license #f)))"#
+        );
+        assert!(parse(r#"(a "b) c"#).is_err());
+        assert!(parse("(a (b)").is_err());
+        assert!(parse("a)").is_err());
+        assert_eq!(parse(r"(#\( x)").unwrap()[0].items.len(), 2);
+    }
+}
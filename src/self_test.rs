@@ -0,0 +1,295 @@
+//! Check of the installation of carguix: a local crate depending on a crate of an embedded
+//! registry is packaged end to end, without touching the network nor the files of the user.
+//!
+//! The registry, a sparse index listing a single crate and the tarball of this crate, is served on
+//! the loopback interface for the duration of the test. Each step of the pipeline is reported as a
+//! [`Check`], the Guix one being skipped when `guix` isn't installed.
+
+use crate::{
+    errors::CarguixError,
+    features::Features,
+    generator::{Generator, Options},
+    guix::{self, Origin},
+    index, nix_hash, scheme_rewrite,
+    tooling::{SystemTooling, Tooling},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    thread,
+    time::Duration,
+};
+use tempdir::TempDir;
+
+const FIXTURE_NAME: &str = "self-test-fixture";
+const FIXTURE_VERSION: &str = "1.0.0";
+/// Tarball of the fixture crate, as `cargo package` would produce it.
+const FIXTURE_TARBALL: &[u8] = include_bytes!("../fixtures/self-test-fixture-1.0.0.crate");
+/// Name of the embedded registry in the cargo configuration of the test project.
+const REGISTRY: &str = "carguix-self-test";
+
+/// Outcome of a step of the self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed(String),
+    /// Step which couldn't run in this environment, with the reason why.
+    Skipped(String),
+    Failed(String),
+}
+
+/// Step of the self-test along with its outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub step: &'static str,
+    pub outcome: Outcome,
+}
+
+impl Check {
+    fn new(step: &'static str, outcome: Outcome) -> Self {
+        Self { step, outcome }
+    }
+
+    pub fn failed(&self) -> bool {
+        matches!(self.outcome, Outcome::Failed(_))
+    }
+}
+
+/// Package the fixture project and check every step of the pipeline.
+///
+/// The hash database and the run state are kept in a temporary data directory, so that the ones
+/// of the user are left untouched. Errors are reserved to the setup of the test, failing steps
+/// being reported by the checks.
+pub fn run() -> Result<Vec<Check>, CarguixError> {
+    let workdir = TempDir::new("carguix-self-test").map_err(CarguixError::TmpdirError)?;
+    let address = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| Ok((listener.local_addr()?, listener)));
+    let (address, listener) = address.map_err(CarguixError::SelfTestRegistryError)?;
+    let base_url = format!("http://{}", address);
+    let checksum = format!("{:x}", Sha256::digest(FIXTURE_TARBALL));
+    let expected_hash = nix_hash::nix_base32(&Sha256::digest(FIXTURE_TARBALL));
+    let files = registry_files(&base_url, &checksum);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(err) = respond(&files, stream) {
+                log::warn!("could not answer a self-test request: {}", err);
+            }
+        }
+    });
+    let project = workdir.path().join("self-test-app");
+    write_project(&project, &base_url)
+        .map_err(|err| CarguixError::InvalidPath(err, project.clone()))?;
+    Ok(run_checks(&project, workdir.path(), &expected_hash))
+}
+
+fn run_checks(project: &Path, data_dir: &Path, expected_hash: &str) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let options = Options {
+        assume_yes: true,
+        sparse_index: true,
+        cargo_config_dirs: vec![project.to_path_buf()],
+        data_dir: Some(data_dir.to_path_buf()),
+        jobs: 1,
+        download_retries: 0,
+        ..Options::default()
+    };
+    let packages = Generator::from_path(project, &Features::default())
+        .and_then(|generator| generator.with_options(options))
+        .and_then(|mut generator| generator.packages().into_iter().collect());
+    let packages: Vec<guix::Package> = match packages {
+        Ok(packages) => packages,
+        Err(err) => {
+            checks.push(Check::new("index", Outcome::Failed(error_chain(&err))));
+            return checks;
+        }
+    };
+    let fixture = packages
+        .iter()
+        .find(|package| package.crate_ref.name == FIXTURE_NAME);
+    let fixture = match fixture {
+        Some(fixture) if fixture.crate_ref.version == FIXTURE_VERSION => {
+            checks.push(Check::new(
+                "index",
+                Outcome::Passed(format!(
+                    "{} resolved to version {} from the sparse index",
+                    FIXTURE_NAME, FIXTURE_VERSION
+                )),
+            ));
+            fixture
+        }
+        Some(fixture) => {
+            checks.push(Check::new(
+                "index",
+                Outcome::Failed(format!(
+                    "{} resolved to version {} instead of {}",
+                    FIXTURE_NAME, fixture.crate_ref.version, FIXTURE_VERSION
+                )),
+            ));
+            return checks;
+        }
+        None => {
+            checks.push(Check::new(
+                "index",
+                Outcome::Failed(format!("{} wasn't packaged", FIXTURE_NAME)),
+            ));
+            return checks;
+        }
+    };
+    checks.push(Check::new(
+        "hashing",
+        match &fixture.origin {
+            Origin::Crate {
+                hash: Some(hash), ..
+            } if hash == expected_hash => {
+                Outcome::Passed(format!("tarball downloaded and hashed to {}", hash))
+            }
+            Origin::Crate {
+                hash: Some(hash), ..
+            } => Outcome::Failed(format!(
+                "tarball hashed to {} instead of {}",
+                hash, expected_hash
+            )),
+            _ => Outcome::Failed("tarball wasn't hashed".to_string()),
+        },
+    ));
+    let module = guix::Module::new(vec!["self-test".to_string()], packages.clone(), None, false);
+    let scheme = module.to_scheme();
+    let defined = guix::defined_variables(&scheme);
+    let missing = packages
+        .iter()
+        .map(guix::Package::variable)
+        .filter(|variable| !defined.contains(variable))
+        .collect::<Vec<_>>();
+    checks.push(Check::new(
+        "rendering",
+        match scheme_rewrite::parse(&scheme) {
+            Err(err) => Outcome::Failed(format!("module isn't valid Scheme: {}", err)),
+            Ok(_) if !missing.is_empty() => {
+                Outcome::Failed(format!("module doesn't define {}", missing.join(", ")))
+            }
+            Ok(forms) => Outcome::Passed(format!(
+                "module of {} packages rendered to {} forms",
+                packages.len(),
+                forms.len()
+            )),
+        },
+    ));
+    checks.push(Check::new("guix", guix_check(data_dir, expected_hash)));
+    checks
+}
+
+/// Whether `guix hash` agrees with the native hashing of carguix.
+fn guix_check(data_dir: &Path, expected_hash: &str) -> Outcome {
+    let tarball = data_dir.join("self-test-fixture-1.0.0.crate");
+    if let Err(err) = fs::write(&tarball, FIXTURE_TARBALL) {
+        return Outcome::Failed(format!("could not write the fixture tarball: {}", err));
+    }
+    match SystemTooling.guix_hash(&tarball) {
+        Err(err) => Outcome::Skipped(format!("guix hash couldn't be run: {}", err)),
+        Ok(hash) if hash == expected_hash => {
+            Outcome::Passed(format!("guix hash agrees on {}", hash))
+        }
+        Ok(hash) => Outcome::Failed(format!(
+            "guix hash printed {} instead of {}",
+            hash, expected_hash
+        )),
+    }
+}
+
+/// Local crate depending on the fixture crate of the embedded registry.
+fn write_project(project: &Path, base_url: &str) -> io::Result<()> {
+    fs::create_dir_all(project.join("src"))?;
+    fs::create_dir_all(project.join(".cargo"))?;
+    fs::write(
+        project.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"self-test-app\"\nversion = \"0.1.0\"\nedition = \"2018\"\nlicense = \"MIT\"\n\n\
+             [dependencies]\n{} = {{ version = \"1\", registry = \"{}\" }}\n",
+            FIXTURE_NAME, REGISTRY
+        ),
+    )?;
+    fs::write(
+        project.join("src/lib.rs"),
+        "pub use self_test_fixture::*;\n",
+    )?;
+    fs::write(
+        project.join(".cargo/config.toml"),
+        format!(
+            "[registries.{}]\nindex = \"sparse+{}/index/\"\n",
+            REGISTRY, base_url
+        ),
+    )
+}
+
+/// Files of the embedded registry by URL path: its configuration, the index file of the fixture
+/// crate and the tarball of the crate.
+fn registry_files(base_url: &str, checksum: &str) -> HashMap<String, Vec<u8>> {
+    let index_line = serde_json::json!({
+        "name": FIXTURE_NAME,
+        "vers": FIXTURE_VERSION,
+        "deps": [],
+        "cksum": checksum,
+        "features": {},
+        "yanked": false,
+    });
+    let mut files = HashMap::new();
+    files.insert(
+        "/index/config.json".to_string(),
+        serde_json::json!({ "dl": format!("{}/crates", base_url) })
+            .to_string()
+            .into_bytes(),
+    );
+    files.insert(
+        format!(
+            "/index/{}",
+            index::index_file_path(FIXTURE_NAME).to_string_lossy()
+        ),
+        format!("{}\n", index_line).into_bytes(),
+    );
+    files.insert(
+        format!("/crates/{}/{}/download", FIXTURE_NAME, FIXTURE_VERSION),
+        FIXTURE_TARBALL.to_vec(),
+    );
+    files
+}
+
+/// Answer a single `GET` request with one of `files`, the connection being closed afterwards.
+fn respond(files: &HashMap<String, Vec<u8>>, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match files.get(path) {
+        Some(body) => ("200 OK", body.as_slice()),
+        None => ("404 Not Found", &b""[..]),
+    };
+    log::debug!("{} {}", request_line.trim(), status);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Error along with its causes, on a single line.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut cause = err.source();
+    while let Some(err) = cause {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        cause = err.source();
+    }
+    message
+}
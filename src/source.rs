@@ -0,0 +1,133 @@
+use crate::{
+    errors::CarguixError,
+    features::Features,
+    guix,
+    path_source::{GitCheckout, ManifestCache, PathSource},
+    registry_source::RegistrySource,
+    Generator,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
+
+/// Section of the manifest a dependency is declared in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyKind {
+    Normal,
+    Build,
+    Development,
+}
+
+impl DependencyKind {
+    /// Kind as written in the `kind` field of index entries.
+    pub fn from_index(kind: Option<&str>) -> Self {
+        match kind {
+            Some("dev") => DependencyKind::Development,
+            Some("build") => DependencyKind::Build,
+            _ => DependencyKind::Normal,
+        }
+    }
+
+    /// Name of the manifest section, without its `-dependencies` suffix.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyKind::Normal => "normal",
+            DependencyKind::Build => "build",
+            DependencyKind::Development => "dev",
+        }
+    }
+}
+
+/// Description a source can be created again from, persisted so that an interrupted run can be
+/// resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum SourceState {
+    Registry {
+        name: String,
+        version: Option<String>,
+        features: Features,
+        registry: Option<String>,
+    },
+    Path {
+        path: PathBuf,
+        /// Root of the workspace the crate is a member of.
+        workspace: Option<PathBuf>,
+        features: Features,
+        #[serde(default)]
+        git: Option<GitCheckout>,
+    },
+}
+
+impl SourceState {
+    pub fn features(&self) -> &Features {
+        match self {
+            SourceState::Registry { features, .. } | SourceState::Path { features, .. } => features,
+        }
+    }
+
+    pub fn merge_features(&mut self, other: &Features) {
+        match self {
+            SourceState::Registry { features, .. } | SourceState::Path { features, .. } => {
+                features.merge(other)
+            }
+        }
+    }
+
+    /// Source described by the state, the manifests and workspaces read so far being shared
+    /// with the other sources.
+    pub fn into_source(
+        self,
+        manifests: &mut ManifestCache,
+    ) -> Result<Box<dyn Source>, CarguixError> {
+        match self {
+            SourceState::Registry {
+                name,
+                version,
+                features,
+                registry,
+            } => Ok(Box::new(
+                RegistrySource::new(&name, &version, &features).with_registry(registry),
+            )),
+            SourceState::Path {
+                path,
+                workspace,
+                features,
+                git,
+            } => {
+                let workspace = workspace
+                    .map(|root| manifests.workspace(&root))
+                    .transpose()?
+                    .flatten();
+                Ok(Box::new(
+                    PathSource::cached(Path::new(&path), workspace, &features, manifests)?
+                        .with_git(git),
+                ))
+            }
+        }
+    }
+}
+
+/// Something carguix knows how to turn into a Guix package.
+pub trait Source: Debug {
+    /// Name and optional version used to avoid packaging the same crate twice.
+    fn crate_key(&self) -> (String, Option<String>);
+
+    /// Description the source can be created again from.
+    fn state(&self) -> SourceState;
+
+    /// Features the crate must be built with.
+    fn features(&self) -> &Features;
+
+    /// Enable the features of `features` in addition to the ones already selected.
+    fn merge_features(&mut self, features: &Features);
+
+    /// Build the package definition along with the sources of its dependencies.
+    fn resolve(
+        &self,
+        generator: &mut Generator,
+    ) -> Result<(guix::Package, Vec<Box<dyn Source>>), CarguixError>;
+}
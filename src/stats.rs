@@ -0,0 +1,148 @@
+use crate::{errors::CarguixError, guix, schema::SCHEMA_VERSION};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where the hashes of the crates of a run came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Hashes pinned in the lock file.
+    pub pinned: usize,
+    /// Hashes converted from the checksums of `Cargo.lock` files.
+    #[serde(default)]
+    pub checksummed: usize,
+    /// Hashes found in the hash database.
+    pub cached: usize,
+    /// Hashes fetched from the hash mirror.
+    #[serde(default)]
+    pub mirrored: usize,
+    /// Hashes computed from the tarballs of the tarball directory.
+    #[serde(default)]
+    pub stored: usize,
+    /// Crates downloaded to be hashed.
+    pub downloaded: usize,
+}
+
+impl CacheStats {
+    /// Share of the hashes obtained without downloading anything.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let total = self.pinned
+            + self.checksummed
+            + self.cached
+            + self.mirrored
+            + self.stored
+            + self.downloaded;
+        if total == 0 {
+            return None;
+        }
+        Some((total - self.downloaded) as f64 / total as f64)
+    }
+}
+
+/// Composition of the closure generated by a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunStats {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_commit: Option<String>,
+    pub packages: usize,
+    /// Crates referenced from Guix instead of being defined.
+    pub existing_packages: usize,
+    /// Package counts by SPDX license expression, `unknown` when the crate declares none.
+    pub licenses: BTreeMap<String, usize>,
+    /// Versions of the crates packaged in more than one version.
+    pub duplicate_versions: BTreeMap<String, Vec<String>>,
+    /// Local crates whose manifest sets `publish = false`, packaged from their sources only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unpublished: Vec<String>,
+    pub cache: CacheStats,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_hit_ratio: Option<f64>,
+}
+
+impl RunStats {
+    pub fn new(
+        packages: &[guix::Package],
+        existing_packages: usize,
+        cache: CacheStats,
+        index_commit: Option<String>,
+    ) -> Self {
+        let mut licenses = BTreeMap::new();
+        let mut versions = BTreeMap::<_, Vec<_>>::new();
+        for package in packages {
+            let license = package.license.as_deref().unwrap_or("unknown");
+            *licenses.entry(license.to_string()).or_default() += 1;
+            versions
+                .entry(package.crate_ref.name.clone())
+                .or_default()
+                .push(package.crate_ref.version.clone());
+        }
+        let duplicate_versions = versions
+            .into_iter()
+            .filter(|(_, versions)| versions.len() > 1)
+            .map(|(name, mut versions)| {
+                versions.sort();
+                (name, versions)
+            })
+            .collect();
+        let unpublished = packages
+            .iter()
+            .filter(|package| package.unpublished)
+            .map(guix::Package::variable)
+            .collect();
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            index_commit,
+            packages: packages.len(),
+            existing_packages,
+            licenses,
+            duplicate_versions,
+            unpublished,
+            cache_hit_ratio: cache.hit_ratio(),
+            cache,
+        }
+    }
+}
+
+/// Statistics file, accumulating one entry per run so that the growth of a closure can be
+/// followed over time. Nothing is ever sent anywhere.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Version of the schema the file was last written with, `0` for files predating it.
+    #[serde(default)]
+    pub schema_version: u32,
+    #[serde(default)]
+    pub runs: Vec<RunStats>,
+}
+
+impl Stats {
+    /// Read a statistics file, a missing file holding no runs.
+    pub fn open(path: &Path) -> Result<Self, CarguixError> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|err| CarguixError::StatsParsingError(err, path.to_path_buf())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CarguixError::StatsIoError(err, path.to_path_buf())),
+        }
+    }
+
+    /// Append the statistics of a run to the file.
+    pub fn append(path: &Path, run: RunStats) -> Result<(), CarguixError> {
+        let mut stats = Self::open(path)?;
+        stats.runs.push(run);
+        stats.schema_version = SCHEMA_VERSION;
+        let content = serde_json::to_string_pretty(&stats)
+            .map_err(|err| CarguixError::StatsParsingError(err, path.to_path_buf()))?;
+        fs::write(path, content + "\n")
+            .map_err(|err| CarguixError::StatsIoError(err, path.to_path_buf()))
+    }
+}
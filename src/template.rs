@@ -0,0 +1,151 @@
+//! Mustache templates the Scheme output is rendered with.
+//!
+//! Scheme forms are given to the templates rendered already, to be written with triple mustaches
+//! such as `{{{variable}}}` since double ones escape HTML characters.
+
+use crate::errors::CarguixError;
+use mustache::Template;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Template of a module file, given its header, the commit of the index and the definitions of
+/// its packages.
+pub const DEFAULT_MODULE_TEMPLATE: &str = include_str!("../templates/module.scm.mustache");
+/// Template of the definitions of a package, given its variable and the fields of its form.
+pub const DEFAULT_PACKAGE_TEMPLATE: &str = include_str!("../templates/package.scm.mustache");
+
+/// Values of the package template.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageData {
+    /// Variable the package is bound to, e.g. `rust-serde-1.0.188`.
+    pub variable: String,
+    /// Procedure returning the package built with a given rust compiler, when `parameterized`.
+    pub procedure: String,
+    /// Package name, e.g. `rust-serde`.
+    pub name: String,
+    pub crate_name: String,
+    pub version: String,
+    /// Define the package as a procedure parameterized over the rust compiler.
+    pub parameterized: bool,
+    /// Fields of the `package` form in order.
+    pub fields: Vec<FieldData>,
+    /// Values of the same fields by name, e.g. `{{{field.build-system}}}`.
+    pub field: BTreeMap<String, String>,
+    pub cargo_inputs: Vec<InputData>,
+    pub cargo_development_inputs: Vec<InputData>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldData {
+    pub name: String,
+    /// Value on a single line.
+    pub value: String,
+    /// Whole `(name value)` field laid out on several lines when it's too long, for the column
+    /// the default template puts the fields at.
+    pub form: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputData {
+    /// Variable of the input package, generated or defined in Guix.
+    pub variable: String,
+    /// Entry of the input list, e.g. `(list "rust-serde-1.0.188" rust-serde-1.0.188)`.
+    pub input: String,
+    /// Requirements the input was resolved from, when recorded.
+    pub requirements: Option<String>,
+}
+
+/// Values of the module template.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModuleData {
+    /// `define-module` form.
+    pub header: String,
+    /// Module name, e.g. `my channel rust-crates`.
+    pub name: String,
+    pub index_commit: Option<String>,
+    pub packages: Vec<ModulePackageData>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModulePackageData {
+    pub variable: String,
+    pub name: String,
+    pub version: String,
+    /// Package definitions rendered with the package template.
+    pub definitions: String,
+}
+
+/// Templates of module files and package definitions.
+#[derive(Debug, Clone)]
+pub struct Templates {
+    module: Template,
+    package: Template,
+    /// File the package template was read from, `None` for the default one.
+    package_path: Option<PathBuf>,
+}
+
+impl Templates {
+    /// Templates read from the given files, the default ones standing for the missing files.
+    pub fn open(module: Option<&Path>, package: Option<&Path>) -> Result<Self, CarguixError> {
+        let compile = |path: Option<&Path>, default| match path {
+            Some(path) => mustache::compile_path(path)
+                .map_err(|err| CarguixError::TemplateError(err, path.to_path_buf())),
+            None => Ok(compile_default(default)),
+        };
+        Ok(Self {
+            module: compile(module, DEFAULT_MODULE_TEMPLATE)?,
+            package: compile(package, DEFAULT_PACKAGE_TEMPLATE)?,
+            package_path: package.map(Path::to_path_buf),
+        })
+    }
+
+    pub fn render_module(&self, data: &ModuleData) -> String {
+        render(&self.module, data)
+    }
+
+    /// [`Self::render_module`] written to `writer` as it is rendered, rather than gathered in a
+    /// string first.
+    pub fn write_module(&self, data: &ModuleData, mut writer: &mut dyn Write) -> io::Result<()> {
+        match self.module.render(&mut writer, data) {
+            Ok(()) => Ok(()),
+            Err(mustache::Error::Io(err)) => Err(err),
+            Err(err) => panic!("template data serializes to mustache data: {}", err),
+        }
+    }
+
+    pub fn render_package(&self, data: &PackageData) -> String {
+        render(&self.package, data)
+    }
+
+    /// Name of the package template in messages, its path or `default` for the default one.
+    pub fn package_template_name(&self) -> String {
+        match &self.package_path {
+            Some(path) => format!("{:?}", path),
+            None => "default".to_string(),
+        }
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            module: compile_default(DEFAULT_MODULE_TEMPLATE),
+            package: compile_default(DEFAULT_PACKAGE_TEMPLATE),
+            package_path: None,
+        }
+    }
+}
+
+fn compile_default(template: &str) -> Template {
+    mustache::compile_str(template).expect("default templates are valid")
+}
+
+fn render(template: &Template, data: &impl Serialize) -> String {
+    template
+        .render_to_string(data)
+        .expect("template data serializes to mustache data and strings accept any write")
+}
@@ -0,0 +1,576 @@
+//! External tools carguix relies on, `guix` and git, behind the [`Tooling`] trait so that they
+//! can be replaced by [`MockTooling`] when exercising the code paths using them, or by other
+//! backends such as the Guix daemon protocol or another git implementation.
+
+use git2::{build::CheckoutBuilder, Oid, Repository, ResetType};
+use shellfn::shell;
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Invocations of the external tools.
+pub trait Tooling: fmt::Debug + Send + Sync {
+    /// Hash of a file as printed by `guix hash`.
+    fn guix_hash(&self, path: &Path) -> io::Result<String>;
+
+    /// Version of the installed Guix, e.g. `1.4.0`.
+    fn guix_version(&self) -> io::Result<String>;
+
+    /// Clone the git repository at `url` into `dir` and return the commit `revision`, as
+    /// understood by `git rev-parse`, points at.
+    fn git_clone(&self, url: &str, revision: &str, dir: &Path) -> io::Result<String>;
+
+    /// Write the tree of `commit` of the repository cloned into `dir` to `target_dir`.
+    fn git_checkout(&self, dir: &Path, commit: &str, target_dir: &Path) -> io::Result<()>;
+
+    /// Commit the repository cloned into `dir` is checked out at.
+    fn git_head(&self, dir: &Path) -> io::Result<String>;
+
+    /// Fetch `branch` from the `origin` remote of the repository cloned into `dir`, or from `url`
+    /// if it has none, and return the commit fetched.
+    fn git_fetch(&self, dir: &Path, url: &str, branch: &str) -> io::Result<String>;
+
+    /// Whether `commit` of the repository cloned into `dir` descends from `ancestor`.
+    fn git_descends_from(&self, dir: &Path, commit: &str, ancestor: &str) -> io::Result<bool>;
+
+    /// Check `commit` out in `dir`, discarding local changes like `git reset --hard`.
+    fn git_reset(&self, dir: &Path, commit: &str) -> io::Result<()>;
+}
+
+/// Tooling running the `guix` command and cloning repositories with libgit2.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTooling;
+
+impl Tooling for SystemTooling {
+    fn guix_hash(&self, path: &Path) -> io::Result<String> {
+        #[shell]
+        fn guix_hash_(file_path: &str) -> Result<String, shellfn::Error<std::convert::Infallible>> {
+            "guix hash $FILE_PATH"
+        }
+        Ok(guix_hash_(&path.to_string_lossy())
+            .map_err(|err| io::Error::other(err.to_string()))?
+            .trim()
+            .to_string())
+    }
+
+    fn guix_version(&self) -> io::Result<String> {
+        #[shell]
+        fn guix_version_() -> Result<String, shellfn::Error<std::convert::Infallible>> {
+            "guix --version"
+        }
+        let output = guix_version_().map_err(|err| io::Error::other(err.to_string()))?;
+        // e.g. `guix (GNU Guix) 1.4.0`
+        let first_line = output.lines().next().unwrap_or_default();
+        Ok(first_line
+            .rsplit(' ')
+            .next()
+            .unwrap_or(first_line)
+            .to_string())
+    }
+
+    fn git_clone(&self, url: &str, revision: &str, dir: &Path) -> io::Result<String> {
+        let repository = Repository::clone(url, dir).map_err(io::Error::other)?;
+        let commit = repository
+            .revparse_single(revision)
+            .and_then(|object| object.peel_to_commit())
+            .map_err(io::Error::other)?;
+        Ok(commit.id().to_string())
+    }
+
+    fn git_checkout(&self, dir: &Path, commit: &str, target_dir: &Path) -> io::Result<()> {
+        let repository = Repository::open(dir).map_err(io::Error::other)?;
+        let commit = Oid::from_str(commit)
+            .and_then(|oid| repository.find_commit(oid))
+            .map_err(io::Error::other)?;
+        repository
+            .checkout_tree(
+                commit.as_object(),
+                Some(CheckoutBuilder::new().force().target_dir(target_dir)),
+            )
+            .map_err(io::Error::other)
+    }
+
+    fn git_head(&self, dir: &Path) -> io::Result<String> {
+        let repository = Repository::open(dir).map_err(io::Error::other)?;
+        let head = repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(io::Error::other)?;
+        Ok(head.id().to_string())
+    }
+
+    fn git_fetch(&self, dir: &Path, url: &str, branch: &str) -> io::Result<String> {
+        let repository = Repository::open(dir).map_err(io::Error::other)?;
+        let mut origin = repository
+            .find_remote("origin")
+            .or_else(|_| repository.remote_anonymous(url))
+            .map_err(io::Error::other)?;
+        origin
+            .fetch(&[branch], None, None)
+            .map_err(io::Error::other)?;
+        let fetched = repository
+            .refname_to_id("FETCH_HEAD")
+            .map_err(io::Error::other)?;
+        Ok(fetched.to_string())
+    }
+
+    fn git_descends_from(&self, dir: &Path, commit: &str, ancestor: &str) -> io::Result<bool> {
+        let repository = Repository::open(dir).map_err(io::Error::other)?;
+        let commit = Oid::from_str(commit).map_err(io::Error::other)?;
+        let ancestor = Oid::from_str(ancestor).map_err(io::Error::other)?;
+        repository
+            .graph_descendant_of(commit, ancestor)
+            .map_err(io::Error::other)
+    }
+
+    fn git_reset(&self, dir: &Path, commit: &str) -> io::Result<()> {
+        let repository = Repository::open(dir).map_err(io::Error::other)?;
+        let object = Oid::from_str(commit)
+            .and_then(|oid| repository.find_object(oid, None))
+            .map_err(io::Error::other)?;
+        repository
+            .reset(&object, ResetType::Hard, None)
+            .map_err(io::Error::other)
+    }
+}
+
+/// Repository [`MockTooling`] serves in place of a git remote.
+#[derive(Debug, Clone, Default)]
+pub struct MockRepository {
+    /// Directory whose content every commit checks out.
+    pub tree: PathBuf,
+    /// Commits of the revisions of the repository, such as `HEAD` or `master`.
+    pub revisions: HashMap<String, String>,
+    /// Parent of the commits which have one.
+    pub parents: HashMap<String, String>,
+}
+
+/// Repository cloned by [`MockTooling`].
+#[derive(Debug, Clone)]
+struct MockClone {
+    url: String,
+    head: String,
+}
+
+/// Tooling answering from canned results without running anything, and recording what it was
+/// asked, e.g. `guix hash /tmp/serde-1.0.0.tar.gz`. Anything it has no result for fails with
+/// [`io::ErrorKind::NotFound`], as if the tool were missing.
+#[derive(Debug, Default)]
+pub struct MockTooling {
+    /// Hashes `guix hash` prints, by file name.
+    pub guix_hashes: HashMap<String, String>,
+    pub guix_version: Option<String>,
+    /// Repositories, by URL.
+    pub repositories: HashMap<String, MockRepository>,
+    /// Repositories cloned so far, by clone directory.
+    clones: Mutex<HashMap<PathBuf, MockClone>>,
+    invocations: Mutex<Vec<String>>,
+}
+
+impl MockTooling {
+    /// Invocations so far, in order.
+    pub fn invocations(&self) -> Vec<String> {
+        self.invocations
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    fn record(&self, invocation: String) {
+        self.invocations
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(invocation);
+    }
+
+    /// Repository cloned into `dir`, along with the clone.
+    fn clone_in(&self, dir: &Path) -> io::Result<(&MockRepository, MockClone)> {
+        let clone = self
+            .clones
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(dir)
+            .cloned()
+            .ok_or_else(|| not_found(format!("no clone in {:?}", dir)))?;
+        Ok((&self.repositories[&clone.url], clone))
+    }
+
+    /// Fail unless `commit` is one of the revisions of `repository` or one of their ancestors.
+    fn known_commit(repository: &MockRepository, url: &str, commit: &str) -> io::Result<()> {
+        let known = repository.revisions.values().any(|known| known == commit)
+            || repository.parents.contains_key(commit)
+            || repository.parents.values().any(|parent| parent == commit);
+        match known {
+            true => Ok(()),
+            false => Err(not_found(format!("no commit {} in {}", commit, url))),
+        }
+    }
+}
+
+fn not_found(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, what)
+}
+
+impl Tooling for MockTooling {
+    fn guix_hash(&self, path: &Path) -> io::Result<String> {
+        self.record(format!("guix hash {}", path.display()));
+        path.file_name()
+            .and_then(|file_name| self.guix_hashes.get(&*file_name.to_string_lossy()))
+            .cloned()
+            .ok_or_else(|| not_found(format!("no hash of {:?}", path)))
+    }
+
+    fn guix_version(&self) -> io::Result<String> {
+        self.record("guix --version".to_string());
+        self.guix_version
+            .clone()
+            .ok_or_else(|| not_found("no guix version".to_string()))
+    }
+
+    fn git_clone(&self, url: &str, revision: &str, dir: &Path) -> io::Result<String> {
+        self.record(format!("git clone {} {}", url, dir.display()));
+        let repository = self
+            .repositories
+            .get(url)
+            .ok_or_else(|| not_found(format!("no repository at {}", url)))?;
+        let commit = repository
+            .revisions
+            .get(revision)
+            .ok_or_else(|| not_found(format!("no revision {} in {}", revision, url)))?;
+        copy_dir(&repository.tree, dir)?;
+        let clone = MockClone {
+            url: url.to_string(),
+            head: commit.clone(),
+        };
+        self.clones
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(dir.to_path_buf(), clone);
+        Ok(commit.clone())
+    }
+
+    fn git_checkout(&self, dir: &Path, commit: &str, target_dir: &Path) -> io::Result<()> {
+        self.record(format!(
+            "git checkout {} {} {}",
+            dir.display(),
+            commit,
+            target_dir.display()
+        ));
+        let (repository, clone) = self.clone_in(dir)?;
+        Self::known_commit(repository, &clone.url, commit)?;
+        copy_dir(&repository.tree, target_dir)
+    }
+
+    fn git_head(&self, dir: &Path) -> io::Result<String> {
+        self.record(format!("git rev-parse HEAD {}", dir.display()));
+        Ok(self.clone_in(dir)?.1.head)
+    }
+
+    fn git_fetch(&self, dir: &Path, _url: &str, branch: &str) -> io::Result<String> {
+        self.record(format!("git fetch {} {}", dir.display(), branch));
+        let (repository, clone) = self.clone_in(dir)?;
+        repository
+            .revisions
+            .get(branch)
+            .cloned()
+            .ok_or_else(|| not_found(format!("no branch {} in {}", branch, clone.url)))
+    }
+
+    fn git_descends_from(&self, dir: &Path, commit: &str, ancestor: &str) -> io::Result<bool> {
+        self.record(format!(
+            "git merge-base --is-ancestor {} {}",
+            ancestor, commit
+        ));
+        let (repository, clone) = self.clone_in(dir)?;
+        Self::known_commit(repository, &clone.url, commit)?;
+        let mut commit = repository.parents.get(commit);
+        while let Some(parent) = commit {
+            if parent == ancestor {
+                return Ok(true);
+            }
+            commit = repository.parents.get(parent);
+        }
+        Ok(false)
+    }
+
+    fn git_reset(&self, dir: &Path, commit: &str) -> io::Result<()> {
+        self.record(format!("git reset --hard {} {}", dir.display(), commit));
+        let (repository, clone) = self.clone_in(dir)?;
+        Self::known_commit(repository, &clone.url, commit)?;
+        copy_dir(&repository.tree, dir)?;
+        if let Some(clone) = self
+            .clones
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get_mut(dir)
+        {
+            clone.head = commit.to_string();
+        }
+        Ok(())
+    }
+}
+
+/// Copy the files below `source` to `target`, created if missing.
+fn copy_dir(source: &Path, target: &Path) -> io::Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Tooling shared by the clones of the options of a run, [`SystemTooling`] by default.
+#[derive(Debug, Clone)]
+pub struct SharedTooling(Arc<dyn Tooling>);
+
+impl SharedTooling {
+    /// Share `tooling`, of which the caller may keep a handle, e.g. to check the invocations of
+    /// a [`MockTooling`].
+    pub fn new(tooling: Arc<dyn Tooling>) -> Self {
+        Self(tooling)
+    }
+}
+
+impl Default for SharedTooling {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemTooling))
+    }
+}
+
+impl Deref for SharedTooling {
+    type Target = dyn Tooling;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        features::Features,
+        generator::{Generator, Options},
+        guix::Origin,
+        index::INDEX_GIT_URL,
+    };
+    use sha2::{Digest, Sha256};
+    use tempdir::TempDir;
+
+    const REPOSITORY_URL: &str = "https://example.org/hello.git";
+    const COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+    const INDEX_COMMIT: &str = "89abcdef0123456789abcdef0123456789abcdef";
+    const GUIX_HASH: &str = "0d7gm4j4hbmkr5lbc2dnbwpbzm7zmjkqbrcnhyij3a4m0c4n8yfv";
+
+    fn write(path: &Path, content: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Options of a run in `dir` with `tooling`, leaving out the enrichers reaching crates.io.
+    fn options(dir: &Path, tooling: &Arc<MockTooling>) -> Options {
+        Options {
+            data_dir: Some(dir.join("data")),
+            enrichers: Some(Vec::new()),
+            cargo_config_dirs: vec![dir.to_path_buf()],
+            tooling: SharedTooling::new(tooling.clone()),
+            ..Options::default()
+        }
+    }
+
+    fn index_repository(dir: &Path) -> MockRepository {
+        let tree = dir.join("index");
+        write(&tree.join("config.json"), b"{}");
+        MockRepository {
+            tree,
+            revisions: vec![("HEAD".to_string(), INDEX_COMMIT.to_string())]
+                .into_iter()
+                .collect(),
+            parents: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn from_git_clones_and_checks_out_through_the_tooling() {
+        let dir = TempDir::new("carguix-tooling").unwrap();
+        let tree = dir.path().join("hello");
+        write(
+            &tree.join("Cargo.toml"),
+            b"[package]\nname = \"hello\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        );
+        write(&tree.join("src/lib.rs"), b"");
+        let repository = MockRepository {
+            tree,
+            revisions: vec![("main".to_string(), COMMIT.to_string())]
+                .into_iter()
+                .collect(),
+            parents: HashMap::new(),
+        };
+        let tooling = Arc::new(MockTooling {
+            repositories: vec![
+                (REPOSITORY_URL.to_string(), repository),
+                (INDEX_GIT_URL.to_string(), index_repository(dir.path())),
+            ]
+            .into_iter()
+            .collect(),
+            ..MockTooling::default()
+        });
+        let mut generator = Generator::from_git(REPOSITORY_URL, "main", None, &Features::default())
+            .unwrap()
+            .with_options(options(dir.path(), &tooling))
+            .unwrap();
+        let packages = generator
+            .packages()
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].crate_ref.name, "hello");
+        match &packages[0].origin {
+            Origin::Git { url, commit, .. } => {
+                assert_eq!(url, REPOSITORY_URL);
+                assert_eq!(commit, COMMIT);
+            }
+            origin => panic!("unexpected origin {:?}", origin),
+        }
+        // without dependencies on crates.io, the index is never cloned
+        assert_eq!(generator.index_commit(), None);
+        let invocations = tooling.invocations();
+        assert!(!invocations
+            .iter()
+            .any(|invocation| invocation.starts_with(&format!("git clone {}", INDEX_GIT_URL))));
+        assert!(invocations
+            .iter()
+            .any(|invocation| invocation.starts_with(&format!("git clone {}", REPOSITORY_URL))));
+        assert!(invocations.iter().any(
+            |invocation| invocation.starts_with("git checkout ") && invocation.contains(COMMIT)
+        ));
+    }
+
+    #[test]
+    fn from_git_reports_unknown_revisions() {
+        let dir = TempDir::new("carguix-tooling").unwrap();
+        let tooling = Arc::new(MockTooling {
+            repositories: vec![(INDEX_GIT_URL.to_string(), index_repository(dir.path()))]
+                .into_iter()
+                .collect(),
+            ..MockTooling::default()
+        });
+        let result = Generator::from_git(REPOSITORY_URL, "main", None, &Features::default())
+            .unwrap()
+            .with_options(options(dir.path(), &tooling));
+        assert!(matches!(
+            result,
+            Err(crate::errors::CarguixError::PatchGitError(err, url))
+                if err.kind() == io::ErrorKind::NotFound && url == REPOSITORY_URL
+        ));
+    }
+
+    #[test]
+    fn guix_hash_hashes_stored_tarballs_through_the_tooling() {
+        let dir = TempDir::new("carguix-tooling").unwrap();
+        let tarball = b"tarball of hello 1.0.0";
+        let tarball_dir = dir.path().join("tarballs");
+        write(&tarball_dir.join("hello-1.0.0.crate"), tarball);
+        let checksum = Sha256::digest(tarball)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let index = dir.path().join("sparse");
+        write(
+            &index.join("he/ll/hello"),
+            format!(
+                "{{\"name\":\"hello\",\"vers\":\"1.0.0\",\"deps\":[],\"cksum\":\"{}\",\"features\":{{}},\"yanked\":false}}\n",
+                checksum
+            )
+            .as_bytes(),
+        );
+        let tooling = Arc::new(MockTooling {
+            guix_hashes: vec![("hello-1.0.0.crate".to_string(), GUIX_HASH.to_string())]
+                .into_iter()
+                .collect(),
+            guix_version: Some("1.4.0".to_string()),
+            ..MockTooling::default()
+        });
+        let options = Options {
+            index_url: Some(format!("file://{}", index.display())),
+            tarball_dir: Some(tarball_dir.clone()),
+            guix_hash: true,
+            ..options(dir.path(), &tooling)
+        };
+        let mut generator = Generator::new("hello", &None, &Features::default())
+            .unwrap()
+            .with_options(options)
+            .unwrap();
+        let packages = generator
+            .packages()
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].hash(), Some(GUIX_HASH));
+        let tarball_path = fs::canonicalize(&tarball_dir)
+            .unwrap()
+            .join("hello-1.0.0.crate");
+        assert!(tooling
+            .invocations()
+            .contains(&format!("guix hash {}", tarball_path.display())));
+        assert!(tooling
+            .invocations()
+            .contains(&"guix --version".to_string()));
+    }
+
+    #[test]
+    fn index_updates_fast_forward_through_the_tooling() {
+        let dir = TempDir::new("carguix-tooling").unwrap();
+        let mut repository = index_repository(dir.path());
+        let clone_dir = dir.path().join("clone");
+        let tooling = MockTooling {
+            repositories: vec![(INDEX_GIT_URL.to_string(), repository.clone())]
+                .into_iter()
+                .collect(),
+            ..MockTooling::default()
+        };
+        tooling
+            .git_clone(INDEX_GIT_URL, "HEAD", &clone_dir)
+            .unwrap();
+        let fetched = "fedcba9876543210fedcba9876543210fedcba98";
+        repository
+            .revisions
+            .insert("master".to_string(), fetched.to_string());
+        repository
+            .parents
+            .insert(fetched.to_string(), INDEX_COMMIT.to_string());
+        let tooling = MockTooling {
+            repositories: vec![(INDEX_GIT_URL.to_string(), repository)]
+                .into_iter()
+                .collect(),
+            clones: Mutex::new(tooling.clones.into_inner().unwrap()),
+            ..MockTooling::default()
+        };
+        assert_eq!(
+            tooling
+                .git_fetch(&clone_dir, INDEX_GIT_URL, "master")
+                .unwrap(),
+            fetched
+        );
+        assert!(tooling
+            .git_descends_from(&clone_dir, fetched, INDEX_COMMIT)
+            .unwrap());
+        assert!(!tooling
+            .git_descends_from(&clone_dir, INDEX_COMMIT, fetched)
+            .unwrap());
+        tooling.git_reset(&clone_dir, fetched).unwrap();
+        assert_eq!(tooling.git_head(&clone_dir).unwrap(), fetched);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{crate_ref::CrateRef, graph::Edge, version::compatibility_range};
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, HashMap};
+
+/// Registry crate packaged by a run, which may be replaced by another version of the same crate.
+#[derive(Debug, Clone)]
+pub struct Candidate<'a> {
+    pub crate_ref: &'a CrateRef,
+    /// Alternative registry of the crate, `None` for crates.io.
+    pub registry: Option<&'a str>,
+    /// Root crates are packaged in the version asked for, whatever depends on them.
+    pub root: bool,
+}
+
+/// Versions to package instead of others, one per semver-compatible range of each crate where
+/// possible, like cargo does within a dependency graph.
+///
+/// Versions of a crate compatible with each other, e.g. `1.0.100` and `1.0.188` or `0.3.1` and
+/// `0.3.9` but not `0.3.1` and `0.4.0`, are replaced by the highest one when it satisfies every
+/// requirement on them in `edges`. Versions and requirements semver rejects are left alone.
+pub fn unified_versions<'a>(
+    candidates: &[Candidate<'a>],
+    edges: impl IntoIterator<Item = &'a Edge>,
+) -> HashMap<CrateRef, CrateRef> {
+    let mut requirements = HashMap::<&CrateRef, Vec<&str>>::new();
+    for edge in edges {
+        if let Some(requirement) = &edge.requirement {
+            requirements
+                .entry(&edge.dependency)
+                .or_default()
+                .push(requirement);
+        }
+    }
+    let mut ranges = BTreeMap::<_, Vec<(Version, &Candidate)>>::new();
+    for candidate in candidates {
+        let crate_ref = candidate.crate_ref;
+        if let Ok(version) = Version::parse(&crate_ref.version) {
+            let range = (
+                &crate_ref.name,
+                candidate.registry,
+                compatibility_range(&version),
+            );
+            ranges.entry(range).or_default().push((version, candidate));
+        }
+    }
+    let mut replacements = HashMap::new();
+    for (_, mut versions) in ranges {
+        versions.sort_by(|(version, _), (other, _)| other.cmp(version));
+        while let Some((highest, replacing)) = versions.first().cloned() {
+            versions.retain(|(version, candidate)| {
+                if *version == highest {
+                    return false;
+                }
+                let replaceable = !candidate.root
+                    && requirements
+                        .get(candidate.crate_ref)
+                        .into_iter()
+                        .flatten()
+                        .all(|requirement| {
+                            VersionReq::parse(requirement)
+                                .is_ok_and(|requirement| requirement.matches(&highest))
+                        });
+                if replaceable {
+                    replacements.insert(candidate.crate_ref.clone(), replacing.crate_ref.clone());
+                }
+                !replaceable
+            });
+        }
+    }
+    replacements
+}
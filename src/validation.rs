@@ -0,0 +1,128 @@
+//! Structural checks of the packages run before writing them, so that a definition Guile would
+//! reject when loading the module, such as a malformed hash or an input referring to an unbound
+//! variable, fails the run with the crate it comes from instead.
+//!
+//! The non-Rust inputs are left unchecked, since they come from modules the configuration and
+//! templates import without carguix knowing what they define.
+
+use crate::{
+    crate_ref::CrateRef,
+    errors::CarguixError,
+    guix::{Origin, Package},
+    nix_hash,
+};
+use std::collections::HashSet;
+
+/// Checks of the packages written together, which may refer to one another.
+#[derive(Debug, Clone, Default)]
+pub struct Validator {
+    /// Variables inputs may refer to besides the ones of the Guix packages reused.
+    defined_variables: HashSet<String>,
+    /// Crates other modules define, which inputs may refer to whatever their version.
+    left_out_crates: HashSet<String>,
+}
+
+impl Validator {
+    pub fn new(packages: &[Package]) -> Self {
+        Self {
+            defined_variables: packages.iter().map(Package::variable).collect(),
+            left_out_crates: HashSet::new(),
+        }
+    }
+
+    /// Accept inputs referring to `variables`, such as the ones the module the packages are
+    /// appended to already defines.
+    pub fn with_defined_variables(mut self, variables: impl IntoIterator<Item = String>) -> Self {
+        self.defined_variables.extend(variables);
+        self
+    }
+
+    /// Accept inputs on `crates`, which the run left out for other modules to define them.
+    pub fn with_left_out_crates(mut self, crates: impl IntoIterator<Item = String>) -> Self {
+        self.left_out_crates.extend(crates);
+        self
+    }
+
+    pub fn validate(&self, package: &Package) -> Result<(), CarguixError> {
+        let problems = self.problems(package);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(CarguixError::InvalidPackage {
+            variable: package.variable(),
+            name: package.crate_ref.name.clone(),
+            version: package.crate_ref.version.clone(),
+            problems: problems.join("; "),
+        })
+    }
+
+    /// Errors of the packages failing the checks.
+    pub fn validate_all(&self, packages: &[Package]) -> Vec<CarguixError> {
+        packages
+            .iter()
+            .filter_map(|package| self.validate(package).err())
+            .collect()
+    }
+
+    fn problems(&self, package: &Package) -> Vec<String> {
+        let mut problems = Vec::new();
+        if package.crate_ref.name.is_empty() {
+            problems.push("the name is empty".to_string());
+        }
+        if package.crate_ref.version.is_empty() {
+            problems.push("the version is empty".to_string());
+        }
+        match &package.origin {
+            Origin::Crate {
+                crates_io: false,
+                mirrors,
+                registry: None,
+                ..
+            } if mirrors.is_empty() => {
+                problems.push("the source has no URI to be fetched from".to_string())
+            }
+            Origin::Git { url, commit, .. } if url.is_empty() || commit.is_empty() => {
+                problems.push("the source has no repository URL or commit".to_string())
+            }
+            _ => (),
+        }
+        match (&package.origin, package.hash()) {
+            (Origin::Local { .. }, _) => (),
+            (_, None) => problems.push("the source was never hashed".to_string()),
+            (_, Some(hash)) if !nix_hash::is_sha256(hash) => {
+                problems.push(format!("hash {:?} is not a SHA256 in Nix base32", hash))
+            }
+            _ => (),
+        }
+        for input in package
+            .cargo_inputs
+            .iter()
+            .chain(&package.cargo_development_inputs)
+        {
+            if let Some(variable) = self.unbound_input(package, input) {
+                problems.push(format!(
+                    "input {} is neither defined by the packages nor imported",
+                    variable
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Variable an input refers to, unless it is defined or imported.
+    fn unbound_input(&self, package: &Package, input: &CrateRef) -> Option<String> {
+        if package.existing_inputs.contains_key(input) || self.left_out_crates.contains(&input.name)
+        {
+            return None;
+        }
+        let variable = package
+            .renamed_inputs
+            .get(input)
+            .cloned()
+            .unwrap_or_else(|| input.format_name_version());
+        match self.defined_variables.contains(&variable) {
+            true => None,
+            false => Some(variable),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{
+    cargo_lock::CargoLock, crate_ref::CrateRef, errors::CarguixError, path_source::read_manifest,
+    version,
+};
+use semver::Version;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Crates locked by a `Cargo.lock` file and unpacked by `cargo vendor`, resolved without any
+/// network access.
+#[derive(Debug, Clone, Default)]
+pub struct VendoredCrates {
+    /// Directory `cargo vendor` unpacked the crates into.
+    pub vendor_dir: PathBuf,
+    /// Locked versions of the registry crates, by crate name.
+    versions: HashMap<String, Vec<String>>,
+}
+
+impl VendoredCrates {
+    /// Crates of `vendor_dir` locked by `cargo_lock`, or every crate of `vendor_dir` if there is
+    /// no such file, the vendored versions standing for the locked ones.
+    ///
+    /// A missing `vendor_dir` holds no crate, a project without registry dependencies having
+    /// nothing to vendor.
+    pub fn open(cargo_lock: &Path, vendor_dir: &Path) -> Result<Self, CarguixError> {
+        let vendor_dir = match vendor_dir.canonicalize() {
+            Ok(vendor_dir) => vendor_dir,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vendor_dir.to_path_buf(),
+            Err(err) => return Err(CarguixError::InvalidPath(err, vendor_dir.to_path_buf())),
+        };
+        let mut versions = HashMap::<_, Vec<_>>::new();
+        if cargo_lock.is_file() {
+            for package in CargoLock::open(cargo_lock)?.package {
+                if package.source.is_some() {
+                    versions
+                        .entry(package.name)
+                        .or_default()
+                        .push(package.version);
+                }
+            }
+        } else {
+            log::warn!(
+                "no {:?}, resolving the requirements against the crates of {:?}",
+                cargo_lock,
+                vendor_dir
+            );
+            let entries = match fs::read_dir(&vendor_dir) {
+                Ok(entries) => Some(entries),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(CarguixError::InvalidPath(err, vendor_dir)),
+            };
+            for entry in entries.into_iter().flatten() {
+                let crate_dir = entry
+                    .map_err(|err| CarguixError::InvalidPath(err, vendor_dir.clone()))?
+                    .path();
+                if !crate_dir.join("Cargo.toml").is_file() {
+                    continue;
+                }
+                let manifest = read_manifest(&crate_dir, None)?;
+                let package = manifest.package();
+                versions
+                    .entry(package.name.clone())
+                    .or_default()
+                    .push(package.version().to_string());
+            }
+        }
+        Ok(Self {
+            vendor_dir,
+            versions,
+        })
+    }
+
+    /// Highest locked version of a crate matching a requirement.
+    pub fn resolve(
+        &self,
+        crate_name: &str,
+        requirement: &str,
+        strict: bool,
+    ) -> Result<CrateRef, CarguixError> {
+        let version_req =
+            version::parse_requirement(crate_name, requirement, strict).map_err(|err| {
+                CarguixError::RequirementParsingError(
+                    err,
+                    crate_name.to_string(),
+                    requirement.to_string(),
+                )
+            })?;
+        self.versions
+            .get(crate_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|locked| Some((Version::parse(locked).ok()?, locked)))
+            .filter(|(version, _)| version_req.matches(version))
+            .max_by(|(version, _), (other_version, _)| version.cmp(other_version))
+            .map(|(_, locked)| CrateRef::new(crate_name, locked))
+            .ok_or_else(|| CarguixError::CrateNotLocked {
+                name: crate_name.to_string(),
+                requirement: requirement.to_string(),
+            })
+    }
+
+    /// Directory of a vendored crate, `cargo vendor` suffixing it with the version when several
+    /// versions of the crate are vendored.
+    pub fn crate_path(&self, crate_ref: &CrateRef) -> PathBuf {
+        let versioned = self
+            .vendor_dir
+            .join(format!("{}-{}", crate_ref.name, crate_ref.version));
+        if versioned.is_dir() {
+            versioned
+        } else {
+            self.vendor_dir.join(&crate_ref.name)
+        }
+    }
+
+    /// Whether a crate lives in the vendor directory.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.vendor_dir)
+    }
+}
@@ -0,0 +1,174 @@
+use semver::{ReqParseError, SemVerError, Version, VersionReq};
+
+/// Parse a version published on the index.
+///
+/// Unless `strict` is set, a version semver rejects is read as its repaired form if it has one,
+/// e.g. `0.1` as `0.1.0`, with a warning.
+pub fn parse_version(
+    crate_name: &str,
+    version: &str,
+    strict: bool,
+) -> Result<Version, SemVerError> {
+    let err = match Version::parse(version) {
+        Ok(version) => return Ok(version),
+        Err(err) => err,
+    };
+    let repaired = repair_version(version).and_then(|repaired| {
+        let parsed = Version::parse(&repaired).ok()?;
+        Some((repaired, parsed))
+    });
+    match repaired {
+        Some((repaired, _)) if strict => {
+            log::warn!(
+                "version {} of crate {} is not valid semver, it would be read as {} without --strict-semver",
+                version,
+                crate_name,
+                repaired
+            );
+            Err(err)
+        }
+        Some((repaired, parsed)) => {
+            log::warn!(
+                "version {} of crate {} is not valid semver, reading it as {}",
+                version,
+                crate_name,
+                repaired
+            );
+            Ok(parsed)
+        }
+        None => Err(err),
+    }
+}
+
+/// Parse a dependency requirement, repairing it like [`parse_version`] unless `strict` is set.
+pub fn parse_requirement(
+    crate_name: &str,
+    requirement: &str,
+    strict: bool,
+) -> Result<VersionReq, ReqParseError> {
+    let err = match VersionReq::parse(requirement) {
+        Ok(requirement) => return Ok(requirement),
+        Err(err) => err,
+    };
+    let repaired = repair_requirement(requirement).and_then(|repaired| {
+        let parsed = VersionReq::parse(&repaired).ok()?;
+        Some((repaired, parsed))
+    });
+    match repaired {
+        Some((repaired, _)) if strict => {
+            log::warn!(
+                "requirement {} on crate {} is not valid semver, it would be read as {} without --strict-semver",
+                requirement,
+                crate_name,
+                repaired
+            );
+            Err(err)
+        }
+        Some((repaired, parsed)) => {
+            log::warn!(
+                "requirement {} on crate {} is not valid semver, reading it as {}",
+                requirement,
+                crate_name,
+                repaired
+            );
+            Ok(parsed)
+        }
+        None => Err(err),
+    }
+}
+
+/// Whether a requirement explicitly mentions a pre-release, e.g. `>=2.0.0-beta.1`, build
+/// metadata aside.
+pub fn mentions_prerelease(requirement: &str) -> bool {
+    requirement.split(',').any(|comparator| {
+        comparator
+            .split('+')
+            .next()
+            .is_some_and(|version| version.contains('-'))
+    })
+}
+
+/// Whether every comparator of a requirement is a wildcard, e.g. `*` or `1.*`, the requirement
+/// then matching whatever versions get published.
+pub fn is_wildcard(requirement: &str) -> bool {
+    requirement.split(',').all(|comparator| {
+        let comparator = comparator.trim();
+        comparator == "*"
+            || comparator.ends_with(".*")
+            || comparator.ends_with(".x")
+            || comparator.ends_with(".X")
+    })
+}
+
+/// Versions semver considers compatible share their leftmost non-zero number, e.g. `1.x.y`,
+/// `0.3.y` or `0.0.4`.
+pub fn compatibility_range(version: &Version) -> (u64, u64, u64) {
+    match (version.major, version.minor) {
+        (0, 0) => (0, 0, version.patch),
+        (0, minor) => (0, minor, 0),
+        (major, _) => (major, 0, 0),
+    }
+}
+
+/// Version with its missing minor and patch numbers set to zero, leading zeros and `v` prefix
+/// removed, e.g. `v01.2-beta` as `1.2.0-beta`.
+fn repair_version(version: &str) -> Option<String> {
+    let (mut numbers, suffix) = repair_numbers(version)?;
+    numbers.resize(3, "0".to_string());
+    Some(format!("{}{}", numbers.join("."), suffix))
+}
+
+/// Numbers of a version without leading zeros nor `v` prefix, along with its pre-release and
+/// build suffix.
+fn repair_numbers(version: &str) -> Option<(Vec<String>, &str)> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    let split = version.find(['-', '+']).unwrap_or(version.len());
+    let (numbers, suffix) = version.split_at(split);
+    let numbers = numbers
+        .split('.')
+        .map(|number| {
+            if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            match number.trim_start_matches('0') {
+                "" => Some("0".to_string()),
+                number => Some(number.to_string()),
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if numbers.len() > 3 {
+        return None;
+    }
+    Some((numbers, suffix))
+}
+
+/// Requirement whose versions are repaired, its comparators being separated by commas, e.g.
+/// `>= 01.0 < 2` as `>=1.0, <2`.
+///
+/// Partial versions are kept as such, `=0.1` matching any `0.1.x` unlike `=0.1.0`.
+fn repair_requirement(requirement: &str) -> Option<String> {
+    let mut comparators = Vec::new();
+    let mut tokens = requirement
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty());
+    while let Some(token) = tokens.next() {
+        let split = token
+            .find(|c| !matches!(c, '<' | '>' | '=' | '^' | '~'))
+            .unwrap_or(token.len());
+        let (operator, version) = match token.split_at(split) {
+            (operator, "") => (operator, tokens.next()?),
+            (operator, version) => (operator, version),
+        };
+        let version = if version.contains(['*', 'x', 'X']) {
+            version.to_string()
+        } else {
+            let (numbers, suffix) = repair_numbers(version)?;
+            format!("{}{}", numbers.join("."), suffix)
+        };
+        comparators.push(format!("{}{}", operator, version));
+    }
+    if comparators.is_empty() {
+        return None;
+    }
+    Some(comparators.join(", "))
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Category of the warnings about the packages a run generates, which `--allow` silences and
+/// `--deny` turns into errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WarningCode {
+    /// License missing or without a Guix counterpart.
+    UnknownLicense,
+    /// Yanked version packaged since no other one matches.
+    YankedVersion,
+    /// Crate binding a system library, through the `links` key of its manifest, without any
+    /// input providing it.
+    LinksWithoutInputs,
+    /// Pre-release packaged since the crate has no stable version.
+    PreRelease,
+    /// Crate packaged at several incompatible versions.
+    DuplicateMajor,
+    /// Development input left out to break a dependency cycle.
+    DependencyCycle,
+    /// Wildcard requirement only the lock file keeps from changing.
+    UnpinnedWildcard,
+    /// Older version packaged since the tarball of the one resolved is missing from the server.
+    MissingTarball,
+}
+
+impl WarningCode {
+    pub const ALL: [WarningCode; 8] = [
+        WarningCode::UnknownLicense,
+        WarningCode::YankedVersion,
+        WarningCode::LinksWithoutInputs,
+        WarningCode::PreRelease,
+        WarningCode::DuplicateMajor,
+        WarningCode::DependencyCycle,
+        WarningCode::UnpinnedWildcard,
+        WarningCode::MissingTarball,
+    ];
+
+    /// Code of the category, e.g. `W001`.
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningCode::UnknownLicense => "W001",
+            WarningCode::YankedVersion => "W002",
+            WarningCode::LinksWithoutInputs => "W003",
+            WarningCode::PreRelease => "W004",
+            WarningCode::DuplicateMajor => "W005",
+            WarningCode::DependencyCycle => "W006",
+            WarningCode::UnpinnedWildcard => "W007",
+            WarningCode::MissingTarball => "W008",
+        }
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for WarningCode {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        WarningCode::ALL
+            .iter()
+            .copied()
+            .find(|warning_code| warning_code.code().eq_ignore_ascii_case(code))
+            .ok_or_else(|| {
+                let codes = WarningCode::ALL
+                    .iter()
+                    .map(|warning_code| warning_code.code())
+                    .collect::<Vec<_>>();
+                format!(
+                    "unknown warning code {}, expected one of {}",
+                    code,
+                    codes.join(", ")
+                )
+            })
+    }
+}
+
+/// Coded warnings of a run, logged unless allowed and recorded when denied so that the run fails
+/// once the packages are generated.
+#[derive(Debug, Clone, Default)]
+pub struct Warnings {
+    pub allowed: BTreeSet<WarningCode>,
+    pub denied: BTreeSet<WarningCode>,
+    /// Denied warnings emitted so far, shared by the clones of the options.
+    emitted_denied: Arc<Mutex<Vec<String>>>,
+}
+
+impl Warnings {
+    pub fn new(allowed: &[WarningCode], denied: &[WarningCode]) -> Self {
+        Self {
+            allowed: allowed.iter().copied().collect(),
+            denied: denied.iter().copied().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Log a warning along with its code, unless its category is allowed.
+    pub fn warn(&self, code: WarningCode, message: fmt::Arguments) {
+        if self.denied.contains(&code) {
+            log::error!("[{}] {}", code, message);
+            self.emitted_denied
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(format!("[{}] {}", code, message));
+        } else if self.allowed.contains(&code) {
+            log::debug!("[{}] {}", code, message);
+        } else {
+            log::warn!("[{}] {}", code, message);
+        }
+    }
+
+    /// Warnings of the denied categories emitted so far.
+    pub fn denied_warnings(&self) -> Vec<String> {
+        self.emitted_denied
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
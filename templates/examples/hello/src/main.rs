@@ -0,0 +1,3 @@
+fn main() {
+    println!("Hello from a Guix package generated by carguix!");
+}
@@ -0,0 +1,4 @@
+/// Greeting addressed to `name`.
+pub fn greeting(name: &str) -> String {
+    format!("Hello, {}, from a Guix package generated by carguix!", name)
+}